@@ -0,0 +1,181 @@
+"use strict";
+
+// the admin token is kept for the browser tab only
+const TOKEN_KEY = "admin-token";
+const PAGE_SIZE = 100;
+
+const $ = (id) => document.getElementById(id);
+
+function showMessage(text, isError) {
+  const message = $("message");
+  message.textContent = text || "";
+  message.className = isError ? "error" : "";
+}
+
+async function api(method, path) {
+  const token = sessionStorage.getItem(TOKEN_KEY);
+  if (!token) {
+    throw new Error("Sign in with the admin token first");
+  }
+
+  const response = await fetch(path, { method, headers: { Authorization: "Bearer " + token } });
+
+  if (response.status === 401) {
+    throw new Error("The admin token was refused");
+  }
+  if (!response.ok) {
+    const body = await response.json().catch(() => ({}));
+    throw new Error(body.message || body.error || response.status + " " + response.statusText);
+  }
+
+  return response;
+}
+
+function fillRows(tbody, rows, cells) {
+  tbody.replaceChildren();
+
+  for (const row of rows) {
+    const tr = document.createElement("tr");
+
+    for (const cell of cells(row)) {
+      const td = document.createElement("td");
+      if (cell instanceof Node) {
+        td.appendChild(cell);
+      } else {
+        td.textContent = cell === null || cell === undefined ? "" : String(cell);
+        td.className = "long";
+      }
+      tr.appendChild(td);
+    }
+
+    tbody.appendChild(tr);
+  }
+
+  if (rows.length === 0) {
+    showMessage("Nothing found");
+  }
+}
+
+// runs a form handler, showing its error instead of failing silently
+function onSubmit(form, handler) {
+  $(form).addEventListener("submit", async (event) => {
+    event.preventDefault();
+    showMessage("");
+    try {
+      await handler();
+    } catch (err) {
+      showMessage(err.message, true);
+    }
+  });
+}
+
+// the blacklist is read from the export, a page at a time with its after_id cursor
+let nextAfterId = 0;
+
+async function loadBlacklist(afterId) {
+  const domainId = $("blacklist-domain").value;
+  const params = new URLSearchParams({ format: "ndjson", after_id: afterId, limit: PAGE_SIZE });
+  if ($("blacklist-status").value) {
+    params.set("status", $("blacklist-status").value);
+  }
+
+  const response = await api("GET", `/api/${encodeURIComponent(domainId)}/blacklist/export?${params}`);
+  const lines = (await response.text()).split("\n").filter((line) => line.trim() !== "");
+  const rows = lines.map((line) => JSON.parse(line));
+
+  const failed = rows.find((row) => row.error);
+  if (failed) {
+    throw new Error(failed.error);
+  }
+
+  fillRows($("blacklist-rows"), rows, (row) => [
+    row.id, row.email, row.status, row.category, row.reason_summary, row.expires_at, row.created_at,
+  ]);
+
+  nextAfterId = rows.length === PAGE_SIZE ? rows[rows.length - 1].id : null;
+  $("blacklist-next").disabled = nextAfterId === null;
+}
+
+onSubmit("blacklist-form", () => loadBlacklist(0));
+
+$("blacklist-next").addEventListener("click", async () => {
+  showMessage("");
+  try {
+    await loadBlacklist(nextAfterId);
+  } catch (err) {
+    showMessage(err.message, true);
+  }
+});
+
+onSubmit("search-form", async () => {
+  const email = $("search-email").value.trim();
+  const response = await api("GET", `/api/is-blacklisted/${encodeURIComponent(email)}?status=all`);
+  const body = await response.json();
+
+  fillRows($("search-rows"), body.data, (row) => [
+    row.domain_name ? `${row.domain_name} (${row.domain_id})` : row.domain_id,
+    row.status, row.category, row.reason_summary, row.expires_at, row.created_at,
+  ]);
+});
+
+onSubmit("stats-form", async () => {
+  const params = new URLSearchParams();
+  if ($("stats-from").value) params.set("from", $("stats-from").value);
+  if ($("stats-to").value) params.set("to", $("stats-to").value);
+
+  const domainId = encodeURIComponent($("stats-domain").value);
+  const response = await api("GET", `/api/${domainId}/stats/daily?${params}`);
+  const body = await response.json();
+
+  fillRows($("stats-rows"), body.data, (row) => [
+    row.day, row.blacklist_size, row.hard_bounces, row.soft_bounces, row.complaints, row.manual,
+    (row.bounce_rate * 100).toFixed(2) + " %",
+  ]);
+});
+
+const DEAD_LETTERS = {
+  sns: "/api/admin/dead-letters",
+  webhook: "/api/admin/webhook-dead-letters",
+};
+
+async function loadDeadLetters() {
+  const base = DEAD_LETTERS[$("dead-letters-kind").value];
+  const body = await (await api("GET", base)).json();
+
+  fillRows($("dead-letters-rows"), body.data, (row) => {
+    const replay = document.createElement("button");
+    replay.textContent = "Replay";
+    replay.addEventListener("click", async () => {
+      showMessage("");
+      try {
+        await api("POST", `${base}/${row.id}/replay`);
+        showMessage(`Dead letter ${row.id} replayed`);
+        await loadDeadLetters();
+      } catch (err) {
+        showMessage(err.message, true);
+      }
+    });
+
+    const replayed = row.replayed_at ? `${row.replay_status} at ${row.replayed_at}` : "";
+    return [row.id, row.domain_id, row.error, row.created_at, replayed, replay];
+  });
+}
+
+onSubmit("dead-letters-form", loadDeadLetters);
+
+$("token-form").addEventListener("submit", (event) => {
+  event.preventDefault();
+  sessionStorage.setItem(TOKEN_KEY, $("token").value);
+  $("token").value = "";
+  showMessage("Signed in for this tab");
+});
+
+for (const button of document.querySelectorAll("nav button")) {
+  button.addEventListener("click", () => {
+    for (const other of document.querySelectorAll("nav button, section")) {
+      other.classList.remove("active");
+    }
+    button.classList.add("active");
+    $(button.dataset.tab).classList.add("active");
+  });
+}
@@ -0,0 +1,21 @@
+-- wrk -t4 -c64 -d60s -s bench/lookup.lua http://localhost:8000
+-- single lookups of a domain, LOOKUP_HIT_RATIO of them against addresses seeded by seed.sh
+local domain = os.getenv("BENCH_DOMAIN_ID") or "1"
+local api_key = os.getenv("BENCH_API_KEY")
+local seeded = tonumber(os.getenv("BENCH_SEEDED") or "10000")
+local hit_ratio = tonumber(os.getenv("LOOKUP_HIT_RATIO") or "0.1")
+
+local headers = {}
+if api_key then
+  headers["X-API-Key"] = api_key
+end
+
+request = function()
+  local email
+  if math.random() < hit_ratio then
+    email = string.format("bench-%d@example.com", math.random(1, seeded))
+  else
+    email = string.format("clean-%d@example.net", math.random(1, 100000000))
+  end
+  return wrk.format("GET", "/api/" .. domain .. "/is-blacklisted/" .. email, headers)
+end
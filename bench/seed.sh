@@ -0,0 +1,21 @@
+#!/bin/sh
+# seeds BENCH_SEEDED (10000) entries into BENCH_DOMAIN_ID (1) through the import endpoint, for the lookup hit ratio
+set -eu
+
+url=${BENCH_URL:-http://localhost:8000}
+domain=${BENCH_DOMAIN_ID:-1}
+count=${BENCH_SEEDED:-10000}
+batch=1000
+
+i=1
+while [ "$i" -le "$count" ]; do
+  end=$((i + batch - 1))
+  [ "$end" -gt "$count" ] && end=$count
+  body=$(seq "$i" "$end" | awk '{ printf "%s{\"email\":\"bench-%d@example.com\",\"reason\":\"bench\"}", (NR > 1 ? "," : ""), $1 }')
+  curl -fsS -X POST "$url/api/$domain/blacklist/import" \
+    -H "Authorization: Bearer $ADMIN_TOKEN" -H "Content-Type: application/json" \
+    -d "[$body]" > /dev/null
+  i=$((end + 1))
+done
+
+echo "seeded $count entries into domain $domain"
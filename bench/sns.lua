@@ -0,0 +1,25 @@
+-- wrk -t2 -c16 -d60s -s bench/sns.lua http://localhost:8000
+-- bounce notifications with a fresh recipient each, run against SNS_VERIFY_SIGNATURES=false
+local domain = os.getenv("BENCH_DOMAIN_ID") or "1"
+
+local file = assert(io.open("bench/samples/bounce.json", "r"))
+local template = file:read("*a"):gsub("%s+", " ")
+file:close()
+
+local function escape(value)
+  return (value:gsub("\\", "\\\\"):gsub('"', '\\"'))
+end
+
+local counter = 0
+
+request = function()
+  counter = counter + 1
+  local email = string.format("load-%d-%d@example.com", math.random(1, 1000000000), counter)
+  local message = template:gsub("recipient@example%.com", email)
+  local body = string.format(
+    '{"Type":"Notification","MessageId":"bench-%d","TopicArn":"arn:aws:sns:us-east-1:123456789012:ses-bounces","Message":"%s","Timestamp":"2024-01-15T10:30:12.500Z","SignatureVersion":"1","Signature":"unsigned","SigningCertURL":"https://sns.us-east-1.amazonaws.com/bench.pem"}',
+    counter,
+    escape(message)
+  )
+  return wrk.format("POST", "/api/" .. domain .. "/sns-endpoint", { ["Content-Type"] = "text/plain; charset=UTF-8" }, body)
+end
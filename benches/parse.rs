@@ -0,0 +1,61 @@
+use std::io::Write;
+
+use actix_web::web::Bytes;
+use aws_ses_bounce::domain::Message;
+use aws_ses_bounce::payload::{decode_body, decode_text, parse, parse_message, SnsPayload};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+// the samples of bench-parse and the wrk profiles, so the numbers compare
+const SNS_BOUNCE: &str = include_str!("../bench/samples/sns_bounce.json");
+const RAW_BOUNCE: &str = include_str!("../bench/samples/bounce.json");
+
+fn gzip(bytes: &[u8]) -> Bytes {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).unwrap();
+    Bytes::from(encoder.finish().unwrap())
+}
+
+// the envelope and the SES message it carries, as the SNS endpoint parses them
+fn envelope_and_message(body: &[u8]) -> Message {
+    let message = match parse(body, false).unwrap() {
+        SnsPayload::Envelope(notification) => notification.message.unwrap_or_default(),
+        SnsPayload::Raw(message) => message,
+    };
+
+    parse_message(&message).unwrap()
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+
+    for (name, body) in [("sns-envelope", SNS_BOUNCE), ("raw", RAW_BOUNCE)] {
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_function(name, |b| b.iter(|| envelope_and_message(body.as_bytes())));
+    }
+
+    group.throughput(Throughput::Bytes(RAW_BOUNCE.len() as u64));
+    group.bench_function("ses-message", |b| b.iter(|| parse_message(RAW_BOUNCE).unwrap()));
+
+    group.finish();
+}
+
+fn decoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let plain = Bytes::from_static(SNS_BOUNCE.as_bytes());
+    let gzipped = gzip(SNS_BOUNCE.as_bytes());
+
+    group.throughput(Throughput::Bytes(SNS_BOUNCE.len() as u64));
+    group.bench_function("plain", |b| {
+        b.iter_batched(|| plain.clone(), |body| decode_text(decode_body(body)), BatchSize::SmallInput)
+    });
+    group.bench_function("gzip", |b| {
+        b.iter_batched(|| gzipped.clone(), |body| decode_text(decode_body(body)), BatchSize::SmallInput)
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, parsing, decoding);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use aws_ses_bounce::payload::parse_message;
+use libfuzzer_sys::fuzz_target;
+
+// the Message of an SNS envelope, which SNS passes on from SES unchecked
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = std::str::from_utf8(data) {
+        let _ = parse_message(message);
+    }
+});
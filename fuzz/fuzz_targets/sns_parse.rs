@@ -0,0 +1,14 @@
+#![no_main]
+
+use aws_ses_bounce::payload::{decode_body, decode_text, parse};
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+
+// the body of a POST to the SNS endpoint as the extractor reads it: decompressed, decoded, then parsed both as an
+// envelope and as a raw delivery
+fuzz_target!(|data: &[u8]| {
+    let (body, _) = decode_text(decode_body(Bytes::copy_from_slice(data)));
+
+    let _ = parse(&body, false);
+    let _ = parse(&body, true);
+});
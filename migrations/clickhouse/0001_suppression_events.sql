@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS suppression_events (
+    domain_id Int32,
+    event_type LowCardinality(String),
+    email String,
+    category LowCardinality(String),
+    expires_at Nullable(DateTime),
+    timestamp DateTime
+) ENGINE = MergeTree
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (domain_id, timestamp);
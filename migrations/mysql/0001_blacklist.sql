@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS blacklist (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id BIGINT NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    reason LONGTEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY blacklist_domain_email (domain_id, email)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS domains (
+    id BIGINT NOT NULL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    bounce_rules TEXT NULL
+);
+
+ALTER TABLE blacklist ADD COLUMN expires_at DATETIME NULL;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id BIGINT NOT NULL,
+    payload LONGTEXT NOT NULL,
+    error TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    replayed_at DATETIME NULL,
+    replay_status VARCHAR(32) NULL,
+    replay_error TEXT NULL
+);
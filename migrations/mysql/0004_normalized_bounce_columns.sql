@@ -0,0 +1,4 @@
+ALTER TABLE blacklist
+    ADD COLUMN bounce_type VARCHAR(64) NULL,
+    ADD COLUMN bounce_sub_type VARCHAR(64) NULL,
+    ADD COLUMN diagnostic_code TEXT NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE domains ADD COLUMN suppression_days TEXT NULL;
+
+ALTER TABLE blacklist ADD COLUMN category VARCHAR(32) NULL;
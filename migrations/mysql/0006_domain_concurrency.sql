@@ -0,0 +1 @@
+ALTER TABLE domains ADD COLUMN max_concurrency BIGINT NULL;
@@ -0,0 +1,16 @@
+-- key_hash is the hex SHA-256 of the key, e.g. INSERT INTO api_keys (name, key_hash) VALUES ('team', SHA2('<key>', 256))
+CREATE TABLE IF NOT EXISTS api_keys (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    key_hash CHAR(64) NOT NULL,
+    daily_quota BIGINT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY api_keys_key_hash (key_hash)
+);
+
+CREATE TABLE IF NOT EXISTS api_usage (
+    api_key_id BIGINT NOT NULL,
+    day DATE NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (api_key_id, day)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS notification_log (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id BIGINT NOT NULL,
+    notification_type VARCHAR(64) NOT NULL,
+    message_id VARCHAR(255) NULL,
+    payload LONGTEXT NOT NULL,
+    received_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    KEY notification_log_domain_received (domain_id, received_at)
+);
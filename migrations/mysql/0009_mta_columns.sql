@@ -0,0 +1,3 @@
+ALTER TABLE blacklist
+    ADD COLUMN reporting_mta VARCHAR(255) NULL,
+    ADD COLUMN remote_mta_ip VARCHAR(64) NULL;
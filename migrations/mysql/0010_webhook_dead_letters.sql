@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id BIGINT NOT NULL,
+    url TEXT NOT NULL,
+    payload LONGTEXT NOT NULL,
+    attempts BIGINT NOT NULL,
+    error TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    replayed_at DATETIME NULL,
+    replay_status VARCHAR(32) NULL,
+    replay_error TEXT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE blacklist
+    ADD COLUMN source_arn VARCHAR(255) NULL,
+    ADD COLUMN sending_account_id VARCHAR(32) NULL;
@@ -0,0 +1,14 @@
+-- partitions per month are created and dropped by the service when LOOKUP_AUDIT=true
+CREATE TABLE IF NOT EXISTS lookup_audit (
+    id BIGINT NOT NULL AUTO_INCREMENT,
+    domain_id BIGINT NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    blacklisted BOOLEAN NOT NULL,
+    api_key_id BIGINT NULL,
+    requested_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id, requested_at),
+    KEY lookup_audit_domain_email (domain_id, email, requested_at)
+)
+PARTITION BY RANGE COLUMNS (requested_at) (
+    PARTITION p_future VALUES LESS THAN (MAXVALUE)
+);
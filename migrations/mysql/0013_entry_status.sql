@@ -0,0 +1,4 @@
+-- lifecycle of an entry: active, pending_review, expired, removed or allowlisted, only active entries suppress
+ALTER TABLE blacklist
+    ADD COLUMN status VARCHAR(32) NOT NULL DEFAULT 'active',
+    ADD COLUMN status_changed_at DATETIME NULL;
@@ -0,0 +1,5 @@
+-- set when the complaint rate of the domain exceeds COMPLAINT_RATE_THRESHOLD
+ALTER TABLE domains
+    ADD COLUMN sending_paused BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN paused_at DATETIME NULL,
+    ADD COLUMN pause_reason VARCHAR(255) NULL;
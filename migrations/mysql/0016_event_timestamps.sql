@@ -0,0 +1,5 @@
+-- when SES saw the bounce or complaint, for time range queries independent of when it was processed
+ALTER TABLE blacklist
+    ADD COLUMN event_at DATETIME NULL;
+
+CREATE INDEX blacklist_domain_event_at ON blacklist (domain_id, event_at);
@@ -0,0 +1,15 @@
+-- per domain snapshots written by the service when DAILY_STATS=true
+CREATE TABLE IF NOT EXISTS daily_stats (
+    domain_id INT NOT NULL,
+    day DATE NOT NULL,
+    blacklist_size BIGINT NOT NULL DEFAULT 0,
+    hard_bounces BIGINT NOT NULL DEFAULT 0,
+    soft_bounces BIGINT NOT NULL DEFAULT 0,
+    complaints BIGINT NOT NULL DEFAULT 0,
+    manual BIGINT NOT NULL DEFAULT 0,
+    bounce_notifications BIGINT NOT NULL DEFAULT 0,
+    delivery_notifications BIGINT NOT NULL DEFAULT 0,
+    bounce_rate DOUBLE NOT NULL DEFAULT 0,
+    computed_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (domain_id, day)
+);
@@ -0,0 +1,5 @@
+-- the classified diagnostic of a bounce (no_such_user, mailbox_full, ...), existing rows are filled by backfill-reasons
+ALTER TABLE blacklist
+    ADD COLUMN diagnostic_class VARCHAR(32) NULL;
+
+CREATE INDEX blacklist_domain_diagnostic_class ON blacklist (domain_id, diagnostic_class);
@@ -0,0 +1,6 @@
+-- a short readable reason for UIs, e.g. "Hard bounce: user unknown (5.1.1)", existing rows are filled by backfill-reasons
+ALTER TABLE blacklist
+    ADD COLUMN reason_summary VARCHAR(300) NULL;
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (20, 19);
@@ -0,0 +1,15 @@
+-- SubscriptionConfirmation messages, kept so a confirmation that failed (e.g. mid-deploy) is retried
+CREATE TABLE IF NOT EXISTS sns_subscriptions (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id INT NOT NULL,
+    topic_arn VARCHAR(255) NULL,
+    subscribe_url TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    confirmed_at DATETIME NULL,
+    INDEX sns_subscriptions_status (status)
+);
+
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (21, 19);
@@ -0,0 +1,5 @@
+-- the JSON shape of the domain's webhook payload with {{field}} placeholders, the plain event when NULL
+ALTER TABLE domains ADD COLUMN webhook_template TEXT NULL;
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (22, 19);
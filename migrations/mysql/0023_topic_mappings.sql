@@ -0,0 +1,12 @@
+-- the domain of each SNS topic delivering to the shared /api/sns-endpoint
+CREATE TABLE IF NOT EXISTS topic_mappings (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    topic_arn VARCHAR(255) NOT NULL,
+    domain_id INT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY topic_mappings_topic_arn (topic_arn)
+);
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (23, 19);
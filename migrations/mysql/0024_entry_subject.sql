@@ -0,0 +1,6 @@
+-- the subject of the message behind the entry, from the SES commonHeaders, for support
+ALTER TABLE blacklist
+    ADD COLUMN subject VARCHAR(300) NULL;
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (24, 19);
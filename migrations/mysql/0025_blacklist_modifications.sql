@@ -0,0 +1,11 @@
+-- when the entries of each domain last changed, for Last-Modified / If-Modified-Since on the full downloads
+CREATE TABLE IF NOT EXISTS blacklist_modifications (
+    domain_id BIGINT NOT NULL PRIMARY KEY,
+    modified_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT IGNORE INTO blacklist_modifications (domain_id, modified_at)
+SELECT domain_id, MAX(GREATEST(created_at, COALESCE(status_changed_at, created_at))) FROM blacklist GROUP BY domain_id;
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (25, 19);
@@ -0,0 +1,13 @@
+-- suppression events written with their entry, put on the event bus by the outbox publisher
+CREATE TABLE IF NOT EXISTS outbox (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id INT NOT NULL,
+    event_type VARCHAR(32) NOT NULL,
+    payload LONGTEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    published_at DATETIME NULL,
+    KEY outbox_published_at (published_at, id)
+);
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (26, 19);
@@ -0,0 +1,14 @@
+-- the SES messages kept as reason of old entries, gzipped here or in S3 (s3_key), see archive-reasons
+CREATE TABLE IF NOT EXISTS reason_archive (
+    blacklist_id BIGINT NOT NULL PRIMARY KEY,
+    domain_id BIGINT NOT NULL,
+    reason_gz LONGBLOB NULL,
+    s3_key VARCHAR(512) NULL,
+    archived_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- set when the reason moved to the archive, the column is then emptied
+ALTER TABLE blacklist ADD COLUMN reason_archived_at DATETIME NULL;
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (27, 19);
@@ -0,0 +1,5 @@
+-- the original payload sealed with DEAD_LETTER_PUBLIC_KEY, payload then holds a redacted copy; NULL when stored in clear
+ALTER TABLE dead_letters ADD COLUMN encrypted_payload LONGTEXT NULL;
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (30, 19);
@@ -0,0 +1,20 @@
+-- progress of the blacklist imports processed in the background, see src/imports.rs
+CREATE TABLE IF NOT EXISTS import_jobs (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id INT NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    total INT NOT NULL,
+    processed INT NOT NULL DEFAULT 0,
+    inserted INT NOT NULL DEFAULT 0,
+    skipped INT NOT NULL DEFAULT 0,
+    failed INT NOT NULL DEFAULT 0,
+    -- JSON array of the first failures
+    errors TEXT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at DATETIME NULL,
+    KEY import_jobs_domain_id (domain_id, id)
+);
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (31, 19);
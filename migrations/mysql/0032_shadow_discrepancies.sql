@@ -0,0 +1,16 @@
+-- notifications the candidate pipeline of SHADOW_MODE read differently from the live one, see src/shadow.rs
+CREATE TABLE IF NOT EXISTS shadow_discrepancies (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id INT NOT NULL,
+    notification_type VARCHAR(64) NOT NULL,
+    message_id VARCHAR(255) NULL,
+    payload LONGTEXT NOT NULL,
+    -- JSON, the entries or the error of each pipeline
+    current_result LONGTEXT NOT NULL,
+    candidate_result LONGTEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    KEY shadow_discrepancies_domain_id (domain_id, id)
+);
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (32, 19);
@@ -0,0 +1,10 @@
+-- addresses are stored trimmed and lowercased (privacy::stored_email), the rows written before are lowered here.
+-- A row whose lowered address already has an entry in the domain keeps its case, IGNORE skips it.
+UPDATE IGNORE blacklist SET email = LOWER(TRIM(email)) WHERE CAST(email AS BINARY) <> CAST(LOWER(TRIM(email)) AS BINARY);
+
+-- the default collations ignore case and accents, a binary one compares the normalized address as Postgres does,
+-- whatever the collation of the server. Rebuilds the blacklist_domain_email unique key.
+ALTER TABLE blacklist MODIFY email VARCHAR(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin NOT NULL;
+
+-- builds of version 19 keep working against it, addresses they write in another case are no longer matched
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (33, 19);
@@ -0,0 +1,16 @@
+-- webhook deliveries not yet accepted by the receiver, attempted by the delivery poller of any instance once
+-- next_attempt_at is due, deleted on success or when moved to webhook_dead_letters
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    domain_id BIGINT NOT NULL,
+    url TEXT NOT NULL,
+    payload LONGTEXT NOT NULL,
+    attempts BIGINT NOT NULL DEFAULT 0,
+    next_attempt_at DATETIME(3) NOT NULL,
+    last_error TEXT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    KEY webhook_deliveries_next_attempt (next_attempt_at)
+);
+
+-- additive, builds of version 19 keep working against it
+INSERT IGNORE INTO schema_version (version, min_compatible) VALUES (35, 19);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS blacklist (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id INTEGER NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (domain_id, email)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS domains (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    bounce_rules TEXT NULL
+);
+
+ALTER TABLE blacklist ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE blacklist
+    ADD COLUMN IF NOT EXISTS bounce_type VARCHAR(64) NULL,
+    ADD COLUMN IF NOT EXISTS bounce_sub_type VARCHAR(64) NULL,
+    ADD COLUMN IF NOT EXISTS diagnostic_code TEXT NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS suppression_days TEXT NULL;
+
+ALTER TABLE blacklist ADD COLUMN IF NOT EXISTS category VARCHAR(32) NULL;
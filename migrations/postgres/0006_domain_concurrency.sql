@@ -0,0 +1 @@
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS max_concurrency INTEGER NULL;
@@ -0,0 +1,15 @@
+-- key_hash is the hex SHA-256 of the key, e.g. INSERT INTO api_keys (name, key_hash) VALUES ('team', encode(sha256('<key>'), 'hex'))
+CREATE TABLE IF NOT EXISTS api_keys (
+    id BIGSERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    daily_quota BIGINT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS api_usage (
+    api_key_id BIGINT NOT NULL,
+    day DATE NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (api_key_id, day)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS notification_log (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id INTEGER NOT NULL,
+    notification_type VARCHAR(64) NOT NULL,
+    message_id VARCHAR(255) NULL,
+    payload TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS notification_log_domain_received ON notification_log (domain_id, received_at);
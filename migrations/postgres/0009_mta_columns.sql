@@ -0,0 +1,3 @@
+ALTER TABLE blacklist
+    ADD COLUMN IF NOT EXISTS reporting_mta VARCHAR(255) NULL,
+    ADD COLUMN IF NOT EXISTS remote_mta_ip VARCHAR(64) NULL;
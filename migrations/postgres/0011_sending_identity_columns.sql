@@ -0,0 +1,3 @@
+ALTER TABLE blacklist
+    ADD COLUMN IF NOT EXISTS source_arn VARCHAR(255) NULL,
+    ADD COLUMN IF NOT EXISTS sending_account_id VARCHAR(32) NULL;
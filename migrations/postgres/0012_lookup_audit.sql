@@ -0,0 +1,12 @@
+-- partitions per month are created and dropped by the service when LOOKUP_AUDIT=true
+CREATE TABLE IF NOT EXISTS lookup_audit (
+    id BIGSERIAL,
+    domain_id INTEGER NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    blacklisted BOOLEAN NOT NULL,
+    api_key_id BIGINT NULL,
+    requested_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id, requested_at)
+) PARTITION BY RANGE (requested_at);
+
+CREATE INDEX IF NOT EXISTS lookup_audit_domain_email ON lookup_audit (domain_id, email, requested_at);
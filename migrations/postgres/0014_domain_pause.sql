@@ -0,0 +1,5 @@
+-- set when the complaint rate of the domain exceeds COMPLAINT_RATE_THRESHOLD
+ALTER TABLE domains
+    ADD COLUMN IF NOT EXISTS sending_paused BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS paused_at TIMESTAMP NULL,
+    ADD COLUMN IF NOT EXISTS pause_reason VARCHAR(255) NULL;
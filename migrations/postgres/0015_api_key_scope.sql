@@ -0,0 +1,3 @@
+-- customer keys only reach the entries of their own domain, NULL keeps a key unscoped
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS domain_id INT NULL;
@@ -0,0 +1,9 @@
+-- every migration from here on records its version and the oldest build version that can still write with it,
+-- the service refuses to start (or serves read-only) against an incompatible schema
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER NOT NULL PRIMARY KEY,
+    min_compatible INTEGER NOT NULL,
+    applied_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO schema_version (version, min_compatible) VALUES (19, 19) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,16 @@
+-- SubscriptionConfirmation messages, kept so a confirmation that failed (e.g. mid-deploy) is retried
+CREATE TABLE IF NOT EXISTS sns_subscriptions (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id INTEGER NOT NULL,
+    topic_arn VARCHAR(255) NULL,
+    subscribe_url TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    confirmed_at TIMESTAMP NULL
+);
+
+CREATE INDEX IF NOT EXISTS sns_subscriptions_status ON sns_subscriptions (status);
+
+INSERT INTO schema_version (version, min_compatible) VALUES (21, 19) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,11 @@
+-- the domain of each SNS topic delivering to the shared /api/sns-endpoint
+CREATE TABLE IF NOT EXISTS topic_mappings (
+    id BIGSERIAL PRIMARY KEY,
+    topic_arn VARCHAR(255) NOT NULL UNIQUE,
+    domain_id INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (23, 19) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,12 @@
+-- when the entries of each domain last changed, for Last-Modified / If-Modified-Since on the full downloads
+CREATE TABLE IF NOT EXISTS blacklist_modifications (
+    domain_id INTEGER PRIMARY KEY,
+    modified_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO blacklist_modifications (domain_id, modified_at)
+SELECT domain_id, MAX(GREATEST(created_at, COALESCE(status_changed_at, created_at))) FROM blacklist GROUP BY domain_id
+ON CONFLICT (domain_id) DO NOTHING;
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (25, 19) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,14 @@
+-- suppression events written with their entry, put on the event bus by the outbox publisher
+CREATE TABLE IF NOT EXISTS outbox (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id INTEGER NOT NULL,
+    event_type VARCHAR(32) NOT NULL,
+    payload TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    published_at TIMESTAMP NULL
+);
+
+CREATE INDEX IF NOT EXISTS outbox_published_at ON outbox (published_at, id);
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (26, 19) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,14 @@
+-- the SES messages kept as reason of old entries, gzipped here or in S3 (s3_key), see archive-reasons
+CREATE TABLE IF NOT EXISTS reason_archive (
+    blacklist_id BIGINT PRIMARY KEY,
+    domain_id INTEGER NOT NULL,
+    reason_gz BYTEA NULL,
+    s3_key VARCHAR(512) NULL,
+    archived_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- set when the reason moved to the archive, the column is then emptied
+ALTER TABLE blacklist ADD COLUMN IF NOT EXISTS reason_archived_at TIMESTAMP NULL;
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (27, 19) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,5 @@
+-- IANA timezone whose midnight starts the domain's daily stats days, e.g. Europe/Berlin; UTC when NULL
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS reporting_timezone VARCHAR(64) NULL;
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (29, 19) ON CONFLICT (version) DO NOTHING;
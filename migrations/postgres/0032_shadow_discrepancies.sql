@@ -0,0 +1,17 @@
+-- notifications the candidate pipeline of SHADOW_MODE read differently from the live one, see src/shadow.rs
+CREATE TABLE IF NOT EXISTS shadow_discrepancies (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id INTEGER NOT NULL,
+    notification_type VARCHAR(64) NOT NULL,
+    message_id VARCHAR(255) NULL,
+    payload TEXT NOT NULL,
+    -- JSON, the entries or the error of each pipeline
+    current_result TEXT NOT NULL,
+    candidate_result TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS shadow_discrepancies_domain_id ON shadow_discrepancies (domain_id, id);
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (32, 19) ON CONFLICT (version) DO NOTHING;
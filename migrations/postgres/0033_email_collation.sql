@@ -0,0 +1,14 @@
+-- addresses are stored trimmed and lowercased (privacy::stored_email), the rows written before are lowered here.
+-- Of the rows that lower to the same address in a domain, one already lowercase is kept as is, otherwise the
+-- oldest is lowered and the others keep their case.
+UPDATE blacklist b SET email = LOWER(TRIM(b.email))
+FROM (
+    SELECT id, ROW_NUMBER() OVER (
+        PARTITION BY domain_id, LOWER(TRIM(email)) ORDER BY (email = LOWER(TRIM(email))) DESC, id
+    ) AS rank
+    FROM blacklist
+) ranked
+WHERE ranked.id = b.id AND ranked.rank = 1 AND b.email <> LOWER(TRIM(b.email));
+
+-- builds of version 19 keep working against it, addresses they write in another case are no longer matched
+INSERT INTO schema_version (version, min_compatible) VALUES (33, 19) ON CONFLICT (version) DO NOTHING;
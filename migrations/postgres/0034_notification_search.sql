@@ -0,0 +1,15 @@
+-- the filters of GET /api/{domain_id}/events, filled for the notifications logged from this version on
+ALTER TABLE notification_log ADD COLUMN IF NOT EXISTS bounce_sub_type VARCHAR(64) NULL;
+
+-- the addresses a logged notification names, as stored in the blacklist (lowercased, or hashed with EMAIL_HASH_KEY)
+CREATE TABLE IF NOT EXISTS notification_recipients (
+    notification_id BIGINT NOT NULL,
+    domain_id INTEGER NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    PRIMARY KEY (notification_id, email)
+);
+
+CREATE INDEX IF NOT EXISTS notification_recipients_domain_email ON notification_recipients (domain_id, email, notification_id);
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (34, 19) ON CONFLICT (version) DO NOTHING;
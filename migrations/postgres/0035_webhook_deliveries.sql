@@ -0,0 +1,17 @@
+-- webhook deliveries not yet accepted by the receiver, attempted by the delivery poller of any instance once
+-- next_attempt_at is due, deleted on success or when moved to webhook_dead_letters
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts BIGINT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL,
+    last_error TEXT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_next_attempt ON webhook_deliveries (next_attempt_at);
+
+-- additive, builds of version 19 keep working against it
+INSERT INTO schema_version (version, min_compatible) VALUES (35, 19) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,45 @@
+// A small single-page admin UI at /admin, compiled in only with `cargo build --features admin-ui`: browsing the
+// blacklist of a domain, looking an address up across domains, the daily stats and replaying dead letters. The
+// page and its script are static and public, they call the admin API with the ADMIN_TOKEN typed in the page.
+
+#[cfg(feature = "admin-ui")]
+pub use ui::*;
+
+#[cfg(not(feature = "admin-ui"))]
+pub fn configure(_cfg: &mut actix_web::web::ServiceConfig) {}
+
+#[cfg(feature = "admin-ui")]
+mod ui {
+    use actix_web::http::header;
+    use actix_web::{web, HttpResponse, Responder};
+
+    const INDEX_HTML: &str = include_str!("../assets/admin/index.html");
+    const APP_JS: &str = include_str!("../assets/admin/app.js");
+
+    // everything comes from this origin, the page runs no inline script
+    const CONTENT_SECURITY_POLICY: &str =
+        "default-src 'self'; style-src 'self' 'unsafe-inline'; frame-ancestors 'none'; base-uri 'none'; form-action 'none'";
+
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        cfg.service(web::resource("/admin").route(web::get().to(index_handler)))
+            .service(web::resource("/admin/app.js").route(web::get().to(script_handler)));
+    }
+
+    fn asset(content_type: &str, body: &'static str) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header((header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY))
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+            // the assets change with the binary, a deploy must not leave a stale script behind
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .body(body)
+    }
+
+    pub async fn index_handler() -> impl Responder {
+        asset("text/html; charset=utf-8", INDEX_HTML)
+    }
+
+    pub async fn script_handler() -> impl Responder {
+        asset("text/javascript; charset=utf-8", APP_JS)
+    }
+}
@@ -0,0 +1,21 @@
+use tokio::sync::broadcast;
+
+use crate::events::{self, Event};
+
+// logs the events an operator has to act on, for the whole life of the process
+pub fn spawn(events: &broadcast::Sender<Event>) {
+    events::subscribe(events, "alerts", |event| match event {
+        Event::DomainPaused(event) => {
+            println!("🚨 Paused sending for domain {}: {}", event.domain_id, event.category);
+        }
+        Event::ComplaintReceived { domain_id, feedback_type, recipients } => {
+            println!(
+                "🚨 Complaint ({}) received for domain {} from {} recipient(s)",
+                feedback_type.as_deref().unwrap_or("unknown type"),
+                domain_id,
+                recipients
+            );
+        }
+        Event::EmailSuppressed(_) | Event::LookupPerformed { .. } => {}
+    });
+}
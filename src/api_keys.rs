@@ -0,0 +1,501 @@
+use std::env;
+use std::fmt;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::auth_failures;
+use crate::responses::{ErrorResponse, ListResponse};
+use crate::handlers::{has_admin_token, is_admin};
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::AppState;
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub daily_quota: Option<i64>,
+    // the only domain the key can access, NULL for internal keys spanning all domains
+    pub domain_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow)]
+pub struct ApiKeyUsage {
+    pub api_key_id: i64,
+    pub name: String,
+    pub day: NaiveDate,
+    pub requests: i64,
+    pub daily_quota: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    pub day: Option<NaiveDate>,
+}
+
+// keys are only stored hashed, e.g. INSERT INTO api_keys (name, key_hash) VALUES ('team', SHA2('<key>', 256)),
+// customer keys also set domain_id
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn pg_table(var: &str, default: &str) -> String {
+    env::var(var).unwrap_or_else(|_| default.into())
+}
+
+// resolves key hashes instead of the api_keys table when registered as app data (web::Data<dyn KeyLookup>), e.g. a
+// fixed set of keys for the route tests
+pub trait KeyLookup: Send + Sync {
+    fn find(&self, key_hash: &str) -> Option<ApiKey>;
+}
+
+async fn find_key(key_hash: &str, data: &web::Data<AppState>) -> Result<Option<ApiKey>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, ApiKey>(r#"SELECT id, name, daily_quota, domain_id FROM api_keys WHERE key_hash = ?"#)
+                .bind(key_hash)
+                .fetch_optional(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(
+                    &format!(
+                        r#"SELECT id, name, daily_quota, domain_id FROM {table} WHERE key_hash = $1"#,
+                        table = pg_table("PG_API_KEYS_TABLE", "api_keys")
+                    ),
+                    &[&key_hash],
+                )
+                .await
+                .map(|row| {
+                    row.map(|row| ApiKey {
+                        id: row.get(0),
+                        name: row.get(1),
+                        daily_quota: row.get(2),
+                        domain_id: row.get(3),
+                    })
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// counts the request and returns the number of requests of the key today
+async fn record_usage(api_key_id: i64, data: &web::Data<AppState>) -> Result<i64, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"INSERT INTO api_usage (api_key_id, day, requests) VALUES (?, UTC_DATE(), 1)
+                   ON DUPLICATE KEY UPDATE requests = requests + 1"#,
+            )
+                .bind(api_key_id)
+                .execute(pool)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            sqlx::query_scalar::<_, i64>(r#"SELECT requests FROM api_usage WHERE api_key_id = ? AND day = UTC_DATE()"#)
+                .bind(api_key_id)
+                .fetch_one(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let table = pg_table("PG_API_USAGE_TABLE", "api_usage");
+
+            client
+                .query_one(
+                    &format!(
+                        r#"INSERT INTO {table} (api_key_id, day, requests) VALUES ($1, (NOW() AT TIME ZONE 'UTC')::date, 1)
+                           ON CONFLICT (api_key_id, day) DO UPDATE SET requests = {table}.requests + 1
+                           RETURNING requests"#,
+                        table = table
+                    ),
+                    &[&api_key_id],
+                )
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// whether the key may access the domain, keys scoped to a domain never reach the entries of another
+pub fn can_access(api_key: &ApiKey, domain_id: i32) -> bool {
+    api_key.domain_id.is_none_or(|scope| scope == domain_id)
+}
+
+// why a request was refused its key, rendered by the v1 and v2 APIs in their own error shapes
+#[derive(Debug)]
+pub enum MeterError {
+    Missing,
+    Invalid,
+    Forbidden(i32),
+    QuotaExceeded(i64),
+    // seconds left of the lockout of the source IP after repeated failures
+    LockedOut(u64),
+    Database(String),
+}
+
+impl MeterError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            MeterError::Missing | MeterError::Invalid => StatusCode::UNAUTHORIZED,
+            MeterError::Forbidden(_) => StatusCode::FORBIDDEN,
+            MeterError::QuotaExceeded(_) | MeterError::LockedOut(_) => StatusCode::TOO_MANY_REQUESTS,
+            MeterError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            MeterError::Missing => "missing_api_key",
+            MeterError::Invalid => "invalid_api_key",
+            MeterError::Forbidden(_) => "forbidden",
+            MeterError::QuotaExceeded(_) => "quota_exceeded",
+            MeterError::LockedOut(_) => "locked_out",
+            MeterError::Database(_) => "database_error",
+        }
+    }
+
+    // seconds until the quota resets or the lockout ends
+    pub fn retry_after(&self) -> Option<i64> {
+        match self {
+            MeterError::QuotaExceeded(_) => Some(seconds_until_midnight()),
+            MeterError::LockedOut(seconds) => Some(*seconds as i64),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MeterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeterError::Missing => write!(f, "Missing API key"),
+            MeterError::Invalid => write!(f, "Invalid API key"),
+            MeterError::Forbidden(domain_id) => write!(f, "API key has no access to domain {}", domain_id),
+            MeterError::QuotaExceeded(quota) => write!(f, "Daily quota of {} requests exceeded", quota),
+            MeterError::LockedOut(seconds) => {
+                write!(f, "Too many failed authentication attempts, retry in {} seconds", seconds)
+            }
+            MeterError::Database(err) => write!(f, "🔥 Failed to query the database: {:?}", err),
+        }
+    }
+}
+
+// resolves the X-API-Key of the request, checks it is allowed on `domain_id`, counts its usage and enforces the
+// daily quota. Requests without a key are anonymous unless REQUIRE_API_KEY=true.
+pub async fn authorize(req: &HttpRequest, data: &web::Data<AppState>, domain_id: i32) -> Result<Option<ApiKey>, MeterError> {
+    // checked before the key or token is, a locked out source learns nothing from the answer
+    if req.headers().contains_key(API_KEY_HEADER) || req.headers().contains_key(header::AUTHORIZATION) {
+        if let Some(seconds) = auth_failures::locked_out(req) {
+            return Err(MeterError::LockedOut(seconds));
+        }
+    }
+
+    // the admin token reaches every domain unmetered, e.g. from the admin UI
+    if !req.headers().contains_key(API_KEY_HEADER) && has_admin_token(req) && is_admin(req) {
+        return Ok(None);
+    }
+
+    let key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty());
+
+    let Some(key) = key else {
+        if env::var("REQUIRE_API_KEY").as_deref() == Ok("true") {
+            return Err(MeterError::Missing);
+        }
+        return Ok(None);
+    };
+
+    let prefix = auth_failures::key_prefix(key);
+    let key_hash = hash_key(key);
+    let found = match req.app_data::<web::Data<dyn KeyLookup>>() {
+        Some(lookup) => Ok(lookup.find(&key_hash)),
+        None => find_key(&key_hash, data).await,
+    };
+
+    let Some(api_key) = found.map_err(MeterError::Database)? else {
+        return Err(match auth_failures::record(req, Some(&prefix), "invalid_api_key") {
+            Some(seconds) => MeterError::LockedOut(seconds),
+            None => MeterError::Invalid,
+        });
+    };
+
+    // rejected before metering, probing other tenants does not use up the quota
+    if !can_access(&api_key, domain_id) {
+        println!("🚨 API key {} scoped to domain {:?} denied access to domain {}", api_key.name, api_key.domain_id, domain_id);
+        return Err(match auth_failures::record(req, Some(&prefix), "forbidden") {
+            Some(seconds) => MeterError::LockedOut(seconds),
+            None => MeterError::Forbidden(domain_id),
+        });
+    }
+
+    // metering must not take the API down, a failure only skips the quota check
+    let requests = match record_usage(api_key.id, data).await {
+        Ok(requests) => requests,
+        Err(err) => {
+            println!("🔥 Failed to record usage for API key {}: {:?}", api_key.name, err);
+            return Ok(Some(api_key));
+        }
+    };
+
+    if let Some(quota) = api_key.daily_quota {
+        if requests > quota {
+            return Err(MeterError::QuotaExceeded(quota));
+        }
+    }
+
+    Ok(Some(api_key))
+}
+
+// authorize() for the v1 handlers, the Err is the response to return to the caller
+pub async fn meter(req: &HttpRequest, data: &web::Data<AppState>, domain_id: i32) -> Result<Option<ApiKey>, HttpResponse> {
+    authorize(req, data, domain_id).await.map_err(|err| {
+        let mut response = HttpResponse::build(err.status());
+
+        if let Some(retry_after) = err.retry_after() {
+            response.insert_header(("Retry-After", retry_after.to_string()));
+        }
+
+        response.json(ErrorResponse::new(err.to_string()))
+    })
+}
+
+fn seconds_until_midnight() -> i64 {
+    let now = Utc::now().naive_utc();
+    let midnight = (now.date() + chrono::Duration::days(1)).and_hms_opt(0, 0, 0).unwrap();
+    (midnight - now).num_seconds()
+}
+
+pub async fn usage_handler(
+    req: HttpRequest,
+    query: web::Query<UsageQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let day = query.day.unwrap_or_else(|| Utc::now().date_naive());
+
+    let usage: Result<Vec<ApiKeyUsage>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, ApiKeyUsage>(
+                r#"SELECT k.id AS api_key_id, k.name, u.day, u.requests, k.daily_quota
+                   FROM api_usage u JOIN api_keys k ON k.id = u.api_key_id
+                   WHERE u.day = ? ORDER BY u.requests DESC"#,
+            )
+                .bind(day)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => client
+                .query(
+                    &format!(
+                        r#"SELECT k.id, k.name, u.day, u.requests, k.daily_quota
+                           FROM {usage} u JOIN {keys} k ON k.id = u.api_key_id
+                           WHERE u.day = $1 ORDER BY u.requests DESC"#,
+                        usage = pg_table("PG_API_USAGE_TABLE", "api_usage"),
+                        keys = pg_table("PG_API_KEYS_TABLE", "api_keys")
+                    ),
+                    &[&day],
+                )
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| ApiKeyUsage {
+                            api_key_id: row.get(0),
+                            name: row.get(1),
+                            day: row.get(2),
+                            requests: row.get(3),
+                            daily_quota: row.get(4),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    match usage {
+        Ok(usage) => HttpResponse::Ok().json(ListResponse::new(usage)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use actix_web::http::Method;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    use super::*;
+    use crate::cache::LookupCache;
+    use crate::shards::RecipientShards;
+    use crate::{bloom, daily_stats, events, handlers, reputation, stats};
+
+    const TENANT_KEY: &str = "tenant-key";
+    const TENANT_DOMAIN: i32 = 1;
+    const OTHER_DOMAIN: i32 = 2;
+
+    impl KeyLookup for HashMap<String, ApiKey> {
+        fn find(&self, key_hash: &str) -> Option<ApiKey> {
+            self.get(key_hash).cloned()
+        }
+    }
+
+    // the keys authorize resolves instead of querying the api_keys table
+    fn lookup(keys: &[(&str, ApiKey)]) -> web::Data<dyn KeyLookup> {
+        let keys: HashMap<String, ApiKey> = keys.iter().map(|(key, api_key)| (hash_key(key), api_key.clone())).collect();
+
+        web::Data::from(Arc::new(keys) as Arc<dyn KeyLookup>)
+    }
+
+    fn api_key(domain_id: Option<i32>) -> ApiKey {
+        ApiKey { id: 7, name: "tenant".into(), daily_quota: None, domain_id }
+    }
+
+    // nothing reaches the database before the key is checked, the url is never connected to
+    fn state() -> web::Data<AppState> {
+        web::Data::new(AppState {
+            db_type: DBType::Postgres,
+            db_url: "postgres://127.0.0.1:1/unused".into(),
+            read_pool: None,
+            read_db_url: None,
+            limiter: Arc::default(),
+            shards: Arc::new(RecipientShards::from_env()),
+            cache: Arc::new(LookupCache::from_env()),
+            events: events::channel(),
+            secondary: None,
+        })
+    }
+
+    #[test]
+    fn scoped_key_accesses_its_domain_only() {
+        let key = api_key(Some(TENANT_DOMAIN));
+
+        assert!(can_access(&key, TENANT_DOMAIN));
+        assert!(!can_access(&key, OTHER_DOMAIN));
+    }
+
+    #[test]
+    fn internal_key_accesses_every_domain() {
+        let key = api_key(None);
+
+        assert!(can_access(&key, TENANT_DOMAIN));
+        assert!(can_access(&key, OTHER_DOMAIN));
+    }
+
+    #[actix_web::test]
+    async fn foreign_domain_is_forbidden_on_every_metered_route() {
+        let app = init_service(
+            App::new()
+                .app_data(state())
+                .app_data(lookup(&[(TENANT_KEY, api_key(Some(TENANT_DOMAIN)))]))
+                .route("/api/{domain_id}/is-blacklisted/{email}", web::get().to(handlers::blacklist::is_email_blacklisted))
+                .route("/api/{domain_id}/is-blacklisted/{email}", web::head().to(handlers::blacklist::head_email_blacklisted))
+                .route("/api/{domain_id}/is-blacklisted", web::post().to(handlers::blacklist::batch_lookup))
+                .route("/api/{domain_id}/blacklist/bloom", web::get().to(bloom::bloom_handler))
+                .route("/api/{domain_id}/stats/recipient-domains", web::get().to(stats::recipient_domain_stats))
+                .route("/api/{domain_id}/stats/daily", web::get().to(daily_stats::daily_stats_handler))
+                .route("/api/{domain_id}/stats/mta", web::get().to(stats::mta_stats))
+                .route("/api/{domain_id}/stats/diagnostic-classes", web::get().to(stats::diagnostic_class_stats))
+                .route("/api/{domain_id}/reputation", web::get().to(reputation::reputation_handler))
+                .configure(handlers::v2::configure),
+        )
+            .await;
+
+        let requests = [
+            (Method::GET, "is-blacklisted/someone@example.com"),
+            (Method::HEAD, "is-blacklisted/someone@example.com"),
+            (Method::POST, "is-blacklisted"),
+            (Method::GET, "blacklist/bloom"),
+            (Method::GET, "stats/recipient-domains"),
+            (Method::GET, "stats/daily"),
+            (Method::GET, "stats/mta"),
+            (Method::GET, "stats/diagnostic-classes"),
+            (Method::GET, "reputation"),
+        ];
+        let v2_requests = [
+            (Method::GET, "suppressions/someone@example.com"),
+            (Method::GET, "reputation"),
+        ];
+
+        let uris = requests
+            .iter()
+            .map(|(method, path)| (method.clone(), format!("/api/{}/{}", OTHER_DOMAIN, path)))
+            .chain(
+                v2_requests
+                    .iter()
+                    .map(|(method, path)| (method.clone(), format!("/api/v2/domains/{}/{}", OTHER_DOMAIN, path))),
+            );
+
+        for (index, (method, uri)) in uris.enumerate() {
+            // one source address per request, the failures must not add up to a lockout
+            let request = TestRequest::default()
+                .method(method.clone())
+                .uri(&uri)
+                .peer_addr(format!("10.0.0.{}:4000", index + 1).parse().unwrap())
+                .insert_header((API_KEY_HEADER, TENANT_KEY))
+                .set_json(serde_json::json!({ "emails": ["someone@example.com"] }))
+                .to_request();
+
+            let response = call_service(&app, request).await;
+
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn locked_out_source_is_refused_a_valid_key() {
+        let data = state();
+        let request = |key: &str, peer: &str| {
+            TestRequest::default()
+                .app_data(lookup(&[(TENANT_KEY, api_key(Some(TENANT_DOMAIN)))]))
+                .peer_addr(peer.parse().unwrap())
+                .insert_header((API_KEY_HEADER, key))
+                .to_http_request()
+        };
+
+        let mut locked = false;
+        for _ in 0..100 {
+            if let Err(MeterError::LockedOut(_)) = authorize(&request("guessed-key", "10.0.1.1:4000"), &data, TENANT_DOMAIN).await {
+                locked = true;
+                break;
+            }
+        }
+        assert!(locked, "the guesses never locked the source out");
+
+        let refused = authorize(&request(TENANT_KEY, "10.0.1.1:4000"), &data, TENANT_DOMAIN).await;
+        assert!(matches!(refused, Err(MeterError::LockedOut(_))), "{:?}", refused);
+
+        // the lockout is the source's, the key still works from elsewhere
+        let accepted = authorize(&request(TENANT_KEY, "10.0.1.2:4000"), &data, TENANT_DOMAIN).await;
+        assert!(matches!(accepted, Ok(Some(_))), "{:?}", accepted);
+    }
+}
@@ -0,0 +1,352 @@
+use std::env;
+use std::io::{Read, Write};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::config::arg_value;
+use crate::handlers::is_admin;
+use crate::outbound;
+use crate::repo::{build_pg_pool, build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::responses::ErrorResponse;
+use crate::sigv4::{self, Target};
+use crate::AppState;
+
+const DEFAULT_BATCH_SIZE: i64 = 500;
+const DEFAULT_AFTER_DAYS: i64 = 90;
+
+// (id, domain_id, reason) of a row whose reason can move to the archive
+type ReasonRow = (i64, i64, String);
+// (domain_id, reason, reason_archived_at) of an entry
+type EntryRow = (i64, String, Option<NaiveDateTime>);
+// (reason_gz, s3_key, archived_at) of an archived reason
+type ArchiveRow = (Option<Vec<u8>>, Option<String>, NaiveDateTime);
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EntryReason {
+    pub id: i64,
+    pub domain_id: i64,
+    // the SES message or the manual reason
+    pub reason: String,
+    // hot, table or s3
+    pub location: String,
+    pub archived_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EntryReasonResponse {
+    pub success: bool,
+    pub data: EntryReason,
+}
+
+fn table() -> String {
+    env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into())
+}
+
+fn archive_table() -> String {
+    env::var("PG_REASON_ARCHIVE_TABLE").unwrap_or_else(|_| "reason_archive".into())
+}
+
+// ARCHIVE_S3_BUCKET keeps the compressed reasons in S3 (ARCHIVE_S3_REGION, under ARCHIVE_S3_PREFIX) and only their
+// key in reason_archive, without it the compressed reason is stored in reason_archive itself
+fn s3_target() -> Option<Target> {
+    let bucket = env::var("ARCHIVE_S3_BUCKET").ok().filter(|bucket| !bucket.is_empty())?;
+    let region = env::var("ARCHIVE_S3_REGION")
+        .or_else(|_| env::var("AWS_REGION"))
+        .unwrap_or_else(|_| "us-east-1".into());
+
+    Some(Target {
+        url_prefix: format!("https://{}.s3.{}.amazonaws.com/", bucket, region),
+        region,
+        service: "s3".into(),
+    })
+}
+
+fn s3_key(domain_id: i64, id: i64) -> String {
+    let prefix = env::var("ARCHIVE_S3_PREFIX").unwrap_or_else(|_| "reasons/".into());
+    format!("{}{}/{}.json.gz", prefix, domain_id, id)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn compress(reason: &str) -> Result<Vec<u8>, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(reason.as_bytes()).map_err(|err| err.to_string())?;
+    encoder.finish().map_err(|err| err.to_string())
+}
+
+fn decompress(bytes: &[u8]) -> Result<String, String> {
+    let mut reason = String::new();
+    GzDecoder::new(bytes).read_to_string(&mut reason).map_err(|err| err.to_string())?;
+    Ok(reason)
+}
+
+// S3 wants the payload hash as a header, it is signed with the others
+async fn s3_request(target: &Target, method: reqwest::Method, key: &str, body: Vec<u8>) -> Result<reqwest::Response, String> {
+    let mut request = outbound::client()
+        .request(method, format!("{}{}", target.url_prefix, key))
+        .header("x-amz-content-sha256", sha256_hex(&body))
+        .body(body)
+        .build()
+        .map_err(|err| err.to_string())?;
+
+    sigv4::sign_request(&mut request, target).await?;
+
+    let response = outbound::client().execute(request).await.map_err(|err| err.to_string())?;
+
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(format!("S3 answered {} for {}", response.status(), key))
+    }
+}
+
+// rows with an SES message as reason (manual reasons are short free text) and normalized columns to keep hot
+async fn fetch_batch(db_type: &DBType, db_url: &str, after_id: i64, after_days: i64, batch_size: i64) -> Result<Vec<ReasonRow>, String> {
+    let condition = "reason_archived_at IS NULL AND reason LIKE '{%' AND reason_summary IS NOT NULL";
+
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, ReasonRow>(&format!(
+                r#"SELECT id, domain_id, reason FROM blacklist WHERE id > ? AND {} AND created_at < NOW() - INTERVAL ? DAY ORDER BY id LIMIT ?"#,
+                condition
+            ))
+                .bind(after_id)
+                .bind(after_days)
+                .bind(batch_size)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            let after_days = after_days as i32;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, domain_id, reason FROM {table} WHERE id > $1 AND {condition} AND created_at < NOW() - make_interval(days => $2) ORDER BY id LIMIT $3"#,
+                        table = table(),
+                        condition = condition
+                    ),
+                    &[&after_id, &after_days, &batch_size],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get::<_, i32>(1) as i64, row.get(2))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// the archive row first, then the hot reason is emptied: an interrupted run leaves both and the next one redoes it
+async fn archive_row(db_type: &DBType, db_url: &str, id: i64, domain_id: i64, reason_gz: Option<Vec<u8>>, s3_key: Option<String>) -> Result<(), String> {
+    match db_type {
+        DBType::MySQL(pool) => {
+            let mut tx = pool.begin().await.map_err(|err| err.to_string())?;
+
+            sqlx::query(r#"INSERT IGNORE INTO reason_archive (blacklist_id, domain_id, reason_gz, s3_key) VALUES (?, ?, ?, ?)"#)
+                .bind(id)
+                .bind(domain_id)
+                .bind(reason_gz)
+                .bind(s3_key)
+                .execute(&mut tx)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            sqlx::query(r#"UPDATE blacklist SET reason = '', reason_archived_at = NOW() WHERE id = ?"#)
+                .bind(id)
+                .execute(&mut tx)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            tx.commit().await.map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let mut client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            let tx = client.transaction().await.map_err(|err| err.to_string())?;
+            let domain_id = domain_id as i32;
+
+            tx.execute(
+                &format!(
+                    r#"INSERT INTO {} (blacklist_id, domain_id, reason_gz, s3_key) VALUES ($1, $2, $3, $4) ON CONFLICT (blacklist_id) DO NOTHING"#,
+                    archive_table()
+                ),
+                &[&id, &domain_id, &reason_gz, &s3_key],
+            )
+                .await
+                .map_err(|err| err.to_string())?;
+
+            tx.execute(
+                &format!(r#"UPDATE {} SET reason = '', reason_archived_at = NOW() WHERE id = $1"#, table()),
+                &[&id],
+            )
+                .await
+                .map_err(|err| err.to_string())?;
+
+            tx.commit().await.map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// `archive-reasons [--older-than-days N] [--batch-size N] [--after-id ID]` moves the SES messages kept as reason of
+// entries older than N days (ARCHIVE_REASONS_AFTER_DAYS, 90 by default) to reason_archive or S3, gzipped. The
+// normalized columns stay in blacklist. Run backfill-reasons first. The space is given back to the filesystem by
+// OPTIMIZE TABLE blacklist (MySQL) or VACUUM FULL (Postgres).
+pub async fn run(db_type: &DBType, db_url: &str, args: &[String]) -> Result<(), String> {
+    let batch_size = arg_value(args, "--batch-size").and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+    let after_days = arg_value(args, "--older-than-days")
+        .or_else(|| env::var("ARCHIVE_REASONS_AFTER_DAYS").ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_AFTER_DAYS)
+        .max(0);
+    let mut after_id = arg_value(args, "--after-id").and_then(|value| value.parse().ok()).unwrap_or(0);
+    let target = s3_target();
+    let (mut archived, mut bytes_before, mut bytes_after) = (0, 0, 0);
+
+    println!(
+        "🚀 Archiving the reasons older than {} days after id {} to {} in batches of {}",
+        after_days,
+        after_id,
+        target.as_ref().map(|target| target.url_prefix.as_str()).unwrap_or("reason_archive"),
+        batch_size
+    );
+
+    loop {
+        let rows = fetch_batch(db_type, db_url, after_id, after_days, batch_size).await?;
+
+        let Some((last_id, _, _)) = rows.last() else {
+            break;
+        };
+        let last_id = *last_id;
+
+        for (id, domain_id, reason) in rows {
+            let compressed = compress(&reason)?;
+            bytes_before += reason.len();
+            bytes_after += compressed.len();
+
+            match &target {
+                Some(target) => {
+                    let key = s3_key(domain_id, id);
+                    s3_request(target, reqwest::Method::PUT, &key, compressed).await?;
+                    archive_row(db_type, db_url, id, domain_id, None, Some(key)).await?;
+                }
+                None => archive_row(db_type, db_url, id, domain_id, Some(compressed), None).await?,
+            }
+
+            archived += 1;
+        }
+
+        after_id = last_id;
+        println!("Processed up to id {}: {} archived", after_id, archived);
+    }
+
+    println!("✅ Archive done: {} reasons, {} bytes compressed to {}", archived, bytes_before, bytes_after);
+
+    Ok(())
+}
+
+async fn load_entry(id: i64, data: &AppState) -> Result<Option<EntryRow>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, EntryRow>(r#"SELECT domain_id, reason, reason_archived_at FROM blacklist WHERE id = ?"#)
+                    .bind(id)
+                    .fetch_optional(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(&format!(r#"SELECT domain_id, reason, reason_archived_at FROM {} WHERE id = $1"#, table()), &[&id])
+                .await
+                .map(|row| row.map(|row| (row.get::<_, i32>(0) as i64, row.get(1), row.get(2))))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn load_archive(id: i64, data: &AppState) -> Result<Option<ArchiveRow>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, ArchiveRow>(r#"SELECT reason_gz, s3_key, archived_at FROM reason_archive WHERE blacklist_id = ?"#)
+                    .bind(id)
+                    .fetch_optional(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(
+                    &format!(r#"SELECT reason_gz, s3_key, archived_at FROM {} WHERE blacklist_id = $1"#, archive_table()),
+                    &[&id],
+                )
+                .await
+                .map(|row| row.map(|row| (row.get(0), row.get(1), row.get(2))))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn reason(id: i64, data: &AppState) -> Result<Option<EntryReason>, String> {
+    let Some((domain_id, reason, archived_at)) = load_entry(id, data).await? else {
+        return Ok(None);
+    };
+
+    if archived_at.is_none() {
+        return Ok(Some(EntryReason { id, domain_id, reason, location: "hot".into(), archived_at }));
+    }
+
+    let (reason_gz, s3_key, archived_at) = load_archive(id, data).await?.ok_or("archived reason missing from reason_archive")?;
+
+    let (compressed, location) = match (reason_gz, s3_key) {
+        (Some(reason_gz), _) => (reason_gz, "table"),
+        (None, Some(key)) => {
+            let target = s3_target().ok_or("the reason is in S3 but ARCHIVE_S3_BUCKET is not set")?;
+            let response = s3_request(&target, reqwest::Method::GET, &key, vec![]).await?;
+            (response.bytes().await.map_err(|err| err.to_string())?.to_vec(), "s3")
+        }
+        (None, None) => return Err("archived reason without content nor S3 key".into()),
+    };
+
+    Ok(Some(EntryReason {
+        id,
+        domain_id,
+        reason: decompress(&compressed)?,
+        location: location.into(),
+        archived_at: Some(archived_at),
+    }))
+}
+
+// the full reason of an entry, from the hot table or the archive
+pub async fn reason_handler(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    match reason(path.into_inner(), &data).await {
+        Ok(Some(reason)) => HttpResponse::Ok().json(EntryReasonResponse { success: true, data: reason }),
+        Ok(None) => HttpResponse::NotFound().json(ErrorResponse::new("Entry not found")),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResponse::new(format!("🔥 Failed to load the reason: {}", err))),
+    }
+}
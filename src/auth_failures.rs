@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::api_keys::MeterError;
+use crate::handlers::{client_ip, is_admin};
+use crate::metrics;
+use crate::responses::{ErrorEnvelope, ErrorResponse};
+
+// failures kept for the admin endpoint
+const RECENT_LIMIT: usize = 200;
+// characters of a presented key kept to tell attempts apart, never enough to use it
+const KEY_PREFIX_LEN: usize = 6;
+
+// AUTH_LOCKOUT_THRESHOLD failed attempts (default 10, 0 disables the lockout) from one source IP within
+// AUTH_LOCKOUT_WINDOW_SECS (300) lock it out for AUTH_LOCKOUT_SECS (900). A locked out source is refused every
+// attempt, a valid key or token included, so the answers tell a guess nothing. The source is the client_ip: behind a
+// load balancer set TRUST_PROXY_HEADERS=true, otherwise all clients share its address. The state is per instance.
+struct Config {
+    threshold: usize,
+    window: Duration,
+    lockout: Duration,
+}
+
+fn config() -> Config {
+    let number = |var: &str, default: u64| env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+
+    Config {
+        threshold: number("AUTH_LOCKOUT_THRESHOLD", 10) as usize,
+        window: Duration::from_secs(number("AUTH_LOCKOUT_WINDOW_SECS", 300)),
+        lockout: Duration::from_secs(number("AUTH_LOCKOUT_SECS", 900)),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuthFailure {
+    pub at: NaiveDateTime,
+    pub source_ip: String,
+    pub key_prefix: Option<String>,
+    // invalid_api_key, forbidden or invalid_admin_token
+    pub reason: String,
+    pub path: String,
+}
+
+#[derive(Default)]
+struct Attempts {
+    failures: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Tracker {
+    // keyed by "ip:<addr>"
+    attempts: HashMap<String, Attempts>,
+    recent: VecDeque<AuthFailure>,
+}
+
+fn tracker() -> &'static Mutex<Tracker> {
+    static TRACKER: OnceLock<Mutex<Tracker>> = OnceLock::new();
+    TRACKER.get_or_init(Default::default)
+}
+
+pub fn source_ip(req: &HttpRequest) -> String {
+    client_ip(req).map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".into())
+}
+
+pub fn key_prefix(key: &str) -> String {
+    key.chars().take(KEY_PREFIX_LEN).collect()
+}
+
+// a key prefix is only reported, never locked out: whoever knows it could otherwise lock out the legitimate key
+fn subject(source_ip: &str) -> String {
+    format!("ip:{}", source_ip)
+}
+
+// seconds left of the lockout of the request's source, checked before its key or token
+pub fn locked_out(req: &HttpRequest) -> Option<u64> {
+    if config().threshold == 0 {
+        return None;
+    }
+
+    let now = Instant::now();
+    let tracker = tracker().lock().unwrap();
+
+    tracker
+        .attempts
+        .get(&subject(&source_ip(req)))?
+        .locked_until
+        .filter(|until| *until > now)
+        .map(|until| (until - now).as_secs().max(1))
+}
+
+// refuses the admin token of a locked out source ahead of the admin handlers, which answer a refused token with
+// 401; API keys are refused by api_keys::authorize in the error shape of their API
+pub async fn guard(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let locked = match req.headers().contains_key(header::AUTHORIZATION) {
+        true => locked_out(req.request()),
+        false => None,
+    };
+
+    let Some(seconds) = locked else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+
+    let err = MeterError::LockedOut(seconds);
+    let mut response = HttpResponse::build(err.status());
+    response.insert_header((header::RETRY_AFTER, seconds.to_string()));
+
+    let response = if req.path().starts_with("/api/v2/") {
+        response.json(ErrorEnvelope::new(err.code(), err.to_string()))
+    } else {
+        response.json(ErrorResponse::new(err.to_string()))
+    };
+
+    Ok(req.into_response(response))
+}
+
+// records a failed attempt, starts a lockout once the source reaches the threshold within the window. Seconds left
+// when the source is locked out, the attempt is then answered as such instead of as invalid.
+pub fn record(req: &HttpRequest, key_prefix: Option<&str>, reason: &str) -> Option<u64> {
+    let config = config();
+    let source_ip = source_ip(req);
+    let now = Instant::now();
+
+    metrics::AUTH_FAILURES.inc();
+
+    let mut tracker = tracker().lock().unwrap();
+
+    if tracker.recent.len() >= RECENT_LIMIT {
+        tracker.recent.pop_front();
+    }
+    tracker.recent.push_back(AuthFailure {
+        at: Utc::now().naive_utc(),
+        source_ip: source_ip.clone(),
+        key_prefix: key_prefix.map(str::to_string),
+        reason: reason.into(),
+        path: req.path().into(),
+    });
+
+    if config.threshold == 0 {
+        return None;
+    }
+
+    let subject = subject(&source_ip);
+    let attempts = tracker.attempts.entry(subject.clone()).or_default();
+
+    while attempts.failures.front().is_some_and(|at| now.duration_since(*at) > config.window) {
+        attempts.failures.pop_front();
+    }
+    attempts.failures.push_back(now);
+
+    if attempts.failures.len() >= config.threshold && attempts.locked_until.is_none_or(|until| until <= now) {
+        attempts.locked_until = Some(now + config.lockout);
+        attempts.failures.clear();
+        metrics::AUTH_LOCKOUTS.inc();
+        println!(
+            "🚨 Possible brute force: {} failed authentication {} times within {:?}, locked out for {:?}",
+            subject, config.threshold, config.window, config.lockout
+        );
+    }
+
+    let locked_until = attempts.locked_until.filter(|until| *until > now);
+
+    // subjects without recent failures nor a running lockout are dropped, so scans do not grow the map forever
+    tracker.attempts.retain(|_, attempts| {
+        !attempts.failures.is_empty() || attempts.locked_until.is_some_and(|until| until > now)
+    });
+
+    locked_until.map(|until| (until - now).as_secs().max(1))
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuthLockout {
+    // "ip:<addr>"
+    pub subject: String,
+    pub retry_after_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuthFailures {
+    // newest first
+    pub recent: Vec<AuthFailure>,
+    pub lockouts: Vec<AuthLockout>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuthFailuresResponse {
+    pub success: bool,
+    pub data: AuthFailures,
+}
+
+// the recent failures and the running lockouts of this instance
+pub async fn list_handler(req: HttpRequest) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let now = Instant::now();
+    let tracker = tracker().lock().unwrap();
+
+    let mut lockouts: Vec<AuthLockout> = tracker
+        .attempts
+        .iter()
+        .filter_map(|(subject, attempts)| {
+            let until = attempts.locked_until.filter(|until| *until > now)?;
+            Some(AuthLockout { subject: subject.clone(), retry_after_secs: (until - now).as_secs().max(1) })
+        })
+        .collect();
+    lockouts.sort_by(|a, b| a.subject.cmp(&b.subject));
+
+    HttpResponse::Ok().json(AuthFailuresResponse {
+        success: true,
+        data: AuthFailures { recent: tracker.recent.iter().rev().cloned().collect(), lockouts },
+    })
+}
@@ -0,0 +1,202 @@
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpResponse, Responder};
+use aws_sdk_sqs::types::QueueAttributeName;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use crate::metrics::{
+    NOTIFICATIONS_IN_FLIGHT, NOTIFICATIONS_WAITING, OUTBOX_LAG, OUTBOX_PENDING, PROCESSING_LAG, SQS_NOT_VISIBLE,
+    SQS_VISIBLE, WEBHOOKS_IN_FLIGHT, WORKER_UTILIZATION,
+};
+use crate::outbound;
+use crate::outbox;
+use crate::repo::{build_pg_pool, DBType};
+use crate::sqs;
+use crate::webhooks;
+use crate::AppState;
+
+const DEFAULT_REFRESH_SECS: u64 = 5;
+
+// without a new event for this long the processing lag reads 0, a storm that ended must not keep the replicas up
+const LAG_WINDOW: Duration = Duration::from_secs(60);
+
+// the load signals for KEDA or an HPA external metric: GET /metrics/scaling answers them as JSON, /metrics as
+// gauges. The outbox and SQS depths are read every AUTOSCALING_REFRESH_SECS and are the same on every replica,
+// the in-flight and waiting counts and the lag are those of the instance answering.
+#[derive(Debug, Clone, Serialize)]
+pub struct Scaling {
+    // notifications_waiting + outbox_pending + sqs_messages_visible, the value to scale on
+    pub queue_depth: u64,
+    pub notifications_in_flight: usize,
+    pub notifications_waiting: usize,
+    // share of the concurrency slots in use among the domains being processed, 0 to 1
+    pub worker_utilization: f64,
+    pub webhook_deliveries_in_flight: usize,
+    pub outbox_pending: u64,
+    pub outbox_lag_seconds: u64,
+    // age of the SES event most recently processed, 0 when none arrived within the last minute
+    pub processing_lag_seconds: u64,
+    // with NOTIFICATION_SOURCE=sqs only
+    pub sqs_messages_visible: Option<u64>,
+    pub sqs_messages_not_visible: Option<u64>,
+}
+
+fn last_lag() -> &'static Mutex<Option<(Instant, f64)>> {
+    static LAST_LAG: Mutex<Option<(Instant, f64)>> = Mutex::new(None);
+    &LAST_LAG
+}
+
+// called with the SES timestamp of every bounce and complaint processed
+pub fn observe_lag(timestamp: DateTime<Utc>) {
+    let lag = (Utc::now() - timestamp).num_milliseconds().max(0) as f64 / 1000.0;
+    *last_lag().lock().unwrap() = Some((Instant::now(), lag));
+    PROCESSING_LAG.set(lag);
+}
+
+fn processing_lag() -> f64 {
+    match *last_lag().lock().unwrap() {
+        Some((at, lag)) if at.elapsed() < LAG_WINDOW => lag,
+        _ => 0.0,
+    }
+}
+
+// (pending events, age of the oldest in seconds)
+async fn outbox_depth(data: &web::Data<AppState>) -> Result<(i64, i64), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, (i64, Option<i64>)>(
+                r#"SELECT COUNT(*), TIMESTAMPDIFF(SECOND, MIN(created_at), NOW()) FROM outbox WHERE published_at IS NULL"#,
+            )
+                .fetch_one(pool)
+                .await
+                .map(|(pending, lag)| (pending, lag.unwrap_or(0)))
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(
+                    &format!(
+                        r#"SELECT COUNT(*), EXTRACT(EPOCH FROM NOW() - MIN(created_at))::BIGINT FROM {} WHERE published_at IS NULL"#,
+                        outbox::table()
+                    ),
+                    &[],
+                )
+                .await
+                .map(|row| (row.get::<_, i64>(0), row.get::<_, Option<i64>>(1).unwrap_or(0)))
+                .map_err(|err| err.to_string())
+        }
+        // suppressions are published without an outbox
+        DBType::DynamoDB(_) => Ok((0, 0)),
+    }
+}
+
+// (visible, not visible) messages of the queue
+async fn sqs_depth(client: &aws_sdk_sqs::Client, queue_url: &str) -> Result<(u64, u64), String> {
+    let output = client
+        .get_queue_attributes()
+        .queue_url(queue_url)
+        .attribute_names(QueueAttributeName::ApproximateNumberOfMessages)
+        .attribute_names(QueueAttributeName::ApproximateNumberOfMessagesNotVisible)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let attributes = output.attributes.unwrap_or_default();
+    let count = |name: &QueueAttributeName| {
+        attributes
+            .get(name)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    };
+
+    Ok((
+        count(&QueueAttributeName::ApproximateNumberOfMessages),
+        count(&QueueAttributeName::ApproximateNumberOfMessagesNotVisible),
+    ))
+}
+
+// the in-process signals, cheap enough to read on every request
+fn refresh_local(data: &web::Data<AppState>) {
+    let usage = data.limiter.usage();
+
+    NOTIFICATIONS_IN_FLIGHT.set(usage.in_use as f64);
+    NOTIFICATIONS_WAITING.set(usage.waiting as f64);
+    WORKER_UTILIZATION.set(if usage.capacity == 0 { 0.0 } else { usage.in_use as f64 / usage.capacity as f64 });
+    WEBHOOKS_IN_FLIGHT.set(webhooks::in_flight() as f64);
+    PROCESSING_LAG.set(processing_lag());
+}
+
+pub fn snapshot(data: &web::Data<AppState>) -> Scaling {
+    refresh_local(data);
+
+    let sqs = sqs::enabled();
+    let outbox_pending = OUTBOX_PENDING.get() as u64;
+    let notifications_waiting = NOTIFICATIONS_WAITING.get() as usize;
+    let sqs_visible = sqs.then(|| SQS_VISIBLE.get() as u64);
+
+    Scaling {
+        queue_depth: notifications_waiting as u64 + outbox_pending + sqs_visible.unwrap_or(0),
+        notifications_in_flight: NOTIFICATIONS_IN_FLIGHT.get() as usize,
+        notifications_waiting,
+        worker_utilization: WORKER_UTILIZATION.get(),
+        webhook_deliveries_in_flight: WEBHOOKS_IN_FLIGHT.get() as usize,
+        outbox_pending,
+        outbox_lag_seconds: OUTBOX_LAG.get() as u64,
+        processing_lag_seconds: PROCESSING_LAG.get() as u64,
+        sqs_messages_visible: sqs_visible,
+        sqs_messages_not_visible: sqs.then(|| SQS_NOT_VISIBLE.get() as u64),
+    }
+}
+
+// public like /metrics, it carries counts only
+pub async fn scaling_handler(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(snapshot(&data))
+}
+
+// reads the shared queue depths every AUTOSCALING_REFRESH_SECS, so the scrapes of every replica do not each query them
+pub async fn start(data: web::Data<AppState>) -> Option<JoinHandle<()>> {
+    let refresh = Duration::from_secs(
+        env::var("AUTOSCALING_REFRESH_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_REFRESH_SECS)
+            .max(1),
+    );
+
+    let queue = match sqs::enabled().then(sqs::config) {
+        Some(Ok(config)) => Some((aws_sdk_sqs::Client::new(&outbound::aws_config().await), config.queue_url().to_string())),
+        _ => None,
+    };
+
+    Some(tokio::spawn(async move {
+        loop {
+            refresh_local(&data);
+
+            match outbox_depth(&data).await {
+                Ok((pending, lag)) => {
+                    OUTBOX_PENDING.set(pending as f64);
+                    OUTBOX_LAG.set(lag as f64);
+                }
+                Err(err) => println!("🔥 Failed to read the outbox depth: {}", err),
+            }
+
+            if let Some((client, queue_url)) = &queue {
+                match sqs_depth(client, queue_url).await {
+                    Ok((visible, not_visible)) => {
+                        SQS_VISIBLE.set(visible as f64);
+                        SQS_NOT_VISIBLE.set(not_visible as f64);
+                    }
+                    Err(err) => println!("🔥 Failed to read the depth of {}: {}", queue_url, err),
+                }
+            }
+
+            tokio::time::sleep(refresh).await;
+        }
+    }))
+}
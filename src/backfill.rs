@@ -0,0 +1,189 @@
+use std::env;
+
+use tokio_postgres::Client;
+
+use crate::blacklist;
+use crate::domain::Message;
+use crate::config::arg_value;
+use crate::diagnostics;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::services::notifications::extract_email_address;
+
+const DEFAULT_BATCH_SIZE: i64 = 500;
+
+// (id, email, reason) of a row still missing its normalized columns
+type ReasonRow = (i64, String, String);
+
+struct Normalized {
+    category: String,
+    bounce_type: String,
+    bounce_sub_type: String,
+    diagnostic_code: Option<String>,
+    diagnostic_class: Option<String>,
+    reason_summary: String,
+    reporting_mta: Option<String>,
+    remote_mta_ip: Option<String>,
+    source_arn: Option<String>,
+    sending_account_id: Option<String>,
+}
+
+// rows created from a bounce keep the whole SES message as reason, manual rows have free text and are skipped
+fn normalize(email: &str, reason: &str) -> Option<Normalized> {
+    let message: Message = serde_json::from_str(reason).ok()?;
+    let bounce = message.bounce?;
+
+    let recipient = bounce
+        .bounced_recipients
+        .iter()
+        .find(|recipient| extract_email_address(&recipient.email_address) == email);
+    let diagnostic_code = recipient.and_then(|recipient| recipient.diagnostic_code.clone());
+    let diagnostic_class = recipient
+        .and_then(|recipient| diagnostics::classify(recipient.status.as_deref(), recipient.diagnostic_code.as_deref()))
+        .map(String::from);
+    let reason_summary = blacklist::bounce_summary(
+        &bounce.bounce_type,
+        &bounce.bounce_sub_type,
+        recipient.and_then(|recipient| recipient.status.as_deref()),
+        diagnostic_code.as_deref(),
+    );
+
+    Some(Normalized {
+        category: blacklist::bounce_category(&bounce.bounce_type).into(),
+        reporting_mta: bounce.reporting_mta,
+        remote_mta_ip: bounce.remote_mta_ip,
+        source_arn: message.mail.as_ref().map(|mail| mail.source_arn.clone()),
+        sending_account_id: message.mail.as_ref().map(|mail| mail.sending_account_id.clone()),
+        bounce_type: bounce.bounce_type,
+        bounce_sub_type: bounce.bounce_sub_type,
+        diagnostic_code,
+        diagnostic_class,
+        reason_summary,
+    })
+}
+
+// `pg` is the client of the batch with DB_TYPE=PG
+async fn fetch_batch(db_type: &DBType, pg: Option<&Client>, after_id: i64, batch_size: i64) -> Result<Vec<ReasonRow>, String> {
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, ReasonRow>(
+                r#"SELECT id, email, reason FROM blacklist WHERE id > ? AND (bounce_type IS NULL OR reason_summary IS NULL OR (diagnostic_class IS NULL AND diagnostic_code IS NOT NULL)) ORDER BY id LIMIT ?"#,
+            )
+                .bind(after_id)
+                .bind(batch_size)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = pg.ok_or("no Postgres client for the batch")?;
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, email, reason FROM {table} WHERE id > $1 AND (bounce_type IS NULL OR reason_summary IS NULL OR (diagnostic_class IS NULL AND diagnostic_code IS NOT NULL)) ORDER BY id LIMIT $2"#,
+                        table = table
+                    ),
+                    &[&after_id, &batch_size],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn update_row(db_type: &DBType, pg: Option<&Client>, id: i64, normalized: &Normalized) -> Result<(), String> {
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"UPDATE blacklist SET category = COALESCE(category, ?), bounce_type = ?, bounce_sub_type = ?, diagnostic_code = ?, diagnostic_class = ?, reason_summary = COALESCE(reason_summary, ?), reporting_mta = ?, remote_mta_ip = ?, source_arn = ?, sending_account_id = ? WHERE id = ?"#,
+            )
+                .bind(&normalized.category)
+                .bind(&normalized.bounce_type)
+                .bind(&normalized.bounce_sub_type)
+                .bind(&normalized.diagnostic_code)
+                .bind(&normalized.diagnostic_class)
+                .bind(&normalized.reason_summary)
+                .bind(&normalized.reporting_mta)
+                .bind(&normalized.remote_mta_ip)
+                .bind(&normalized.source_arn)
+                .bind(&normalized.sending_account_id)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = pg.ok_or("no Postgres client for the batch")?;
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET category = COALESCE(category, $1), bounce_type = $2, bounce_sub_type = $3, diagnostic_code = $4, diagnostic_class = $5, reason_summary = COALESCE(reason_summary, $6), reporting_mta = $7, remote_mta_ip = $8, source_arn = $9, sending_account_id = $10 WHERE id = $11"#,
+                        table = table
+                    ),
+                    &[
+                        &normalized.category,
+                        &normalized.bounce_type,
+                        &normalized.bounce_sub_type,
+                        &normalized.diagnostic_code,
+                        &normalized.diagnostic_class,
+                        &normalized.reason_summary,
+                        &normalized.reporting_mta,
+                        &normalized.remote_mta_ip,
+                        &normalized.source_arn,
+                        &normalized.sending_account_id,
+                        &id,
+                    ],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// `backfill-reasons [--batch-size N] [--after-id ID]`, an interrupted run is resumed with the last reported id
+pub async fn run(db_type: &DBType, db_url: &str, args: &[String]) -> Result<(), String> {
+    let batch_size = arg_value(args, "--batch-size").and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+    let mut after_id = arg_value(args, "--after-id").and_then(|value| value.parse().ok()).unwrap_or(0);
+    let (mut updated, mut skipped) = (0, 0);
+
+    println!("🚀 Backfilling normalized reason columns after id {} in batches of {}", after_id, batch_size);
+
+    loop {
+        // one connection per batch, opening one per row costs a handshake each
+        let pg = match db_type {
+            DBType::Postgres => Some(build_pg_pool(db_url).await.map_err(|err| err.to_string())?),
+            _ => None,
+        };
+        let rows = fetch_batch(db_type, pg.as_ref(), after_id, batch_size).await?;
+
+        let Some((last_id, _, _)) = rows.last() else {
+            break;
+        };
+        let last_id = *last_id;
+
+        for (id, email, reason) in &rows {
+            match normalize(email, reason) {
+                Some(normalized) => {
+                    update_row(db_type, pg.as_ref(), *id, &normalized).await?;
+                    updated += 1;
+                }
+                None => skipped += 1,
+            }
+        }
+
+        after_id = last_id;
+        println!("Processed up to id {}: {} updated, {} skipped", after_id, updated, skipped);
+    }
+
+    println!("✅ Backfill done: {} updated, {} skipped", updated, skipped);
+
+    Ok(())
+}
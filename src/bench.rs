@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+use crate::blacklist;
+use crate::config::arg_value;
+use crate::diagnostics;
+use crate::domain::Message;
+use crate::payload::{self, SnsPayload};
+
+const DEFAULT_ITERATIONS: usize = 100_000;
+
+// the samples the wrk profiles in bench/ send, so both measure the same payloads
+const SNS_BOUNCE: &str = include_str!("../bench/samples/sns_bounce.json");
+const RAW_BOUNCE: &str = include_str!("../bench/samples/bounce.json");
+
+// the CPU work of one notification before any query: the envelope, the SES message and the derived columns
+fn parse_once(body: &[u8]) -> Result<usize, String> {
+    let message = match payload::parse(body, false)? {
+        SnsPayload::Envelope(notification) => notification.message.unwrap_or_default(),
+        SnsPayload::Raw(message) => message,
+    };
+
+    let message: Message = serde_json::from_str(&message).map_err(|err| err.to_string())?;
+    let bounce = message.bounce.ok_or("sample without bounce")?;
+    let mut summaries = 0;
+
+    for recipient in &bounce.bounced_recipients {
+        let status = recipient.status.as_deref();
+        let diagnostic_code = recipient.diagnostic_code.as_deref();
+
+        diagnostics::classify(status, diagnostic_code);
+        summaries += blacklist::bounce_summary(&bounce.bounce_type, &bounce.bounce_sub_type, status, diagnostic_code).len();
+    }
+
+    Ok(summaries)
+}
+
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let index = ((sorted.len() as f64 - 1.0) * percentile).round() as usize;
+    sorted.get(index).copied().unwrap_or_default()
+}
+
+fn run_sample(name: &str, body: &[u8], iterations: usize) -> Result<f64, String> {
+    // warms the caches and fails early on a broken sample
+    parse_once(body)?;
+
+    let mut timings = Vec::with_capacity(iterations);
+    let started = Instant::now();
+
+    for _ in 0..iterations {
+        let start = Instant::now();
+        std::hint::black_box(parse_once(std::hint::black_box(body))?);
+        timings.push(start.elapsed());
+    }
+
+    let elapsed = started.elapsed();
+    timings.sort();
+    let ops = iterations as f64 / elapsed.as_secs_f64();
+
+    println!(
+        "{:<12} {:>10.0} ops/s   p50 {:>8.2?}   p99 {:>8.2?}   max {:>8.2?}",
+        name,
+        ops,
+        percentile(&timings, 0.5),
+        percentile(&timings, 0.99),
+        timings.last().copied().unwrap_or_default()
+    );
+
+    Ok(ops)
+}
+
+// `bench-parse [--iterations N] [--min-ops-per-sec N]`, exits 1 when a sample parses slower than the minimum,
+// for release pipelines. Build with --release, debug builds are an order of magnitude slower.
+pub fn run(args: &[String]) -> Result<bool, String> {
+    let iterations = arg_value(args, "--iterations")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_ITERATIONS)
+        .max(1);
+    let min_ops: Option<f64> = arg_value(args, "--min-ops-per-sec").and_then(|value| value.parse().ok());
+
+    println!("🚀 Parsing each sample {} times", iterations);
+
+    let mut passed = true;
+
+    for (name, body) in [("sns-envelope", SNS_BOUNCE), ("raw", RAW_BOUNCE)] {
+        let ops = run_sample(name, body.as_bytes(), iterations)?;
+
+        if let Some(min_ops) = min_ops.filter(|min_ops| ops < *min_ops) {
+            println!("🔥 {} parses at {:.0} ops/s, below the minimum of {:.0}", name, ops, min_ops);
+            passed = false;
+        }
+    }
+
+    Ok(passed)
+}
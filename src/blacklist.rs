@@ -0,0 +1,968 @@
+use std::collections::HashSet;
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::ToSql;
+use utoipa::ToSchema;
+
+use crate::complaint_review;
+use crate::diagnostics;
+use crate::domain::{Bounce, BouncedRecipient, Complaint, Mail};
+use crate::domains::{self, DomainSettings};
+use crate::events::{self, Event, LiveEvent};
+use crate::faults;
+use crate::imports;
+use crate::last_modified;
+use crate::outbox;
+use crate::responses::{ErrorResponse, StatusResponse};
+use crate::privacy;
+use crate::rules::{self, RuleAction};
+use crate::simulator;
+use crate::verification;
+use crate::responses::ListResponse;
+use crate::handlers::is_admin;
+use crate::repo::status::{self, TransitionError};
+use crate::repo::{build_pg_read_client, dynamodb, prepared_client, read_mysql, DBType, EntryStatus, effective_status_sql};
+use crate::services::notifications::extract_email_address;
+use crate::AppState;
+
+pub const CATEGORY_HARD_BOUNCE: &str = "hard_bounce";
+pub const CATEGORY_SOFT_BOUNCE: &str = "soft_bounce";
+pub const CATEGORY_MANUAL: &str = "manual";
+pub const CATEGORY_COMPLAINT: &str = "complaint";
+// a one-click List-Unsubscribe, see unsubscribe.rs
+pub const CATEGORY_UNSUBSCRIBE: &str = "unsubscribe";
+
+// max body size accepted by the import endpoint
+pub const IMPORT_LIMIT: usize = 16 * 1024 * 1024;
+
+pub fn bounce_category(bounce_type: &str) -> &'static str {
+    match bounce_type {
+        "Permanent" => CATEGORY_HARD_BOUNCE,
+        _ => CATEGORY_SOFT_BOUNCE,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NewEntry {
+    pub domain_id: i32,
+    pub email: String,
+    pub reason: String,
+    pub category: String,
+    pub expires_at: Option<NaiveDateTime>,
+    pub bounce_type: Option<String>,
+    pub bounce_sub_type: Option<String>,
+    pub diagnostic_code: Option<String>,
+    // e.g. no_such_user or mailbox_full, see diagnostics::classify
+    pub diagnostic_class: Option<String>,
+    // short text for UIs, e.g. "Hard bounce: user unknown (5.1.1)"
+    pub reason_summary: Option<String>,
+    // of the message that bounced or was complained about, for support
+    pub subject: Option<String>,
+    pub reporting_mta: Option<String>,
+    pub remote_mta_ip: Option<String>,
+    pub source_arn: Option<String>,
+    pub sending_account_id: Option<String>,
+    // defaults to now, set when rebuilding from past notifications
+    pub created_at: Option<NaiveDateTime>,
+    // when SES saw the bounce or complaint
+    pub event_at: Option<NaiveDateTime>,
+    // stored as pending_review instead of active, see complaint_review
+    pub pending_review: bool,
+}
+
+impl NewEntry {
+    pub fn status(&self) -> EntryStatus {
+        if self.pending_review { EntryStatus::PendingReview } else { EntryStatus::Active }
+    }
+}
+
+pub fn is_duplicate(err: &str) -> bool {
+    err.contains("Duplicate entry") || err.contains("duplicate key")
+}
+
+// DUPLICATE_RESPONSE=ok (default) answers 200 with status duplicate so SNS stops redelivering,
+// conflict answers 409 for deployments whose retry policy treats 4xx as permanent anyway
+pub fn duplicate_response(message: String) -> HttpResponse {
+    match env::var("DUPLICATE_RESPONSE").as_deref() {
+        Ok("conflict") => HttpResponse::Conflict().json(StatusResponse::fail(message)),
+        _ => HttpResponse::Ok().json(StatusResponse::duplicate(message)),
+    }
+}
+
+pub fn table() -> String {
+    match env::var("DB_TYPE").as_deref() {
+        Ok("PG") => env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into()),
+        _ => "blacklist".into(),
+    }
+}
+
+pub fn table_for(db_type: &DBType) -> String {
+    match db_type {
+        DBType::Postgres => env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into()),
+        DBType::MySQL(_) => "blacklist".into(),
+        DBType::DynamoDB(store) => store.table().into(),
+    }
+}
+
+// summaries are shown in lists, long manual reasons are cut
+const SUMMARY_LIMIT: usize = 255;
+
+fn truncate_summary(summary: String) -> String {
+    match summary.char_indices().nth(SUMMARY_LIMIT - 1) {
+        Some((end, _)) => format!("{}…", &summary[..end]),
+        None => summary,
+    }
+}
+
+// e.g. "Hard bounce: user unknown (5.1.1)", the SES sub type stands in when the diagnostic is not recognized
+pub fn bounce_summary(bounce_type: &str, bounce_sub_type: &str, status: Option<&str>, diagnostic_code: Option<&str>) -> String {
+    let kind = if bounce_category(bounce_type) == CATEGORY_HARD_BOUNCE { "Hard bounce" } else { "Soft bounce" };
+    let cause = diagnostics::classify(status, diagnostic_code)
+        .and_then(diagnostics::label)
+        .map(String::from)
+        .unwrap_or_else(|| bounce_sub_type.to_lowercase());
+
+    match diagnostics::status_code(status, diagnostic_code) {
+        Some(code) => format!("{}: {} ({})", kind, cause, code),
+        None => format!("{}: {}", kind, cause),
+    }
+}
+
+pub fn complaint_summary(feedback_type: &str) -> String {
+    match feedback_type {
+        "" => "Complaint".into(),
+        feedback_type => format!("Complaint: {}", feedback_type),
+    }
+}
+
+// the subject stored with an entry, left out with EMAIL_HASH_KEY because it often names the recipient
+fn stored_subject(mail: Option<&Mail>) -> Option<String> {
+    if privacy::hashing_enabled() {
+        return None;
+    }
+
+    mail.and_then(Mail::subject)
+        .map(str::trim)
+        .filter(|subject| !subject.is_empty())
+        .map(|subject| truncate_summary(subject.to_string()))
+}
+
+// the entries a bounce results in once the domain rules are applied, ignored recipients are left out
+pub fn bounce_entries(
+    domain_id: i32,
+    bounce: &Bounce,
+    mail: Option<&Mail>,
+    reason: &str,
+    settings: &DomainSettings,
+    received_at: NaiveDateTime,
+) -> Vec<NewEntry> {
+    let entries = bounce
+        .bounced_recipients
+        .iter()
+        .filter_map(|recipient| bounce_entry(domain_id, bounce, recipient, mail, reason, settings, received_at))
+        .collect();
+
+    unique(entries, &bounce.feedback_id)
+}
+
+// SES can name a recipient more than once in one notification (CC/BCC expansions), only the first entry of an
+// address is kept so the notification writes each address once instead of failing as a duplicate midway
+fn unique(entries: Vec<NewEntry>, feedback_id: &str) -> Vec<NewEntry> {
+    let count = entries.len();
+    let mut seen = HashSet::new();
+    let entries: Vec<NewEntry> = entries.into_iter().filter(|entry| seen.insert(entry.email.to_lowercase())).collect();
+
+    if entries.len() < count {
+        println!("Collapsed {} repeated recipients of notification {}", count - entries.len(), feedback_id);
+    }
+
+    entries
+}
+
+// the entry of one bounced recipient, None when it is a simulator address or a domain rule ignores it. A temporary
+// suppression runs from `received_at`, when the notification came in, also when it is replayed later.
+pub fn bounce_entry(
+    domain_id: i32,
+    bounce: &Bounce,
+    recipient: &BouncedRecipient,
+    mail: Option<&Mail>,
+    reason: &str,
+    settings: &DomainSettings,
+    received_at: NaiveDateTime,
+) -> Option<NewEntry> {
+    let category = bounce_category(&bounce.bounce_type);
+    let email = extract_email_address(recipient.email_address.as_str());
+
+    if simulator::excluded(&email) {
+        println!("Ignoring bounce for SES mailbox simulator address: {}", email);
+        return None;
+    }
+
+    let expires_at = match rules::evaluate(&settings.bounce_rules, bounce, recipient, mail) {
+        Some(RuleAction::Ignore) => {
+            println!("Ignoring bounce for: {} by domain rule", email);
+            return None;
+        }
+        // the days are bounded when the rules are parsed, past chrono's dates the entry would never expire anyway
+        Some(RuleAction::SuppressTemporarily { days }) => {
+            Duration::try_days(days).and_then(|days| received_at.checked_add_signed(days))
+        }
+        Some(RuleAction::Alert) => {
+            println!(
+                "🚨 Alert rule matched for: {} domain: {} bounce: {} / {} diagnostic: {:?}",
+                email, domain_id, bounce.bounce_type, bounce.bounce_sub_type, recipient.diagnostic_code
+            );
+            None
+        }
+        Some(RuleAction::SuppressPermanently) => None,
+        None => settings.expiry_from(category, received_at),
+    };
+
+    Some(NewEntry {
+        domain_id,
+        email: privacy::stored_email(&email),
+        reason: privacy::stored_reason(reason, &bounce.bounce_type, &bounce.bounce_sub_type),
+        category: category.into(),
+        expires_at,
+        bounce_type: Some(bounce.bounce_type.clone()),
+        bounce_sub_type: Some(bounce.bounce_sub_type.clone()),
+        diagnostic_code: recipient.diagnostic_code.as_deref().map(privacy::stored_text),
+        diagnostic_class: diagnostics::classify(recipient.status.as_deref(), recipient.diagnostic_code.as_deref()).map(String::from),
+        reason_summary: Some(bounce_summary(
+            &bounce.bounce_type,
+            &bounce.bounce_sub_type,
+            recipient.status.as_deref(),
+            recipient.diagnostic_code.as_deref(),
+        )),
+        subject: stored_subject(mail),
+        reporting_mta: bounce.reporting_mta.clone(),
+        remote_mta_ip: bounce.remote_mta_ip.clone(),
+        source_arn: mail.map(|mail| mail.source_arn.clone()),
+        sending_account_id: mail.map(|mail| mail.sending_account_id.clone()),
+        created_at: None,
+        event_at: Some(bounce.timestamp.naive_utc()),
+        pending_review: false,
+    })
+}
+
+// COMPLAINT_DESTINATION_FALLBACK=false turns off suppressing mail.destination when a complaint names no recipient
+fn destination_fallback() -> bool {
+    env::var("COMPLAINT_DESTINATION_FALLBACK").as_deref() != Ok("false")
+}
+
+// the entries a complaint results in, Err when it names no recipient and the fallback does not apply
+pub fn complaint_entries(
+    domain_id: i32,
+    complaint: &Complaint,
+    mail: Option<&Mail>,
+    reason: &str,
+    settings: &DomainSettings,
+) -> Result<Vec<NewEntry>, String> {
+    let mut recipients: Vec<String> = complaint
+        .complained_recipients
+        .iter()
+        .map(|recipient| recipient.email_address.clone())
+        .collect();
+
+    if recipients.is_empty() {
+        let destination = mail.map(|mail| mail.destination.clone()).unwrap_or_default();
+
+        if !destination_fallback() || destination.is_empty() {
+            return Err(format!("complaint {} without complained recipients", complaint.feedback_id));
+        }
+
+        println!("Complaint {} names no recipient, suppressing the destination: {:?}", complaint.feedback_id, destination);
+        recipients = destination;
+    }
+
+    let feedback_type = complaint.complaint_feedback_type.clone().unwrap_or_default();
+    let mut entries = vec![];
+
+    for recipient in recipients {
+        let email = extract_email_address(&recipient);
+
+        if simulator::excluded(&email) {
+            println!("Ignoring complaint for SES mailbox simulator address: {}", email);
+            continue;
+        }
+
+        entries.push(NewEntry {
+            domain_id,
+            email: privacy::stored_email(&email),
+            reason: privacy::stored_reason(reason, "Complaint", &feedback_type),
+            category: CATEGORY_COMPLAINT.into(),
+            reason_summary: Some(complaint_summary(&feedback_type)),
+            subject: stored_subject(mail),
+            expires_at: settings.default_expiry(CATEGORY_COMPLAINT),
+            source_arn: mail.map(|mail| mail.source_arn.clone()),
+            sending_account_id: mail.map(|mail| mail.sending_account_id.clone()),
+            event_at: Some(complaint.timestamp.naive_utc()),
+            pending_review: complaint_review::holds(&email),
+            ..NewEntry::default()
+        });
+    }
+
+    Ok(unique(entries, &complaint.feedback_id))
+}
+
+// writes the entry and queues its `event_type` event (bounce, complaint or blacklist) in the outbox, the outbox
+// publisher puts it on the event bus once committed. An entry of the address already there is a duplicate.
+pub async fn insert(entry: &NewEntry, event_type: &str, data: &web::Data<AppState>) -> Result<(), String> {
+    save(entry, event_type, false, data).await
+}
+
+// insert() for a new bounce, complaint or unsubscribe: an entry of the address that no longer suppresses (expired,
+// removed, or held for review when the new one is active) is replaced by it, with its expiry and reason. Only an
+// active entry is a duplicate, and an allowlisted one, the operator decided the address is never suppressed.
+pub async fn insert_or_reactivate(entry: &NewEntry, event_type: &str, data: &web::Data<AppState>) -> Result<(), String> {
+    save(entry, event_type, true, data).await
+}
+
+async fn save(entry: &NewEntry, event_type: &str, reactivate: bool, data: &web::Data<AppState>) -> Result<(), String> {
+    faults::db_latency().await;
+    if let Some(err) = faults::insert_failure() {
+        return Err(err);
+    }
+
+    // an entry held for review suppresses nothing yet, its event says so; approving it publishes the complaint
+    let event_type = if entry.pending_review { complaint_review::PENDING_EVENT } else { event_type };
+    let event = LiveEvent::suppressed(event_type, entry);
+    let result = write(&data.db_type, &data.db_url, &table(), entry, Some(&event), reactivate).await;
+    data.cache.evict(entry.domain_id, &entry.email);
+
+    if result.is_ok() {
+        match &data.db_type {
+            // stored without an outbox row, published at once (at most once)
+            DBType::DynamoDB(_) => events::publish(data, Event::EmailSuppressed(event)),
+            _ => outbox::wake(),
+        }
+        last_modified::touch(entry.domain_id, data).await;
+    }
+
+    // while migrating, the secondary backend gets a copy. It is best effort, the consistency-check command finds gaps
+    if let Some(secondary) = &data.secondary {
+        if result.is_ok() || result.as_ref().is_err_and(|err| is_duplicate(err)) {
+            match write(&secondary.db_type, &secondary.db_url, &table_for(&secondary.db_type), entry, None, reactivate).await {
+                Err(err) if !is_duplicate(&err) => {
+                    println!("🔥 Dual write of {} to the secondary database failed: {}", entry.email, err);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    result
+}
+
+pub async fn insert_into(table: &str, entry: &NewEntry, data: &web::Data<AppState>) -> Result<(), String> {
+    write(&data.db_type, &data.db_url, table, entry, None, false).await
+}
+
+const COLUMNS: &str = "domain_id, email, reason, category, expires_at, bounce_type, bounce_sub_type, diagnostic_code, diagnostic_class, reason_summary, subject, reporting_mta, remote_mta_ip, source_arn, sending_account_id, created_at, event_at, status";
+
+// the entries a new one replaces with `reactivate`, as stored: expired, removed, active past expires_at, and held
+// for review when the new entry is active. `alias` qualifies the columns, `new_status` is the new entry's status.
+fn reactivatable_sql(alias: &str, new_status: &str) -> String {
+    format!(
+        "({a}status IN ('expired', 'removed') OR ({a}status = 'active' AND {a}expires_at IS NOT NULL AND {a}expires_at <= NOW()) OR ({a}status = 'pending_review' AND {new_status} = 'active'))",
+        a = alias,
+        new_status = new_status
+    )
+}
+
+const REACTIVATED_COLUMNS: [&str; 15] = [
+    "reason", "category", "expires_at", "bounce_type", "bounce_sub_type", "diagnostic_code", "diagnostic_class",
+    "reason_summary", "subject", "reporting_mta", "remote_mta_ip", "source_arn", "sending_account_id", "event_at", "status",
+];
+
+// writes the entry, with `event` also its outbox row in the same transaction, so the entry and its publication
+// are either both stored or neither is. With `reactivate` an entry that no longer suppresses is replaced.
+async fn write(db_type: &DBType, db_url: &str, table: &str, entry: &NewEntry, event: Option<&LiveEvent>, reactivate: bool) -> Result<(), String> {
+    match db_type {
+        DBType::MySQL(pool) => {
+            let sql = format!(
+                r#"INSERT INTO {table} ({columns}) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,COALESCE(?, CURRENT_TIMESTAMP),?,?)"#,
+                table = table,
+                columns = COLUMNS
+            );
+            let query = sqlx::query(&sql)
+                .bind(entry.domain_id)
+                .bind(&entry.email)
+                .bind(&entry.reason)
+                .bind(&entry.category)
+                .bind(entry.expires_at)
+                .bind(&entry.bounce_type)
+                .bind(&entry.bounce_sub_type)
+                .bind(&entry.diagnostic_code)
+                .bind(&entry.diagnostic_class)
+                .bind(&entry.reason_summary)
+                .bind(&entry.subject)
+                .bind(&entry.reporting_mta)
+                .bind(&entry.remote_mta_ip)
+                .bind(&entry.source_arn)
+                .bind(&entry.sending_account_id)
+                .bind(entry.created_at)
+                .bind(entry.event_at)
+                .bind(entry.status().as_str());
+
+            if event.is_none() && !reactivate {
+                return query.execute(pool).await.map(|_| ()).map_err(|err| err.to_string());
+            }
+
+            let mut tx = pool.begin().await.map_err(|err| err.to_string())?;
+
+            // a duplicate key only fails the statement, the replacement runs in the same transaction
+            match query.execute(&mut tx).await.map_err(|err| err.to_string()) {
+                Err(err) if reactivate && is_duplicate(&err) => {
+                    let sql = format!(
+                        r#"UPDATE {table} SET {columns}, status_changed_at = NOW() WHERE domain_id = ? AND email = ? AND {reactivatable}"#,
+                        table = table,
+                        columns = REACTIVATED_COLUMNS.map(|column| format!("{} = ?", column)).join(", "),
+                        reactivatable = reactivatable_sql("", "?")
+                    );
+                    let replaced = sqlx::query(&sql)
+                        .bind(&entry.reason)
+                        .bind(&entry.category)
+                        .bind(entry.expires_at)
+                        .bind(&entry.bounce_type)
+                        .bind(&entry.bounce_sub_type)
+                        .bind(&entry.diagnostic_code)
+                        .bind(&entry.diagnostic_class)
+                        .bind(&entry.reason_summary)
+                        .bind(&entry.subject)
+                        .bind(&entry.reporting_mta)
+                        .bind(&entry.remote_mta_ip)
+                        .bind(&entry.source_arn)
+                        .bind(&entry.sending_account_id)
+                        .bind(entry.event_at)
+                        .bind(entry.status().as_str())
+                        .bind(entry.domain_id)
+                        .bind(&entry.email)
+                        .bind(entry.status().as_str())
+                        .execute(&mut tx)
+                        .await
+                        .map_err(|err| err.to_string())?;
+
+                    // the entry still suppresses, the duplicate stands
+                    if replaced.rows_affected() == 0 {
+                        return Err(err);
+                    }
+                }
+                result => result.map(|_| ())?,
+            }
+
+            if let Some(event) = event {
+                outbox::enqueue(&mut tx, event).await?;
+            }
+            tx.commit().await.map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let pg = prepared_client(db_url).await.map_err(|err| err.to_string())?;
+            let status = entry.status().as_str();
+            let mut sql = format!(
+                r#"INSERT INTO {table} AS b ({columns}) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,COALESCE($16::timestamp, LOCALTIMESTAMP),$17,$18)"#,
+                table = table,
+                columns = COLUMNS
+            );
+            if reactivate {
+                sql.push_str(&format!(
+                    r#" ON CONFLICT (domain_id, email) DO UPDATE SET {columns}, status_changed_at = NOW() WHERE {reactivatable}"#,
+                    columns = REACTIVATED_COLUMNS.map(|column| format!("{c} = EXCLUDED.{c}", c = column)).join(", "),
+                    reactivatable = reactivatable_sql("b.", "EXCLUDED.status")
+                ));
+            }
+            let mut params: Vec<&(dyn ToSql + Sync)> = vec![
+                &entry.domain_id,
+                &entry.email,
+                &entry.reason,
+                &entry.category,
+                &entry.expires_at,
+                &entry.bounce_type,
+                &entry.bounce_sub_type,
+                &entry.diagnostic_code,
+                &entry.diagnostic_class,
+                &entry.reason_summary,
+                &entry.subject,
+                &entry.reporting_mta,
+                &entry.remote_mta_ip,
+                &entry.source_arn,
+                &entry.sending_account_id,
+                &entry.created_at,
+                &entry.event_at,
+                &status,
+            ];
+
+            // one statement is atomic, the outbox row is inserted by a CTE off the entry
+            let payload = event.map(outbox::payload);
+            if let (Some(event), Some(payload)) = (event, &payload) {
+                sql = outbox::pg_with_entry(&sql);
+                params.push(&event.event_type);
+                params.push(payload);
+            }
+
+            match pg.execute(&sql, &params).await.map_err(|err| err.to_string())? {
+                // the conflicting entry still suppresses, read as the duplicate key it is
+                0 if reactivate => Err(format!("duplicate key: {} in domain {}", entry.email, entry.domain_id)),
+                _ => Ok(()),
+            }
+        }
+        // no outbox to write the event with, insert publishes it once the entry is stored
+        DBType::DynamoDB(store) => dynamodb::put_entry(store, entry, reactivate).await,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ManualEntry {
+    pub email: String,
+    pub reason: Option<String>,
+    // defaults to manual
+    pub category: Option<String>,
+    // RFC 3339, defaults to the domain suppression days of the category
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ManualEntry {
+    pub fn into_new_entry(self, domain_id: i32, settings: &DomainSettings) -> NewEntry {
+        let category = self.category.unwrap_or_else(|| CATEGORY_MANUAL.into());
+        let reason = self.reason.unwrap_or_else(|| CATEGORY_MANUAL.into());
+
+        NewEntry {
+            domain_id,
+            email: privacy::stored_email(&extract_email_address(self.email.trim())),
+            reason_summary: Some(truncate_summary(format!("Manual: {}", reason))),
+            reason,
+            expires_at: self
+                .expires_at
+                .map(|expires_at| expires_at.naive_utc())
+                .or_else(|| settings.default_expiry(&category)),
+            category,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub duplicates: usize,
+    pub failed: usize,
+    // the first failures, to diagnose a bad file without flooding the response
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportResponse {
+    pub success: bool,
+    pub data: ImportSummary,
+}
+
+pub fn import_config() -> web::JsonConfig {
+    web::JsonConfig::default().limit(IMPORT_LIMIT)
+}
+
+// an entry added by hand, ordered with the notifications of the recipient (see RecipientShards) so a bounce being
+// written does not interleave with it. Imports take one recipient at a time, taking every queue of a file would hold
+// up the notifications of the domain.
+pub async fn insert_manual(entry: &NewEntry, data: &web::Data<AppState>) -> Result<(), String> {
+    let _recipient = data.shards.lock(entry.domain_id, &entry.email).await;
+
+    insert(entry, "blacklist", data).await
+}
+
+pub async fn add_entry(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<ManualEntry>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain_id = path.into_inner();
+    let settings = domains::load(domain_id, &data).await;
+    let entry = body.into_inner().into_new_entry(domain_id, &settings);
+
+    match insert_manual(&entry, &data).await {
+        Ok(_) => HttpResponse::Created().json(StatusResponse::success()),
+        Err(err) if is_duplicate(&err) => HttpResponse::BadRequest()
+            .json(StatusResponse::fail(format!("blacklist entry already exists for: {}", entry.email))),
+        Err(err) => {
+            println!("Failed to execute query: {:?}", err);
+            HttpResponse::InternalServerError().json(StatusResponse::error(format!("{:?}", err)))
+        }
+    }
+}
+
+pub async fn import_entries(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<Vec<ManualEntry>>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain_id = path.into_inner();
+    let settings = domains::load(domain_id, &data).await;
+    let entries: Vec<NewEntry> = body
+        .into_inner()
+        .into_iter()
+        .map(|entry| entry.into_new_entry(domain_id, &settings))
+        .collect();
+
+    // large files would outlast the gateway timeouts, they are answered with a job to follow instead
+    if imports::wants_async(&req, entries.len()) {
+        return imports::enqueue(domain_id, entries, &data).await;
+    }
+
+    let mut summary = ImportSummary::default();
+
+    for entry in entries {
+        match insert_manual(&entry, &data).await {
+            Ok(_) => summary.inserted += 1,
+            Err(err) if is_duplicate(&err) => summary.duplicates += 1,
+            Err(err) => {
+                summary.failed += 1;
+                if summary.errors.len() < 10 {
+                    summary.errors.push(format!("{}: {}", entry.email, err));
+                }
+            }
+        }
+    }
+
+    println!("Imported blacklist entries for domain {}: {:?}", domain_id, summary);
+
+    HttpResponse::Ok().json(ImportResponse { success: true, data: summary })
+}
+
+// the entry of an address in one domain
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct DomainSuppression {
+    pub domain_id: i64,
+    pub domain_name: Option<String>,
+    pub status: String,
+    pub category: Option<String>,
+    pub reason_summary: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SuppressionQuery {
+    // a status to list, `all` for every entry, active by default
+    pub status: Option<String>,
+}
+
+// every domain that currently suppresses the address, for operators investigating a complaint,
+// ?status= lists the entries in another status of the lifecycle instead
+pub async fn lookup_all_domains(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<SuppressionQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let email = privacy::stored_email(&path.into_inner());
+    let status = match query.status.as_deref() {
+        None => Some(EntryStatus::Active.as_str()),
+        Some("all") => None,
+        Some(status) => match EntryStatus::parse(status) {
+            Some(status) => Some(status.as_str()),
+            None => {
+                return HttpResponse::BadRequest().json(ErrorResponse::new(format!("unknown status {:?}", status)));
+            }
+        },
+    };
+    let effective = effective_status_sql("b.");
+
+    let query_result: Result<Vec<DomainSuppression>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(&data, pool, |pool| {
+                let email = email.clone();
+                let effective = effective.clone();
+                async move {
+                    sqlx::query_as::<_, DomainSuppression>(&format!(
+                        r#"SELECT b.domain_id, d.name AS domain_name, {effective} AS status, b.category, b.reason_summary, b.expires_at, b.created_at
+                           FROM blacklist b LEFT JOIN domains d ON d.id = b.domain_id
+                           WHERE b.email = ? AND (? IS NULL OR {effective} = ?)
+                           ORDER BY b.domain_id"#,
+                        effective = effective
+                    ))
+                        .bind(email)
+                        .bind(status)
+                        .bind(status)
+                        .fetch_all(&pool)
+                        .await
+                }
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_read_client(&data).await {
+            Ok(client) => client
+                .query(
+                    &format!(
+                        r#"SELECT b.domain_id, d.name AS domain_name, {effective} AS status, b.category, b.reason_summary, b.expires_at, b.created_at
+                           FROM {table} b LEFT JOIN {domains} d ON d.id = b.domain_id
+                           WHERE b.email = $1 AND ($2::text IS NULL OR {effective} = $2)
+                           ORDER BY b.domain_id"#,
+                        effective = effective,
+                        table = table(),
+                        domains = env::var("PG_DOMAINS_TABLE").unwrap_or_else(|_| "domains".into())
+                    ),
+                    &[&email, &status],
+                )
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| DomainSuppression {
+                            domain_id: row.get::<_, i32>("domain_id") as i64,
+                            domain_name: row.get("domain_name"),
+                            status: row.get("status"),
+                            category: row.get("category"),
+                            reason_summary: row.get("reason_summary"),
+                            expires_at: row.get("expires_at"),
+                            created_at: row.get("created_at"),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    match query_result {
+        Ok(suppressions) => HttpResponse::Ok().json(ListResponse::new(suppressions)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct StatusChange {
+    pub status: EntryStatus,
+    // lifts the suppression even when EMAIL_VERIFICATION_MODE=block finds the mailbox undeliverable
+    #[serde(default)]
+    pub skip_verification: bool,
+}
+
+// moves an entry through its lifecycle, 409 when the transition is not allowed from the current status
+pub async fn set_status(
+    req: HttpRequest,
+    path: web::Path<(i32, String)>,
+    body: web::Json<StatusChange>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let (domain_id, email) = path.into_inner();
+    let to = body.status;
+
+    let warning = match verification::before_removal(&email, to, body.skip_verification).await {
+        Ok(warning) => warning,
+        Err(err) => return HttpResponse::UnprocessableEntity().json(ErrorResponse::new(err)),
+    };
+
+    match status::transition(domain_id, &privacy::stored_email(&email), to, &data).await {
+        Ok(from) => {
+            println!("Blacklist entry {} of domain {} moved from {} to {}", email, domain_id, from.as_str(), to.as_str());
+            HttpResponse::Ok().json(StatusResponse { message: warning, ..StatusResponse::success() })
+        }
+        Err(err @ TransitionError::NotFound) => HttpResponse::NotFound().json(ErrorResponse::new(err.to_string())),
+        Err(err @ TransitionError::Invalid { .. }) => HttpResponse::Conflict().json(ErrorResponse::new(err.to_string())),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResponse::new(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+    use std::time::Duration as StdDuration;
+
+    use super::*;
+    use crate::cache::LookupCache;
+    use crate::domain::ComplainedRecipient;
+    use crate::shards::RecipientShards;
+    use crate::rules::BounceRule;
+
+    const DOMAIN_ID: i32 = 1;
+
+    // EMAIL_HASH_KEY is read on every call, the tests setting it must not run beside the ones expecting plaintext
+    fn hashing(key: Option<&str>) -> MutexGuard<'static, ()> {
+        static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+        let guard = LOCK.get_or_init(Default::default).lock().unwrap_or_else(|err| err.into_inner());
+
+        match key {
+            Some(key) => env::set_var("EMAIL_HASH_KEY", key),
+            None => env::remove_var("EMAIL_HASH_KEY"),
+        }
+
+        guard
+    }
+
+    fn bounce(recipients: &[&str]) -> Bounce {
+        Bounce {
+            feedback_id: "feedback-1".into(),
+            bounce_type: "Permanent".into(),
+            bounce_sub_type: "General".into(),
+            bounced_recipients: recipients
+                .iter()
+                .map(|email| BouncedRecipient {
+                    email_address: email.to_string(),
+                    diagnostic_code: Some(format!("smtp; 550 5.1.1 <{}>: user unknown", email)),
+                    ..BouncedRecipient::default()
+                })
+                .collect(),
+            timestamp: Utc::now(),
+            ..Bounce::default()
+        }
+    }
+
+    fn complaint(recipients: &[&str]) -> Complaint {
+        Complaint {
+            feedback_id: "feedback-2".into(),
+            complained_recipients: recipients
+                .iter()
+                .map(|email| ComplainedRecipient { email_address: email.to_string() })
+                .collect(),
+            timestamp: Utc::now(),
+            complaint_feedback_type: Some("abuse".into()),
+            ..Complaint::default()
+        }
+    }
+
+    fn bounce_emails(recipients: &[&str]) -> Vec<String> {
+        let entries = bounce_entries(
+            DOMAIN_ID,
+            &bounce(recipients),
+            None,
+            "{}",
+            &DomainSettings::default(),
+            Utc::now().naive_utc(),
+        );
+
+        entries.into_iter().map(|entry| entry.email).collect()
+    }
+
+    fn complaint_emails(recipients: &[&str]) -> Vec<String> {
+        let entries = complaint_entries(DOMAIN_ID, &complaint(recipients), None, "{}", &DomainSettings::default()).unwrap();
+
+        entries.into_iter().map(|entry| entry.email).collect()
+    }
+
+    #[test]
+    fn repeated_recipients_are_written_once() {
+        let _env = hashing(None);
+        let recipients = ["jane@example.com", "john@example.com", "jane@example.com"];
+
+        assert_eq!(bounce_emails(&recipients), vec!["jane@example.com", "john@example.com"]);
+        assert_eq!(complaint_emails(&recipients), vec!["jane@example.com", "john@example.com"]);
+    }
+
+    #[test]
+    fn recipients_differing_in_case_are_one_address() {
+        let _env = hashing(None);
+        let recipients = ["Jane@Example.com", "jane@example.com", "Jane Doe <JANE@EXAMPLE.COM>", " jane@example.com "];
+
+        assert_eq!(bounce_emails(&recipients), vec!["jane@example.com"]);
+        assert_eq!(complaint_emails(&recipients), vec!["jane@example.com"]);
+    }
+
+    #[test]
+    fn hashed_recipients_are_written_once() {
+        let _env = hashing(Some("test-key"));
+        let recipients = ["jane@example.com", "JANE@example.com", "john@example.com", "jane@example.com"];
+        let expected = vec![privacy::stored_email("jane@example.com"), privacy::stored_email("john@example.com")];
+
+        assert!(expected.iter().all(|email| email.len() == 64 && !email.contains('@')));
+        assert_eq!(bounce_emails(&recipients), expected);
+        assert_eq!(complaint_emails(&recipients), expected);
+
+        // neither the reason nor the diagnostic code keeps the address
+        let entries = bounce_entries(
+            DOMAIN_ID,
+            &bounce(&recipients),
+            None,
+            r#"{"emailAddress":"jane@example.com"}"#,
+            &DomainSettings::default(),
+            Utc::now().naive_utc(),
+        );
+        for entry in &entries {
+            assert_eq!(entry.reason, "Permanent/General");
+            assert!(!entry.diagnostic_code.as_deref().unwrap_or_default().contains("@example.com"));
+        }
+
+        env::remove_var("EMAIL_HASH_KEY");
+    }
+
+    #[test]
+    fn rule_days_past_the_calendar_do_not_panic() {
+        let _env = hashing(None);
+        let rule = BounceRule {
+            bounce_type: None,
+            bounce_sub_type: None,
+            diagnostic_code: None,
+            diagnostic_class: None,
+            source_arn: None,
+            sending_account_id: None,
+            action: RuleAction::SuppressTemporarily { days: i64::MAX },
+        };
+        let settings = DomainSettings { bounce_rules: vec![rule], ..DomainSettings::default() };
+
+        let entries = bounce_entries(DOMAIN_ID, &bounce(&["jane@example.com"]), None, "{}", &settings, Utc::now().naive_utc());
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].expires_at, None);
+    }
+
+    // the writes fail at the connection, the url is never reachable
+    fn state() -> web::Data<AppState> {
+        web::Data::new(AppState {
+            db_type: DBType::Postgres,
+            db_url: "postgres://127.0.0.1:1/unused".into(),
+            read_pool: None,
+            read_db_url: None,
+            limiter: Arc::default(),
+            shards: Arc::new(RecipientShards::from_env()),
+            cache: Arc::new(LookupCache::from_env()),
+            events: events::channel(),
+            secondary: None,
+        })
+    }
+
+    #[actix_web::test]
+    async fn manual_write_waits_for_the_bounce_of_the_recipient() {
+        let data = state();
+        let manual = NewEntry {
+            domain_id: DOMAIN_ID,
+            email: "jane@example.com".into(),
+            category: CATEGORY_MANUAL.into(),
+            ..NewEntry::default()
+        };
+
+        // a bounce of the recipient being written, as notifications::suppress holds it
+        let bounce = data.shards.lock_all(DOMAIN_ID, ["Jane@Example.com", "john@example.com"].into_iter()).await;
+
+        let write = insert_manual(&manual, &data);
+        tokio::pin!(write);
+        assert!(tokio::time::timeout(StdDuration::from_millis(100), &mut write).await.is_err());
+
+        // another recipient is not held up
+        let other = NewEntry { email: "max@example.com".into(), ..manual.clone() };
+        assert!(tokio::time::timeout(StdDuration::from_secs(5), insert_manual(&other, &data)).await.is_ok());
+
+        drop(bounce);
+        assert!(tokio::time::timeout(StdDuration::from_secs(5), &mut write).await.is_ok());
+    }
+}
@@ -0,0 +1,286 @@
+use std::env;
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::api_keys;
+use crate::last_modified;
+use crate::repo::{build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::responses::ErrorResponse;
+use crate::AppState;
+
+const BATCH_SIZE: i64 = 10_000;
+const DEFAULT_FP_RATE: f64 = 0.01;
+const MIN_FP_RATE: f64 = 0.000_001;
+const MAX_FP_RATE: f64 = 0.5;
+const MAX_HASHES: u32 = 30;
+const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+// the file starts with MAGIC, VERSION, the number of hashes (u8), the number of bits (u64 BE) and of
+// addresses (u64 BE), then the bits: bit i is `bits[i / 8] & (1 << (i % 8))`
+const MAGIC: &[u8; 4] = b"SESB";
+const VERSION: u8 = 1;
+
+// (id, email) of a suppressed address
+type EmailRow = (i64, String);
+
+#[derive(Debug, Deserialize)]
+pub struct BloomQuery {
+    // the false positive rate the filter is sized for, BLOOM_FP_RATE (0.01) by default
+    pub fp_rate: Option<f64>,
+}
+
+struct BloomFilter {
+    bits: Vec<u8>,
+    bit_count: u64,
+    hashes: u32,
+    items: u64,
+}
+
+impl BloomFilter {
+    // the optimal size for `items` addresses: m = -n ln(p) / ln(2)^2 bits and k = m / n ln(2) hashes
+    fn with_rate(items: u64, fp_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = ((-(items.max(1) as f64) * fp_rate.ln()) / (ln2 * ln2)).ceil().max(8.0) as u64;
+        let hashes = ((bit_count as f64 / items.max(1) as f64) * ln2).round().clamp(1.0, MAX_HASHES as f64) as u32;
+
+        BloomFilter {
+            bits: vec![0; bit_count.div_ceil(8) as usize],
+            bit_count,
+            hashes,
+            items: 0,
+        }
+    }
+
+    fn insert(&mut self, email: &str) {
+        let (h1, h2) = hash_pair(email);
+
+        for i in 0..self.hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count;
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+
+        self.items += 1;
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(22 + self.bits.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.hashes as u8);
+        bytes.extend_from_slice(&self.bit_count.to_be_bytes());
+        bytes.extend_from_slice(&self.items.to_be_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+}
+
+// the two halves of the double hashing (Kirsch-Mitzenmacher): the first 16 bytes of SHA-256 of the trimmed,
+// lowercased address as two u64 BE, the i-th bit is (h1 + i * h2) mod bits. Clients hash the address the same way
+// (the HMAC hex digest when EMAIL_HASH_KEY is set), a hit is confirmed with the lookup API.
+fn hash_pair(email: &str) -> (u64, u64) {
+    let digest = Sha256::digest(email.trim().to_lowercase().as_bytes());
+    let h1 = u64::from_be_bytes(digest[0..8].try_into().expect("8 bytes"));
+    let h2 = u64::from_be_bytes(digest[8..16].try_into().expect("8 bytes"));
+
+    (h1, h2)
+}
+
+fn max_bytes() -> usize {
+    env::var("BLOOM_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BYTES)
+}
+
+fn table() -> String {
+    env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into())
+}
+
+const ACTIVE: &str = "status = 'active' AND (expires_at IS NULL OR expires_at > NOW())";
+
+async fn count(domain_id: i32, data: &AppState) -> Result<i64, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM blacklist WHERE domain_id = ? AND {}", ACTIVE))
+                    .bind(domain_id)
+                    .fetch_one(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(&format!("SELECT COUNT(*) FROM {} WHERE domain_id = $1 AND {}", table(), ACTIVE), &[&domain_id])
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// reads the suppressed addresses in id order, BATCH_SIZE at a time, so large domains are never held in memory at once
+async fn fill(filter: &mut BloomFilter, domain_id: i32, data: &AppState) -> Result<(), String> {
+    let mut after_id = 0;
+
+    match &data.db_type {
+        DBType::MySQL(pool) => loop {
+            let rows = read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, EmailRow>(&format!(
+                    "SELECT id, email FROM blacklist WHERE domain_id = ? AND id > ? AND {} ORDER BY id LIMIT ?",
+                    ACTIVE
+                ))
+                    .bind(domain_id)
+                    .bind(after_id)
+                    .bind(BATCH_SIZE)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())?;
+
+            rows.iter().for_each(|(_, email)| filter.insert(email));
+
+            match rows.last() {
+                Some((id, _)) if rows.len() as i64 == BATCH_SIZE => after_id = *id,
+                _ => return Ok(()),
+            }
+        },
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+            let sql = format!(
+                "SELECT id, email FROM {} WHERE domain_id = $1 AND id > $2 AND {} ORDER BY id LIMIT $3",
+                table(),
+                ACTIVE
+            );
+
+            loop {
+                let rows: Vec<EmailRow> = client
+                    .query(&sql, &[&domain_id, &after_id, &BATCH_SIZE])
+                    .await
+                    .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                    .map_err(|err| err.to_string())?;
+
+                rows.iter().for_each(|(_, email)| filter.insert(email));
+
+                match rows.last() {
+                    Some((id, _)) if rows.len() as i64 == BATCH_SIZE => after_id = *id,
+                    _ => return Ok(()),
+                }
+            }
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+fn etag(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    let hex: String = digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\"{}\"", hex)
+}
+
+// a Bloom filter of the domain's active suppressions, for senders to check addresses locally and only call the
+// lookup API on a hit. It has no false negatives at the time it is built, so it should be downloaded again
+// periodically; If-Modified-Since against the Last-Modified of the domain answers 304 before the filter is built,
+// the ETag makes an unchanged filter a 304 after.
+pub async fn bloom_handler(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<BloomQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    if let Err(response) = api_keys::meter(&req, &data, domain_id).await {
+        return response;
+    }
+
+    let fp_rate = query
+        .fp_rate
+        .or_else(|| env::var("BLOOM_FP_RATE").ok().and_then(|value| value.parse().ok()))
+        .unwrap_or(DEFAULT_FP_RATE);
+
+    if !(MIN_FP_RATE..=MAX_FP_RATE).contains(&fp_rate) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "fp_rate must be between {} and {}",
+            MIN_FP_RATE, MAX_FP_RATE
+        )));
+    }
+
+    // read before the entries, a change landing while the filter is built only makes the next request download again
+    let modified_at = match last_modified::modified_at(domain_id, &data).await {
+        Ok(modified_at) => modified_at,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    if let Some(modified_at) = modified_at.filter(|modified_at| last_modified::not_modified(&req, *modified_at)) {
+        return HttpResponse::NotModified().insert_header(last_modified::header(modified_at)).finish();
+    }
+
+    let items = match count(domain_id, &data).await {
+        Ok(items) => items.max(0) as u64,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    let mut filter = BloomFilter::with_rate(items, fp_rate);
+
+    if filter.bits.len() > max_bytes() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResponse::new(format!(
+            "a filter of {} addresses at fp_rate {} needs {} bytes, more than the {} allowed, ask for a higher fp_rate",
+            items,
+            fp_rate,
+            filter.bits.len(),
+            max_bytes()
+        )));
+    }
+
+    if let Err(err) = fill(&mut filter, domain_id, &data).await {
+        return HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+    }
+
+    let bytes = filter.to_bytes();
+    let etag = etag(&bytes);
+
+    let unchanged = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+
+    let mut response = if unchanged { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+    response.insert_header((header::ETAG, etag));
+
+    if let Some(modified_at) = modified_at {
+        response.insert_header(last_modified::header(modified_at));
+    }
+
+    if unchanged {
+        return response.finish();
+    }
+
+    response
+        .content_type("application/octet-stream")
+        .insert_header(("X-Bloom-Items", filter.items.to_string()))
+        .insert_header(("X-Bloom-Bits", filter.bit_count.to_string()))
+        .insert_header(("X-Bloom-Hashes", filter.hashes.to_string()))
+        .insert_header(("X-Bloom-FP-Rate", fp_rate.to_string()))
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"suppressions-{}.bloom\"", domain_id),
+        ))
+        .body(bytes)
+}
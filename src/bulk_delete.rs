@@ -0,0 +1,300 @@
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::{MySql, MySqlArguments};
+use sqlx::query::QueryAs;
+use tokio_postgres::types::ToSql;
+use utoipa::ToSchema;
+
+use crate::blacklist;
+use crate::diagnostics;
+use crate::handlers::is_admin;
+use crate::last_modified;
+use crate::privacy;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::ErrorResponse;
+use crate::AppState;
+
+const DEFAULT_BATCH_SIZE: i64 = 1000;
+
+// (id, email)
+type EntryRow = (i64, String);
+
+// the entries of the domain matching every given filter, at least one is required
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct BulkDeleteFilter {
+    pub category: Option<String>,
+    // RFC 3339
+    pub created_before: Option<DateTime<Utc>>,
+    // e.g. example.com, matches the part after the @ of the stored address
+    pub recipient_domain: Option<String>,
+    pub bounce_sub_type: Option<String>,
+    // e.g. no_such_user, see diagnostics::CLASSES
+    pub diagnostic_class: Option<String>,
+    // only counts the matching entries
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BulkDeleteSummary {
+    pub dry_run: bool,
+    // the entries matching the filters, removed unless dry_run
+    pub matched: i64,
+    pub deleted: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BulkDeleteResponse {
+    pub success: bool,
+    pub data: BulkDeleteSummary,
+}
+
+enum Value {
+    Text(String),
+    Time(NaiveDateTime),
+}
+
+// not a backslash, whose meaning in string literals differs between MySQL and Postgres
+const LIKE_ESCAPE: char = '!';
+
+// the value matched literally by LIKE ... ESCAPE LIKE_ESCAPE, "%" or "_" in a recipient domain is no wildcard
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if matches!(c, '%' | '_') || c == LIKE_ESCAPE {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+// the WHERE clause of the filters after the domain, with the values to bind in order.
+// `placeholder` renders the n-th placeholder, the domain is the first.
+fn conditions(filter: &BulkDeleteFilter, placeholder: impl Fn(usize) -> String) -> (String, Vec<Value>) {
+    let mut clauses = vec![format!("domain_id = {}", placeholder(1))];
+    let mut values = vec![];
+
+    let mut add = |clause: &str, value: Value| {
+        values.push(value);
+        clauses.push(clause.replace("{}", &placeholder(values.len() + 1)));
+    };
+
+    if let Some(category) = &filter.category {
+        add("category = {}", Value::Text(category.clone()));
+    }
+    if let Some(created_before) = filter.created_before {
+        add("created_at < {}", Value::Time(created_before.naive_utc()));
+    }
+    if let Some(recipient_domain) = &filter.recipient_domain {
+        // the ESCAPE character is LIKE_ESCAPE
+        let pattern = format!("%@{}", escape_like(&recipient_domain.trim().to_lowercase()));
+        add("email LIKE {} ESCAPE '!'", Value::Text(pattern));
+    }
+    if let Some(bounce_sub_type) = &filter.bounce_sub_type {
+        add("bounce_sub_type = {}", Value::Text(bounce_sub_type.clone()));
+    }
+    if let Some(diagnostic_class) = &filter.diagnostic_class {
+        add("diagnostic_class = {}", Value::Text(diagnostic_class.clone()));
+    }
+
+    (clauses.join(" AND "), values)
+}
+
+fn bind_all<'q, O>(
+    mut query: QueryAs<'q, MySql, O, MySqlArguments>,
+    values: &'q [Value],
+) -> QueryAs<'q, MySql, O, MySqlArguments> {
+    for value in values {
+        query = match value {
+            Value::Text(text) => query.bind(text),
+            Value::Time(time) => query.bind(time),
+        };
+    }
+
+    query
+}
+
+fn pg_params<'a>(domain_id: &'a i32, values: &'a [Value]) -> Vec<&'a (dyn ToSql + Sync)> {
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![domain_id];
+
+    for value in values {
+        params.push(match value {
+            Value::Text(text) => text,
+            Value::Time(time) => time,
+        });
+    }
+
+    params
+}
+
+async fn count(domain_id: i32, filter: &BulkDeleteFilter, data: &AppState) -> Result<i64, String> {
+    let table = blacklist::table_for(&data.db_type);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let (clause, values) = conditions(filter, |_| "?".into());
+            let sql = format!("SELECT COUNT(*) FROM {} WHERE {}", table, clause);
+
+            bind_all(sqlx::query_as::<_, (i64,)>(&sql).bind(domain_id), &values)
+                .fetch_one(pool)
+                .await
+                .map(|(count,)| count)
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let (clause, values) = conditions(filter, |n| format!("${}", n));
+
+            client
+                .query_one(&format!("SELECT COUNT(*) FROM {} WHERE {}", table, clause), &pg_params(&domain_id, &values))
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// deletes one batch of matching entries, returns the addresses removed
+async fn delete_batch(domain_id: i32, filter: &BulkDeleteFilter, batch_size: i64, data: &AppState) -> Result<Vec<String>, String> {
+    let table = blacklist::table_for(&data.db_type);
+
+    let rows: Vec<EntryRow> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            let (clause, values) = conditions(filter, |_| "?".into());
+            let sql = format!("SELECT id, email FROM {} WHERE {} ORDER BY id LIMIT {}", table, clause, batch_size);
+
+            bind_all(sqlx::query_as::<_, EntryRow>(&sql).bind(domain_id), &values)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())?
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let (clause, values) = conditions(filter, |n| format!("${}", n));
+            let sql = format!("SELECT id, email FROM {} WHERE {} ORDER BY id LIMIT {}", table, clause, batch_size);
+
+            client
+                .query(&sql, &pg_params(&domain_id, &values))
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                .map_err(|err| err.to_string())?
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    if rows.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let ids = rows.iter().map(|(id, _)| id.to_string()).collect::<Vec<_>>().join(",");
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(&format!("DELETE FROM {} WHERE domain_id = ? AND id IN ({})", table, ids))
+                .bind(domain_id)
+                .execute(pool)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(&format!("DELETE FROM {} WHERE domain_id = $1 AND id IN ({})", table, ids), &[&domain_id])
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    }
+
+    last_modified::touch(domain_id, data).await;
+
+    Ok(rows.into_iter().map(|(_, email)| email).collect())
+}
+
+// removes the matching entries in batches of BULK_DELETE_BATCH_SIZE, e.g. stale soft bounces,
+// with dry_run answering how many entries would go
+pub async fn bulk_delete_handler(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<BulkDeleteFilter>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain_id = path.into_inner();
+    let filter = body.into_inner();
+
+    if filter.category.is_none() && filter.created_before.is_none() && filter.recipient_domain.is_none() && filter.bounce_sub_type.is_none()
+        && filter.diagnostic_class.is_none()
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("at least one filter is required"));
+    }
+
+    if let Some(class) = filter.diagnostic_class.as_deref().filter(|class| !diagnostics::CLASSES.contains(class)) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("unknown diagnostic_class {:?}", class)));
+    }
+
+    if filter.recipient_domain.is_some() && privacy::hashing_enabled() {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("recipient_domain cannot match hashed addresses"));
+    }
+
+    let matched = match count(domain_id, &filter, &data).await {
+        Ok(matched) => matched,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    if filter.dry_run {
+        return HttpResponse::Ok().json(BulkDeleteResponse {
+            success: true,
+            data: BulkDeleteSummary { dry_run: true, matched, deleted: 0 },
+        });
+    }
+
+    let batch_size = env::var("BULK_DELETE_BATCH_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+        .max(1);
+    let mut deleted = 0;
+
+    loop {
+        let emails = match delete_batch(domain_id, &filter, batch_size, &data).await {
+            Ok(emails) => emails,
+            Err(err) => {
+                println!("🔥 Bulk delete for domain {} stopped after {} entries: {}", domain_id, deleted, err);
+                return HttpResponse::InternalServerError()
+                    .json(ErrorResponse::new(format!("🔥 Failed to query the database after deleting {} entries: {:?}", deleted, err)));
+            }
+        };
+
+        if emails.is_empty() {
+            break;
+        }
+
+        for email in &emails {
+            data.cache.evict(domain_id, email);
+        }
+
+        deleted += emails.len() as i64;
+    }
+
+    println!("Bulk deleted {} blacklist entries of domain {} matching {:?}", deleted, domain_id, filter);
+
+    HttpResponse::Ok().json(BulkDeleteResponse {
+        success: true,
+        data: BulkDeleteSummary { dry_run: false, matched, deleted },
+    })
+}
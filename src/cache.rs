@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::blacklist;
+use crate::handlers::is_admin;
+use crate::privacy;
+use crate::repo::EntryStatus;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::ErrorResponse;
+use crate::AppState;
+
+const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+// (domain_id, email as stored)
+type CacheKey = (i32, String);
+
+// (domain_id, email) of the warmup query
+type WarmRow = (i32, String);
+
+// caches the entry status of single lookups for LOOKUP_CACHE_TTL_SECS, off while 0 (the default). Suppressions written by this
+// instance are evicted right away, other instances' changes show up once the entry expires.
+#[derive(Debug)]
+pub struct LookupCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<CacheKey, (Option<EntryStatus>, Instant)>>,
+    // since startup, for the admin endpoint
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DomainCacheCount {
+    pub domain_id: i32,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub max_entries: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: f64,
+    // largest first
+    pub domains: Vec<DomainCacheCount>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CacheStatsResponse {
+    pub success: bool,
+    pub data: CacheStats,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CacheInvalidation {
+    pub evicted: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CacheInvalidationResponse {
+    pub success: bool,
+    pub data: CacheInvalidation,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InvalidationQuery {
+    // without both, the whole cache is cleared; an email needs its domain
+    pub domain_id: Option<i32>,
+    pub email: Option<String>,
+}
+
+impl LookupCache {
+    pub fn from_env() -> Self {
+        let number = |var: &str, default: u64| env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+
+        LookupCache {
+            ttl: Duration::from_secs(number("LOOKUP_CACHE_TTL_SECS", 0)),
+            max_entries: number("LOOKUP_CACHE_MAX_ENTRIES", DEFAULT_MAX_ENTRIES as u64) as usize,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.max_entries > 0
+    }
+
+    pub fn get(&self, domain_id: i32, email: &str) -> Option<Option<EntryStatus>> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let key = (domain_id, email.to_string());
+
+        let cached = match entries.get(&key) {
+            Some((status, expires)) if *expires > Instant::now() => Some(*status),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        };
+
+        let counter = if cached.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        cached
+    }
+
+    pub fn put(&self, domain_id: i32, email: &str, status: Option<EntryStatus>) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        // when full the expired entries go first, new results are not cached while it stays full
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, (_, expires)| *expires > now);
+        }
+        if entries.len() < self.max_entries {
+            entries.insert((domain_id, email.to_string()), (status, now + self.ttl));
+        }
+    }
+
+    pub fn evict(&self, domain_id: i32, email: &str) {
+        if self.enabled() {
+            self.entries.lock().unwrap().remove(&(domain_id, email.to_string()));
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    // every cached answer of the domain, returns how many were dropped
+    pub fn evict_domain(&self, domain_id: i32) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|(cached_domain, _), _| *cached_domain != domain_id);
+        before - entries.len()
+    }
+
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let cleared = entries.len();
+        entries.clear();
+        cleared
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut per_domain: HashMap<i32, usize> = HashMap::new();
+        let entries = {
+            let entries = self.entries.lock().unwrap();
+            for (domain_id, _) in entries.keys() {
+                *per_domain.entry(*domain_id).or_default() += 1;
+            }
+            entries.len()
+        };
+
+        let mut domains: Vec<DomainCacheCount> = per_domain
+            .into_iter()
+            .map(|(domain_id, entries)| DomainCacheCount { domain_id, entries })
+            .collect();
+        domains.sort_by(|a, b| b.entries.cmp(&a.entries).then(a.domain_id.cmp(&b.domain_id)));
+
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+
+        CacheStats {
+            enabled: self.enabled(),
+            ttl_secs: self.ttl.as_secs(),
+            max_entries: self.max_entries,
+            entries,
+            hits,
+            misses,
+            hit_ratio: if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 },
+            domains,
+        }
+    }
+}
+
+async fn recent_entries(db_type: &DBType, db_url: &str, per_domain: i64) -> Result<Vec<WarmRow>, String> {
+    let table = blacklist::table_for(db_type);
+
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, WarmRow>(&format!(
+                r#"SELECT domain_id, email FROM (
+                       SELECT domain_id, email, ROW_NUMBER() OVER (PARTITION BY domain_id ORDER BY created_at DESC) AS recency
+                       FROM {table} WHERE status = 'active' AND (expires_at IS NULL OR expires_at > NOW())
+                   ) recent WHERE recency <= ?"#,
+                table = table
+            ))
+                .bind(per_domain)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT domain_id, email FROM (
+                               SELECT domain_id, email, ROW_NUMBER() OVER (PARTITION BY domain_id ORDER BY created_at DESC) AS recency
+                               FROM {table} WHERE status = 'active' AND (expires_at IS NULL OR expires_at > NOW())
+                           ) recent WHERE recency <= $1"#,
+                        table = table
+                    ),
+                    &[&per_domain],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// LOOKUP_CACHE_WARMUP=N preloads the N most recent active suppressions of every domain before the server starts,
+// so a deploy during a campaign send does not start on a cold cache
+pub async fn warm(cache: &LookupCache, db_type: &DBType, db_url: &str) {
+    let per_domain: i64 = env::var("LOOKUP_CACHE_WARMUP").ok().and_then(|value| value.parse().ok()).unwrap_or(0);
+
+    if per_domain <= 0 || !cache.enabled() {
+        return;
+    }
+
+    match recent_entries(db_type, db_url, per_domain).await {
+        Ok(rows) => {
+            for (domain_id, email) in &rows {
+                cache.put(*domain_id, email, Some(EntryStatus::Active));
+            }
+            println!("✅ Warmed the lookup cache with {} suppressions", cache.size());
+        }
+        Err(err) => println!("🔥 Lookup cache warmup failed: {}", err),
+    }
+}
+
+// the cache of this instance only, every instance has its own
+pub async fn stats_handler(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    HttpResponse::Ok().json(CacheStatsResponse { success: true, data: data.cache.stats() })
+}
+
+// drops stale answers after the database was edited by hand: ?domain_id=1&email=a@b.c for one address,
+// ?domain_id=1 for a domain, nothing for the whole cache
+pub async fn invalidate_handler(
+    req: HttpRequest,
+    query: web::Query<InvalidationQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let evicted = match (query.domain_id, &query.email) {
+        (Some(domain_id), Some(email)) => {
+            let email = privacy::stored_email(email);
+            let cached = data.cache.entries.lock().unwrap().contains_key(&(domain_id, email.clone()));
+            data.cache.evict(domain_id, &email);
+            cached as usize
+        }
+        (Some(domain_id), None) => data.cache.evict_domain(domain_id),
+        (None, Some(_)) => {
+            return HttpResponse::BadRequest().json(ErrorResponse::new("email needs a domain_id"));
+        }
+        (None, None) => data.cache.clear(),
+    };
+
+    println!("Lookup cache invalidated: {} entries (domain {:?})", evicted, query.domain_id);
+
+    HttpResponse::Ok().json(CacheInvalidationResponse { success: true, data: CacheInvalidation { evicted } })
+}
@@ -0,0 +1,136 @@
+use std::env;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::events::{Event, LiveEvent};
+use crate::outbound;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_ATTEMPTS: u32 = 3;
+
+// optional analytics sink, CLICKHOUSE_URL=http://clickhouse:8123 streams every suppression event into
+// CLICKHOUSE_TABLE (see migrations/clickhouse) in batches of CLICKHOUSE_BATCH_SIZE or every CLICKHOUSE_FLUSH_SECS
+struct Config {
+    url: String,
+    table: String,
+    user: Option<String>,
+    password: Option<String>,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+fn config() -> Option<Config> {
+    let url = env::var("CLICKHOUSE_URL").ok().filter(|url| !url.is_empty())?;
+    let number = |var: &str, default: u64| env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+
+    Some(Config {
+        url,
+        table: env::var("CLICKHOUSE_TABLE").unwrap_or_else(|_| "suppression_events".into()),
+        user: env::var("CLICKHOUSE_USER").ok(),
+        password: env::var("CLICKHOUSE_PASSWORD").ok(),
+        batch_size: number("CLICKHOUSE_BATCH_SIZE", 1000).max(1) as usize,
+        flush_interval: Duration::from_secs(number("CLICKHOUSE_FLUSH_SECS", 5).max(1)),
+    })
+}
+
+async fn insert(client: &reqwest::Client, config: &Config, rows: &[LiveEvent]) -> Result<(), String> {
+    let body: String = rows
+        .iter()
+        .map(|row| serde_json::to_string(row).unwrap_or_default() + "\n")
+        .collect();
+
+    let mut request = client
+        .post(&config.url)
+        .query(&[
+            ("query", format!("INSERT INTO {} FORMAT JSONEachRow", config.table)),
+            // NaiveDateTime is serialized as ISO 8601
+            ("date_time_input_format", "best_effort".into()),
+        ])
+        .body(body);
+
+    if let Some(user) = &config.user {
+        request = request.header("X-ClickHouse-User", user);
+    }
+    if let Some(password) = &config.password {
+        request = request.header("X-ClickHouse-Key", password);
+    }
+
+    let response = request.send().await.map_err(|err| err.to_string())?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        let status = response.status();
+        Err(format!("{}: {}", status, response.text().await.unwrap_or_default()))
+    }
+}
+
+async fn flush(client: &reqwest::Client, config: &Config, batch: &mut Vec<LiveEvent>) {
+    if batch.is_empty() {
+        return;
+    }
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        match insert(client, config, batch).await {
+            Ok(()) => {
+                batch.clear();
+                return;
+            }
+            Err(err) if attempt < MAX_ATTEMPTS => {
+                println!("🔥 ClickHouse insert failed (attempt {}): {}", attempt, err);
+                tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+            }
+            Err(err) => println!("🔥 ClickHouse insert failed, dropping {} events: {}", batch.len(), err),
+        }
+    }
+
+    batch.clear();
+}
+
+// subscribes to the event bus and runs until the channel closes, a no-op without CLICKHOUSE_URL
+pub fn spawn(events: &broadcast::Sender<Event>) {
+    let Some(config) = config() else {
+        return;
+    };
+
+    println!("🚀 Streaming suppression events to ClickHouse table {}", config.table);
+
+    let mut receiver = events.subscribe();
+
+    tokio::spawn(async move {
+        let client = outbound::client_builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        let mut batch: Vec<LiveEvent> = Vec::with_capacity(config.batch_size);
+        let mut deadline = Instant::now() + config.flush_interval;
+
+        loop {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Ok(event)) => {
+                    let Some(event) = event.live() else {
+                        continue;
+                    };
+                    batch.push(event.clone());
+                    if batch.len() < config.batch_size {
+                        continue;
+                    }
+                }
+                Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                    println!("🔥 ClickHouse sink lagged, skipped {} events", skipped);
+                    continue;
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => {
+                    flush(&client, &config, &mut batch).await;
+                    return;
+                }
+                Err(_) => {}
+            }
+
+            flush(&client, &config, &mut batch).await;
+            deadline = Instant::now() + config.flush_interval;
+        }
+    });
+}
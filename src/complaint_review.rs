@@ -0,0 +1,362 @@
+use std::env;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+use crate::blacklist::{self, CATEGORY_COMPLAINT};
+use crate::domains;
+use crate::events::LiveEvent;
+use crate::handlers::is_admin;
+use crate::outbox;
+use crate::repo::{build_pg_pool, build_pg_read_client, dynamodb, read_mysql, status, status::TransitionError, DBType, EntryStatus};
+use crate::responses::{ErrorResponse, ListResponse, StatusResponse};
+use crate::AppState;
+
+// the event of a complaint entry held for review, the complaint event follows when it is approved
+pub const PENDING_EVENT: &str = "complaint_pending_review";
+
+const AUTO_APPROVE_EVERY: Duration = Duration::from_secs(60);
+const AUTO_APPROVE_BATCH: i64 = 100;
+const DEFAULT_LIMIT: i64 = 100;
+
+// (id, domain_id, email, reason_summary, created_at) of a complaint waiting for review
+type PendingRow = (i64, i64, String, Option<String>, NaiveDateTime);
+
+// COMPLAINT_REVIEW=true stores complaints as pending_review instead of suppressing at once, until support approves
+// (suppress) or rejects (ignore) them. COMPLAINT_REVIEW_RECIPIENT_DOMAINS, e.g. "vip-customer.com,partner.org",
+// limits the review to those recipients, the other complaints suppress as before. Complaints not reviewed within
+// COMPLAINT_REVIEW_AUTO_APPROVE_HOURS (48, 0 never) are approved, a complaint is never ignored by default.
+pub fn enabled() -> bool {
+    env::var("COMPLAINT_REVIEW").as_deref() == Ok("true")
+}
+
+fn auto_approve_hours() -> i64 {
+    env::var("COMPLAINT_REVIEW_AUTO_APPROVE_HOURS")
+        .ok()
+        .and_then(|hours| hours.parse().ok())
+        .unwrap_or(48)
+        .max(0)
+}
+
+// whether a complaint of the address is held for review
+pub fn holds(email: &str) -> bool {
+    if !enabled() {
+        return false;
+    }
+
+    let domains = env::var("COMPLAINT_REVIEW_RECIPIENT_DOMAINS").unwrap_or_default();
+    let mut domains = domains.split(',').map(|domain| domain.trim().to_lowercase()).filter(|domain| !domain.is_empty()).peekable();
+
+    if domains.peek().is_none() {
+        return true;
+    }
+
+    let recipient_domain = email.rsplit('@').next().unwrap_or_default().to_lowercase();
+    domains.any(|domain| domain == recipient_domain)
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PendingComplaint {
+    pub id: i64,
+    pub domain_id: i64,
+    pub email: String,
+    pub reason_summary: Option<String>,
+    pub created_at: NaiveDateTime,
+    // when it is approved unless reviewed before, absent when COMPLAINT_REVIEW_AUTO_APPROVE_HOURS=0
+    pub auto_approve_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PendingQuery {
+    pub domain_id: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+async fn pending(data: &web::Data<AppState>, domain_id: Option<i32>, limit: i64) -> Result<Vec<PendingRow>, String> {
+    let table = blacklist::table_for(&data.db_type);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let sql = format!(
+                r#"SELECT id, CAST(domain_id AS SIGNED), email, reason_summary, created_at FROM {table}
+                   WHERE status = 'pending_review' AND category = ? AND (? IS NULL OR domain_id = ?)
+                   ORDER BY id LIMIT ?"#,
+                table = table
+            );
+
+            read_mysql(data, pool, |pool| {
+                let sql = sql.clone();
+                async move {
+                    sqlx::query_as::<_, PendingRow>(&sql)
+                        .bind(CATEGORY_COMPLAINT)
+                        .bind(domain_id)
+                        .bind(domain_id)
+                        .bind(limit)
+                        .fetch_all(&pool)
+                        .await
+                }
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, domain_id::bigint, email, reason_summary, created_at FROM {table}
+                           WHERE status = 'pending_review' AND category = $1 AND ($2::int IS NULL OR domain_id = $2)
+                           ORDER BY id LIMIT $3"#,
+                        table = table
+                    ),
+                    &[&CATEGORY_COMPLAINT, &domain_id, &limit],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// the ids of the complaints waiting longer than the auto-approve timeout
+async fn overdue(data: &web::Data<AppState>, hours: i64) -> Result<Vec<i64>, String> {
+    let table = blacklist::table_for(&data.db_type);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query_scalar::<_, i64>(&format!(
+            r#"SELECT id FROM {table} WHERE status = 'pending_review' AND category = ? AND created_at <= NOW() - INTERVAL ? HOUR
+               ORDER BY id LIMIT ?"#,
+            table = table
+        ))
+            .bind(CATEGORY_COMPLAINT)
+            .bind(hours)
+            .bind(AUTO_APPROVE_BATCH)
+            .fetch_all(pool)
+            .await
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let hours = hours as i32;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id FROM {table} WHERE status = 'pending_review' AND category = $1
+                           AND created_at <= NOW() - make_interval(hours => $2) ORDER BY id LIMIT $3"#,
+                        table = table
+                    ),
+                    &[&CATEGORY_COMPLAINT, &hours, &AUTO_APPROVE_BATCH],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| row.get(0)).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// (domain_id, email, stored status) of an entry
+async fn find(data: &web::Data<AppState>, id: i64) -> Result<Option<(i32, String, String)>, String> {
+    let table = blacklist::table_for(&data.db_type);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, (i64, String, String)>(&format!(
+            r#"SELECT domain_id, email, status FROM {} WHERE id = ?"#,
+            table
+        ))
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.map(|(domain_id, email, status)| (domain_id as i32, email, status)))
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(&format!(r#"SELECT domain_id, email, status FROM {} WHERE id = $1"#, table), &[&id])
+                .await
+                .map(|row| row.map(|row| (row.get(0), row.get(1), row.get(2))))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// an approved complaint expires like one suppressed at once, counted from the approval
+async fn set_expiry(data: &web::Data<AppState>, id: i64, expires_at: Option<NaiveDateTime>) -> Result<(), String> {
+    let table = blacklist::table_for(&data.db_type);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(&format!(r#"UPDATE {} SET expires_at = ? WHERE id = ?"#, table))
+            .bind(expires_at)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(&format!(r#"UPDATE {} SET expires_at = $1 WHERE id = $2"#, table), &[&expires_at, &id])
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+#[derive(Debug)]
+enum ReviewError {
+    NotFound,
+    NotPending(String),
+    Transition(TransitionError),
+    Database(String),
+}
+
+// approve suppresses the address and publishes the complaint, reject removes the entry so the address is not
+// suppressed; both only apply to an entry still pending review
+async fn review(data: &web::Data<AppState>, id: i64, approve: bool) -> Result<(i32, String), ReviewError> {
+    let (domain_id, email, _) = find(data, id).await.map_err(ReviewError::Database)?.ok_or(ReviewError::NotFound)?;
+    // held until the expiry is set, so a bounce of the recipient is applied before or after the whole review
+    let _recipient = data.shards.lock(domain_id, &email).await;
+    let (_, _, stored) = find(data, id).await.map_err(ReviewError::Database)?.ok_or(ReviewError::NotFound)?;
+
+    // e.g. allowlisted through the status endpoint meanwhile, approving must not reactivate it
+    if stored != EntryStatus::PendingReview.as_str() {
+        return Err(ReviewError::NotPending(stored));
+    }
+
+    let to = if approve { EntryStatus::Active } else { EntryStatus::Removed };
+    status::transition_locked(domain_id, &email, to, data).await.map_err(ReviewError::Transition)?;
+
+    if approve {
+        let settings = domains::load(domain_id, data).await;
+        let expires_at = settings.default_expiry(CATEGORY_COMPLAINT);
+
+        if expires_at.is_some() {
+            set_expiry(data, id, expires_at).await.map_err(ReviewError::Database)?;
+            data.cache.evict(domain_id, &email);
+        }
+
+        let event = LiveEvent {
+            domain_id,
+            event_type: "complaint".into(),
+            email: email.clone(),
+            category: CATEGORY_COMPLAINT.into(),
+            expires_at,
+            timestamp: chrono::Utc::now().naive_utc(),
+        };
+
+        if let Err(err) = outbox::enqueue_committed(data, &event).await {
+            println!("🔥 Failed to publish the approved complaint {}: {}", id, err);
+        }
+    }
+
+    Ok((domain_id, email))
+}
+
+fn review_response(id: i64, result: Result<(i32, String), ReviewError>, action: &str) -> HttpResponse {
+    match result {
+        Ok((domain_id, email)) => {
+            println!("✅ Complaint entry {} ({} of domain {}) {}", id, email, domain_id, action);
+            HttpResponse::Ok().json(StatusResponse::success())
+        }
+        Err(ReviewError::NotFound) | Err(ReviewError::Transition(TransitionError::NotFound)) => {
+            HttpResponse::NotFound().json(ErrorResponse::new(format!("blacklist entry {} not found", id)))
+        }
+        Err(ReviewError::NotPending(status)) => {
+            HttpResponse::Conflict().json(ErrorResponse::new(format!("entry {} is {}, not pending review", id, status)))
+        }
+        Err(ReviewError::Transition(err @ TransitionError::Invalid { .. })) => {
+            HttpResponse::Conflict().json(ErrorResponse::new(err.to_string()))
+        }
+        Err(ReviewError::Transition(err)) => HttpResponse::InternalServerError().json(ErrorResponse::new(err.to_string())),
+        Err(ReviewError::Database(err)) => {
+            HttpResponse::InternalServerError().json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)))
+        }
+    }
+}
+
+// the complaints waiting for review, oldest first
+pub async fn list_handler(req: HttpRequest, query: web::Query<PendingQuery>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, 1000);
+    let hours = auto_approve_hours();
+
+    match pending(&data, query.domain_id, limit).await {
+        Ok(rows) => HttpResponse::Ok().json(ListResponse::new(
+            rows.into_iter()
+                .map(|(id, domain_id, email, reason_summary, created_at)| PendingComplaint {
+                    id,
+                    domain_id,
+                    email,
+                    reason_summary,
+                    created_at,
+                    auto_approve_at: (hours > 0).then(|| created_at + chrono::Duration::hours(hours)),
+                })
+                .collect(),
+        )),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+pub async fn approve_handler(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let id = path.into_inner();
+    review_response(id, review(&data, id, true).await, "approved")
+}
+
+pub async fn reject_handler(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let id = path.into_inner();
+    review_response(id, review(&data, id, false).await, "rejected")
+}
+
+// approves the complaints left unreviewed past COMPLAINT_REVIEW_AUTO_APPROVE_HOURS, every minute
+pub fn start(data: web::Data<AppState>) -> Option<JoinHandle<()>> {
+    let hours = auto_approve_hours();
+
+    if !enabled() || hours == 0 {
+        return None;
+    }
+
+    println!("🚀 Complaint review auto-approval started, after {} hours", hours);
+
+    Some(tokio::spawn(async move {
+        loop {
+            match overdue(&data, hours).await {
+                Ok(ids) => {
+                    for id in ids {
+                        match review(&data, id, true).await {
+                            Ok((domain_id, email)) => {
+                                println!("✅ Complaint entry {} ({} of domain {}) auto-approved", id, email, domain_id)
+                            }
+                            Err(err) => println!("🔥 Failed to auto-approve complaint entry {}: {:?}", id, err),
+                        }
+                    }
+                }
+                Err(err) => println!("🔥 Failed to look up the complaints to auto-approve: {}", err),
+            }
+
+            tokio::time::sleep(AUTO_APPROVE_EVERY).await;
+        }
+    }))
+}
@@ -0,0 +1,94 @@
+use std::env;
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, ContentEncoding, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::Error;
+
+// single lookups stay well below this, exports and lists go above
+const DEFAULT_MIN_BYTES: u64 = 1024;
+
+// COMPRESSION_ALGORITHMS lists the encodings to offer in order of preference, e.g. gzip to save CPU or off.
+// The levels are the ones of the Compress middleware: fast gzip and a low brotli quality.
+fn algorithms() -> Vec<String> {
+    env::var("COMPRESSION_ALGORITHMS")
+        .unwrap_or_else(|_| "br,gzip".into())
+        .split(',')
+        .map(|algorithm| algorithm.trim().to_ascii_lowercase())
+        .filter(|algorithm| matches!(algorithm.as_str(), "br" | "gzip" | "deflate" | "zstd"))
+        .collect()
+}
+
+pub fn enabled() -> bool {
+    !algorithms().is_empty()
+}
+
+// COMPRESSION_MIN_BYTES, responses of a known smaller size are sent as they are
+fn min_bytes() -> u64 {
+    env::var("COMPRESSION_MIN_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MIN_BYTES)
+}
+
+// whether the Accept-Encoding value allows the encoding, an explicit q=0 refuses it
+fn accepts(accept_encoding: &str, algorithm: &str) -> bool {
+    let mut wildcard = false;
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let refused = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .any(|quality| quality.trim().parse::<f32>().is_ok_and(|quality| quality <= 0.0));
+
+        if name == algorithm {
+            return !refused;
+        }
+
+        if name == "*" {
+            wildcard = !refused;
+        }
+    }
+
+    wildcard
+}
+
+// picks the preferred configured encoding the client accepts and leaves only that one for the Compress middleware,
+// so the server preference wins over the order of the client
+pub async fn negotiate(mut req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let accept_encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let chosen = algorithms()
+        .into_iter()
+        .find(|algorithm| accepts(&accept_encoding, algorithm))
+        .unwrap_or_else(|| "identity".into());
+
+    if let Ok(value) = HeaderValue::from_str(&chosen) {
+        req.headers_mut().insert(header::ACCEPT_ENCODING, value);
+    }
+
+    next.call(req).await
+}
+
+// runs inside the Compress middleware, marks small responses so they skip it. Streamed responses like the exports
+// have no known size and are always compressed.
+pub async fn skip_small(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut response = next.call(req).await?;
+
+    let small = matches!(response.response().body().size(), BodySize::Sized(size) if size < min_bytes());
+
+    if small && !response.headers().contains_key(header::CONTENT_ENCODING) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, ContentEncoding::Identity.to_header_value());
+    }
+
+    Ok(response)
+}
@@ -0,0 +1,107 @@
+use std::env;
+
+// statements executed on every new connection, separated by `;` outside quotes, e.g. MYSQL_INIT_SQL="SET time_zone = '+00:00'"
+pub fn init_statements(var: &str) -> Vec<String> {
+    split_statements(&env::var(var).unwrap_or_default())
+}
+
+// splits on the `;` outside quotes, so a string literal or a quoted identifier may hold one. A doubled quote
+// closes and reopens the literal, which keeps the split right.
+fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+
+    for ch in sql.chars() {
+        match (quote, ch) {
+            (None, ';') => {
+                statements.push(std::mem::take(&mut current));
+                continue;
+            }
+            (None, '\'' | '"' | '`') => quote = Some(ch),
+            (Some(open), _) if open == ch => quote = None,
+            _ => {}
+        }
+        current.push(ch);
+    }
+    statements.push(current);
+
+    statements
+        .into_iter()
+        .map(|stmt| stmt.trim().to_string())
+        .filter(|stmt| !stmt.is_empty())
+        .collect()
+}
+
+// value following a `--name` command line flag
+pub fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
+
+// maintenance commands dispatched from main instead of starting the server
+pub fn is_command(args: &[String]) -> bool {
+    matches!(
+        args.get(1).map(String::as_str),
+        Some(
+            "rebuild-blacklist" | "backfill-reasons" | "archive-reasons" | "hash-emails" | "consistency-check" | "indexes"
+                | "bench-parse" | "decrypt-payload"
+        )
+    ) || args.iter().any(|arg| arg == "--self-test")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn init_statements_are_split_and_trimmed() {
+        env::set_var("TEST_CONFIG_INIT_SQL", " SET time_zone = '+00:00';; SET NAMES utf8mb4 ; ");
+
+        assert_eq!(
+            init_statements("TEST_CONFIG_INIT_SQL"),
+            vec!["SET time_zone = '+00:00'".to_string(), "SET NAMES utf8mb4".to_string()]
+        );
+        assert!(init_statements("TEST_CONFIG_INIT_SQL_UNSET").is_empty());
+    }
+
+    #[test]
+    fn quoted_semicolons_do_not_split_statements() {
+        assert_eq!(
+            split_statements("SET @note = 'a;b'; SET sql_mode = \"ANSI;QUOTES\"; SET @it = 'it''s; fine'; SELECT `a;b`"),
+            vec![
+                "SET @note = 'a;b'".to_string(),
+                "SET sql_mode = \"ANSI;QUOTES\"".to_string(),
+                "SET @it = 'it''s; fine'".to_string(),
+                "SELECT `a;b`".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn arg_value_follows_its_flag() {
+        let args = args(&["aws-ses-bounce", "rebuild-blacklist", "--batch", "500", "--dry-run"]);
+
+        assert_eq!(arg_value(&args, "--batch").as_deref(), Some("500"));
+        assert_eq!(arg_value(&args, "--dry-run"), None);
+        assert_eq!(arg_value(&args, "--domain"), None);
+    }
+
+    #[test]
+    fn commands_are_recognised() {
+        assert!(is_command(&args(&["aws-ses-bounce", "rebuild-blacklist"])));
+        assert!(is_command(&args(&["aws-ses-bounce", "indexes", "--apply"])));
+        assert!(is_command(&args(&["aws-ses-bounce", "--self-test"])));
+
+        assert!(!is_command(&args(&["aws-ses-bounce"])));
+        assert!(!is_command(&args(&["aws-ses-bounce", "serve"])));
+        // only the first argument names a command
+        assert!(!is_command(&args(&["aws-ses-bounce", "--port", "indexes"])));
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_postgres::types::ToSql;
+use utoipa::ToSchema;
+
+use crate::api_keys;
+use crate::blacklist::{self, CATEGORY_COMPLAINT, CATEGORY_HARD_BOUNCE, CATEGORY_MANUAL, CATEGORY_SOFT_BOUNCE};
+use crate::domains;
+use crate::notification_log;
+use crate::repo::{build_pg_pool, build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::responses::{ErrorResponse, ListResponse};
+use crate::AppState;
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(3600);
+const DEFAULT_RANGE_DAYS: i64 = 30;
+const MAX_RANGE_DAYS: i64 = 3660;
+
+// (domain_id, key, count), the key is a category or a notification type
+type GroupedRow = (i64, Option<String>, i64);
+
+// DAILY_STATS=true snapshots every domain once an hour into daily_stats, recomputing today and the
+// DAILY_STATS_BACKFILL_DAYS (1) days before it, so a finished day gets its final numbers after midnight
+pub fn enabled() -> bool {
+    env::var("DAILY_STATS").as_deref() == Ok("true")
+}
+
+fn backfill_days() -> i64 {
+    env::var("DAILY_STATS_BACKFILL_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(1)
+        .max(0)
+}
+
+pub fn table() -> String {
+    env::var("PG_DAILY_STATS_TABLE").unwrap_or_else(|_| "daily_stats".into())
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct DailyStats {
+    pub domain_id: i32,
+    // in the domain's reporting timezone, UTC by default
+    pub day: NaiveDate,
+    // active entries created by the end of the day
+    pub blacklist_size: i64,
+    pub hard_bounces: i64,
+    pub soft_bounces: i64,
+    pub complaints: i64,
+    pub manual: i64,
+    pub bounce_notifications: i64,
+    // only logged when SES publishes deliveries for the identity
+    pub delivery_notifications: i64,
+    // bounce notifications per delivery notification, 0 without deliveries
+    pub bounce_rate: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DailyStatsQuery {
+    // inclusive, the last 30 days by default
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+// runs a `SELECT domain_id, key, count` query with the timestamps bound in order.
+// Both SQL variants cast the domain to a 64 bit integer.
+async fn grouped(db_type: &DBType, db_url: &str, mysql: &str, postgres: &str, params: &[NaiveDateTime]) -> Result<Vec<GroupedRow>, String> {
+    match db_type {
+        DBType::MySQL(pool) => {
+            let mut query = sqlx::query_as::<_, GroupedRow>(mysql);
+
+            for param in params {
+                query = query.bind(param);
+            }
+
+            query.fetch_all(pool).await.map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|param| param as &(dyn ToSql + Sync)).collect();
+
+            client
+                .query(postgres, &params)
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+fn entry(stats: &mut HashMap<i64, DailyStats>, domain_id: i64, day: NaiveDate) -> &mut DailyStats {
+    stats.entry(domain_id).or_insert_with(|| DailyStats { domain_id: domain_id as i32, day, ..Default::default() })
+}
+
+// the stats of every domain for `day` spanning [start, end) in UTC
+async fn compute(db_type: &DBType, db_url: &str, day: NaiveDate, start: NaiveDateTime, end: NaiveDateTime) -> Result<Vec<DailyStats>, String> {
+    let blacklist = blacklist::table_for(db_type);
+    let log = notification_log::table();
+
+    let suppressions = grouped(
+        db_type,
+        db_url,
+        "SELECT CAST(domain_id AS SIGNED), category, COUNT(*) FROM blacklist WHERE created_at >= ? AND created_at < ? GROUP BY domain_id, category",
+        &format!("SELECT domain_id::bigint, category, COUNT(*) FROM {} WHERE created_at >= $1 AND created_at < $2 GROUP BY domain_id, category", blacklist),
+        &[start, end],
+    )
+        .await?;
+
+    let sizes = grouped(
+        db_type,
+        db_url,
+        "SELECT CAST(domain_id AS SIGNED), CAST(NULL AS CHAR), COUNT(*) FROM blacklist WHERE created_at < ? AND status = 'active' GROUP BY domain_id",
+        &format!("SELECT domain_id::bigint, NULL::text, COUNT(*) FROM {} WHERE created_at < $1 AND status = 'active' GROUP BY domain_id", blacklist),
+        &[end],
+    )
+        .await?;
+
+    let notifications = grouped(
+        db_type,
+        db_url,
+        "SELECT CAST(domain_id AS SIGNED), notification_type, COUNT(*) FROM notification_log WHERE received_at >= ? AND received_at < ? AND notification_type IN ('Bounce', 'Delivery') GROUP BY domain_id, notification_type",
+        &format!("SELECT domain_id::bigint, notification_type, COUNT(*) FROM {} WHERE received_at >= $1 AND received_at < $2 AND notification_type IN ('Bounce', 'Delivery') GROUP BY domain_id, notification_type", log),
+        &[start, end],
+    )
+        .await?;
+
+    let mut stats: HashMap<i64, DailyStats> = HashMap::new();
+
+    for (domain_id, category, count) in suppressions {
+        let stats = entry(&mut stats, domain_id, day);
+
+        match category.as_deref() {
+            Some(CATEGORY_HARD_BOUNCE) => stats.hard_bounces += count,
+            Some(CATEGORY_SOFT_BOUNCE) => stats.soft_bounces += count,
+            Some(CATEGORY_COMPLAINT) => stats.complaints += count,
+            Some(CATEGORY_MANUAL) => stats.manual += count,
+            _ => {}
+        }
+    }
+
+    for (domain_id, _, count) in sizes {
+        entry(&mut stats, domain_id, day).blacklist_size = count;
+    }
+
+    for (domain_id, notification_type, count) in notifications {
+        let stats = entry(&mut stats, domain_id, day);
+
+        match notification_type.as_deref() {
+            Some("Bounce") => stats.bounce_notifications = count,
+            Some("Delivery") => stats.delivery_notifications = count,
+            _ => {}
+        }
+    }
+
+    Ok(stats
+        .into_values()
+        .map(|mut stats| {
+            if stats.delivery_notifications > 0 {
+                stats.bounce_rate = stats.bounce_notifications as f64 / stats.delivery_notifications as f64;
+            }
+            stats
+        })
+        .collect())
+}
+
+async fn store(db_type: &DBType, db_url: &str, stats: &DailyStats) -> Result<(), String> {
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"INSERT INTO daily_stats (domain_id, day, blacklist_size, hard_bounces, soft_bounces, complaints, manual,
+                       bounce_notifications, delivery_notifications, bounce_rate, computed_at)
+                   VALUES (?,?,?,?,?,?,?,?,?,?,NOW())
+                   ON DUPLICATE KEY UPDATE blacklist_size = VALUES(blacklist_size), hard_bounces = VALUES(hard_bounces),
+                       soft_bounces = VALUES(soft_bounces), complaints = VALUES(complaints), manual = VALUES(manual),
+                       bounce_notifications = VALUES(bounce_notifications), delivery_notifications = VALUES(delivery_notifications),
+                       bounce_rate = VALUES(bounce_rate), computed_at = VALUES(computed_at)"#,
+            )
+                .bind(stats.domain_id)
+                .bind(stats.day)
+                .bind(stats.blacklist_size)
+                .bind(stats.hard_bounces)
+                .bind(stats.soft_bounces)
+                .bind(stats.complaints)
+                .bind(stats.manual)
+                .bind(stats.bounce_notifications)
+                .bind(stats.delivery_notifications)
+                .bind(stats.bounce_rate)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, day, blacklist_size, hard_bounces, soft_bounces, complaints, manual,
+                               bounce_notifications, delivery_notifications, bounce_rate, computed_at)
+                           VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,NOW())
+                           ON CONFLICT (domain_id, day) DO UPDATE SET blacklist_size = EXCLUDED.blacklist_size,
+                               hard_bounces = EXCLUDED.hard_bounces, soft_bounces = EXCLUDED.soft_bounces,
+                               complaints = EXCLUDED.complaints, manual = EXCLUDED.manual,
+                               bounce_notifications = EXCLUDED.bounce_notifications,
+                               delivery_notifications = EXCLUDED.delivery_notifications,
+                               bounce_rate = EXCLUDED.bounce_rate, computed_at = EXCLUDED.computed_at"#,
+                        table = table()
+                    ),
+                    &[
+                        &stats.domain_id,
+                        &stats.day,
+                        &stats.blacklist_size,
+                        &stats.hard_bounces,
+                        &stats.soft_bounces,
+                        &stats.complaints,
+                        &stats.manual,
+                        &stats.bounce_notifications,
+                        &stats.delivery_notifications,
+                        &stats.bounce_rate,
+                    ],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// the days of a domain are those of its reporting timezone: one pass in UTC for the domains without one, then a
+// pass per timezone in use, each keeping only the domains of its timezone
+pub async fn snapshot(db_type: &DBType, db_url: &str) -> Result<usize, String> {
+    let timezones = domains::reporting_timezones(db_type, db_url).await?;
+    let mut passes: Vec<Option<Tz>> = vec![None];
+
+    for tz in timezones.values() {
+        if !passes.contains(&Some(*tz)) {
+            passes.push(Some(*tz));
+        }
+    }
+
+    let mut stored = 0;
+
+    for tz in passes {
+        let today = domains::today(tz);
+
+        for days_ago in (0..=backfill_days()).rev() {
+            let day = today - chrono::Duration::days(days_ago);
+            let (start, end) = domains::day_bounds(tz, day);
+
+            for stats in compute(db_type, db_url, day, start, end).await? {
+                if timezones.get(&i64::from(stats.domain_id)).copied() != tz {
+                    continue;
+                }
+
+                store(db_type, db_url, &stats).await?;
+                stored += 1;
+            }
+        }
+    }
+
+    Ok(stored)
+}
+
+pub async fn start(db_type: &DBType, db_url: &str) -> Option<JoinHandle<()>> {
+    if !enabled() {
+        return None;
+    }
+
+    let db_type = db_type.clone();
+    let db_url = db_url.to_string();
+
+    Some(tokio::spawn(async move {
+        loop {
+            match snapshot(&db_type, &db_url).await {
+                Ok(stored) => println!("✅ Stored {} daily stats snapshots", stored),
+                Err(err) => println!("🔥 Daily stats snapshot failed: {}", err),
+            }
+
+            tokio::time::sleep(SNAPSHOT_INTERVAL).await;
+        }
+    }))
+}
+
+async fn query(domain_id: i32, from: NaiveDate, to: NaiveDate, data: &web::Data<AppState>) -> Result<Vec<DailyStats>, String> {
+    let columns = "domain_id, day, blacklist_size, hard_bounces, soft_bounces, complaints, manual, bounce_notifications, delivery_notifications, bounce_rate";
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let sql = format!("SELECT {} FROM daily_stats WHERE domain_id = ? AND day >= ? AND day <= ? ORDER BY day", columns);
+
+            read_mysql(data, pool, |pool| {
+                let sql = sql.clone();
+                async move {
+                    sqlx::query(&sql)
+                        .bind(domain_id)
+                        .bind(from)
+                        .bind(to)
+                        .fetch_all(&pool)
+                        .await
+                }
+            })
+                .await
+                .map(|rows| {
+                    use sqlx::Row;
+
+                    rows.iter()
+                        .map(|row| DailyStats {
+                            domain_id: row.get(0),
+                            day: row.get(1),
+                            blacklist_size: row.get(2),
+                            hard_bounces: row.get(3),
+                            soft_bounces: row.get(4),
+                            complaints: row.get(5),
+                            manual: row.get(6),
+                            bounce_notifications: row.get(7),
+                            delivery_notifications: row.get(8),
+                            bounce_rate: row.get(9),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!("SELECT {} FROM {} WHERE domain_id = $1 AND day >= $2 AND day <= $3 ORDER BY day", columns, table()),
+                    &[&domain_id, &from, &to],
+                )
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| DailyStats {
+                            domain_id: row.get(0),
+                            day: row.get(1),
+                            blacklist_size: row.get(2),
+                            hard_bounces: row.get(3),
+                            soft_bounces: row.get(4),
+                            complaints: row.get(5),
+                            manual: row.get(6),
+                            bounce_notifications: row.get(7),
+                            delivery_notifications: row.get(8),
+                            bounce_rate: row.get(9),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// the stored snapshots of the domain for charting, one point per day that had activity
+pub async fn daily_stats_handler(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query_params: web::Query<DailyStatsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    if let Err(response) = api_keys::meter(&req, &data, domain_id).await {
+        return response;
+    }
+
+    // the days are those of the domain's reporting timezone, so is today
+    let to = match query_params.to {
+        Some(to) => to,
+        None => domains::today(domains::load(domain_id, &data).await.reporting_timezone),
+    };
+    let from = query_params.from.unwrap_or(to - chrono::Duration::days(DEFAULT_RANGE_DAYS - 1));
+
+    if from > to || (to - from).num_days() >= MAX_RANGE_DAYS {
+        return HttpResponse::BadRequest()
+            .json(ErrorResponse::new(format!("from must not be after to and the range at most {} days", MAX_RANGE_DAYS)));
+    }
+
+    match query(domain_id, from, to, &data).await {
+        Ok(stats) => HttpResponse::Ok().json(ListResponse::new(stats)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
@@ -0,0 +1,282 @@
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::domain::DeadLetter;
+use crate::redaction;
+use crate::payload::parse;
+use crate::responses::{ErrorResponse, ListResponse, ReplayOutcome, ReplayResponse};
+use crate::handlers::is_admin;
+use crate::repo::{build_pg_pool, build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::services::notifications::{process_notification, to_completion};
+use crate::AppState;
+
+const LIST_LIMIT: i64 = 100;
+
+fn pg_table() -> String {
+    env::var("PG_DEAD_LETTERS_TABLE").unwrap_or_else(|_| "dead_letters".into())
+}
+
+fn from_pg_row(row: &tokio_postgres::Row) -> DeadLetter {
+    DeadLetter {
+        id: row.get("id"),
+        domain_id: row.get::<_, i32>("domain_id") as i64,
+        payload: row.get("payload"),
+        error: row.get("error"),
+        created_at: row.get("created_at"),
+        replayed_at: row.get("replayed_at"),
+        replay_status: row.get("replay_status"),
+        replay_error: row.get("replay_error"),
+        encrypted_payload: row.get("encrypted_payload"),
+    }
+}
+
+// keeps a payload that could not be parsed so it can be replayed once the parsing is fixed, redacted and sealed
+// with DEAD_LETTER_PUBLIC_KEY, see redaction
+// UNVERIFIED_DEAD_LETTERS_PER_MINUTE (default 60, 0 stores none) bounds the unauthenticated deliveries stored per
+// instance, raw ones or all of them with SNS_VERIFY_SIGNATURES=false: anyone can send those, they must not fill
+// the table. Over the limit they are refused instead.
+pub fn admit_unverified() -> bool {
+    static WINDOW: OnceLock<Mutex<(Instant, u64)>> = OnceLock::new();
+
+    let limit = env::var("UNVERIFIED_DEAD_LETTERS_PER_MINUTE").ok().and_then(|value| value.parse().ok()).unwrap_or(60);
+    let mut window = WINDOW.get_or_init(|| Mutex::new((Instant::now(), 0))).lock().unwrap();
+
+    if window.0.elapsed() >= Duration::from_secs(60) {
+        *window = (Instant::now(), 0);
+    }
+    if window.1 >= limit {
+        return false;
+    }
+
+    window.1 += 1;
+    true
+}
+
+pub async fn store(domain_id: i32, payload: &str, error: &str, data: &web::Data<AppState>) {
+    let (payload, encrypted_payload) = redaction::dead_letter_payload(payload);
+
+    let query_result: Result<(), String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"INSERT INTO dead_letters (domain_id, payload, error, encrypted_payload) VALUES (?,?,?,?)"#)
+                .bind(domain_id)
+                .bind(&payload)
+                .bind(error)
+                .bind(&encrypted_payload)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, payload, error, encrypted_payload) VALUES ($1,$2,$3,$4)"#,
+                        table = pg_table()
+                    ),
+                    &[&domain_id, &payload, &error, &encrypted_payload],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    if let Err(err) = query_result {
+        println!("🔥 Failed to store dead letter for domain {}: {:?}", domain_id, err);
+    }
+}
+
+async fn find(id: i64, data: &web::Data<AppState>) -> Result<Option<DeadLetter>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, DeadLetter>(r#"SELECT * FROM dead_letters WHERE id = ?"#)
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(&format!(r#"SELECT * FROM {table} WHERE id = $1"#, table = pg_table()), &[&id])
+                .await
+                .map(|row| row.as_ref().map(from_pg_row))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn record_replay(id: i64, status: &str, error: Option<&str>, data: &web::Data<AppState>) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"UPDATE dead_letters SET replayed_at = NOW(), replay_status = ?, replay_error = ? WHERE id = ?"#)
+                .bind(status)
+                .bind(error)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET replayed_at = NOW(), replay_status = $1, replay_error = $2 WHERE id = $3"#,
+                        table = pg_table()
+                    ),
+                    &[&status, &error, &id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+pub async fn list_dead_letters(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let query_result: Result<Vec<DeadLetter>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(&data, pool, |pool| async move {
+                sqlx::query_as::<_, DeadLetter>(r#"SELECT * FROM dead_letters ORDER BY id DESC LIMIT ?"#)
+                    .bind(LIST_LIMIT)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_read_client(&data).await {
+            Ok(client) => client
+                .query(
+                    &format!(r#"SELECT * FROM {table} ORDER BY id DESC LIMIT $1"#, table = pg_table()),
+                    &[&LIST_LIMIT],
+                )
+                .await
+                .map(|rows| rows.iter().map(from_pg_row).collect())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    match query_result {
+        Ok(dead_letters) => HttpResponse::Ok().json(ListResponse::new(dead_letters)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReplayRequest {
+    // RFC 6902 JSON patch applied to the stored SNS payload before it is processed again
+    pub patch: Option<json_patch::Patch>,
+    // the original of a redacted dead letter, opened from its encrypted_payload with `decrypt-payload`
+    pub payload: Option<String>,
+}
+
+pub async fn replay_dead_letter(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    body: Option<web::Json<ReplayRequest>>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let id = path.into_inner();
+    let body = body.map(|body| body.into_inner()).unwrap_or_default();
+
+    let dead_letter = match find(id, &data).await {
+        Ok(Some(dead_letter)) => dead_letter,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResponse::new("Dead letter not found"));
+        }
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    let original = match (&body.payload, &dead_letter.encrypted_payload) {
+        (Some(payload), _) => payload.clone(),
+        // the redacted copy would suppress masked addresses
+        (None, Some(_)) => {
+            return HttpResponse::Conflict().json(ErrorResponse::new(
+                "The stored payload is redacted, replay it with the payload decrypted from encrypted_payload",
+            ));
+        }
+        (None, None) => dead_letter.payload.clone(),
+    };
+
+    let payload = match &body.patch {
+        None => original,
+        Some(patch) => {
+            let Ok(mut document) = serde_json::from_str::<Value>(&original) else {
+                return HttpResponse::BadRequest()
+                    .json(ErrorResponse::new("Stored payload is not valid JSON, a patch cannot be applied"));
+            };
+
+            if let Err(err) = json_patch::patch(&mut document, patch) {
+                return HttpResponse::BadRequest().json(ErrorResponse::new(format!("Failed to apply patch: {}", err)));
+            }
+
+            document.to_string()
+        }
+    };
+
+    let domain_id = dead_letter.domain_id as i32;
+    let replay_data = data.clone();
+
+    // the replay and its outcome are recorded to the end even when the caller disconnects
+    let replayed = to_completion(async move {
+        let data = replay_data;
+        let result = match parse(payload.as_bytes(), false) {
+            Ok(payload) => process_notification(domain_id, payload, &data).await,
+            Err(err) => Err(err),
+        };
+
+        let (status, error) = match result {
+            Ok(response) if response.status().is_success() => ("success", None),
+            Ok(response) => ("failed", Some(format!("processing returned status {}", response.status()))),
+            Err(err) => ("failed", Some(err)),
+        };
+
+        println!("Replayed dead letter {}: {} {:?}", id, status, error);
+
+        if let Err(err) = record_replay(id, status, error.as_deref(), &data).await {
+            println!("🔥 Failed to record replay outcome for dead letter {}: {:?}", id, err);
+        }
+
+        (status, error)
+    })
+    .await;
+
+    let (status, error) = match replayed {
+        Ok(outcome) => outcome,
+        Err(err) => return HttpResponse::InternalServerError().json(ErrorResponse::new(err)),
+    };
+
+    HttpResponse::Ok().json(ReplayResponse {
+        success: true,
+        data: ReplayOutcome { id, status: status.into(), error },
+    })
+}
@@ -0,0 +1,122 @@
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+
+use crate::responses::StatusResponse;
+
+// SNS waits 15 seconds for an answer before it redelivers
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 14_000;
+// lookups sit in the sending path of the callers
+const DEFAULT_LOOKUP_TIMEOUT_MS: u64 = 2_000;
+// imports, batch lookups and exports go through whole tables
+const DEFAULT_BULK_TIMEOUT_MS: u64 = 300_000;
+
+// 0 while no query timeout applies, e.g. for the long running maintenance commands
+static QUERY_TIMEOUT_MS: AtomicU64 = AtomicU64::new(0);
+
+fn timeout_from_env(var: &str, default: u64) -> Duration {
+    Duration::from_millis(env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default))
+}
+
+// REQUEST_TIMEOUT_MS bounds the SNS ingestion
+pub fn request_timeout() -> Duration {
+    timeout_from_env("REQUEST_TIMEOUT_MS", DEFAULT_REQUEST_TIMEOUT_MS)
+}
+
+// routes share the timeout of their class, LOOKUP_TIMEOUT_MS and BULK_TIMEOUT_MS next to REQUEST_TIMEOUT_MS
+#[derive(Debug, Clone, Copy)]
+enum RouteClass {
+    Ingestion,
+    Lookup,
+    Bulk,
+}
+
+impl RouteClass {
+    fn name(&self) -> &'static str {
+        match self {
+            RouteClass::Ingestion => "ingestion",
+            RouteClass::Lookup => "lookup",
+            RouteClass::Bulk => "bulk",
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        match self {
+            RouteClass::Ingestion => request_timeout(),
+            RouteClass::Lookup => timeout_from_env("LOOKUP_TIMEOUT_MS", DEFAULT_LOOKUP_TIMEOUT_MS),
+            RouteClass::Bulk => timeout_from_env("BULK_TIMEOUT_MS", DEFAULT_BULK_TIMEOUT_MS),
+        }
+    }
+}
+
+// applied to every database connection opened afterwards, whatever route the connection serves next. The pools
+// are shared by the classes, so QUERY_TIMEOUT_MS defaults to the longest class timeout (the bulk one unless
+// configured otherwise) and only stops queries that outlive every request; the class deadlines answer earlier.
+pub fn enable_query_timeouts() {
+    let longest = [RouteClass::Ingestion, RouteClass::Lookup, RouteClass::Bulk]
+        .iter()
+        .map(|class| class.timeout())
+        .max()
+        .unwrap_or_default();
+
+    let timeout = env::var("QUERY_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(longest.as_millis() as u64);
+
+    QUERY_TIMEOUT_MS.store(timeout, Ordering::Relaxed);
+}
+
+// session statement run on new connections, max_execution_time only limits SELECTs on MySQL
+pub fn mysql_session_statement() -> Option<String> {
+    match QUERY_TIMEOUT_MS.load(Ordering::Relaxed) {
+        0 => None,
+        timeout => Some(format!("SET SESSION max_execution_time = {}", timeout)),
+    }
+}
+
+pub fn pg_session_statement() -> Option<String> {
+    match QUERY_TIMEOUT_MS.load(Ordering::Relaxed) {
+        0 => None,
+        timeout => Some(format!("SET statement_timeout = {}", timeout)),
+    }
+}
+
+// answers 503 once the timeout of the class elapses, so SNS redelivers and callers fail fast instead of waiting
+// on a stuck query. Streamed responses are bounded until their first byte.
+async fn enforce(
+    class: RouteClass,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let http_req = req.request().clone();
+    let timeout = class.timeout();
+
+    match tokio::time::timeout(timeout, next.call(req)).await {
+        Ok(response) => response.map(ServiceResponse::map_into_boxed_body),
+        Err(_) => {
+            println!("🔥 {} request {} timed out after {:?}", class.name(), http_req.path(), timeout);
+            let response = HttpResponse::ServiceUnavailable()
+                .json(StatusResponse::error(format!("{} request timed out after {:?}", class.name(), timeout)));
+
+            Ok(ServiceResponse::new(http_req, response))
+        }
+    }
+}
+
+pub async fn ingestion(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    enforce(RouteClass::Ingestion, req, next).await
+}
+
+pub async fn lookup(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    enforce(RouteClass::Lookup, req, next).await
+}
+
+pub async fn bulk(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    enforce(RouteClass::Bulk, req, next).await
+}
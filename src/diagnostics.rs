@@ -0,0 +1,111 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+pub const NO_SUCH_USER: &str = "no_such_user";
+pub const BAD_DOMAIN: &str = "bad_domain";
+pub const MAILBOX_DISABLED: &str = "mailbox_disabled";
+pub const MAILBOX_FULL: &str = "mailbox_full";
+pub const MESSAGE_TOO_LARGE: &str = "message_too_large";
+pub const TIMEOUT: &str = "timeout";
+pub const POLICY_BLOCK: &str = "policy_block";
+pub const OTHER: &str = "other";
+
+pub const CLASSES: &[&str] = &[
+    NO_SUCH_USER,
+    BAD_DOMAIN,
+    MAILBOX_DISABLED,
+    MAILBOX_FULL,
+    MESSAGE_TOO_LARGE,
+    TIMEOUT,
+    POLICY_BLOCK,
+    OTHER,
+];
+
+// an RFC 3463 enhanced status code, e.g. 5.1.1 in "smtp; 550 5.1.1 user unknown"
+fn dsn_regex() -> &'static Regex {
+    static DSN: OnceLock<Regex> = OnceLock::new();
+    DSN.get_or_init(|| Regex::new(r"\b([245])\.(\d{1,3})\.(\d{1,3})\b").expect("valid DSN regex"))
+}
+
+fn by_status(class: &str, subject: &str, detail: &str) -> Option<&'static str> {
+    Some(match (subject, detail) {
+        ("1", "1" | "0" | "6" | "10") => NO_SUCH_USER,
+        ("1", "2") | ("4", "4") => BAD_DOMAIN,
+        ("2", "1") => MAILBOX_DISABLED,
+        ("2", "2") => MAILBOX_FULL,
+        ("2", "3") | ("3", "4") => MESSAGE_TOO_LARGE,
+        ("4", "1" | "2" | "7") => TIMEOUT,
+        ("7", _) => POLICY_BLOCK,
+        // other persistent transient failures mostly end in a timeout
+        _ if class == "4" && subject == "4" => TIMEOUT,
+        _ => return None,
+    })
+}
+
+// for diagnostics without a status code, the usual wording of the big providers
+fn by_text(diagnostic: &str) -> Option<&'static str> {
+    let diagnostic = diagnostic.to_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|needle| diagnostic.contains(needle));
+
+    if has(&["user unknown", "no such user", "does not exist", "unknown recipient", "recipient not found", "invalid recipient", "no mailbox"]) {
+        Some(NO_SUCH_USER)
+    } else if has(&["mailbox full", "quota", "over the limit", "insufficient storage"]) {
+        Some(MAILBOX_FULL)
+    } else if has(&["disabled", "inactive", "suspended"]) {
+        Some(MAILBOX_DISABLED)
+    } else if has(&["host not found", "domain not found", "no mx", "unrouteable"]) {
+        Some(BAD_DOMAIN)
+    } else if has(&["timed out", "timeout", "expired"]) {
+        Some(TIMEOUT)
+    } else if has(&["blocked", "spam", "blacklist", "blocklist", "policy", "rejected", "denied"]) {
+        Some(POLICY_BLOCK)
+    } else {
+        None
+    }
+}
+
+// the human category of a bounced recipient from its DSN status (e.g. 5.1.1) and diagnostic code,
+// the status code in the diagnostic wins over the recipient status, which SES sometimes reports generically
+pub fn classify(status: Option<&str>, diagnostic_code: Option<&str>) -> Option<&'static str> {
+    if status.is_none() && diagnostic_code.is_none() {
+        return None;
+    }
+
+    let from_code = |text: &str| {
+        dsn_regex()
+            .captures(text)
+            .and_then(|caps| by_status(&caps[1], &caps[2], &caps[3]))
+    };
+
+    let class = diagnostic_code
+        .and_then(from_code)
+        .or_else(|| status.and_then(from_code))
+        .or_else(|| diagnostic_code.and_then(by_text))
+        .unwrap_or(OTHER);
+
+    Some(class)
+}
+
+// the enhanced status code of a recipient, e.g. 5.1.1, as found by classify
+pub fn status_code(status: Option<&str>, diagnostic_code: Option<&str>) -> Option<String> {
+    diagnostic_code
+        .into_iter()
+        .chain(status)
+        .find_map(|text| dsn_regex().find(text))
+        .map(|code| code.as_str().to_string())
+}
+
+// the wording of a class in summaries, None for other
+pub fn label(class: &str) -> Option<&'static str> {
+    Some(match class {
+        NO_SUCH_USER => "user unknown",
+        BAD_DOMAIN => "domain not found",
+        MAILBOX_DISABLED => "mailbox disabled",
+        MAILBOX_FULL => "mailbox full",
+        MESSAGE_TOO_LARGE => "message too large",
+        TIMEOUT => "delivery timed out",
+        POLICY_BLOCK => "blocked by policy",
+        _ => return None,
+    })
+}
@@ -0,0 +1,156 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::rr::RecordType;
+use hickory_resolver::TokioAsyncResolver;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::responses::ErrorResponse;
+use crate::selftest::CheckResult;
+use crate::handlers::is_admin;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DnsCheckQuery {
+    // comma separated DKIM selectors, the three SES Easy DKIM tokens of the identity
+    pub dkim_selectors: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DnsReport {
+    pub domain: String,
+    pub ready: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+fn check(name: &str, result: Result<String, String>) -> CheckResult {
+    match result {
+        Ok(detail) => CheckResult { name: name.into(), passed: true, detail },
+        Err(detail) => CheckResult { name: name.into(), passed: false, detail },
+    }
+}
+
+fn resolver() -> Result<TokioAsyncResolver, String> {
+    TokioAsyncResolver::tokio_from_system_conf().map_err(|err| format!("failed to configure the DNS resolver: {}", err))
+}
+
+// TXT records of a name, an empty list when the name has none
+async fn txt_records(resolver: &TokioAsyncResolver, name: &str) -> Result<Vec<String>, String> {
+    match resolver.txt_lookup(name).await {
+        Ok(lookup) => Ok(lookup
+            .iter()
+            .map(|txt| {
+                txt.txt_data()
+                    .iter()
+                    .map(|part| String::from_utf8_lossy(part).into_owned())
+                    .collect::<String>()
+            })
+            .collect()),
+        Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(vec![]),
+        Err(err) => Err(format!("{} lookup failed: {}", name, err)),
+    }
+}
+
+async fn check_mx(resolver: &TokioAsyncResolver, domain: &str) -> Result<String, String> {
+    let lookup = resolver
+        .mx_lookup(domain)
+        .await
+        .map_err(|err| format!("no MX records for {}: {}", domain, err))?;
+
+    let hosts: Vec<String> = lookup.iter().map(|mx| mx.exchange().to_utf8()).collect();
+
+    if hosts.is_empty() {
+        Err(format!("no MX records for {}", domain))
+    } else {
+        Ok(hosts.join(", "))
+    }
+}
+
+// SES sends from amazonses.com, unless a custom MAIL FROM domain with its own SPF is used
+async fn check_spf(resolver: &TokioAsyncResolver, domain: &str) -> Result<String, String> {
+    let records: Vec<String> = txt_records(resolver, domain)
+        .await?
+        .into_iter()
+        .filter(|record| record.starts_with("v=spf1"))
+        .collect();
+
+    match records.as_slice() {
+        [] => Err(format!("no SPF record for {}", domain)),
+        [record] if record.contains("include:amazonses.com") => Ok(record.clone()),
+        [record] => Err(format!("SPF record does not include amazonses.com: {}", record)),
+        _ => Err(format!("{} SPF records for {}, receivers treat that as a permanent error", records.len(), domain)),
+    }
+}
+
+async fn check_dkim(resolver: &TokioAsyncResolver, domain: &str, selectors: &[String]) -> Result<String, String> {
+    if selectors.is_empty() {
+        return Err("no DKIM selectors given, pass ?dkim_selectors= with the SES DKIM tokens".into());
+    }
+
+    let mut missing = vec![];
+
+    for selector in selectors {
+        let name = format!("{}._domainkey.{}", selector, domain);
+
+        // SES Easy DKIM publishes CNAMEs, BYODKIM a TXT record
+        let found = resolver.lookup(name.as_str(), RecordType::CNAME).await.is_ok()
+            || !txt_records(resolver, &name).await.unwrap_or_default().is_empty();
+
+        if !found {
+            missing.push(name);
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(format!("{} selector(s) published", selectors.len()))
+    } else {
+        Err(format!("missing DKIM records: {}", missing.join(", ")))
+    }
+}
+
+async fn check_dmarc(resolver: &TokioAsyncResolver, domain: &str) -> Result<String, String> {
+    let name = format!("_dmarc.{}", domain);
+
+    txt_records(resolver, &name)
+        .await?
+        .into_iter()
+        .find(|record| record.starts_with("v=DMARC1"))
+        .ok_or_else(|| format!("no DMARC record at {}", name))
+}
+
+pub async fn dns_check_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DnsCheckQuery>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain = path.into_inner().trim().trim_end_matches('.').to_lowercase();
+    let selectors: Vec<String> = query
+        .dkim_selectors
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|selector| selector.trim().to_string())
+        .filter(|selector| !selector.is_empty())
+        .collect();
+
+    let resolver = match resolver() {
+        Ok(resolver) => resolver,
+        Err(err) => return HttpResponse::InternalServerError().json(ErrorResponse::new(err)),
+    };
+
+    let checks = vec![
+        check("mx", check_mx(&resolver, &domain).await),
+        check("spf", check_spf(&resolver, &domain).await),
+        check("dkim", check_dkim(&resolver, &domain, &selectors).await),
+        check("dmarc", check_dmarc(&resolver, &domain).await),
+    ];
+
+    HttpResponse::Ok().json(DnsReport {
+        ready: checks.iter().all(|check| check.passed),
+        domain,
+        checks,
+    })
+}
@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::Deserialize;
 use serde::Serialize;
+use utoipa::ToSchema;
 
 
 
@@ -13,6 +17,23 @@ pub struct SnsNotification {
 
     #[serde(rename = "SubscribeURL")]
     pub subscribe_url: Option<String>,
+
+    #[serde(rename = "MessageId")]
+    pub message_id: Option<String>,
+    #[serde(rename = "TopicArn")]
+    pub topic_arn: Option<String>,
+    #[serde(rename = "Subject")]
+    pub subject: Option<String>,
+    #[serde(rename = "Timestamp")]
+    pub timestamp: Option<String>,
+    #[serde(rename = "Token")]
+    pub token: Option<String>,
+    #[serde(rename = "SignatureVersion")]
+    pub signature_version: Option<String>,
+    #[serde(rename = "Signature")]
+    pub signature: Option<String>,
+    #[serde(rename = "SigningCertURL")]
+    pub signing_cert_url: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -26,16 +47,50 @@ pub enum SnsNotificationType {
 pub struct Message {
     pub notification_type: NotificationType,
     pub bounce: Option<Bounce>,
+    pub complaint: Option<Complaint>,
     pub message: Option<String>,
     pub mail: Option<Mail>,
 }
 
+// types SES may add later are kept as Other instead of failing the whole message
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum NotificationType {
     Bounce,
     Complaint,
     Delivery,
-    AmazonSnsSubscriptionSucceeded
+    AmazonSnsSubscriptionSucceeded,
+    Other(String),
+}
+
+impl NotificationType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            NotificationType::Bounce => "Bounce",
+            NotificationType::Complaint => "Complaint",
+            NotificationType::Delivery => "Delivery",
+            NotificationType::AmazonSnsSubscriptionSucceeded => "AmazonSnsSubscriptionSucceeded",
+            NotificationType::Other(other) => other,
+        }
+    }
+}
+
+impl From<String> for NotificationType {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "Bounce" => NotificationType::Bounce,
+            "Complaint" => NotificationType::Complaint,
+            "Delivery" => NotificationType::Delivery,
+            "AmazonSnsSubscriptionSucceeded" => NotificationType::AmazonSnsSubscriptionSucceeded,
+            _ => NotificationType::Other(value),
+        }
+    }
+}
+
+impl From<NotificationType> for String {
+    fn from(value: NotificationType) -> Self {
+        value.as_str().to_string()
+    }
 }
 
 #[derive(Default, Debug,Clone, PartialEq, Serialize, Deserialize)]
@@ -45,7 +100,7 @@ pub struct Bounce {
     pub bounce_type: String,
     pub bounce_sub_type: String,
     pub bounced_recipients: Vec<BouncedRecipient>,
-    pub timestamp: String,
+    pub timestamp: DateTime<Utc>,
     pub remote_mta_ip: Option<String>,
     #[serde(rename = "reportingMTA")]
     pub reporting_mta: Option<String>,
@@ -60,10 +115,30 @@ pub struct BouncedRecipient {
     pub diagnostic_code: Option<String>,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Complaint {
+    pub feedback_id: String,
+    // SES leaves it empty for some feedback loops, mail.destination then holds the recipients
+    #[serde(default)]
+    pub complained_recipients: Vec<ComplainedRecipient>,
+    pub timestamp: DateTime<Utc>,
+    pub complaint_feedback_type: Option<String>,
+    pub complaint_sub_type: Option<String>,
+    pub user_agent: Option<String>,
+    pub arrival_date: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComplainedRecipient {
+    pub email_address: String,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Mail {
-    pub timestamp: String,
+    pub timestamp: DateTime<Utc>,
     pub source: String,
     pub source_arn: String,
     pub source_ip: String,
@@ -71,13 +146,418 @@ pub struct Mail {
     pub sending_account_id: String,
     pub message_id: String,
     pub destination: Vec<String>,
+    // the original headers, only sent when the identity includes them and complete when headersTruncated is false
+    #[serde(default)]
+    pub headers_truncated: bool,
+    #[serde(default)]
+    pub headers: Vec<MailHeader>,
+    #[serde(default)]
+    pub common_headers: Option<CommonHeaders>,
+    // the message tags of the send, e.g. {"ses:configuration-set": ["marketing"]}
+    pub tags: Option<HashMap<String, Vec<String>>>,
+}
+
+impl Mail {
+    // the subject of the message that caused the notification, from commonHeaders or the complete headers
+    pub fn subject(&self) -> Option<&str> {
+        self.common_headers
+            .as_ref()
+            .and_then(|common| common.subject.as_deref())
+            .or_else(|| {
+                self.complete_headers()
+                    .iter()
+                    .find(|header| header.name.eq_ignore_ascii_case("subject"))
+                    .map(|header| header.value.as_str())
+            })
+    }
+
+    // the headers when SES sent all of them, a truncated list is not relied upon
+    pub fn complete_headers(&self) -> &[MailHeader] {
+        if self.headers_truncated {
+            &[]
+        } else {
+            &self.headers
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommonHeaders {
+    #[serde(default)]
+    pub from: Vec<String>,
+    #[serde(default)]
+    pub to: Vec<String>,
+    pub subject: Option<String>,
+    pub message_id: Option<String>,
+    pub date: Option<String>,
+    pub return_path: Option<String>,
+    pub reply_to: Option<Vec<String>>,
+    pub sender: Option<String>,
+    pub cc: Option<Vec<String>>,
+    pub bcc: Option<Vec<String>>,
 }
 
 
+#[allow(dead_code)]
 #[derive( Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Blacklist {
     pub id: Option<i64>,
     pub domain_id: i64,
     pub email: String,
     pub reason: String,
-}
\ No newline at end of file
+    pub category: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub bounce_type: Option<String>,
+    pub bounce_sub_type: Option<String>,
+    pub diagnostic_code: Option<String>,
+    pub reporting_mta: Option<String>,
+    pub remote_mta_ip: Option<String>,
+    pub source_arn: Option<String>,
+    pub sending_account_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub domain_id: i64,
+    pub payload: String,
+    pub error: String,
+    pub created_at: NaiveDateTime,
+    pub replayed_at: Option<NaiveDateTime>,
+    pub replay_status: Option<String>,
+    pub replay_error: Option<String>,
+    // the original sealed with DEAD_LETTER_PUBLIC_KEY, payload is then a redacted copy
+    pub encrypted_payload: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct WebhookDeadLetter {
+    pub id: i64,
+    pub domain_id: i64,
+    pub url: String,
+    pub payload: String,
+    pub attempts: i64,
+    pub error: String,
+    pub created_at: NaiveDateTime,
+    pub replayed_at: Option<NaiveDateTime>,
+    pub replay_status: Option<String>,
+    pub replay_error: Option<String>,
+}
+
+#[cfg(test)]
+pub mod tests {
+    use proptest::prelude::*;
+    use serde_json::{json, Map, Value};
+
+    use super::*;
+
+    const RAW_BOUNCE: &str = include_str!("../bench/samples/bounce.json");
+
+    pub type Field = (&'static str, BoxedStrategy<Option<Value>>);
+
+    pub fn required(name: &'static str, strategy: impl Strategy<Value = Value> + 'static) -> Field {
+        (name, strategy.prop_map(Some).boxed())
+    }
+
+    pub fn optional(name: &'static str, strategy: impl Strategy<Value = Value> + 'static) -> Field {
+        (name, proptest::option::of(strategy).boxed())
+    }
+
+    // fields SES may add later, the models must ignore them
+    fn unknown_fields() -> impl Strategy<Value = Map<String, Value>> {
+        let value = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<String>().prop_map(Value::from),
+            Just(json!({ "nested": [1, "two", { "three": null }] })),
+        ];
+
+        prop::collection::btree_map("unknown[A-Za-z]{1,8}", value, 0..3).prop_map(|fields| fields.into_iter().collect())
+    }
+
+    // the fields are left out at random when optional, the unknown ones (named unknown*) are added after them
+    pub fn object(fields: Vec<Field>) -> BoxedStrategy<Value> {
+        let (names, values): (Vec<_>, Vec<_>) = fields.into_iter().unzip();
+
+        (values, unknown_fields())
+            .prop_map(move |(values, unknown)| {
+                let mut object: Map<String, Value> = names
+                    .iter()
+                    .zip(values)
+                    .filter_map(|(name, value)| value.map(|value| (name.to_string(), value)))
+                    .collect();
+                object.extend(unknown);
+
+                Value::Object(object)
+            })
+            .boxed()
+    }
+
+    pub fn text() -> impl Strategy<Value = Value> {
+        any::<String>().prop_map(Value::from)
+    }
+
+    fn address() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            "[a-z0-9._+-]{1,12}@[a-z0-9-]{1,10}\\.[a-z]{2,4}",
+            "[A-Za-z ,\"]{1,12} <[a-z0-9.]{1,12}@example\\.com>",
+            any::<String>(),
+        ]
+            .prop_map(Value::from)
+    }
+
+    fn addresses() -> impl Strategy<Value = Value> {
+        prop::collection::vec(address(), 0..4).prop_map(Value::from)
+    }
+
+    // serialized the way chrono writes them, so a parsed timestamp compares equal to its input
+    fn timestamp() -> impl Strategy<Value = Value> {
+        (0i64..4_102_444_800_000).prop_map(|millis| json!(DateTime::<Utc>::from_timestamp_millis(millis).unwrap()))
+    }
+
+    fn bounce() -> BoxedStrategy<Value> {
+        let recipient = object(vec![
+            required("emailAddress", address()),
+            optional("action", text()),
+            optional("status", text()),
+            optional("diagnosticCode", text()),
+        ]);
+
+        object(vec![
+            required("feedbackId", text()),
+            required("bounceType", prop_oneof![Just("Permanent".into()), Just("Transient".into()), text()]),
+            required("bounceSubType", text()),
+            required("bouncedRecipients", prop::collection::vec(recipient, 0..4).prop_map(Value::from)),
+            required("timestamp", timestamp()),
+            optional("remoteMtaIp", text()),
+            optional("reportingMTA", text()),
+        ])
+    }
+
+    fn complaint() -> BoxedStrategy<Value> {
+        let recipient = object(vec![required("emailAddress", address())]);
+
+        object(vec![
+            required("feedbackId", text()),
+            optional("complainedRecipients", prop::collection::vec(recipient, 0..4).prop_map(Value::from)),
+            required("timestamp", timestamp()),
+            optional("complaintFeedbackType", text()),
+            optional("complaintSubType", text()),
+            optional("userAgent", text()),
+            optional("arrivalDate", text()),
+        ])
+    }
+
+    // the delivery details are not modelled, only the notification type is read
+    fn delivery() -> BoxedStrategy<Value> {
+        object(vec![
+            required("timestamp", timestamp()),
+            required("recipients", addresses()),
+            optional("processingTimeMillis", any::<u32>().prop_map(Value::from)),
+            optional("smtpResponse", text()),
+        ])
+    }
+
+    fn mail() -> BoxedStrategy<Value> {
+        let header = object(vec![required("name", text()), required("value", text())]);
+        let common_headers = object(vec![
+            optional("from", addresses()),
+            optional("to", addresses()),
+            optional("subject", text()),
+            optional("messageId", text()),
+            optional("date", text()),
+            optional("returnPath", text()),
+            optional("replyTo", addresses()),
+            optional("sender", text()),
+            optional("cc", addresses()),
+            optional("bcc", addresses()),
+        ]);
+        let tags = prop::collection::btree_map(any::<String>(), prop::collection::vec(any::<String>(), 0..3), 0..3)
+            .prop_map(|tags| json!(tags));
+
+        object(vec![
+            required("timestamp", timestamp()),
+            required("source", address()),
+            required("sourceArn", text()),
+            required("sourceIp", text()),
+            required("callerIdentity", text()),
+            required("sendingAccountId", text()),
+            required("messageId", text()),
+            required("destination", addresses()),
+            optional("headersTruncated", any::<bool>().prop_map(Value::from)),
+            optional("headers", prop::collection::vec(header, 0..3).prop_map(Value::from)),
+            optional("commonHeaders", common_headers),
+            optional("tags", tags),
+        ])
+    }
+
+    // an SES notification of any type, known or not
+    pub fn ses_message() -> BoxedStrategy<Value> {
+        let other_type = any::<String>()
+            .prop_filter("a known type", |value| matches!(NotificationType::from(value.clone()), NotificationType::Other(_)))
+            .prop_map(Value::from);
+
+        prop_oneof![
+            object(vec![required("notificationType", Just("Bounce".into())), required("bounce", bounce()), optional("mail", mail())]),
+            object(vec![
+                required("notificationType", Just("Complaint".into())),
+                required("complaint", complaint()),
+                optional("mail", mail()),
+            ]),
+            object(vec![
+                required("notificationType", Just("Delivery".into())),
+                required("delivery", delivery()),
+                optional("mail", mail()),
+            ]),
+            object(vec![required("notificationType", other_type), optional("message", text()), optional("mail", mail())]),
+        ]
+            .boxed()
+    }
+
+    // every modelled field of the input found with the same value in the output
+    pub fn assert_kept(input: &Value, output: &Value, path: &str) {
+        match (input, output) {
+            (Value::Object(input), Value::Object(output)) => {
+                for (name, value) in input {
+                    if name.starts_with("unknown") || name == "delivery" {
+                        continue;
+                    }
+
+                    let path = format!("{}.{}", path, name);
+                    let kept = output.get(name).unwrap_or_else(|| panic!("{} was dropped", path));
+                    assert_kept(value, kept, &path);
+                }
+            }
+            (Value::Array(input), Value::Array(output)) => {
+                assert_eq!(input.len(), output.len(), "{} changed length", path);
+
+                for (index, (value, kept)) in input.iter().zip(output).enumerate() {
+                    assert_kept(value, kept, &format!("{}[{}]", path, index));
+                }
+            }
+            _ => assert_eq!(input, output, "{} changed", path),
+        }
+    }
+
+    // the non-ASCII characters written as \u escapes, surrogate pairs included, as some senders do
+    fn ascii_escaped(json: &str) -> String {
+        json.chars()
+            .map(|char| match char.is_ascii() {
+                true => char.to_string(),
+                false => char.encode_utf16(&mut [0; 2]).iter().map(|unit| format!("\\u{:04x}", unit)).collect(),
+            })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn ses_messages_are_parsed_without_loss(input in ses_message()) {
+            let message: Message = serde_json::from_value(input.clone()).unwrap();
+            let output = serde_json::to_value(&message).unwrap();
+
+            assert_kept(&input, &output, "message");
+            prop_assert_eq!(serde_json::from_value::<Message>(output).unwrap(), message);
+        }
+
+        #[test]
+        fn escaped_characters_are_decoded(input in ses_message()) {
+            let plain: Message = serde_json::from_value(input.clone()).unwrap();
+            let escaped: Message = serde_json::from_str(&ascii_escaped(&input.to_string())).unwrap();
+
+            prop_assert_eq!(escaped, plain);
+        }
+
+        #[test]
+        fn truncated_or_incomplete_messages_are_errors(input in ses_message(), cut in any::<prop::sample::Index>()) {
+            let json = input.to_string().into_bytes();
+            prop_assert!(serde_json::from_slice::<Message>(&json[..cut.index(json.len())]).is_err());
+
+            let Value::Object(mut object) = input else { unreachable!() };
+            object.remove("notificationType");
+            prop_assert!(serde_json::from_value::<Message>(Value::Object(object)).is_err());
+        }
+
+        #[test]
+        fn arbitrary_text_never_panics(text in any::<String>()) {
+            let _ = serde_json::from_str::<Message>(&text);
+            let _ = serde_json::from_str::<SnsNotification>(&text);
+        }
+    }
+
+    fn snapshot(name: &str, json: &str) {
+        let message: Message = serde_json::from_str(json).unwrap();
+
+        insta::with_settings!({ sort_maps => true }, {
+            insta::assert_json_snapshot!(name, message);
+        });
+    }
+
+    #[test]
+    fn bounce_is_normalized() {
+        snapshot("bounce", RAW_BOUNCE);
+    }
+
+    #[test]
+    fn complaint_is_normalized() {
+        snapshot(
+            "complaint",
+            r#"{
+                "notificationType": "Complaint",
+                "complaint": {
+                    "feedbackId": "0100018c2f4b7d1e-complaint-000000",
+                    "complainedRecipients": [{ "emailAddress": "\"Jane Doe\" <jane@example.com>" }],
+                    "timestamp": "2024-01-15T11:00:00.250Z",
+                    "complaintFeedbackType": "abuse",
+                    "userAgent": "Yahoo!-Mail-Feedback/2.0",
+                    "arrivalDate": "2024-01-15T10:59:58.000Z",
+                    "complaintSubType": null
+                },
+                "mail": {
+                    "timestamp": "2024-01-15T10:30:10.000Z",
+                    "source": "sender@example.org",
+                    "sourceArn": "arn:aws:ses:us-east-1:123456789012:identity/example.org",
+                    "sourceIp": "198.51.100.7",
+                    "callerIdentity": "ses-sender",
+                    "sendingAccountId": "123456789012",
+                    "messageId": "0100018c2f4b7c6a-000000",
+                    "destination": ["jane@example.com"],
+                    "headersTruncated": false,
+                    "headers": [{ "name": "Subject", "value": "Your order été shipped" }],
+                    "commonHeaders": { "from": ["sender@example.org"], "subject": "Your order été shipped" },
+                    "tags": { "ses:configuration-set": ["marketing"], "campaign": ["spring"] }
+                }
+            }"#,
+        );
+    }
+
+    #[test]
+    fn delivery_and_unknown_types_are_normalized() {
+        snapshot(
+            "delivery",
+            r#"{
+                "notificationType": "Delivery",
+                "delivery": { "timestamp": "2024-01-15T10:30:11.000Z", "recipients": ["jane@example.com"] },
+                "mail": {
+                    "timestamp": "2024-01-15T10:30:10Z",
+                    "source": "sender@example.org",
+                    "sourceArn": "arn:aws:ses:us-east-1:123456789012:identity/example.org",
+                    "sourceIp": "198.51.100.7",
+                    "callerIdentity": "ses-sender",
+                    "sendingAccountId": "123456789012",
+                    "messageId": "0100018c2f4b7c6a-000001",
+                    "destination": ["jane@example.com"],
+                    "deliveryDelay": { "delayType": "MailboxFull" }
+                }
+            }"#,
+        );
+        snapshot("unknown_type", r#"{ "notificationType": "Subscription", "message": "hello", "subscription": {} }"#);
+    }
+}
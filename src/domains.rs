@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::env;
+
+use actix_web::web;
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use tokio::sync::Semaphore;
+
+use crate::rules::{self, BounceRule};
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::AppState;
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+// per domain configuration stored in the domains table, a missing row means the defaults
+#[derive(Debug, Clone, Default)]
+pub struct DomainSettings {
+    pub bounce_rules: Vec<BounceRule>,
+    // days an entry stays suppressed per category, e.g. {"soft_bounce": 90}, categories without a value never expire
+    pub suppression_days: HashMap<String, i64>,
+    // notifications of the domain processed at the same time, DOMAIN_CONCURRENCY when not set
+    pub max_concurrency: Option<i64>,
+    // the shape of the webhook payload, see webhooks::render; the plain event when not set
+    pub webhook_template: Option<String>,
+    // IANA name, e.g. "Europe/Berlin", the days of the daily stats start at its midnight; UTC when not set
+    pub reporting_timezone: Option<Tz>,
+}
+
+impl DomainSettings {
+    pub fn default_expiry(&self, category: &str) -> Option<NaiveDateTime> {
+        self.expiry_from(category, Utc::now().naive_utc())
+    }
+
+    // the expiry of an entry of the category suppressed at `from`, never past chrono's dates
+    pub fn expiry_from(&self, category: &str, from: NaiveDateTime) -> Option<NaiveDateTime> {
+        self.suppression_days
+            .get(category)
+            .and_then(|days| Duration::try_days(*days))
+            .and_then(|days| from.checked_add_signed(days))
+    }
+
+    // a value that is not positive is ignored, a huge one capped at what a semaphore holds
+    pub fn concurrency_limit(&self) -> usize {
+        self.max_concurrency
+            .filter(|limit| *limit > 0)
+            .map(|limit| usize::try_from(limit).unwrap_or(usize::MAX))
+            .or_else(|| env::var("DOMAIN_CONCURRENCY").ok().and_then(|limit| limit.parse().ok()).filter(|limit| *limit > 0))
+            .unwrap_or(DEFAULT_CONCURRENCY)
+            .min(Semaphore::MAX_PERMITS)
+    }
+}
+
+// (bounce_rules, suppression_days, max_concurrency, webhook_template, reporting_timezone), the first two are JSON
+// columns
+type SettingsRow = (Option<String>, Option<String>, Option<i64>, Option<String>, Option<String>);
+
+fn parse_timezone(domain_id: i64, raw: &str) -> Option<Tz> {
+    match raw.trim().parse::<Tz>() {
+        Ok(tz) => Some(tz),
+        Err(err) => {
+            println!("🔥 Invalid reporting timezone {:?} of domain {}, reporting in UTC: {}", raw, domain_id, err);
+            None
+        }
+    }
+}
+
+// the current day in the timezone
+pub fn today(tz: Option<Tz>) -> NaiveDate {
+    match tz {
+        Some(tz) => Utc::now().with_timezone(&tz).date_naive(),
+        None => Utc::now().date_naive(),
+    }
+}
+
+// the UTC start and end of a day in the timezone, 23 or 25 hours long on DST changes. Where midnight does not
+// exist (a DST change at midnight) the day starts at the first valid time after it.
+pub fn day_bounds(tz: Option<Tz>, day: NaiveDate) -> (NaiveDateTime, NaiveDateTime) {
+    let midnight = |day: NaiveDate| {
+        let local = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+
+        match tz {
+            Some(tz) => (0..=3)
+                .find_map(|hours| tz.from_local_datetime(&(local + Duration::hours(hours))).earliest())
+                .map(|start| start.naive_utc())
+                .unwrap_or(local),
+            None => local,
+        }
+    };
+
+    (midnight(day), midnight(day + Duration::days(1)))
+}
+
+// the suppression days of each category are 0 to rules::MAX_DAYS, like the days of a rule
+pub fn check_suppression_days(days: &HashMap<String, i64>) -> Result<(), String> {
+    match days.iter().find(|(_, days)| !(0..=rules::MAX_DAYS).contains(*days)) {
+        Some((category, days)) => Err(format!(
+            "suppression days of {} must be between 0 and {}, got {}",
+            category,
+            rules::MAX_DAYS,
+            days
+        )),
+        None => Ok(()),
+    }
+}
+
+fn parse_suppression_days(raw: &str) -> HashMap<String, i64> {
+    let parsed = serde_json::from_str(raw).map_err(|err| format!("{:?}", err)).and_then(|days| {
+        check_suppression_days(&days)?;
+        Ok(days)
+    });
+
+    match parsed {
+        Ok(days) => days,
+        Err(err) => {
+            println!("🔥 Failed to parse suppression days, falling back to defaults: {:?}", err);
+            HashMap::new()
+        }
+    }
+}
+
+pub async fn load(domain_id: i32, data: &web::Data<AppState>) -> DomainSettings {
+    let row: Result<Option<SettingsRow>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, SettingsRow>(
+                r#"SELECT bounce_rules, suppression_days, max_concurrency, webhook_template, reporting_timezone FROM domains WHERE id = ?"#,
+            )
+                .bind(domain_id)
+                .fetch_optional(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => {
+                let table = env::var("PG_DOMAINS_TABLE").unwrap_or_else(|_| "domains".into());
+
+                client
+                    .query_opt(
+                        &format!(r#"SELECT bounce_rules, suppression_days, max_concurrency, webhook_template, reporting_timezone FROM {table} WHERE id = $1"#, table = table),
+                        &[&domain_id],
+                    )
+                    .await
+                    .map(|row| row.map(|row| (row.get(0), row.get(1), row.get::<_, Option<i32>>(2).map(i64::from), row.get(3), row.get(4))))
+                    .map_err(|err| err.to_string())
+            }
+            Err(err) => Err(err.to_string()),
+        },
+        // no domains table, every domain has the default settings
+        DBType::DynamoDB(_) => Ok(None),
+    };
+
+    match row {
+        Ok(Some((bounce_rules, suppression_days, max_concurrency, webhook_template, reporting_timezone))) => DomainSettings {
+            bounce_rules: bounce_rules.map(|raw| rules::parse_rules(&raw)).unwrap_or_default(),
+            suppression_days: suppression_days.map(|raw| parse_suppression_days(&raw)).unwrap_or_default(),
+            max_concurrency,
+            webhook_template: webhook_template.filter(|template| !template.trim().is_empty()),
+            reporting_timezone: reporting_timezone
+                .filter(|raw| !raw.trim().is_empty())
+                .and_then(|raw| parse_timezone(domain_id.into(), &raw)),
+        },
+        Ok(None) => DomainSettings::default(),
+        Err(err) => {
+            println!("Failed to load settings for domain {}, using defaults: {:?}", domain_id, err);
+            DomainSettings::default()
+        }
+    }
+}
+
+// (id, reporting_timezone) of the domains reporting in a timezone of their own
+type TimezoneRow = (i64, String);
+
+// the domains with a valid reporting timezone, the others report in UTC
+pub async fn reporting_timezones(db_type: &DBType, db_url: &str) -> Result<HashMap<i64, Tz>, String> {
+    let rows: Vec<TimezoneRow> = match db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, TimezoneRow>(
+            r#"SELECT CAST(id AS SIGNED), reporting_timezone FROM domains WHERE reporting_timezone IS NOT NULL AND reporting_timezone <> ''"#,
+        )
+            .fetch_all(pool)
+            .await
+            .map_err(|err| err.to_string())?,
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            let table = env::var("PG_DOMAINS_TABLE").unwrap_or_else(|_| "domains".into());
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id::bigint, reporting_timezone FROM {} WHERE reporting_timezone IS NOT NULL AND reporting_timezone <> ''"#,
+                        table
+                    ),
+                    &[],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                .map_err(|err| err.to_string())?
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(domain_id, raw)| parse_timezone(domain_id, &raw).map(|tz| (domain_id, tz)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(days: i64) -> DomainSettings {
+        DomainSettings { suppression_days: HashMap::from([("soft_bounce".to_string(), days)]), ..DomainSettings::default() }
+    }
+
+    #[test]
+    fn expiry_is_the_suppression_days_after() {
+        let from = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap().and_hms_opt(10, 0, 0).unwrap();
+
+        assert_eq!(settings(90).expiry_from("soft_bounce", from), Some(from + Duration::days(90)));
+        assert_eq!(settings(90).expiry_from("hard_bounce", from), None);
+    }
+
+    #[test]
+    fn days_past_the_calendar_do_not_panic() {
+        let from = Utc::now().naive_utc();
+
+        assert_eq!(settings(i64::MAX).expiry_from("soft_bounce", from), None);
+        assert_eq!(settings(i64::MAX / 86_400).expiry_from("soft_bounce", from), None);
+    }
+
+    #[test]
+    fn out_of_range_suppression_days_are_refused() {
+        assert!(check_suppression_days(&settings(rules::MAX_DAYS).suppression_days).is_ok());
+        assert!(check_suppression_days(&settings(rules::MAX_DAYS + 1).suppression_days).is_err());
+        assert!(check_suppression_days(&settings(-1).suppression_days).is_err());
+
+        assert_eq!(parse_suppression_days(r#"{"soft_bounce": 90}"#), settings(90).suppression_days);
+        assert!(parse_suppression_days(r#"{"soft_bounce": 90, "complaint": 9223372036854775807}"#).is_empty());
+    }
+}
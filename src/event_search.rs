@@ -0,0 +1,270 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use sqlx::mysql::{MySql, MySqlArguments};
+use sqlx::query::QueryAs;
+use tokio_postgres::types::ToSql;
+use utoipa::ToSchema;
+
+use crate::handlers::is_admin;
+use crate::notification_log;
+use crate::privacy;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::ErrorResponse;
+use crate::AppState;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+const TYPES: [&str; 4] = ["Bounce", "Complaint", "Delivery", "AmazonSnsSubscriptionSucceeded"];
+
+// (id, notification_type, message_id, bounce_sub_type, payload, received_at)
+type EventRow = (i64, String, Option<String>, Option<String>, String, NaiveDateTime);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EventQuery {
+    // Bounce, Complaint, Delivery, ... in any case
+    #[serde(rename = "type")]
+    pub notification_type: Option<String>,
+    // an address the notification names as destination or bounced or complained recipient
+    pub email: Option<String>,
+    // received at or after, RFC 3339 or a UTC day such as 2024-03-01
+    pub from: Option<String>,
+    // received before, same formats
+    pub to: Option<String>,
+    pub bounce_subtype: Option<String>,
+    // desc (newest first, the default) or asc
+    pub sort: Option<String>,
+    // the next_cursor of the previous page
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+// a notification as received and logged
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LoggedEvent {
+    pub id: i64,
+    pub notification_type: String,
+    pub message_id: Option<String>,
+    pub bounce_sub_type: Option<String>,
+    pub received_at: NaiveDateTime,
+    // the SES message
+    #[schema(value_type = Object)]
+    pub payload: Json,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EventPage {
+    pub success: bool,
+    pub data: Vec<LoggedEvent>,
+    // passed as ?cursor= for the next page, absent on the last one
+    pub next_cursor: Option<i64>,
+}
+
+enum Value {
+    Domain(i32),
+    Id(i64),
+    Text(String),
+    Time(NaiveDateTime),
+}
+
+struct Search {
+    domain_id: i32,
+    notification_type: Option<String>,
+    email: Option<String>,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+    bounce_sub_type: Option<String>,
+    ascending: bool,
+    cursor: Option<i64>,
+    limit: i64,
+}
+
+fn parse_time(name: &str, value: &str) -> Result<NaiveDateTime, String> {
+    let value = value.trim();
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.naive_utc())
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|day| day.and_hms_opt(0, 0, 0).unwrap_or_default()))
+        .map_err(|_| format!("{} must be an RFC 3339 time or a YYYY-MM-DD day", name))
+}
+
+fn search(domain_id: i32, query: EventQuery) -> Result<Search, String> {
+    let notification_type = query.notification_type.map(|requested| {
+        TYPES
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(requested.trim()))
+            .map(|known| known.to_string())
+            .unwrap_or(requested)
+    });
+
+    let ascending = match query.sort.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        None | Some("desc") => false,
+        Some("asc") => true,
+        Some(other) => return Err(format!("sort must be asc or desc, not {}", other)),
+    };
+
+    Ok(Search {
+        domain_id,
+        notification_type,
+        // addresses are logged as they are stored in the blacklist
+        email: query.email.filter(|email| !email.trim().is_empty()).map(|email| privacy::stored_email(&email)),
+        from: query.from.as_deref().map(|from| parse_time("from", from)).transpose()?,
+        to: query.to.as_deref().map(|to| parse_time("to", to)).transpose()?,
+        bounce_sub_type: query.bounce_subtype.filter(|sub_type| !sub_type.is_empty()),
+        ascending,
+        cursor: query.cursor,
+        limit: query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    })
+}
+
+// the query of one page, with the values to bind in order. `placeholder` renders the n-th placeholder.
+fn page_sql(search: &Search, placeholder: impl Fn(usize) -> String) -> (String, Vec<Value>) {
+    let mut values = vec![Value::Domain(search.domain_id)];
+    let mut clauses = vec![format!("l.domain_id = {}", placeholder(1))];
+
+    let mut add = |clause: &str, value: Value| {
+        values.push(value);
+        clauses.push(clause.replace("{}", &placeholder(values.len())));
+    };
+
+    if let Some(notification_type) = &search.notification_type {
+        add("l.notification_type = {}", Value::Text(notification_type.clone()));
+    }
+    if let Some(from) = search.from {
+        add("l.received_at >= {}", Value::Time(from));
+    }
+    if let Some(to) = search.to {
+        add("l.received_at < {}", Value::Time(to));
+    }
+    if let Some(bounce_sub_type) = &search.bounce_sub_type {
+        add("l.bounce_sub_type = {}", Value::Text(bounce_sub_type.clone()));
+    }
+    if let Some(cursor) = search.cursor {
+        add(if search.ascending { "l.id > {}" } else { "l.id < {}" }, Value::Id(cursor));
+    }
+    if let Some(email) = &search.email {
+        // the domain again, MySQL placeholders bind once each
+        add("l.id IN (SELECT r.notification_id FROM RECIPIENTS r WHERE r.domain_id = {}", Value::Domain(search.domain_id));
+        let last = clauses.pop().unwrap_or_default();
+        values.push(Value::Text(email.clone()));
+        clauses.push(format!("{} AND r.email = {})", last, placeholder(values.len())));
+    }
+
+    values.push(Value::Id(search.limit + 1));
+    let sql = format!(
+        r#"SELECT l.id, l.notification_type, l.message_id, l.bounce_sub_type, l.payload, l.received_at
+           FROM LOG l WHERE {} ORDER BY l.id {} LIMIT {}"#,
+        clauses.join(" AND "),
+        if search.ascending { "ASC" } else { "DESC" },
+        placeholder(values.len())
+    );
+
+    (sql, values)
+}
+
+fn bind_all<'q, O>(
+    mut query: QueryAs<'q, MySql, O, MySqlArguments>,
+    values: &'q [Value],
+) -> QueryAs<'q, MySql, O, MySqlArguments> {
+    for value in values {
+        query = match value {
+            Value::Domain(domain_id) => query.bind(domain_id),
+            Value::Id(id) => query.bind(id),
+            Value::Text(text) => query.bind(text),
+            Value::Time(time) => query.bind(time),
+        };
+    }
+
+    query
+}
+
+fn pg_params(values: &[Value]) -> Vec<&(dyn ToSql + Sync)> {
+    values
+        .iter()
+        .map(|value| -> &(dyn ToSql + Sync) {
+            match value {
+                Value::Domain(domain_id) => domain_id,
+                Value::Id(id) => id,
+                Value::Text(text) => text,
+                Value::Time(time) => time,
+            }
+        })
+        .collect()
+}
+
+async fn fetch(search: &Search, data: &web::Data<AppState>) -> Result<Vec<EventRow>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let (sql, values) = page_sql(search, |_| "?".into());
+            let sql = sql.replace("LOG", "notification_log").replace("RECIPIENTS", "notification_recipients");
+
+            bind_all(sqlx::query_as::<_, EventRow>(&sql), &values)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let (sql, values) = page_sql(search, |n| format!("${}", n));
+            let sql = sql
+                .replace("LOG", &notification_log::table())
+                .replace("RECIPIENTS", &notification_log::recipients_table());
+
+            client
+                .query(&sql, &pg_params(&values))
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4), row.get(5)))
+                        .collect()
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// the notifications received for the domain, filtered and paged by id, for investigations without database
+// access. The email and bounce_subtype filters only find the notifications logged since schema version 34.
+pub async fn events_handler(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<EventQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let search = match search(path.into_inner(), query.into_inner()) {
+        Ok(search) => search,
+        Err(err) => return HttpResponse::BadRequest().json(ErrorResponse::new(err)),
+    };
+
+    let mut rows = match fetch(&search, &data).await {
+        Ok(rows) => rows,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)))
+        }
+    };
+
+    let more = rows.len() as i64 > search.limit;
+    rows.truncate(search.limit as usize);
+
+    let next_cursor = if more { rows.last().map(|row| row.0) } else { None };
+    let events = rows
+        .into_iter()
+        .map(|(id, notification_type, message_id, bounce_sub_type, payload, received_at)| LoggedEvent {
+            id,
+            notification_type,
+            message_id,
+            bounce_sub_type,
+            received_at,
+            payload: serde_json::from_str(&payload).unwrap_or(Json::String(payload)),
+        })
+        .collect();
+
+    HttpResponse::Ok().json(EventPage { success: true, data: events, next_cursor })
+}
@@ -0,0 +1,140 @@
+use std::env;
+use std::time::Duration;
+
+use aws_sdk_eventbridge::types::PutEventsRequestEntry;
+use aws_sdk_eventbridge::Client;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::events::Event;
+use crate::outbound;
+
+// PutEvents takes at most 10 entries per request
+const MAX_BATCH_SIZE: usize = 10;
+const MAX_ATTEMPTS: u32 = 3;
+
+// optional publication to EventBridge, EVENTBRIDGE_BUS (a bus name or ARN) receives every suppression and domain
+// pause event, so rules can start a Step Function or a Lambda without a webhook endpoint. The detail is the same JSON
+// the webhooks and the SSE stream carry, EVENTBRIDGE_SOURCE and EVENTBRIDGE_DETAIL_TYPE set the fields rules match on.
+struct Config {
+    bus: String,
+    source: String,
+    // one detail-type for every event, by default "Email Suppressed" or "Domain Paused"
+    detail_type: Option<String>,
+    flush_interval: Duration,
+}
+
+fn config() -> Option<Config> {
+    let bus = env::var("EVENTBRIDGE_BUS").ok().filter(|bus| !bus.is_empty())?;
+
+    Some(Config {
+        bus,
+        source: env::var("EVENTBRIDGE_SOURCE").unwrap_or_else(|_| "aws-ses-bounce".into()),
+        detail_type: env::var("EVENTBRIDGE_DETAIL_TYPE").ok().filter(|detail_type| !detail_type.is_empty()),
+        flush_interval: Duration::from_millis(
+            env::var("EVENTBRIDGE_FLUSH_MS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(500u64)
+                .max(1),
+        ),
+    })
+}
+
+fn entry(config: &Config, event: &Event) -> Option<PutEventsRequestEntry> {
+    let detail_type = match event {
+        Event::EmailSuppressed(_) => "Email Suppressed",
+        Event::DomainPaused(_) => "Domain Paused",
+        Event::ComplaintReceived { .. } | Event::LookupPerformed { .. } => return None,
+    };
+    let detail = serde_json::to_string(event.live()?).ok()?;
+
+    Some(
+        PutEventsRequestEntry::builder()
+            .event_bus_name(&config.bus)
+            .source(&config.source)
+            .detail_type(config.detail_type.as_deref().unwrap_or(detail_type))
+            .detail(detail)
+            .build(),
+    )
+}
+
+// sends the batch, the entries EventBridge refused (throttled or failed internally) are sent again
+async fn flush(client: &Client, batch: &mut Vec<PutEventsRequestEntry>) {
+    for attempt in 1..=MAX_ATTEMPTS {
+        if batch.is_empty() {
+            return;
+        }
+
+        let error = match client.put_events().set_entries(Some(batch.clone())).send().await {
+            Ok(output) if output.failed_entry_count == 0 => {
+                batch.clear();
+                return;
+            }
+            // the results are in the order of the entries, the failed ones carry an error code
+            Ok(output) => {
+                let results = output.entries.unwrap_or_default();
+                let error = results.iter().find_map(|result| result.error_message.clone()).unwrap_or_default();
+                let mut results = results.iter();
+                batch.retain(|_| results.next().is_some_and(|result| result.error_code.is_some()));
+                format!("{} entries failed: {}", batch.len(), error)
+            }
+            Err(err) => err.to_string(),
+        };
+
+        if attempt < MAX_ATTEMPTS {
+            println!("🔥 EventBridge PutEvents failed (attempt {}): {}", attempt, error);
+            tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+        } else {
+            println!("🔥 EventBridge PutEvents failed, dropping {} events: {}", batch.len(), error);
+        }
+    }
+
+    batch.clear();
+}
+
+// subscribes to the event bus and runs until the channel closes, a no-op without EVENTBRIDGE_BUS
+pub fn spawn(events: &broadcast::Sender<Event>) {
+    let Some(config) = config() else {
+        return;
+    };
+
+    println!("🚀 Publishing suppression events to the EventBridge bus {} as {}", config.bus, config.source);
+
+    let mut receiver = events.subscribe();
+
+    tokio::spawn(async move {
+        let client = Client::new(&outbound::aws_config().await);
+        let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+        let mut deadline = Instant::now() + config.flush_interval;
+
+        loop {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Ok(event)) => {
+                    let Some(entry) = entry(&config, &event) else {
+                        continue;
+                    };
+                    if batch.is_empty() {
+                        deadline = Instant::now() + config.flush_interval;
+                    }
+                    batch.push(entry);
+                    if batch.len() < MAX_BATCH_SIZE {
+                        continue;
+                    }
+                }
+                Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                    println!("🔥 EventBridge publisher lagged, skipped {} events", skipped);
+                    continue;
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => {
+                    flush(&client, &mut batch).await;
+                    return;
+                }
+                Err(_) => {}
+            }
+
+            flush(&client, &mut batch).await;
+            deadline = Instant::now() + config.flush_interval;
+        }
+    });
+}
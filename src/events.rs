@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use actix_web::http::header::ContentEncoding;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+use crate::blacklist::NewEntry;
+use crate::responses::ErrorResponse;
+use crate::handlers::is_admin;
+use crate::AppState;
+
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+// comment lines keep idle connections open through proxies
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LiveEvent {
+    pub domain_id: i32,
+    // bounce, complaint, complaint_pending_review, unsubscribe, blacklist or domain_paused
+    pub event_type: String,
+    pub email: String,
+    pub category: String,
+    pub expires_at: Option<NaiveDateTime>,
+    pub timestamp: NaiveDateTime,
+}
+
+impl LiveEvent {
+    pub fn suppressed(event_type: &str, entry: &NewEntry) -> Self {
+        LiveEvent {
+            domain_id: entry.domain_id,
+            event_type: event_type.into(),
+            email: entry.email.clone(),
+            category: entry.category.clone(),
+            expires_at: entry.expires_at,
+            timestamp: Utc::now().naive_utc(),
+        }
+    }
+
+    // the domain crossed its complaint rate threshold, `reason` is carried in category
+    pub fn domain_paused(domain_id: i32, reason: &str) -> Self {
+        LiveEvent {
+            domain_id,
+            event_type: "domain_paused".into(),
+            email: String::new(),
+            category: reason.into(),
+            expires_at: None,
+            timestamp: Utc::now().naive_utc(),
+        }
+    }
+}
+
+// what the internal bus carries. The handlers only publish, the SSE stream, webhooks, the ClickHouse sink,
+// metrics and alerts subscribe to what they need.
+#[derive(Debug, Clone)]
+pub enum Event {
+    // an address was blacklisted by a bounce, a complaint or the API
+    EmailSuppressed(LiveEvent),
+    // a complaint notification arrived, whether or not its recipients end up suppressed
+    ComplaintReceived { domain_id: i32, feedback_type: Option<String>, recipients: usize },
+    // lookups answered for the domain, one event per request or batch chunk
+    LookupPerformed { domain_id: i32, lookups: usize, blacklisted: usize },
+    // the domain crossed its complaint rate threshold
+    DomainPaused(LiveEvent),
+}
+
+impl Event {
+    pub fn domain_paused(domain_id: i32, reason: &str) -> Self {
+        Event::DomainPaused(LiveEvent::domain_paused(domain_id, reason))
+    }
+
+    pub fn domain_id(&self) -> i32 {
+        match self {
+            Event::EmailSuppressed(event) | Event::DomainPaused(event) => event.domain_id,
+            Event::ComplaintReceived { domain_id, .. } | Event::LookupPerformed { domain_id, .. } => *domain_id,
+        }
+    }
+
+    // the part delivered to external consumers, internal events have none
+    pub fn live(&self) -> Option<&LiveEvent> {
+        match self {
+            Event::EmailSuppressed(event) | Event::DomainPaused(event) => Some(event),
+            Event::ComplaintReceived { .. } | Event::LookupPerformed { .. } => None,
+        }
+    }
+}
+
+pub fn channel() -> broadcast::Sender<Event> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+// sending only fails when nobody is listening, which is fine
+pub fn publish(data: &web::Data<AppState>, event: Event) {
+    let _ = data.events.send(event);
+}
+
+// runs `handle` on every event until the channel closes, `name` identifies the subscriber when it lags behind
+pub fn subscribe<F>(events: &broadcast::Sender<Event>, name: &'static str, mut handle: F) -> JoinHandle<()>
+where
+    F: FnMut(Event) + Send + 'static,
+{
+    let mut receiver = events.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => handle(event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    println!("🔥 {} lagged behind the event bus, skipped {} events", name, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    })
+}
+
+fn sse_frame(event: &LiveEvent) -> Bytes {
+    let payload = serde_json::to_string(event).unwrap_or_default();
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event.event_type, payload))
+}
+
+pub async fn stream_events(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain_id = path.into_inner();
+    let receiver = data.events.subscribe();
+
+    let stream = futures_util::stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match tokio::time::timeout(KEEP_ALIVE, receiver.recv()).await {
+                Ok(Ok(event)) if event.domain_id() == domain_id => {
+                    if let Some(event) = event.live() {
+                        return Some((Ok::<_, actix_web::Error>(sse_frame(event)), receiver));
+                    }
+                }
+                Ok(Ok(_)) => continue,
+                Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                    println!("Event stream for domain {} lagged, skipped {} events", domain_id, skipped);
+                    continue;
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+                Err(_) => return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), receiver)),
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // the Compress middleware would buffer the frames
+        .insert_header(ContentEncoding::Identity)
+        .streaming(stream)
+}
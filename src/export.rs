@@ -0,0 +1,319 @@
+use std::env;
+use std::io::Write;
+
+use actix_web::http::header::{self, ContentEncoding};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+
+use crate::handlers::is_admin;
+use crate::repo::{build_pg_read_client, dynamodb, effective_status_sql, read_mysql, DBType, EntryStatus};
+use crate::responses::ErrorResponse;
+use crate::stats::csv_field;
+use crate::AppState;
+
+const DEFAULT_PAGE_SIZE: i64 = 5000;
+const MAX_PAGE_SIZE: i64 = 50_000;
+
+const CSV_HEADER: &str = "id,email,status,category,reason_summary,expires_at,created_at\n";
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ExportRow {
+    // the cursor, a cut export resumes with ?after_id= set to the last id received
+    pub id: i64,
+    pub email: String,
+    pub status: String,
+    pub category: Option<String>,
+    pub reason_summary: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    // ndjson (default) or csv
+    pub format: Option<String>,
+    // only the entries with a greater id, to resume an export
+    pub after_id: Option<i64>,
+    // a status to export, every entry by default
+    pub status: Option<String>,
+    // at most this many entries, to read the list a page at a time; all of them by default
+    pub limit: Option<i64>,
+    // gzip=true sends a .gz file compressed on the fly, whatever the Accept-Encoding
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ndjson,
+    Csv,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Format::Ndjson => "application/x-ndjson",
+            Format::Csv => "text/csv",
+        }
+    }
+}
+
+// EXPORT_PAGE_SIZE, the entries read per query, each page is sent before the next is read
+fn page_size() -> i64 {
+    env::var("EXPORT_PAGE_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE)
+}
+
+fn table() -> String {
+    env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into())
+}
+
+// the state carried from one page to the next
+struct Export {
+    data: web::Data<AppState>,
+    domain_id: i32,
+    status: Option<&'static str>,
+    format: Format,
+    after_id: i64,
+    page_size: i64,
+    // what is left of ?limit=
+    remaining: Option<i64>,
+    // one read connection for the whole export instead of one per page
+    pg: Option<tokio_postgres::Client>,
+    gzip: Option<GzEncoder<Vec<u8>>>,
+    started: bool,
+    done: bool,
+}
+
+// one keyset page: the entries after the cursor in id order, so every page is an index range scan however deep
+// into the list it is, where an OFFSET would read and skip all the rows before it
+async fn fetch_page(export: &mut Export) -> Result<Vec<ExportRow>, String> {
+    let effective = effective_status_sql("");
+    let limit = export.remaining.map_or(export.page_size, |remaining| remaining.min(export.page_size));
+    let (domain_id, after_id, status) = (export.domain_id, export.after_id, export.status);
+
+    match &export.data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(&export.data, pool, |pool| {
+                let effective = effective.clone();
+                async move {
+                    sqlx::query_as::<_, ExportRow>(&format!(
+                        r#"SELECT id, email, {effective} AS status, category, reason_summary, expires_at, created_at
+                           FROM blacklist WHERE domain_id = ? AND id > ? AND (? IS NULL OR {effective} = ?)
+                           ORDER BY id LIMIT ?"#,
+                        effective = effective
+                    ))
+                        .bind(domain_id)
+                        .bind(after_id)
+                        .bind(status)
+                        .bind(status)
+                        .bind(limit)
+                        .fetch_all(&pool)
+                        .await
+                }
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            if export.pg.is_none() {
+                export.pg = Some(build_pg_read_client(&export.data).await.map_err(|err| err.to_string())?);
+            }
+            let client = export.pg.as_ref().expect("connected above");
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, email, {effective} AS status, category, reason_summary, expires_at, created_at
+                           FROM {table} WHERE domain_id = $1 AND id > $2 AND ($3::text IS NULL OR {effective} = $3)
+                           ORDER BY id LIMIT $4"#,
+                        effective = effective,
+                        table = table()
+                    ),
+                    &[&domain_id, &after_id, &status, &limit],
+                )
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| ExportRow {
+                            id: row.get("id"),
+                            email: row.get("email"),
+                            status: row.get("status"),
+                            category: row.get("category"),
+                            reason_summary: row.get("reason_summary"),
+                            expires_at: row.get("expires_at"),
+                            created_at: row.get("created_at"),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+fn encode(format: Format, row: &ExportRow, out: &mut Vec<u8>) {
+    match format {
+        Format::Ndjson => {
+            out.extend_from_slice(&serde_json::to_vec(row).unwrap_or_default());
+            out.push(b'\n');
+        }
+        Format::Csv => {
+            let optional = |value: &Option<String>| csv_field(value.as_deref().unwrap_or_default());
+            out.extend_from_slice(
+                format!(
+                    "{},{},{},{},{},{},{}\n",
+                    row.id,
+                    csv_field(&row.email),
+                    row.status,
+                    optional(&row.category),
+                    optional(&row.reason_summary),
+                    row.expires_at.map(|at| at.to_string()).unwrap_or_default(),
+                    row.created_at
+                )
+                    .as_bytes(),
+            );
+        }
+    }
+}
+
+// the next chunk of the body: one page, gzipped when asked. A failed page ends the export, with an NDJSON line
+// carrying the cursor to resume from (a CSV export just stops, its last id is the cursor).
+async fn next_chunk(mut export: Export) -> Option<(Result<Bytes, actix_web::Error>, Export)> {
+    if export.done {
+        return None;
+    }
+
+    let mut out = Vec::new();
+
+    if !export.started && export.format == Format::Csv {
+        out.extend_from_slice(CSV_HEADER.as_bytes());
+    }
+    export.started = true;
+
+    match fetch_page(&mut export).await {
+        Ok(rows) => {
+            for row in &rows {
+                encode(export.format, row, &mut out);
+            }
+
+            export.remaining = export.remaining.map(|remaining| remaining - rows.len() as i64);
+            export.done = (rows.len() as i64) < export.page_size || export.remaining == Some(0);
+            if let Some(last) = rows.last() {
+                export.after_id = last.id;
+            }
+        }
+        Err(err) => {
+            println!("🔥 Export of domain {} failed after id {}: {}", export.domain_id, export.after_id, err);
+
+            if export.format == Format::Ndjson {
+                let line = serde_json::json!({ "error": err, "after_id": export.after_id });
+                out.extend_from_slice(line.to_string().as_bytes());
+                out.push(b'\n');
+            }
+            export.done = true;
+        }
+    }
+
+    if let Some(encoder) = export.gzip.as_mut() {
+        // a sync flush makes everything written so far decodable, the client sees the rows as they arrive
+        if let Err(err) = encoder.write_all(&out).and_then(|_| encoder.flush()) {
+            return Some((Err(actix_web::error::ErrorInternalServerError(err)), export));
+        }
+        out = std::mem::take(encoder.get_mut());
+
+        if export.done {
+            match export.gzip.take().expect("checked above").finish() {
+                Ok(trailer) => out.extend_from_slice(&trailer),
+                Err(err) => return Some((Err(actix_web::error::ErrorInternalServerError(err)), export)),
+            }
+        }
+    }
+
+    Some((Ok(Bytes::from(out)), export))
+}
+
+// streams every entry of the domain in id order, one page in memory at a time, so the size of the list only sets
+// how long the transfer takes. A transfer cut midway is resumed with ?after_id= set to the last id received.
+// Without gzip=true the response is compressed by the Compress middleware when the client accepts it.
+pub async fn export_handler(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<ExportQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain_id = path.into_inner();
+
+    let format = match query.format.as_deref() {
+        None | Some("ndjson") => Format::Ndjson,
+        Some("csv") => Format::Csv,
+        Some(other) => {
+            return HttpResponse::BadRequest()
+                .json(ErrorResponse::new(format!("unknown format {:?}, use ndjson or csv", other)));
+        }
+    };
+
+    let status = match query.status.as_deref() {
+        None => None,
+        Some(status) => match EntryStatus::parse(status) {
+            Some(status) => Some(status.as_str()),
+            None => {
+                return HttpResponse::BadRequest().json(ErrorResponse::new(format!("unknown status {:?}", status)));
+            }
+        },
+    };
+
+    let after_id = query.after_id.unwrap_or(0);
+    println!("🚀 Exporting the blacklist of domain {} as {} after id {}", domain_id, format.extension(), after_id);
+
+    let export = Export {
+        data,
+        domain_id,
+        status,
+        format,
+        after_id,
+        page_size: page_size(),
+        remaining: query.limit.map(|limit| limit.max(0)),
+        pg: None,
+        gzip: query.gzip.then(|| GzEncoder::new(Vec::new(), Compression::fast())),
+        started: false,
+        done: false,
+    };
+
+    let body = stream::unfold(export, next_chunk);
+    let mut response = HttpResponse::Ok();
+
+    if query.gzip {
+        // already compressed, the identity encoding keeps the Compress middleware off it
+        response
+            .content_type("application/gzip")
+            .insert_header((header::CONTENT_ENCODING, ContentEncoding::Identity.to_header_value()))
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"blacklist-{}-after-{}.{}.gz\"", domain_id, after_id, format.extension()),
+            ));
+    } else {
+        response.content_type(format.content_type());
+    }
+
+    response.streaming(body)
+}
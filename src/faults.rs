@@ -0,0 +1,130 @@
+// Fault injection for resilience testing, compiled in only with `cargo build --features fault-injection`, never in
+// production builds. /api/admin/faults sets artificial latency before the blacklist reads and writes, a share of
+// inserts that fail and a share of webhook deliveries that are dropped, to see the SNS retries, the dead letters
+// and the webhook retries at work. Without the feature the hooks below are no-ops the compiler removes.
+
+#[cfg(feature = "fault-injection")]
+pub use injection::*;
+
+#[cfg(not(feature = "fault-injection"))]
+pub fn configure(_cfg: &mut actix_web::web::ServiceConfig) {}
+
+#[cfg(not(feature = "fault-injection"))]
+pub async fn db_latency() {}
+
+#[cfg(not(feature = "fault-injection"))]
+pub fn insert_failure() -> Option<String> {
+    None
+}
+
+#[cfg(not(feature = "fault-injection"))]
+pub fn drop_webhook() -> bool {
+    false
+}
+
+#[cfg(feature = "fault-injection")]
+mod injection {
+    use std::sync::{Once, OnceLock, RwLock};
+    use std::time::Duration;
+
+    use actix_web::{web, HttpRequest, HttpResponse, Responder};
+    use rand::Rng;
+    use serde::{Deserialize, Serialize};
+
+    use crate::handlers::is_admin;
+    use crate::responses::ErrorResponse;
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct Faults {
+        // added before every blacklist lookup that misses the cache and every insert
+        #[serde(default)]
+        pub db_latency_ms: u64,
+        // share of inserts failing, 0.0 to 1.0
+        #[serde(default)]
+        pub insert_failure_rate: f64,
+        // share of webhook deliveries dropped as if the receiver failed, 0.0 to 1.0
+        #[serde(default)]
+        pub webhook_drop_rate: f64,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct FaultsResponse {
+        pub success: bool,
+        pub data: Faults,
+    }
+
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        // configured once per worker, warned once
+        static WARNED: Once = Once::new();
+        WARNED.call_once(|| println!("⚠️ Built with fault injection, /api/admin/faults is enabled"));
+
+        cfg.service(
+            web::resource("/api/admin/faults")
+                .route(web::get().to(get_handler))
+                .route(web::put().to(put_handler))
+                .route(web::delete().to(delete_handler)),
+        );
+    }
+
+    fn faults() -> &'static RwLock<Faults> {
+        static FAULTS: OnceLock<RwLock<Faults>> = OnceLock::new();
+        FAULTS.get_or_init(Default::default)
+    }
+
+    fn happens(rate: f64) -> bool {
+        rate > 0.0 && rand::thread_rng().gen::<f64>() < rate
+    }
+
+    pub async fn db_latency() {
+        let latency = faults().read().unwrap().db_latency_ms;
+
+        if latency > 0 {
+            tokio::time::sleep(Duration::from_millis(latency)).await;
+        }
+    }
+
+    pub fn insert_failure() -> Option<String> {
+        happens(faults().read().unwrap().insert_failure_rate).then(|| "insert failed by fault injection".to_string())
+    }
+
+    pub fn drop_webhook() -> bool {
+        happens(faults().read().unwrap().webhook_drop_rate)
+    }
+
+    pub async fn get_handler(req: HttpRequest) -> impl Responder {
+        if !is_admin(&req) {
+            return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+        }
+
+        HttpResponse::Ok().json(FaultsResponse { success: true, data: faults().read().unwrap().clone() })
+    }
+
+    // replaces the faults of this instance, the fields left out are off
+    pub async fn put_handler(req: HttpRequest, body: web::Json<Faults>) -> impl Responder {
+        if !is_admin(&req) {
+            return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+        }
+
+        let rates = [body.insert_failure_rate, body.webhook_drop_rate];
+        if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+            return HttpResponse::BadRequest().json(ErrorResponse::new("rates must be between 0.0 and 1.0"));
+        }
+
+        let faults_set = body.into_inner();
+        println!("⚠️ Fault injection set to {:?}", faults_set);
+        *faults().write().unwrap() = faults_set.clone();
+
+        HttpResponse::Ok().json(FaultsResponse { success: true, data: faults_set })
+    }
+
+    pub async fn delete_handler(req: HttpRequest) -> impl Responder {
+        if !is_admin(&req) {
+            return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+        }
+
+        println!("✅ Fault injection cleared");
+        *faults().write().unwrap() = Faults::default();
+
+        HttpResponse::Ok().json(FaultsResponse { success: true, data: Faults::default() })
+    }
+}
@@ -0,0 +1,201 @@
+use std::env;
+
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api_keys::{self, ApiKey};
+use crate::events::{self, Event};
+use crate::lookup_audit;
+use crate::privacy;
+use crate::repo;
+use crate::responses::{BatchLookupResult, ErrorResponse, LookupResponse};
+use crate::AppState;
+
+// carries the result of HEAD lookups
+const BLACKLISTED_HEADER: &str = "X-Blacklisted";
+
+// failed lookups answered nothing, so only results are audited
+pub async fn audit(domain_id: i32, email: &str, blacklisted: Option<bool>, api_key: Option<&ApiKey>, data: &web::Data<AppState>) {
+    if let Some(blacklisted) = blacklisted {
+        events::publish(data, Event::LookupPerformed { domain_id, lookups: 1, blacklisted: blacklisted as usize });
+        lookup_audit::record(domain_id, &privacy::stored_email(email), blacklisted, api_key.map(|key| key.id), data).await;
+    }
+}
+
+pub async fn is_email_blacklisted(
+    req: HttpRequest,
+    path: web::Path<(i32, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let (domain_id, email) = path.into_inner();
+
+    let api_key = match api_keys::meter(&req, &data, domain_id).await {
+        Ok(api_key) => api_key,
+        Err(response) => return response,
+    };
+
+    let result = repo::lookup_status(domain_id, &email, &data).await;
+    let blacklisted = result.as_ref().ok().map(|status| status.is_some_and(|status| status.suppresses()));
+    audit(domain_id, &email, blacklisted, api_key.as_ref(), &data).await;
+
+    match result {
+        Ok(status) => {
+            HttpResponse::Ok().json(LookupResponse::new(status))
+        }
+        Err(err) => {
+            HttpResponse::InternalServerError().json(ErrorResponse::new(err))
+        }
+    }
+}
+
+// bodyless variant of the lookup for callers that only need the boolean: 200 when blacklisted, 404 otherwise
+pub async fn head_email_blacklisted(
+    req: HttpRequest,
+    path: web::Path<(i32, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let (domain_id, email) = path.into_inner();
+
+    let api_key = match api_keys::meter(&req, &data, domain_id).await {
+        Ok(api_key) => api_key,
+        Err(response) => return response,
+    };
+
+    let result = repo::lookup(domain_id, &email, &data).await;
+    audit(domain_id, &email, result.as_ref().ok().copied(), api_key.as_ref(), &data).await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().insert_header((BLACKLISTED_HEADER, "true")).finish(),
+        Ok(false) => HttpResponse::NotFound().insert_header((BLACKLISTED_HEADER, "false")).finish(),
+        Err(err) => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// batches are checked in chunks of BATCH_LOOKUP_CHUNK_SIZE addresses, one query each,
+// with at most BATCH_LOOKUP_CONCURRENCY chunks in flight so the pool keeps connections for the SNS endpoint
+const DEFAULT_CHUNK_SIZE: usize = 1000;
+const MAX_CHUNK_SIZE: usize = 10_000;
+const DEFAULT_CONCURRENCY: usize = 4;
+const BATCH_LIMIT: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchLookup {
+    pub emails: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BatchLookupQuery {
+    // `blacklisted` or `clean` only streams those addresses, the others are left out
+    pub only: Option<String>,
+}
+
+pub fn batch_config() -> web::JsonConfig {
+    web::JsonConfig::default().limit(BATCH_LIMIT)
+}
+
+fn env_number(var: &str, default: usize) -> usize {
+    env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+fn ndjson_line<T: Serialize>(value: &T) -> Bytes {
+    let mut line = serde_json::to_vec(value).unwrap_or_default();
+    line.push(b'\n');
+    Bytes::from(line)
+}
+
+async fn check_chunk(
+    domain_id: i32,
+    emails: Vec<String>,
+    only: Option<bool>,
+    api_key_id: Option<i64>,
+    data: web::Data<AppState>,
+) -> Bytes {
+    let stored: Vec<String> = emails.iter().map(|email| privacy::stored_email(email)).collect();
+
+    let blacklisted = match repo::lookup_many(domain_id, &stored, &data).await {
+        Ok(blacklisted) => blacklisted,
+        Err(err) => {
+            println!("{}", err);
+            return ndjson_line(&ErrorResponse::new(format!("lookup of {} addresses failed: {}", emails.len(), err)));
+        }
+    };
+
+    let results: Vec<(String, bool)> = stored
+        .into_iter()
+        .map(|email| {
+            let found = blacklisted.contains(&email);
+            (email, found)
+        })
+        .collect();
+
+    events::publish(
+        &data,
+        Event::LookupPerformed {
+            domain_id,
+            lookups: results.len(),
+            blacklisted: results.iter().filter(|(_, found)| *found).count(),
+        },
+    );
+    lookup_audit::record_many(domain_id, &results, api_key_id, &data).await;
+
+    let mut body = Vec::new();
+
+    for (email, (_, blacklisted)) in emails.into_iter().zip(results) {
+        if only.is_none_or(|only| only == blacklisted) {
+            body.extend_from_slice(&ndjson_line(&BatchLookupResult { email, blacklisted }));
+        }
+    }
+
+    Bytes::from(body)
+}
+
+// checks a list of addresses and streams one NDJSON line per address in input order, as chunks complete;
+// a failed chunk yields an error line instead of its addresses
+pub async fn batch_lookup(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<BatchLookupQuery>,
+    body: web::Json<BatchLookup>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    let api_key = match api_keys::meter(&req, &data, domain_id).await {
+        Ok(api_key) => api_key,
+        Err(response) => return response,
+    };
+
+    let only = match query.only.as_deref() {
+        None => None,
+        Some("blacklisted") => Some(true),
+        Some("clean") => Some(false),
+        Some(other) => {
+            return HttpResponse::BadRequest()
+                .json(ErrorResponse::new(format!("unknown value {:?} for only, use blacklisted or clean", other)));
+        }
+    };
+
+    let api_key_id = api_key.map(|key| key.id);
+    let chunk_size = env_number("BATCH_LOOKUP_CHUNK_SIZE", DEFAULT_CHUNK_SIZE).clamp(1, MAX_CHUNK_SIZE);
+    let concurrency = env_number("BATCH_LOOKUP_CONCURRENCY", DEFAULT_CONCURRENCY).max(1);
+
+    let chunks: Vec<Vec<String>> = body
+        .into_inner()
+        .emails
+        .chunks(chunk_size)
+        .map(|chunk| chunk.to_vec())
+        .collect();
+
+    let lines = stream::iter(chunks)
+        .map(move |chunk| check_chunk(domain_id, chunk, only, api_key_id, data.clone()))
+        .buffered(concurrency)
+        .map(Ok::<_, actix_web::Error>);
+
+    HttpResponse::Ok().content_type("application/x-ndjson").streaming(lines)
+}
@@ -0,0 +1,14 @@
+use actix_web::{HttpResponse, Responder};
+use utoipa::OpenApi;
+
+use crate::responses::{ApiDoc, HealthResponse};
+
+pub async fn health_checker_handler() -> impl Responder {
+    const MESSAGE: &str = "SES Blacklist API is running!";
+
+    HttpResponse::Ok().json(HealthResponse { status: "success".into(), message: MESSAGE.into() })
+}
+
+pub async fn openapi_handler() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
@@ -0,0 +1,139 @@
+use std::env;
+use std::net::IpAddr;
+
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use openssl::memcmp;
+use sha2::{Digest, Sha256};
+
+use crate::auth_failures;
+
+pub mod blacklist;
+pub mod health;
+pub mod sns;
+pub mod v2;
+
+// admin endpoints are only enabled when ADMIN_TOKEN is set and must be called with `Authorization: Bearer <ADMIN_TOKEN>`
+pub fn is_admin(req: &HttpRequest) -> bool {
+    let Ok(token) = env::var("ADMIN_TOKEN") else {
+        return false;
+    };
+
+    if token.is_empty() {
+        return false;
+    }
+
+    // a locked out source is refused the right token as well, see auth_failures
+    if bearer_token(req).is_some() && auth_failures::locked_out(req).is_some() {
+        return false;
+    }
+
+    match bearer_token(req) {
+        Some(value) if token_matches(value, &token) => true,
+        Some(_) => {
+            auth_failures::record(req, None, "invalid_admin_token");
+            false
+        }
+        None => false,
+    }
+}
+
+// in constant time, the digests are compared so neither the content nor the length of the token leaks through timing
+fn token_matches(given: &str, token: &str) -> bool {
+    memcmp::eq(&Sha256::digest(given.as_bytes()), &Sha256::digest(token.as_bytes()))
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// the token comparison of is_admin alone, for middleware running ahead of the handler: it neither records a failure
+// nor checks the lockout, the handler's is_admin still does both
+pub fn has_admin_token(req: &HttpRequest) -> bool {
+    match env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => bearer_token(req).is_some_and(|value| token_matches(value, &token)),
+        _ => false,
+    }
+}
+
+// the peer address, or with TRUST_PROXY_HEADERS=true behind a load balancer the last X-Forwarded-For hop, the one
+// the load balancer appended; the earlier hops are whatever the client sent
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    if env::var("TRUST_PROXY_HEADERS").as_deref() == Ok("true") {
+        let forwarded = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|hop| hop.trim().parse().ok());
+
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> HttpRequest {
+        request_from("192.0.2.1:4000", authorization)
+    }
+
+    fn request_from(peer: &str, authorization: Option<&str>) -> HttpRequest {
+        let request = TestRequest::default().peer_addr(peer.parse().unwrap());
+
+        match authorization {
+            Some(value) => request.insert_header((header::AUTHORIZATION, value)).to_http_request(),
+            None => request.to_http_request(),
+        }
+    }
+
+    #[test]
+    fn bearer_token_is_read_from_the_authorization_header() {
+        assert_eq!(bearer_token(&request(Some("Bearer s3cret"))), Some("s3cret"));
+        assert_eq!(bearer_token(&request(Some("Bearer "))), Some(""));
+
+        assert_eq!(bearer_token(&request(None)), None);
+        assert_eq!(bearer_token(&request(Some("Basic czNjcmV0"))), None);
+        assert_eq!(bearer_token(&request(Some("bearer s3cret"))), None);
+    }
+
+    // the only test reading ADMIN_TOKEN, the cases run in sequence so none sees another's value
+    #[test]
+    fn is_admin_requires_the_configured_token() {
+        env::remove_var("ADMIN_TOKEN");
+        assert!(!is_admin(&request(Some("Bearer s3cret"))));
+
+        // an empty token disables the admin endpoints instead of matching an empty bearer
+        env::set_var("ADMIN_TOKEN", "");
+        assert!(!is_admin(&request(Some("Bearer "))));
+
+        env::set_var("ADMIN_TOKEN", "s3cret");
+        assert!(is_admin(&request(Some("Bearer s3cret"))));
+        assert!(has_admin_token(&request(Some("Bearer s3cret"))));
+
+        assert!(!is_admin(&request(Some("Bearer s3cre"))));
+        assert!(!is_admin(&request(Some("Bearer s3cret "))));
+        assert!(!is_admin(&request(Some("s3cret"))));
+        assert!(!is_admin(&request(None)));
+
+        // a locked out source is refused the right token too, the others are not
+        for _ in 0..100 {
+            assert!(!is_admin(&request_from("192.0.2.2:4000", Some("Bearer guess"))));
+        }
+        assert!(auth_failures::locked_out(&request_from("192.0.2.2:4000", None)).is_some());
+        assert!(!is_admin(&request_from("192.0.2.2:4000", Some("Bearer s3cret"))));
+        assert!(is_admin(&request(Some("Bearer s3cret"))));
+
+        env::remove_var("ADMIN_TOKEN");
+    }
+}
@@ -0,0 +1,89 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+
+use crate::dead_letters;
+use crate::responses::StatusResponse;
+use crate::services::notifications::{process_notification, to_completion};
+use crate::payload::SnsPayload;
+use crate::sns::VerifiedSnsMessage;
+use crate::topic_mappings;
+use crate::AppState;
+
+// sent by SNS on every HTTP delivery, the only place raw deliveries name their topic
+const TOPIC_ARN_HEADER: &str = "x-amz-sns-topic-arn";
+
+pub async fn handle_sns_notification(
+    path: web::Path<i32>,
+    message: VerifiedSnsMessage,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    process(path.into_inner(), message, &data).await
+}
+
+// the shared endpoint: the domain comes from the topic_mappings of the delivering topic, so topics can be
+// rearranged without new subscription URLs
+pub async fn handle_shared_sns_notification(
+    req: HttpRequest,
+    message: VerifiedSnsMessage,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let topic_arn = match &message.payload {
+        Ok(SnsPayload::Envelope(notification)) => notification.topic_arn.clone(),
+        _ => None,
+    }
+        .or_else(|| {
+            req.headers()
+                .get(TOPIC_ARN_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        });
+
+    let Some(topic_arn) = topic_arn else {
+        println!("🔥 Rejected SNS notification on the shared endpoint without a topic ARN");
+        return HttpResponse::BadRequest().json(StatusResponse::fail("the notification names no SNS topic"));
+    };
+
+    match topic_mappings::domain_for(&topic_arn, &data).await {
+        Ok(Some(domain_id)) => process(domain_id, message, &data).await,
+        // not 2xx, so SNS redelivers once the topic is mapped
+        Ok(None) => {
+            println!("🔥 SNS topic {} has no domain mapping", topic_arn);
+            HttpResponse::NotFound().json(StatusResponse::fail(format!("SNS topic {} is not mapped to a domain", topic_arn)))
+        }
+        Err(err) => {
+            println!("🔥 Failed to resolve the domain of SNS topic {}: {}", topic_arn, err);
+            HttpResponse::ServiceUnavailable().json(StatusResponse::error("failed to resolve the domain of the topic"))
+        }
+    }
+}
+
+async fn process(domain_id: i32, message: VerifiedSnsMessage, data: &web::Data<AppState>) -> HttpResponse {
+    let data = data.clone();
+
+    // processed and dead-lettered to the end even when SNS stops waiting for the answer
+    let processed = to_completion(async move {
+        let result = match message.payload {
+            Ok(payload) => process_notification(domain_id, payload, &data).await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(response) => response,
+            Err(err) if !message.verified && !dead_letters::admit_unverified() => {
+                println!("🔥 Refused unverified SNS notification of domain {} over the dead letter limit: {}", domain_id, err);
+                HttpResponse::BadRequest().json(StatusResponse::fail(err))
+            }
+            Err(err) => {
+                println!("Received SNS notification error: {} with bytes: {:?}", err, message.body);
+                dead_letters::store(domain_id, &String::from_utf8_lossy(&message.body), &err, &data).await;
+                HttpResponse::Ok().body("ok")
+            }
+        }
+    })
+    .await;
+
+    // not 2xx, so SNS redelivers and the notification resumes where it stopped
+    processed.unwrap_or_else(|err| {
+        println!("🔥 {} for domain {}", err, domain_id);
+        HttpResponse::InternalServerError().json(StatusResponse::error(err))
+    })
+}
@@ -0,0 +1,236 @@
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::{middleware, web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::api_keys::{self, MeterError};
+use crate::blacklist::{self, ManualEntry, StatusChange};
+use crate::deadline;
+use crate::domains;
+use crate::handlers::blacklist::audit;
+use crate::handlers::is_admin;
+use crate::http_cache;
+use crate::idempotency;
+use crate::privacy;
+use crate::repo::{self, status, status::TransitionError, EntryStatus};
+use crate::reputation::{self, Reputation};
+use crate::responses::{Envelope, ErrorEnvelope};
+use crate::verification;
+use crate::AppState;
+
+// /api/v2: resources under /domains/{domain_id}, snake_case fields, every answer in an Envelope or an ErrorEnvelope.
+// The v1 routes keep their shapes for the existing integrations.
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Suppression {
+    pub domain_id: i32,
+    pub email: String,
+    pub blacklisted: bool,
+    // absent when the address has no entry
+    pub status: Option<EntryStatus>,
+    // e.g. "Hard bounce: user unknown (5.1.1)", absent without an entry
+    pub reason_summary: Option<String>,
+    // of the message that caused the entry, when SES sent its headers
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreatedSuppression {
+    pub domain_id: i32,
+    pub email: String,
+    pub category: String,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StatusTransition {
+    pub domain_id: i32,
+    pub email: String,
+    pub from: EntryStatus,
+    pub to: EntryStatus,
+    // set when the address still looks undeliverable and EMAIL_VERIFICATION_MODE=warn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorEnvelope::new(code, message))
+}
+
+fn unauthorized() -> HttpResponse {
+    error(StatusCode::UNAUTHORIZED, "unauthorized", "admin token required")
+}
+
+fn database_error(err: impl std::fmt::Display) -> HttpResponse {
+    println!("🔥 Failed to query the database: {}", err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database_error", "failed to query the database")
+}
+
+fn meter_error(err: MeterError) -> HttpResponse {
+    if let MeterError::Database(err) = &err {
+        return database_error(err);
+    }
+
+    let mut response = HttpResponse::build(err.status());
+
+    if let Some(retry_after) = err.retry_after() {
+        response.insert_header(("Retry-After", retry_after.to_string()));
+    }
+
+    response.json(ErrorEnvelope::new(err.code(), err.to_string()))
+}
+
+// malformed bodies and paths answer the error envelope instead of actix' plain text
+fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _| {
+        let response = error(StatusCode::BAD_REQUEST, "invalid_body", err.to_string());
+        InternalError::from_response(err, response).into()
+    })
+}
+
+fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|err, _| {
+        let response = error(StatusCode::NOT_FOUND, "invalid_path", err.to_string());
+        InternalError::from_response(err, response).into()
+    })
+}
+
+async fn not_found() -> HttpResponse {
+    error(StatusCode::NOT_FOUND, "not_found", "no such resource")
+}
+
+pub async fn get_suppression(req: HttpRequest, path: web::Path<(i32, String)>, data: web::Data<AppState>) -> impl Responder {
+    let (domain_id, email) = path.into_inner();
+
+    let api_key = match api_keys::authorize(&req, &data, domain_id).await {
+        Ok(api_key) => api_key,
+        Err(err) => return meter_error(err),
+    };
+
+    let result = repo::lookup_status(domain_id, &email, &data).await;
+    let blacklisted = result.as_ref().ok().map(|status| status.is_some_and(|status| status.suppresses()));
+    audit(domain_id, &email, blacklisted, api_key.as_ref(), &data).await;
+
+    let status = match result {
+        Ok(status) => status,
+        Err(err) => return database_error(err),
+    };
+
+    // only entries have details, so the lookups of clean addresses stay a single cached query
+    let (reason_summary, subject) = match status {
+        Some(_) => match repo::entry_details(domain_id, &email, &data).await {
+            Ok(details) => details,
+            Err(err) => return database_error(err),
+        },
+        None => (None, None),
+    };
+
+    HttpResponse::Ok().json(Envelope::new(Suppression {
+        domain_id,
+        email,
+        blacklisted: status.is_some_and(|status| status.suppresses()),
+        status,
+        reason_summary,
+        subject,
+    }))
+}
+
+pub async fn create_suppression(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<ManualEntry>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let domain_id = path.into_inner();
+    let settings = domains::load(domain_id, &data).await;
+    let entry = body.into_inner().into_new_entry(domain_id, &settings);
+
+    match blacklist::insert(&entry, "blacklist", &data).await {
+        Ok(_) => HttpResponse::Created().json(Envelope::new(CreatedSuppression {
+            domain_id,
+            email: entry.email,
+            category: entry.category,
+            expires_at: entry.expires_at,
+        })),
+        Err(err) if blacklist::is_duplicate(&err) => error(
+            StatusCode::CONFLICT,
+            "duplicate",
+            format!("blacklist entry already exists for: {}", entry.email),
+        ),
+        Err(err) => database_error(err),
+    }
+}
+
+pub async fn set_suppression_status(
+    req: HttpRequest,
+    path: web::Path<(i32, String)>,
+    body: web::Json<StatusChange>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let (domain_id, email) = path.into_inner();
+    let to = body.status;
+
+    let warning = match verification::before_removal(&email, to, body.skip_verification).await {
+        Ok(warning) => warning,
+        Err(err) => return error(StatusCode::UNPROCESSABLE_ENTITY, "undeliverable", err),
+    };
+
+    match status::transition(domain_id, &privacy::stored_email(&email), to, &data).await {
+        Ok(from) => HttpResponse::Ok().json(Envelope::new(StatusTransition { domain_id, email, from, to, warning })),
+        Err(err @ TransitionError::NotFound) => error(StatusCode::NOT_FOUND, "not_found", err.to_string()),
+        Err(err @ TransitionError::Invalid { .. }) => error(StatusCode::CONFLICT, "invalid_transition", err.to_string()),
+        Err(TransitionError::Database(err)) => database_error(err),
+    }
+}
+
+pub async fn get_reputation(req: HttpRequest, path: web::Path<i32>, data: web::Data<AppState>) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    if let Err(err) = api_keys::authorize(&req, &data, domain_id).await {
+        return meter_error(err);
+    }
+
+    match reputation::reputation(domain_id, &data).await {
+        Ok(reputation) => HttpResponse::Ok().json(Envelope::<Reputation>::new(reputation)),
+        Err(err) => database_error(err),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/v2")
+            .app_data(json_config())
+            .app_data(path_config())
+            .service(
+                web::resource("/domains/{domain_id}/suppressions")
+                    .wrap(middleware::from_fn(idempotency::check))
+                    .route(web::post().to(create_suppression)),
+            )
+            .service(
+                web::resource("/domains/{domain_id}/suppressions/{email}")
+                    .wrap(middleware::from_fn(deadline::lookup))
+                    .wrap(middleware::from_fn(http_cache::lookup))
+                    .route(web::get().to(get_suppression)),
+            )
+            .service(
+                web::resource("/domains/{domain_id}/suppressions/{email}/status")
+                    .route(web::put().to(set_suppression_status)),
+            )
+            .service(
+                web::resource("/domains/{domain_id}/reputation")
+                    .wrap(middleware::from_fn(deadline::lookup))
+                    .route(web::get().to(get_reputation)),
+            )
+            .default_service(web::to(not_found)),
+    );
+}
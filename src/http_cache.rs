@@ -0,0 +1,140 @@
+use std::env;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+
+// the answer of a lookup depends on the credentials it was asked with (api_keys::API_KEY_HEADER or the admin
+// token), a shared cache must keep one per caller
+const VARY: &str = "X-API-Key, Authorization";
+
+// query parameters the lookups read, with the value they default to; the others are dropped from the canonical URL
+const KNOWN_PARAMS: &[(&str, &str)] = &[("status", "active")];
+
+// LOOKUP_CACHE_MAX_AGE lets a CDN or reverse proxy in front of the lookups answer them for that many seconds, 0
+// (the default) leaves the answers without freshness so nothing serves them stale
+fn max_age() -> u64 {
+    env::var("LOOKUP_CACHE_MAX_AGE").ok().and_then(|value| value.parse().ok()).unwrap_or(0)
+}
+
+// LOOKUP_CANONICAL_REDIRECT=true answers a lookup URL that is not canonical with a 308 to the canonical one, so a
+// proxy keyed on the URL caches one answer per address instead of one per spelling
+fn redirects() -> bool {
+    env::var("LOOKUP_CANONICAL_REDIRECT").as_deref() == Ok("true")
+}
+
+// RFC 3986 pchar, kept as they are in the canonical path
+fn is_path_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&byte)
+}
+
+fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| if is_path_char(byte) { (byte as char).to_string() } else { format!("%{:02X}", byte) })
+        .collect()
+}
+
+// the segment as web::Path reads it, the router leaves %2F, %25 and %2B encoded in match_info
+fn decode_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// the address lowercased and trimmed as it is looked up, the known parameters sorted and without their defaults
+fn canonical(req: &ServiceRequest) -> Option<String> {
+    let email = req.match_info().get("email")?;
+    let path = req.uri().path();
+    let prefix = &path[..path.rfind('/')?];
+
+    let mut params: Vec<(String, String)> = url::form_urlencoded::parse(req.query_string().as_bytes())
+        .filter_map(|(name, value)| {
+            let value = value.trim().to_lowercase();
+            KNOWN_PARAMS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .filter(|(_, default)| *default != value)
+                .map(|(known, _)| (known.to_string(), value))
+        })
+        .collect();
+    params.sort();
+    params.dedup_by(|a, b| a.0 == b.0);
+
+    let email = decode_segment(email);
+    let mut url = format!("{}/{}", prefix, encode_segment(&email.trim().to_lowercase()));
+    if !params.is_empty() {
+        url.push('?');
+        url.push_str(&url::form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish());
+    }
+
+    Some(url)
+}
+
+fn requested(req: &ServiceRequest) -> String {
+    match req.query_string() {
+        "" => req.uri().path().to_string(),
+        query => format!("{}?{}", req.uri().path(), query),
+    }
+}
+
+// wraps the lookups of one address for the HTTP caches in front of the service: the answers vary on the
+// credentials, are fresh for LOOKUP_CACHE_MAX_AGE and errors are never stored
+pub async fn lookup(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let head = req.method() == Method::HEAD;
+
+    if redirects() && matches!(*req.method(), Method::GET | Method::HEAD) {
+        if let Some(canonical) = canonical(&req).filter(|canonical| *canonical != requested(&req)) {
+            let response = HttpResponse::PermanentRedirect()
+                .insert_header((header::LOCATION, canonical))
+                .insert_header((header::CACHE_CONTROL, "max-age=86400"))
+                .finish();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    let mut res = next.call(req).await?;
+    let status = res.status();
+    let headers = res.headers_mut();
+
+    headers.append(header::VARY, HeaderValue::from_static(VARY));
+
+    // a clean address answers 404 to HEAD, as much an answer as a 200
+    let answer = status == StatusCode::OK || (head && status == StatusCode::NOT_FOUND);
+    let cache_control = match max_age() {
+        _ if !answer => Some("no-store".to_string()),
+        0 => None,
+        max_age => Some(format!("max-age={max_age}, s-maxage={max_age}", max_age = max_age)),
+    };
+
+    if let Some(value) = cache_control.and_then(|value| HeaderValue::from_str(&value).ok()) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+
+    Ok(res.map_into_left_body())
+}
@@ -0,0 +1,354 @@
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{error, web, Error, HttpResponse};
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::blacklist::IMPORT_LIMIT;
+use crate::handlers::has_admin_token;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::{ErrorEnvelope, StatusResponse};
+use crate::AppState;
+
+const HEADER: &str = "Idempotency-Key";
+const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const MAX_KEY_LEN: usize = 255;
+// a reservation left behind by a process that died before answering is taken over after this
+const ABANDONED_AFTER_SECS: i64 = 300;
+const PURGE_EVERY: Duration = Duration::from_secs(600);
+
+// (fingerprint, status code, response body, age in seconds) of a stored key, no status while the first call runs
+type KeyRow = (String, Option<i32>, Option<String>, i64);
+
+fn table() -> String {
+    env::var("PG_IDEMPOTENCY_KEYS_TABLE").unwrap_or_else(|_| "idempotency_keys".into())
+}
+
+// a key is answered from the table for IDEMPOTENCY_TTL_HOURS, a day by default
+fn ttl_secs() -> i64 {
+    env::var("IDEMPOTENCY_TTL_HOURS").ok().and_then(|value| value.parse::<i64>().ok()).unwrap_or(24).max(1) * 3600
+}
+
+// the method, the path (so the domain) and the body, a key reused for another request is refused
+fn fingerprint(method: &str, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+async fn find(data: &web::Data<AppState>, key: &str, path: &str) -> Result<Option<KeyRow>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, KeyRow>(
+            r#"SELECT fingerprint, status_code, response, TIMESTAMPDIFF(SECOND, created_at, NOW())
+               FROM idempotency_keys WHERE idempotency_key = ? AND request_path = ?"#,
+        )
+            .bind(key)
+            .bind(path)
+            .fetch_optional(pool)
+            .await
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(
+                    &format!(
+                        r#"SELECT fingerprint, status_code, response, EXTRACT(EPOCH FROM NOW() - created_at)::BIGINT
+                           FROM {} WHERE idempotency_key = $1 AND request_path = $2"#,
+                        table()
+                    ),
+                    &[&key, &path],
+                )
+                .await
+                .map(|row| row.map(|row| (row.get(0), row.get(1), row.get(2), row.get(3))))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// claims the key for this call, false when another call holds it
+async fn reserve(data: &web::Data<AppState>, key: &str, path: &str, fingerprint: &str) -> Result<bool, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(
+            r#"INSERT IGNORE INTO idempotency_keys (idempotency_key, request_path, fingerprint) VALUES (?, ?, ?)"#,
+        )
+            .bind(key)
+            .bind(path)
+            .bind(fingerprint)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {} (idempotency_key, request_path, fingerprint) VALUES ($1, $2, $3)
+                           ON CONFLICT (idempotency_key, request_path) DO NOTHING"#,
+                        table()
+                    ),
+                    &[&key, &path, &fingerprint],
+                )
+                .await
+                .map(|inserted| inserted > 0)
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn complete(data: &web::Data<AppState>, key: &str, path: &str, status: i32, response: &str) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(
+            r#"UPDATE idempotency_keys SET status_code = ?, response = ? WHERE idempotency_key = ? AND request_path = ?"#,
+        )
+            .bind(status)
+            .bind(response)
+            .bind(key)
+            .bind(path)
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {} SET status_code = $1, response = $2 WHERE idempotency_key = $3 AND request_path = $4"#,
+                        table()
+                    ),
+                    &[&status, &response, &key, &path],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn release(data: &web::Data<AppState>, key: &str, path: &str) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"DELETE FROM idempotency_keys WHERE idempotency_key = ? AND request_path = ?"#)
+                .bind(key)
+                .bind(path)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(r#"DELETE FROM {} WHERE idempotency_key = $1 AND request_path = $2"#, table()),
+                    &[&key, &path],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn purge(data: &web::Data<AppState>) -> Result<u64, String> {
+    let ttl_secs = ttl_secs();
+
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(r#"DELETE FROM idempotency_keys WHERE created_at < NOW() - INTERVAL ? SECOND"#)
+            .bind(ttl_secs)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let ttl_secs = ttl_secs as f64;
+
+            client
+                .execute(&format!(r#"DELETE FROM {} WHERE created_at < NOW() - make_interval(secs => $1)"#, table()), &[&ttl_secs])
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// expired keys are deleted by the writes themselves, at most every PURGE_EVERY per instance
+async fn purge_if_due(data: &web::Data<AppState>) {
+    static PURGED_AT: OnceLock<Mutex<Instant>> = OnceLock::new();
+
+    {
+        let mut purged_at = PURGED_AT.get_or_init(|| Mutex::new(Instant::now())).lock().unwrap();
+        if purged_at.elapsed() < PURGE_EVERY {
+            return;
+        }
+        *purged_at = Instant::now();
+    }
+
+    if let Err(err) = purge(data).await {
+        println!("🔥 Failed to purge the idempotency keys: {}", err);
+    }
+}
+
+// the answers worth replaying, the ones a retry could change (throttling, timeouts, server errors) are not kept
+fn storable(status: StatusCode) -> bool {
+    !(status.is_server_error()
+        || matches!(
+            status,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+        ))
+}
+
+fn refuse(req: ServiceRequest, status: StatusCode, code: &str, message: String) -> ServiceResponse<BoxBody> {
+    let response = if req.path().starts_with("/api/v2/") {
+        HttpResponse::build(status).json(ErrorEnvelope::new(code, message))
+    } else {
+        HttpResponse::build(status).json(StatusResponse::fail(message))
+    };
+
+    req.into_response(response)
+}
+
+fn replay(req: ServiceRequest, status: i32, response: Option<String>) -> ServiceResponse<BoxBody> {
+    let status = u16::try_from(status).ok().and_then(|status| StatusCode::from_u16(status).ok()).unwrap_or(StatusCode::OK);
+    let response = HttpResponse::build(status)
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .insert_header((REPLAYED_HEADER, "true"))
+        .body(response.unwrap_or_default());
+
+    req.into_response(response)
+}
+
+async fn read_body(payload: &mut Payload) -> Result<Option<Bytes>, Error> {
+    let mut body = BytesMut::new();
+
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+
+        if body.len() > IMPORT_LIMIT {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(body.freeze()))
+}
+
+// An `Idempotency-Key` header on the manual writes makes a retry of the same request answer what the first call
+// answered, without inserting, auditing or counting anything again. The key is scoped to the path and bound to a
+// fingerprint of the request: reused for a different body it is refused with 422, while the first call is still
+// running a retry gets 409. Calls without the header, or without the admin token, go through untouched and the
+// handler answers them as before.
+pub async fn check(mut req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let key = req.headers().get(HEADER).map(|value| value.to_str().map(|key| key.trim().to_string()));
+
+    let key = match key {
+        Some(Ok(key)) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key,
+        Some(_) => {
+            let message = format!("{} must be 1 to {} visible ASCII characters", HEADER, MAX_KEY_LEN);
+            return Ok(refuse(req, StatusCode::BAD_REQUEST, "invalid_idempotency_key", message));
+        }
+        None => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+    };
+
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+
+    if !has_admin_token(req.request()) {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let Some(body) = read_body(req.parts_mut().1).await? else {
+        let message = format!("request body larger than {} bytes", IMPORT_LIMIT);
+        return Ok(refuse(req, StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", message));
+    };
+
+    let path = req.path().to_string();
+    let fingerprint = fingerprint(req.method().as_str(), &path, &body);
+    req.set_payload(Payload::from(body));
+
+    let reserved = match find(&data, &key, &path).await {
+        Ok(None) => reserve(&data, &key, &path, &fingerprint).await,
+        Ok(Some((_, status, _, age))) if age >= ttl_secs() || (status.is_none() && age >= ABANDONED_AFTER_SECS) => {
+            match release(&data, &key, &path).await {
+                Ok(()) => reserve(&data, &key, &path, &fingerprint).await,
+                Err(err) => Err(err),
+            }
+        }
+        Ok(Some((stored, _, _, _))) if stored != fingerprint => {
+            let message = format!("{} {:?} was already used for a different request", HEADER, key);
+            return Ok(refuse(req, StatusCode::UNPROCESSABLE_ENTITY, "idempotency_key_reused", message));
+        }
+        Ok(Some((_, Some(status), response, _))) => return Ok(replay(req, status, response)),
+        Ok(Some((_, None, _, _))) => Ok(false),
+        Err(err) => Err(err),
+    };
+
+    match reserved {
+        Ok(true) => {}
+        Ok(false) => {
+            let message = format!("a request with {} {:?} is still being processed", HEADER, key);
+            let mut response = refuse(req, StatusCode::CONFLICT, "idempotency_key_in_use", message);
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+            return Ok(response);
+        }
+        Err(err) => {
+            println!("🔥 Failed to look up idempotency key {:?}: {}", key, err);
+            let message = "idempotency keys are unavailable".to_string();
+            return Ok(refuse(req, StatusCode::SERVICE_UNAVAILABLE, "idempotency_unavailable", message));
+        }
+    }
+
+    let response = match next.call(req).await {
+        Ok(response) => response.map_into_boxed_body(),
+        Err(err) => {
+            if let Err(err) = release(&data, &key, &path).await {
+                println!("🔥 Failed to release idempotency key {:?}: {}", key, err);
+            }
+            return Err(err);
+        }
+    };
+
+    let status = response.status();
+
+    if !storable(status) {
+        if let Err(err) = release(&data, &key, &path).await {
+            println!("🔥 Failed to release idempotency key {:?}: {}", key, err);
+        }
+        return Ok(response);
+    }
+
+    let (req, response) = response.into_parts();
+    let (response, body) = response.into_parts();
+    let body = body::to_bytes(body).await.map_err(error::ErrorInternalServerError)?;
+
+    // the write is done, failing to store its answer only loses the replay
+    if let Err(err) = complete(&data, &key, &path, status.as_u16() as i32, &String::from_utf8_lossy(&body)).await {
+        println!("🔥 Failed to store the answer of idempotency key {:?}: {}", key, err);
+    }
+
+    purge_if_due(&data).await;
+
+    Ok(ServiceResponse::new(req, response.set_body(BoxBody::new(body))))
+}
@@ -0,0 +1,352 @@
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use futures_util::StreamExt;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::blacklist::{self, NewEntry};
+use crate::handlers::is_admin;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::ErrorResponse;
+use crate::AppState;
+
+const DEFAULT_SYNC_MAX_ROWS: usize = 1000;
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_MAX_JOBS: usize = 2;
+const MAX_ERRORS: usize = 10;
+const FLUSH_EVERY: Duration = Duration::from_secs(1);
+// a running job whose progress has not moved for this long belonged to a process that stopped
+const STALE_AFTER_SECS: i64 = 60;
+
+// jobs running in this instance, bounded by IMPORT_MAX_JOBS
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+fn table() -> String {
+    env::var("PG_IMPORT_JOBS_TABLE").unwrap_or_else(|_| "import_jobs".into())
+}
+
+fn env_usize(var: &str, default: usize) -> usize {
+    env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+// (id, domain_id, status, total, processed, inserted, skipped, failed, errors, created_at, updated_at,
+// finished_at, seconds since updated_at)
+type JobRow = (
+    i64,
+    i32,
+    String,
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    Option<String>,
+    NaiveDateTime,
+    NaiveDateTime,
+    Option<NaiveDateTime>,
+    i64,
+);
+
+// the progress of an import processed in the background
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportJob {
+    pub id: i64,
+    pub domain_id: i32,
+    // running, completed, or interrupted when the instance running it stopped before the end
+    pub status: String,
+    // rows in the request
+    pub total: i32,
+    pub processed: i32,
+    pub inserted: i32,
+    // already in the blacklist
+    pub skipped: i32,
+    pub failed: i32,
+    // the first failures, as in the summary of a synchronous import
+    pub errors: Vec<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+impl From<JobRow> for ImportJob {
+    fn from(row: JobRow) -> Self {
+        let (id, domain_id, status, total, processed, inserted, skipped, failed, errors, created_at, updated_at, finished_at, age) =
+            row;
+
+        ImportJob {
+            id,
+            domain_id,
+            status: match status.as_str() {
+                "running" if age > STALE_AFTER_SECS => "interrupted".into(),
+                _ => status,
+            },
+            total,
+            processed,
+            inserted,
+            skipped,
+            failed,
+            errors: errors.and_then(|errors| serde_json::from_str(&errors).ok()).unwrap_or_default(),
+            created_at,
+            updated_at,
+            finished_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportAccepted {
+    pub success: bool,
+    pub job_id: i64,
+    // GET it for the progress
+    pub status_url: String,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    processed: i32,
+    inserted: i32,
+    skipped: i32,
+    failed: i32,
+    errors: Vec<String>,
+}
+
+// imports of more than IMPORT_SYNC_MAX_ROWS rows, or sent with `Prefer: respond-async`, run in the background
+pub fn wants_async(req: &HttpRequest, rows: usize) -> bool {
+    let prefer_async = req
+        .headers()
+        .get_all("Prefer")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"));
+
+    prefer_async || rows > env_usize("IMPORT_SYNC_MAX_ROWS", DEFAULT_SYNC_MAX_ROWS)
+}
+
+// a slot among the IMPORT_MAX_JOBS of the instance, released when the job ends
+struct Slot;
+
+impl Slot {
+    fn acquire() -> Option<Slot> {
+        let max = env_usize("IMPORT_MAX_JOBS", DEFAULT_MAX_JOBS).max(1);
+        RUNNING
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| (running < max).then_some(running + 1))
+            .ok()
+            .map(|_| Slot)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn create(data: &web::Data<AppState>, domain_id: i32, total: i32) -> Result<i64, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(r#"INSERT INTO import_jobs (domain_id, status, total) VALUES (?, 'running', ?)"#)
+            .bind(domain_id)
+            .bind(total)
+            .execute(pool)
+            .await
+            .map(|result| result.last_insert_id() as i64)
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(
+                    &format!(r#"INSERT INTO {} (domain_id, status, total) VALUES ($1, 'running', $2) RETURNING id"#, table()),
+                    &[&domain_id, &total],
+                )
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// writes the counts, and marks the job completed once every row went through
+async fn save(data: &web::Data<AppState>, id: i64, progress: &Progress, done: bool) -> Result<(), String> {
+    let errors = serde_json::to_string(&progress.errors).map_err(|err| err.to_string())?;
+    let status = if done { "completed" } else { "running" };
+
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(
+            r#"UPDATE import_jobs SET status = ?, processed = ?, inserted = ?, skipped = ?, failed = ?, errors = ?,
+               updated_at = NOW(), finished_at = IF(? = 'completed', NOW(), NULL) WHERE id = ?"#,
+        )
+            .bind(status)
+            .bind(progress.processed)
+            .bind(progress.inserted)
+            .bind(progress.skipped)
+            .bind(progress.failed)
+            .bind(&errors)
+            .bind(status)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {} SET status = $1, processed = $2, inserted = $3, skipped = $4, failed = $5, errors = $6,
+                           updated_at = NOW(), finished_at = CASE WHEN $1 = 'completed' THEN NOW() END WHERE id = $7"#,
+                        table()
+                    ),
+                    &[&status, &progress.processed, &progress.inserted, &progress.skipped, &progress.failed, &errors, &id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn find(data: &web::Data<AppState>, domain_id: i32, id: i64) -> Result<Option<ImportJob>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, JobRow>(
+            r#"SELECT id, domain_id, status, total, processed, inserted, skipped, failed, errors, created_at, updated_at,
+                      finished_at, TIMESTAMPDIFF(SECOND, updated_at, NOW())
+               FROM import_jobs WHERE id = ? AND domain_id = ?"#,
+        )
+            .bind(id)
+            .bind(domain_id)
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.map(ImportJob::from))
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(
+                    &format!(
+                        r#"SELECT id, domain_id, status, total, processed, inserted, skipped, failed, errors, created_at,
+                                  updated_at, finished_at, EXTRACT(EPOCH FROM NOW() - updated_at)::BIGINT
+                           FROM {} WHERE id = $1 AND domain_id = $2"#,
+                        table()
+                    ),
+                    &[&id, &domain_id],
+                )
+                .await
+                .map(|row| {
+                    row.map(|row| {
+                        ImportJob::from((
+                            row.get(0),
+                            row.get(1),
+                            row.get(2),
+                            row.get(3),
+                            row.get(4),
+                            row.get(5),
+                            row.get(6),
+                            row.get(7),
+                            row.get(8),
+                            row.get(9),
+                            row.get(10),
+                            row.get(11),
+                            row.get(12),
+                        ))
+                    })
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// inserts IMPORT_CONCURRENCY rows at a time, so a large file does not take every connection of the pool from the
+// notifications, and writes the progress about every second
+async fn run(id: i64, entries: Vec<NewEntry>, data: web::Data<AppState>, _slot: Slot) {
+    let concurrency = env_usize("IMPORT_CONCURRENCY", DEFAULT_CONCURRENCY).max(1);
+    let mut progress = Progress::default();
+    let mut flushed = Instant::now();
+
+    let mut results = futures_util::stream::iter(entries)
+        .map(|entry| {
+            let data = data.clone();
+            async move {
+                let result = blacklist::insert_manual(&entry, &data).await;
+                (entry.email, result)
+            }
+        })
+        .buffer_unordered(concurrency);
+
+    while let Some((email, result)) = results.next().await {
+        progress.processed += 1;
+        match result {
+            Ok(_) => progress.inserted += 1,
+            Err(err) if blacklist::is_duplicate(&err) => progress.skipped += 1,
+            Err(err) => {
+                progress.failed += 1;
+                if progress.errors.len() < MAX_ERRORS {
+                    progress.errors.push(format!("{}: {}", email, err));
+                }
+            }
+        }
+
+        if flushed.elapsed() >= FLUSH_EVERY {
+            if let Err(err) = save(&data, id, &progress, false).await {
+                println!("🔥 Failed to save the progress of import job {}: {}", id, err);
+            }
+            flushed = Instant::now();
+        }
+    }
+
+    match save(&data, id, &progress, true).await {
+        Ok(_) => println!("Import job {} completed: {:?}", id, progress),
+        Err(err) => println!("🔥 Failed to complete import job {} ({:?}): {}", id, progress, err),
+    }
+}
+
+// records the job and answers 202 with its id, the rows are inserted in the background
+pub async fn enqueue(domain_id: i32, entries: Vec<NewEntry>, data: &web::Data<AppState>) -> HttpResponse {
+    let Some(slot) = Slot::acquire() else {
+        return HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, "60"))
+            .json(ErrorResponse::new("Too many imports running, retry later"));
+    };
+
+    let job_id = match create(data, domain_id, entries.len() as i32).await {
+        Ok(job_id) => job_id,
+        Err(err) => {
+            println!("🔥 Failed to create an import job for domain {}: {}", domain_id, err);
+            return HttpResponse::InternalServerError().json(ErrorResponse::new(err));
+        }
+    };
+
+    println!("Import job {} started for domain {}: {} rows", job_id, domain_id, entries.len());
+    tokio::spawn(run(job_id, entries, data.clone(), slot));
+
+    let status_url = format!("/api/{}/imports/{}", domain_id, job_id);
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, status_url.clone()))
+        .json(ImportAccepted { success: true, job_id, status_url })
+}
+
+pub async fn job_handler(req: HttpRequest, path: web::Path<(i32, i64)>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let (domain_id, job_id) = path.into_inner();
+
+    match find(&data, domain_id, job_id).await {
+        Ok(Some(job)) => HttpResponse::Ok().json(job),
+        Ok(None) => HttpResponse::NotFound().json(ErrorResponse::new("Import job not found")),
+        Err(err) => {
+            println!("🔥 Failed to read import job {}: {}", job_id, err);
+            HttpResponse::InternalServerError().json(ErrorResponse::new(err))
+        }
+    }
+}
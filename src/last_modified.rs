@@ -0,0 +1,124 @@
+use std::env;
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::header::{self, Header, HttpDate, IfModifiedSince, LastModified};
+use actix_web::HttpRequest;
+use chrono::{NaiveDateTime, Utc};
+
+use crate::repo::{build_pg_pool, build_pg_read_client, read_mysql, DBType};
+use crate::AppState;
+
+// (last write recorded, last expiry passed) of a domain
+type ModifiedRow = (Option<NaiveDateTime>, Option<NaiveDateTime>);
+
+fn table() -> String {
+    env::var("PG_BLACKLIST_MODIFICATIONS_TABLE").unwrap_or_else(|_| "blacklist_modifications".into())
+}
+
+fn blacklist_table() -> String {
+    env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into())
+}
+
+// records that the entries of the domain changed, called after every write to the blacklist. It is best effort: a
+// failure is logged and the write stands, the next change of the domain moves the timestamp again.
+pub async fn touch(domain_id: i32, data: &AppState) {
+    let now = Utc::now().naive_utc();
+
+    let result = match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"INSERT INTO blacklist_modifications (domain_id, modified_at) VALUES (?, ?)
+                   ON DUPLICATE KEY UPDATE modified_at = GREATEST(modified_at, VALUES(modified_at))"#,
+            )
+                .bind(domain_id)
+                .bind(now)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, modified_at) VALUES ($1, $2)
+                           ON CONFLICT (domain_id) DO UPDATE SET modified_at = GREATEST({table}.modified_at, EXCLUDED.modified_at)"#,
+                        table = table()
+                    ),
+                    &[&domain_id, &now],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Ok(()),
+    };
+
+    if let Err(err) = result {
+        println!("🔥 Failed to record the modification of the blacklist of domain {}: {:?}", domain_id, err);
+    }
+}
+
+// when the active entries of the domain last changed (UTC): the last write, or the last expiry that passed since,
+// as expiring entries leave the list without a write. None for a domain that never had an entry.
+pub async fn modified_at(domain_id: i32, data: &AppState) -> Result<Option<NaiveDateTime>, String> {
+    let (written, expired): ModifiedRow = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, ModifiedRow>(
+                    r#"SELECT (SELECT modified_at FROM blacklist_modifications WHERE domain_id = ?),
+                              (SELECT MAX(expires_at) FROM blacklist WHERE domain_id = ? AND expires_at <= NOW())"#,
+                )
+                    .bind(domain_id)
+                    .bind(domain_id)
+                    .fetch_one(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())?
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(
+                    &format!(
+                        r#"SELECT (SELECT modified_at FROM {modifications} WHERE domain_id = $1),
+                                  (SELECT MAX(expires_at) FROM {table} WHERE domain_id = $1 AND expires_at <= NOW())"#,
+                        modifications = table(),
+                        table = blacklist_table()
+                    ),
+                    &[&domain_id],
+                )
+                .await
+                .map(|row| (row.get(0), row.get(1)))
+                .map_err(|err| err.to_string())?
+        }
+        DBType::DynamoDB(_) => return Ok(None),
+    };
+
+    Ok(written.max(expired))
+}
+
+// HTTP dates have a resolution of one second
+fn http_time(modified_at: NaiveDateTime) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(modified_at.and_utc().timestamp().max(0) as u64)
+}
+
+pub fn header(modified_at: NaiveDateTime) -> LastModified {
+    LastModified(HttpDate::from(http_time(modified_at)))
+}
+
+// whether the If-Modified-Since of the request is at or after `modified_at`. If-None-Match takes precedence
+// (RFC 9110 13.2.2), so the date is ignored when the request carries an ETag to compare.
+pub fn not_modified(req: &HttpRequest, modified_at: NaiveDateTime) -> bool {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+
+    match IfModifiedSince::parse(req) {
+        Ok(IfModifiedSince(since)) => SystemTime::from(since) >= http_time(modified_at),
+        Err(_) => false,
+    }
+}
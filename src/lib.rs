@@ -0,0 +1,4 @@
+// the payload models and parsers without the server, so the fuzz targets (fuzz/) and the benchmarks (benches/) can
+// link them; the binary uses them from here as well
+pub mod domain;
+pub mod payload;
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// bounds how many notifications of the same domain are processed at once, so a bounce storm on one
+// domain cannot take all the capacity from the others
+#[derive(Debug, Default)]
+pub struct DomainLimiter {
+    semaphores: Mutex<HashMap<i32, Slots>>,
+    // notifications waiting for a slot, across domains
+    waiting: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Slots {
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    // permits beyond the limit after it was lowered while they were held, forgotten instead of returned
+    owed: Arc<AtomicUsize>,
+}
+
+impl Slots {
+    fn new(limit: usize) -> Self {
+        Slots { limit, semaphore: Arc::new(Semaphore::new(limit)), owed: Arc::default() }
+    }
+
+    // the limit was changed in the domains table: the semaphore is kept, so the permits held on it still count
+    fn resize(&mut self, limit: usize) {
+        let total = self.limit + self.owed.load(Ordering::SeqCst);
+
+        if limit >= total {
+            self.owed.store(0, Ordering::SeqCst);
+            self.semaphore.add_permits(limit - total);
+        } else {
+            let excess = total - limit;
+            let forgotten = self.semaphore.forget_permits(excess);
+            self.owed.store(excess - forgotten, Ordering::SeqCst);
+        }
+
+        self.limit = limit;
+    }
+}
+
+// a slot of a domain, released when dropped
+#[derive(Debug)]
+pub struct Permit {
+    permit: Option<OwnedSemaphorePermit>,
+    owed: Arc<AtomicUsize>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+
+        if self.owed.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |owed| owed.checked_sub(1)).is_ok() {
+            permit.forget();
+        }
+    }
+}
+
+// how busy the limiter is, for the autoscaling metrics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub in_use: usize,
+    pub waiting: usize,
+    // the slots of the domains with notifications in flight
+    pub capacity: usize,
+}
+
+impl DomainLimiter {
+    fn semaphore(&self, domain_id: i32, limit: usize) -> (Arc<Semaphore>, Arc<AtomicUsize>) {
+        let mut semaphores = self.semaphores.lock().unwrap();
+        let slots = semaphores.entry(domain_id).or_insert_with(|| Slots::new(limit));
+
+        if slots.limit != limit {
+            slots.resize(limit);
+        }
+
+        (slots.semaphore.clone(), slots.owed.clone())
+    }
+
+    // None when no slot became free within the timeout
+    pub async fn acquire(&self, domain_id: i32, limit: usize, timeout: Duration) -> Option<Permit> {
+        let (semaphore, owed) = self.semaphore(domain_id, limit.clamp(1, Semaphore::MAX_PERMITS));
+
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit = tokio::time::timeout(timeout, semaphore.acquire_owned()).await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+
+        match permit {
+            Ok(Ok(permit)) => Some(Permit { permit: Some(permit), owed }),
+            _ => None,
+        }
+    }
+
+    pub fn usage(&self) -> Usage {
+        let semaphores = self.semaphores.lock().unwrap();
+        let mut usage = Usage { waiting: self.waiting.load(Ordering::Relaxed), ..Usage::default() };
+
+        for slots in semaphores.values() {
+            let total = slots.limit + slots.owed.load(Ordering::Relaxed);
+            let in_use = total.saturating_sub(slots.semaphore.available_permits());
+            if in_use > 0 {
+                usage.in_use += in_use;
+                usage.capacity += slots.limit;
+            }
+        }
+
+        usage
+    }
+}
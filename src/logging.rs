@@ -0,0 +1,155 @@
+use std::env;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock, RwLock};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::handlers::is_admin;
+use crate::responses::ErrorResponse;
+
+const FORMATS: &[&str] = &["pretty", "json"];
+
+// what the log crate outputs (the access log and the debug diagnostics), RUST_LOG and LOG_FORMAT at startup
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LogSettings {
+    // RUST_LOG syntax, e.g. "actix_web=info,aws_ses_bounce=debug"
+    pub filter: String,
+    // pretty or json
+    pub format: String,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct LogSettingsUpdate {
+    pub filter: Option<String>,
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LogSettingsResponse {
+    pub success: bool,
+    pub data: LogSettings,
+}
+
+// env_logger builds its filter once, the logger installed is this wrapper so a new one can be swapped in
+struct RuntimeLogger {
+    inner: RwLock<env_logger::Logger>,
+}
+
+impl Log for RuntimeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush()
+    }
+}
+
+static LOGGER: OnceLock<RuntimeLogger> = OnceLock::new();
+
+fn settings() -> &'static Mutex<LogSettings> {
+    static SETTINGS: OnceLock<Mutex<LogSettings>> = OnceLock::new();
+    SETTINGS.get_or_init(|| {
+        Mutex::new(LogSettings {
+            filter: env::var("RUST_LOG").unwrap_or_default(),
+            format: env::var("LOG_FORMAT").ok().filter(|format| FORMATS.contains(&format.as_str())).unwrap_or_else(|| "pretty".into()),
+        })
+    })
+}
+
+fn build(settings: &LogSettings) -> env_logger::Logger {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(&settings.filter);
+
+    if settings.format == "json" {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": Utc::now().to_rfc3339(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+
+    builder.build()
+}
+
+// replaces env_logger::init()
+pub fn init() {
+    let settings = settings().lock().unwrap().clone();
+    let logger = LOGGER.get_or_init(|| RuntimeLogger { inner: RwLock::new(build(&settings)) });
+
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.inner.read().unwrap().filter());
+    }
+}
+
+// the directives env_logger would silently ignore: a level that does not parse after `=`
+fn invalid_directive(filter: &str) -> Option<&str> {
+    filter
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .find(|directive| match directive.split_once('=') {
+            Some((module, level)) => module.is_empty() || level.parse::<LevelFilter>().is_err(),
+            None => false,
+        })
+}
+
+pub async fn get_handler(req: HttpRequest) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    HttpResponse::Ok().json(LogSettingsResponse { success: true, data: settings().lock().unwrap().clone() })
+}
+
+// applies at once on this instance and lasts until the next change or restart, each instance is set on its own
+pub async fn put_handler(req: HttpRequest, body: web::Json<LogSettingsUpdate>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    if let Some(directive) = body.filter.as_deref().and_then(invalid_directive) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("invalid log directive {:?}", directive)));
+    }
+
+    if let Some(format) = body.format.as_deref().filter(|format| !FORMATS.contains(format)) {
+        return HttpResponse::BadRequest()
+            .json(ErrorResponse::new(format!("format must be one of {}, got {:?}", FORMATS.join(", "), format)));
+    }
+
+    let Some(logger) = LOGGER.get() else {
+        return HttpResponse::ServiceUnavailable().json(ErrorResponse::new("the runtime logger is not installed"));
+    };
+
+    let mut settings = settings().lock().unwrap();
+
+    if let Some(filter) = &body.filter {
+        settings.filter = filter.trim().to_string();
+    }
+    if let Some(format) = &body.format {
+        settings.format = format.clone();
+    }
+
+    let rebuilt = build(&settings);
+    log::set_max_level(rebuilt.filter());
+    *logger.inner.write().unwrap() = rebuilt;
+
+    println!("✅ Log settings changed to filter {:?}, format {}", settings.filter, settings.format);
+
+    HttpResponse::Ok().json(LogSettingsResponse { success: true, data: settings.clone() })
+}
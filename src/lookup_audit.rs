@@ -0,0 +1,280 @@
+use std::env;
+use std::time::Duration;
+
+use actix_web::web;
+use chrono::{Datelike, Months, NaiveDate, Utc};
+use tokio::task::JoinHandle;
+use tokio_postgres::types::ToSql;
+
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::schema_version;
+use crate::AppState;
+
+const DEFAULT_RETENTION_DAYS: i64 = 365;
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// LOOKUP_AUDIT=true records every lookup with its result and caller, so compliance can show when and why
+// sends to an address were blocked. The table is partitioned by month, partitions older than
+// LOOKUP_AUDIT_RETENTION_DAYS are dropped.
+pub fn enabled() -> bool {
+    env::var("LOOKUP_AUDIT").as_deref() == Ok("true") && !schema_version::read_only()
+}
+
+fn retention_days() -> i64 {
+    env::var("LOOKUP_AUDIT_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+        .max(1)
+}
+
+pub fn table() -> String {
+    env::var("PG_LOOKUP_AUDIT_TABLE").unwrap_or_else(|_| "lookup_audit".into())
+}
+
+// `email` is the queried address as stored, i.e. hashed when EMAIL_HASH_KEY is set
+pub async fn record(domain_id: i32, email: &str, blacklisted: bool, api_key_id: Option<i64>, data: &web::Data<AppState>) {
+    if !enabled() {
+        return;
+    }
+
+    let query_result: Result<(), String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"INSERT INTO lookup_audit (domain_id, email, blacklisted, api_key_id) VALUES (?,?,?,?)"#)
+                .bind(domain_id)
+                .bind(email)
+                .bind(blacklisted)
+                .bind(api_key_id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, email, blacklisted, api_key_id) VALUES ($1,$2,$3,$4)"#,
+                        table = table()
+                    ),
+                    &[&domain_id, &email, &blacklisted, &api_key_id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Ok(()),
+    };
+
+    if let Err(err) = query_result {
+        println!("🔥 Failed to audit lookup for domain {}: {:?}", domain_id, err);
+    }
+}
+
+// one row per (email as stored, blacklisted) of a batch lookup, written with a single insert
+pub async fn record_many(domain_id: i32, results: &[(String, bool)], api_key_id: Option<i64>, data: &web::Data<AppState>) {
+    if !enabled() || results.is_empty() {
+        return;
+    }
+
+    let query_result: Result<(), String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            let values = vec!["(?,?,?,?)"; results.len()].join(",");
+            let sql = format!(
+                r#"INSERT INTO lookup_audit (domain_id, email, blacklisted, api_key_id) VALUES {values}"#,
+                values = values
+            );
+            let mut query = sqlx::query(&sql);
+
+            for (email, blacklisted) in results {
+                query = query.bind(domain_id).bind(email).bind(blacklisted).bind(api_key_id);
+            }
+
+            query.execute(pool).await.map(|_| ()).map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => {
+                let values: Vec<String> = (0..results.len())
+                    .map(|row| format!("($1,${},${},$2)", 2 * row + 3, 2 * row + 4))
+                    .collect();
+                let mut params: Vec<&(dyn ToSql + Sync)> = vec![&domain_id, &api_key_id];
+
+                for (email, blacklisted) in results {
+                    params.push(email);
+                    params.push(blacklisted);
+                }
+
+                client
+                    .execute(
+                        &format!(
+                            r#"INSERT INTO {table} (domain_id, email, blacklisted, api_key_id) VALUES {values}"#,
+                            table = table(),
+                            values = values.join(",")
+                        ),
+                        &params,
+                    )
+                    .await
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            }
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Ok(()),
+    };
+
+    if let Err(err) = query_result {
+        println!("🔥 Failed to audit {} lookups for domain {}: {:?}", results.len(), domain_id, err);
+    }
+}
+
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+fn next_month(start: NaiveDate) -> NaiveDate {
+    start + Months::new(1)
+}
+
+// partitions are named after their month, p202401 holds January 2024
+fn partition_name(start: NaiveDate) -> String {
+    start.format("p%Y%m").to_string()
+}
+
+fn partition_month(name: &str) -> Option<NaiveDate> {
+    let month = name.rsplit_once('p').map(|(_, month)| month)?;
+    NaiveDate::parse_from_str(&format!("{}01", month), "%Y%m%d").ok()
+}
+
+async fn maintain_mysql(pool: &sqlx::MySqlPool, months: &[NaiveDate], cutoff: NaiveDate) -> Result<(), String> {
+    let existing = sqlx::query_scalar::<_, String>(
+        r#"SELECT partition_name FROM information_schema.partitions
+           WHERE table_schema = DATABASE() AND table_name = 'lookup_audit' AND partition_name IS NOT NULL"#,
+    )
+        .fetch_all(pool)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // new months are split off p_future, which catches everything past the last month
+    for start in months {
+        let name = partition_name(*start);
+
+        if existing.contains(&name) {
+            continue;
+        }
+
+        sqlx::query(&format!(
+            r#"ALTER TABLE lookup_audit REORGANIZE PARTITION p_future INTO (PARTITION {name} VALUES LESS THAN ('{end}'), PARTITION p_future VALUES LESS THAN (MAXVALUE))"#,
+            name = name,
+            end = next_month(*start)
+        ))
+            .execute(pool)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        println!("✅ Created lookup audit partition {}", name);
+    }
+
+    for name in &existing {
+        match partition_month(name) {
+            Some(start) if next_month(start) <= cutoff => {
+                sqlx::query(&format!(r#"ALTER TABLE lookup_audit DROP PARTITION {}"#, name))
+                    .execute(pool)
+                    .await
+                    .map_err(|err| err.to_string())?;
+
+                println!("✅ Dropped lookup audit partition {}", name);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn maintain_pg(db_url: &str, months: &[NaiveDate], cutoff: NaiveDate) -> Result<(), String> {
+    let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+    let table = table();
+
+    for start in months {
+        client
+            .execute(
+                &format!(
+                    r#"CREATE TABLE IF NOT EXISTS {table}_{name} PARTITION OF {table} FOR VALUES FROM ('{start}') TO ('{end}')"#,
+                    table = table,
+                    name = partition_name(*start),
+                    start = start,
+                    end = next_month(*start)
+                ),
+                &[],
+            )
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    let existing: Vec<String> = client
+        .query(
+            r#"SELECT child.relname::text FROM pg_inherits
+               JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+               JOIN pg_class parent ON parent.oid = pg_inherits.inhparent
+               WHERE parent.relname = $1"#,
+            &[&table],
+        )
+        .await
+        .map(|rows| rows.iter().map(|row| row.get(0)).collect())
+        .map_err(|err| err.to_string())?;
+
+    for name in existing {
+        match partition_month(&name) {
+            Some(start) if next_month(start) <= cutoff => {
+                client
+                    .execute(&format!(r#"DROP TABLE IF EXISTS {}"#, name), &[])
+                    .await
+                    .map_err(|err| err.to_string())?;
+
+                println!("✅ Dropped lookup audit partition {}", name);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// makes sure the current and the next month have a partition and drops the expired ones
+pub async fn maintain(db_type: &DBType, db_url: &str) -> Result<(), String> {
+    let today = Utc::now().date_naive();
+    let current = month_start(today);
+    let months = [current, next_month(current)];
+    let cutoff = today - chrono::Duration::days(retention_days());
+
+    match db_type {
+        DBType::MySQL(pool) => maintain_mysql(pool, &months, cutoff).await,
+        DBType::Postgres => maintain_pg(db_url, &months, cutoff).await,
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// runs the partition maintenance before the server accepts lookups and then every hour, None when auditing is off
+pub async fn start(db_type: &DBType, db_url: &str) -> Option<JoinHandle<()>> {
+    if !enabled() {
+        return None;
+    }
+
+    if let Err(err) = maintain(db_type, db_url).await {
+        println!("🔥 Lookup audit partition maintenance failed: {}", err);
+    }
+
+    let db_type = db_type.clone();
+    let db_url = db_url.to_string();
+
+    Some(tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(MAINTENANCE_INTERVAL).await;
+
+            if let Err(err) = maintain(&db_type, &db_url).await {
+                println!("🔥 Lookup audit partition maintenance failed: {}", err);
+            }
+        }
+    }))
+}
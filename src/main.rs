@@ -1,342 +1,632 @@
-mod domain;
+mod admin_ui;
+mod alerts;
+mod api_keys;
+mod archive;
+mod auth_failures;
+mod autoscaling;
+mod backfill;
+mod bench;
+mod blacklist;
+mod bloom;
+mod bulk_delete;
+mod cache;
+mod clickhouse;
+mod complaint_review;
+mod compression;
+mod config;
+mod daily_stats;
+mod dead_letters;
+mod deadline;
+mod diagnostics;
+mod dns;
+mod domains;
+mod event_search;
+mod eventbridge;
+mod events;
+mod export;
+mod faults;
+mod handlers;
+mod http_cache;
+mod idempotency;
+mod imports;
+mod last_modified;
+mod limiter;
+mod logging;
+mod lookup_audit;
+mod merge;
+mod metrics;
+mod notification_log;
+mod onboarding;
+mod outbound;
+mod outbox;
+mod preview;
+mod privacy;
+mod rebuild;
+mod redaction;
+mod repo;
+mod reputation;
+mod responses;
+mod rules;
+mod schema;
+mod schema_version;
+mod secondary;
+mod secrets;
+mod selftest;
+mod server;
+mod ses_reconcile;
+mod services;
+mod shadow;
+mod shards;
+mod simulator;
+mod sigv4;
+mod sns;
+mod sns_allowlist;
+mod sqs;
+mod stats;
+mod strict;
+mod subscriptions;
+mod timestamps;
+mod topic_mappings;
+mod unsubscribe;
+mod verification;
+mod webhooks;
 
 use std::env;
-use crate::domain::SnsNotificationType::{Notification, SubscriptionConfirmation};
-use crate::domain::{Message, NotificationType, SnsNotification};
-use actix_web::web::Bytes;
-use actix_web::{middleware, middleware::Logger, web, App, HttpResponse, HttpServer, Responder};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use crate::cache::LookupCache;
+use crate::config::is_command;
+use crate::events::Event;
+use crate::handlers::blacklist::{batch_config, batch_lookup, head_email_blacklisted, is_email_blacklisted};
+use crate::handlers::health::{health_checker_handler, openapi_handler};
+use crate::handlers::sns::{handle_shared_sns_notification, handle_sns_notification};
+use crate::limiter::DomainLimiter;
+use crate::shards::RecipientShards;
+use crate::repo::{build_mysql_pool, build_mysql_read_pool, dynamodb, DBType};
+use crate::secondary::Secondary;
+use crate::server::Probes;
+use aws_ses_bounce::{domain, payload};
+use actix_web::{middleware, middleware::Logger, web, App, HttpServer};
 use dotenv::dotenv;
-use serde_json::json;
-use sqlx::mysql::{MySqlPool, MySqlPoolOptions};
-use regex::Regex;
-use tokio_postgres::NoTls;
-
-
-#[derive(Debug, Clone)]
-enum DBType {
-    Postgres,
-    MySQL(MySqlPool),
-}
+use sqlx::mysql::MySqlPool;
+use tokio::sync::broadcast;
 
 pub struct AppState {
     db_type: DBType,
     db_url: String,
+    // optional read replica (READ_DATABASE_URL) used by lookups, lists and stats
+    read_pool: Option<MySqlPool>,
+    read_db_url: Option<String>,
+    // shared by all workers
+    limiter: Arc<DomainLimiter>,
+    shards: Arc<RecipientShards>,
+    cache: Arc<LookupCache>,
+    events: broadcast::Sender<Event>,
+    // dual-write target while migrating between backends
+    secondary: Option<Secondary>,
 }
 
-
-async fn build_mysql_pool(database_url: &str) -> Result<MySqlPool, Box<dyn std::error::Error + Send + Sync>> {
-    println!("🚀 Connecting to the MySQL database...");
-
-    let pool = match MySqlPoolOptions::new()
-        .max_connections(10)
-        .connect(database_url)
-        .await
-    {
-        Ok(pool) => {
-            println!("✅Connection to the database is successful!");
-            pool
-        }
-        Err(err) => {
-            println!("🔥 Failed to connect to the database: {:?}", err);
-            std::process::exit(1);
-        }
-    };
-
-    Ok(pool)
-}
-
-async fn build_pg_pool(database_url: &str) -> Result<tokio_postgres::Client, Box<dyn std::error::Error + Send + Sync>> {
-    println!("🚀 Connecting to the PG database...");
-
-    let (client, connection) = tokio_postgres::connect(database_url, NoTls).await?;
-
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-
-    println!("✅Connection to the database is successful!");
-
-    Ok(client)
-}
-
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "actix_web=info");
     }
     dotenv().ok();
-    env_logger::init();
+    logging::init();
 
     // create the pool depending on the db type, db = MYSQL or = POSTGRES
     let db = std::env::var("DB_TYPE").unwrap_or_else(|_| "MYSQL".into());
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-
-
-    let db_type = match db.as_str() {
-        "PG" => {
-            DBType::Postgres
-        }
-        "MYSQL" => {
-            let pool = build_mysql_pool(&database_url).await.unwrap();
-            DBType::MySQL(pool)
+    let secret_source = secrets::source();
+    let limiter = Arc::new(DomainLimiter::default());
+    let shards = Arc::new(RecipientShards::from_env());
+    let cache = Arc::new(LookupCache::from_env());
+    let events = events::channel();
+    clickhouse::spawn(&events);
+    eventbridge::spawn(&events);
+    metrics::spawn(&events);
+    alerts::spawn(&events);
+
+    let args: Vec<String> = env::args().collect();
+
+    // measures the parsing of the bench/ samples, without a database
+    if args.get(1).map(String::as_str) == Some("bench-parse") {
+        match bench::run(&args) {
+            Ok(passed) => std::process::exit(if passed { 0 } else { 1 }),
+            Err(err) => {
+                println!("🔥 Benchmark failed: {}", err);
+                std::process::exit(2);
+            }
         }
-        _ => {
-             println!("🔥 Unsupported database type: {}", db);
+    }
+
+    // opens the encrypted_payload of a dead letter, without a database
+    if args.get(1).map(String::as_str) == Some("decrypt-payload") {
+        if let Err(err) = redaction::run(&args) {
+            println!("🔥 Decryption failed: {}", err);
             std::process::exit(1);
         }
-    };
-
-
-    println!("🚀 Server started successfully");
-
-    HttpServer::new(move || {
-        App::new()
-            .wrap(middleware::Compress::default())
-            .app_data(web::Data::new(AppState { db_type: db_type.clone(), db_url: database_url.clone() }))
-            .wrap(Logger::new(
-                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#,
-            ))
-            .service(
-                web::resource("/api/v1/health_check").route(web::get().to(health_checker_handler)),
-            )
-            .service(
-                web::resource("/api/{domain_id}/sns-endpoint")
-                    .route(web::post().to(handle_sns_notification)),
-            )
-            .service(
-                web::resource("/api/{domain_id}/is-blacklisted/{email}")
-                    .route(web::get().to(is_email_blacklisted)),
-            )
-    })
-        .bind("0.0.0.0:8000")?
-        .run()
-        .await
-}
+        std::process::exit(0);
+    }
 
-async fn health_checker_handler() -> impl Responder {
-    const MESSAGE: &str = "SES Blacklist API is running!";
+    // maintenance commands run long queries on purpose
+    if !is_command(&args) {
+        deadline::enable_query_timeouts();
+    }
 
-    HttpResponse::Ok().json(json!({"status": "success","message": MESSAGE}))
-}
+    let mut listeners: Option<server::Listeners> = None;
 
-async fn is_email_blacklisted(
-    path: web::Path<(i32, String)>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    let (domain_id, email) = path.into_inner();
-
-    let found: Result<bool, String> = match &data.db_type {
-        DBType::MySQL(pool) => {
-            let query_result = sqlx::query(r#"SELECT * FROM blacklist WHERE domain_id = ? AND email = ?"#)
-                .bind(domain_id)
-                .bind(email)
-                .fetch_one(pool)
-                .await;
-
-            match query_result {
-                Ok(_) => Ok(true),
-                Err(err) => match err {
-                    sqlx::Error::RowNotFound => Ok(false),
-                    _ => Err(format!("🔥 Failed to query the database: {:?}", err))
-                },
+    loop {
+        let database_url = match &secret_source {
+            Some(source) => match secrets::resolve_database_url(source).await {
+                Ok(url) => url,
+                Err(err) => {
+                    println!("🔥 Failed to load the database credentials: {}", err);
+                    std::process::exit(1);
+                }
+            },
+            None if db == "DYNAMODB" => String::new(),
+            None => std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+        };
+
+        let db_type = match db.as_str() {
+            "PG" => {
+                DBType::Postgres
+            }
+            "MYSQL" => {
+                let pool = build_mysql_pool(&database_url).await.unwrap();
+                DBType::MySQL(pool)
+            }
+            "DYNAMODB" => DBType::DynamoDB(dynamodb::connect().await),
+            _ => {
+                 println!("🔥 Unsupported database type: {}", db);
+                std::process::exit(1);
+            }
+        };
+
+        let read_db_url = env::var("READ_DATABASE_URL").ok().filter(|url| !url.is_empty());
+        let read_pool = match (&db_type, &read_db_url) {
+            (DBType::MySQL(_), Some(read_url)) => build_mysql_read_pool(read_url),
+            _ => None,
+        };
+
+        let secondary = secondary::from_env();
+
+        if args.get(1).map(String::as_str) == Some("consistency-check") {
+            match secondary::consistency_check(&db_type, &database_url, secondary.as_ref(), &args).await {
+                Ok(consistent) => std::process::exit(if consistent { 0 } else { 1 }),
+                Err(err) => {
+                    println!("🔥 Consistency check failed: {}", err);
+                    std::process::exit(2);
+                }
             }
         }
-        DBType::Postgres => {
-            let Ok(client) = build_pg_pool(&data.db_url).await else {
-                return HttpResponse::InternalServerError().json(json!({
-                    "success": false,
-                    "error": "Failed to connect to the database"
-                }))
-            };
 
-            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
-
-            let query_result = client
-                .query_opt(
-                    &format!(r#"SELECT  FROM {table} WHERE domain_id = $1 AND email = $2"#, table = table),
-                    &[&domain_id, &email],
-                )
-                .await;
-
-            match query_result {
-                Ok(Some(_)) => Ok(true),
-                Ok(None) => Ok(false),
-                Err(err) => Err(format!("🔥 Failed to query the database: {:?}", err)),
+        if args.get(1).map(String::as_str) == Some("rebuild-blacklist") {
+            let data = web::Data::new(AppState {
+                db_type: db_type.clone(),
+                db_url: database_url.clone(),
+                read_pool: None,
+                read_db_url: None,
+                limiter: limiter.clone(),
+                shards: shards.clone(),
+                cache: cache.clone(),
+                events: events.clone(),
+                secondary: secondary.clone(),
+            });
+
+            if let Err(err) = rebuild::run(&data, &args).await {
+                println!("🔥 Rebuild failed: {:?}", err);
+                std::process::exit(1);
             }
+            std::process::exit(0);
         }
-    };
 
+        if args.get(1).map(String::as_str) == Some("hash-emails") {
+            if let Err(err) = privacy::run(&db_type, &database_url, &args).await {
+                println!("🔥 Hashing failed: {:?}", err);
+                std::process::exit(1);
+            }
+            std::process::exit(0);
+        }
 
-    match found {
-        Ok(blacklisted) => {
-            HttpResponse::Ok().json(json!({
-                "success": true,
-                "data": {
-                    "blacklisted": blacklisted
+        if args.get(1).map(String::as_str) == Some("indexes") {
+            match schema::run_indexes(&db_type, &database_url, &args).await {
+                Ok(complete) => std::process::exit(if complete { 0 } else { 1 }),
+                Err(err) => {
+                    println!("🔥 Index check failed: {}", err);
+                    std::process::exit(2);
                 }
-            }))
-        }
-        Err(err) => {
-            HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "error": err
-            }))
+            }
         }
-    }
-}
 
-async fn handle_sns_notification(
-    path: web::Path<i32>,
-    bytes: Bytes,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    let domain_id = path.into_inner();
-
-    let Some(notification): Option<SnsNotification> = serde_json::from_slice(&bytes).ok() else {
-        println!("Received SNS notification error with bytes: {:?}", bytes);
-        return HttpResponse::Ok().body("ok");
-    };
-
-    println!("Received SNS notification: {:?}", notification);
-
-    match notification.type_field {
-        SubscriptionConfirmation => {
-            let a = &notification.subscribe_url.unwrap();
-            // To confirm the subscription, visit the SubscribeURL from the incoming message
-            println!("Confirm the subscription by visiting: {}", a);
-            // Subscribe to the topic using reqwest
-            let client = reqwest::Client::new();
-            let _ = client.get(a).send().await;
-
-            HttpResponse::Ok().body("ok")
+        if args.get(1).map(String::as_str) == Some("archive-reasons") {
+            if let Err(err) = archive::run(&db_type, &database_url, &args).await {
+                println!("🔥 Archiving failed: {:?}", err);
+                std::process::exit(1);
+            }
+            std::process::exit(0);
         }
-        Notification => {
-            let message = notification.message.unwrap();
-            let message: Message = serde_json::from_str(&message).unwrap();
-
-            match message.notification_type {
-                NotificationType::Bounce => handle_bounce(message, domain_id, data).await,
-                _ => {
-                    println!(
-                        "Received unknown notification type: {:?}",
-                        message.notification_type
-                    );
-                    HttpResponse::Ok().body("ok")
-                }
+
+        if args.get(1).map(String::as_str) == Some("backfill-reasons") {
+            if let Err(err) = backfill::run(&db_type, &database_url, &args).await {
+                println!("🔥 Backfill failed: {:?}", err);
+                std::process::exit(1);
             }
+            std::process::exit(0);
         }
-    }
-}
 
-// having a str with: \"Desert Rose Florals, LLC\" <desertroseflorals@gmail.com>" extract only the email
-fn extract_email_address(input: &str) -> String {
+        // `--self-test` checks the dependencies, prints the report and exits, for deployment pipelines
+        if args.iter().any(|arg| arg == "--self-test") {
+            let report = selftest::run(&db_type, &database_url).await;
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            std::process::exit(if report.passed { 0 } else { 1 });
+        }
 
-    // if no index of < or >, return the same string
-    if input.find("<").is_none() || input.find(">").is_none() {
-        return input.to_string();
-    }
+        schema_version::check_on_boot(&db_type, &database_url).await;
 
-    let re = Regex::new(r"<(.*)>").unwrap();
-    let caps = re.captures(input);
+        // with NOTIFICATION_SOURCE=sqs the notifications are polled and the SNS endpoint is not exposed
+        let sns_endpoint = !sqs::enabled();
+        let sqs_config = if sqs::enabled() {
+            match sqs::config() {
+                Ok(config) => Some(config),
+                Err(err) => {
+                    println!("🔥 {}", err);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            None
+        };
+
+        // the boot work runs once the server is bound, the probes hold back traffic until it is done
+        let probes = Arc::new(Probes::default());
+        let startup_data = web::Data::new(AppState {
+            db_type: db_type.clone(),
+            db_url: database_url.clone(),
+            read_pool: read_pool.clone(),
+            read_db_url: read_db_url.clone(),
+            limiter: limiter.clone(),
+            shards: shards.clone(),
+            cache: cache.clone(),
+            events: events.clone(),
+            secondary: secondary.clone(),
+        });
+
+        // the sender and the delivery poller write with the current credentials, so they are restarted with the server
+        let webhook_tasks = webhooks::spawn(startup_data.clone());
+
+        println!("🚀 Server started successfully");
+
+        let limiter = limiter.clone();
+        let shards = shards.clone();
+        let cache = cache.clone();
+        let events = events.clone();
+        let current_url = database_url.clone();
+        // closed once the server drained, so the connections of expired credentials do not linger
+        let retired_pools: Vec<_> = match &db_type {
+            DBType::MySQL(pool) => std::iter::once(pool.clone()).chain(read_pool.clone()).collect(),
+            DBType::Postgres | DBType::DynamoDB(_) => vec![],
+        };
+        let server_probes = web::Data::from(probes.clone());
+        let compress = compression::enabled();
+        let mut server = HttpServer::new(move || {
+            App::new()
+                .wrap(middleware::from_fn(compression::skip_small))
+                .wrap(middleware::Condition::new(compress, middleware::Compress::default()))
+                .wrap(middleware::from_fn(compression::negotiate))
+                .wrap(middleware::from_fn(schema_version::guard))
+                .wrap(middleware::from_fn(auth_failures::guard))
+                .wrap(middleware::from_fn(metrics::track))
+                .app_data(web::Data::new(AppState {
+                    db_type: db_type.clone(),
+                    db_url: database_url.clone(),
+                    read_pool: read_pool.clone(),
+                    read_db_url: read_db_url.clone(),
+                    limiter: limiter.clone(),
+                    shards: shards.clone(),
+                    cache: cache.clone(),
+                    events: events.clone(),
+                    secondary: secondary.clone(),
+                }))
+                .app_data(server_probes.clone())
+                .wrap(Logger::new(
+                    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#,
+                ))
+                .service(
+                    web::resource("/api/v1/health_check").route(web::get().to(health_checker_handler)),
+                )
+                .service(web::resource("/live").route(web::get().to(server::live_handler)))
+                .service(web::resource("/ready").route(web::get().to(server::ready_handler)))
+                .service(web::resource("/startup").route(web::get().to(server::startup_handler)))
+                .service(
+                    web::resource("/metrics").route(web::get().to(metrics::metrics_handler)),
+                )
+                .service(
+                    web::resource("/metrics/scaling").route(web::get().to(autoscaling::scaling_handler)),
+                )
+                .service(
+                    web::resource("/api/v1/openapi.json").route(web::get().to(openapi_handler)),
+                )
+                .configure(|cfg| {
+                    if sns_endpoint {
+                        cfg.service(
+                            web::resource("/api/{domain_id}/sns-endpoint")
+                                .app_data(web::PayloadConfig::new(payload::max_body_bytes()))
+                                .wrap(middleware::from_fn(deadline::ingestion))
+                                .wrap(middleware::from_fn(sns_allowlist::check))
+                                .route(web::post().to(handle_sns_notification)),
+                        );
+                        cfg.service(
+                            web::resource("/api/sns-endpoint")
+                                .app_data(web::PayloadConfig::new(payload::max_body_bytes()))
+                                .wrap(middleware::from_fn(deadline::ingestion))
+                                .wrap(middleware::from_fn(sns_allowlist::check))
+                                .route(web::post().to(handle_shared_sns_notification)),
+                        );
+                    }
+                })
+                .configure(handlers::v2::configure)
+                .service(
+                    web::resource("/api/{domain_id}/is-blacklisted/{email}")
+                        .wrap(middleware::from_fn(deadline::lookup))
+                        .wrap(middleware::from_fn(http_cache::lookup))
+                        .route(web::get().to(is_email_blacklisted))
+                        .route(web::head().to(head_email_blacklisted)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/is-blacklisted")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .app_data(batch_config())
+                        .route(web::post().to(batch_lookup)),
+                )
+                .service(
+                    web::resource("/api/is-blacklisted/{email}")
+                        .wrap(middleware::from_fn(deadline::lookup))
+                        .wrap(middleware::from_fn(http_cache::lookup))
+                        .route(web::get().to(blacklist::lookup_all_domains)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/blacklist")
+                        .wrap(middleware::from_fn(idempotency::check))
+                        .route(web::post().to(blacklist::add_entry)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/blacklist/{email}/status")
+                        .route(web::put().to(blacklist::set_status)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/blacklist/bulk-delete")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::post().to(bulk_delete::bulk_delete_handler)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/blacklist/import")
+                        .wrap(middleware::from_fn(idempotency::check))
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .app_data(blacklist::import_config())
+                        .route(web::post().to(blacklist::import_entries)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/imports/{job_id}")
+                        .route(web::get().to(imports::job_handler)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/blacklist/export")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(export::export_handler)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/blacklist/bloom")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(bloom::bloom_handler)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/unsubscribe-link")
+                        .route(web::get().to(unsubscribe::link_handler)),
+                )
+                .service(
+                    web::resource("/unsubscribe/{token}")
+                        .app_data(web::PayloadConfig::new(64 * 1024))
+                        .route(web::post().to(unsubscribe::one_click_handler))
+                        .route(web::get().to(unsubscribe::page_handler)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/events")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(event_search::events_handler)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/events/stream")
+                        .route(web::get().to(events::stream_events)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/stats/recipient-domains")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(stats::recipient_domain_stats)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/stats/daily")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(daily_stats::daily_stats_handler)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/stats/mta")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(stats::mta_stats)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/stats/identities")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(stats::identity_stats)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/stats/diagnostic-classes")
+                        .wrap(middleware::from_fn(deadline::bulk))
+                        .route(web::get().to(stats::diagnostic_class_stats)),
+                )
+                .service(
+                    web::resource("/api/{domain_id}/reputation")
+                        .wrap(middleware::from_fn(deadline::lookup))
+                        .route(web::get().to(reputation::reputation_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/domains").route(web::post().to(onboarding::onboard_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/domains/{domain_id}/resume")
+                        .route(web::post().to(reputation::resume_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/complaint-reviews")
+                        .route(web::get().to(complaint_review::list_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/complaint-reviews/{id}/approve")
+                        .route(web::post().to(complaint_review::approve_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/complaint-reviews/{id}/reject")
+                        .route(web::post().to(complaint_review::reject_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/domains/{domain_id}/merge")
+                        .route(web::post().to(merge::merge_handler)),
+                )
+                .configure(faults::configure)
+                .configure(admin_ui::configure)
+                .service(
+                    web::resource("/api/admin/usage")
+                        .route(web::get().to(api_keys::usage_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/dns-check/{domain}")
+                        .route(web::get().to(dns::dns_check_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/indexes")
+                        .route(web::get().to(schema::indexes_handler))
+                        .route(web::post().to(schema::create_indexes_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/selftest")
+                        .route(web::get().to(selftest::self_test_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/parse-preview")
+                        .route(web::post().to(preview::parse_preview_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/shadow/discrepancies")
+                        .route(web::get().to(shadow::list_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/dead-letters")
+                        .route(web::get().to(dead_letters::list_dead_letters)),
+                )
+                .service(
+                    web::resource("/api/admin/dead-letters/{id}/replay")
+                        .route(web::post().to(dead_letters::replay_dead_letter)),
+                )
+                .service(
+                    web::resource("/api/admin/sns-subscriptions")
+                        .route(web::get().to(subscriptions::list_subscriptions)),
+                )
+                .service(
+                    web::resource("/api/admin/sns-subscriptions/confirm")
+                        .route(web::post().to(subscriptions::confirm_pending_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/entries/{id}/reason")
+                        .route(web::get().to(archive::reason_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/log-settings")
+                        .route(web::get().to(logging::get_handler))
+                        .route(web::put().to(logging::put_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/auth-failures")
+                        .route(web::get().to(auth_failures::list_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/cache")
+                        .route(web::get().to(cache::stats_handler))
+                        .route(web::delete().to(cache::invalidate_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/topic-mappings")
+                        .route(web::get().to(topic_mappings::list_mappings))
+                        .route(web::put().to(topic_mappings::put_mapping)),
+                )
+                .service(
+                    web::resource("/api/admin/topic-mappings/{topic_arn}")
+                        .route(web::delete().to(topic_mappings::delete_mapping)),
+                )
+                .service(
+                    web::resource("/api/admin/ses-reconciliation")
+                        .route(web::get().to(ses_reconcile::report_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/ses-reconciliation/run")
+                        .route(web::post().to(ses_reconcile::run_handler)),
+                )
+                .service(
+                    web::resource("/api/admin/webhook-dead-letters")
+                        .route(web::get().to(webhooks::list_dead_letters)),
+                )
+                .service(
+                    web::resource("/api/admin/webhook-dead-letters/{id}/replay")
+                        .route(web::post().to(webhooks::replay_dead_letter)),
+                )
+        });
 
-    match caps {
-        Some(caps) => caps[1].to_string(),
-        None => input.to_string()
-    }
-}
+        if listeners.is_none() {
+            listeners = Some(server::Listeners::bind()?);
+        }
+        let bound = listeners.as_ref().expect("bound above");
 
-async fn handle_bounce(msg: Message, domain_id: i32, data: web::Data<AppState>) -> HttpResponse {
-    let reason = serde_json::to_string(&msg.clone()).unwrap();
+        if let Some(listener) = &bound.tcp {
+            server = server.listen(listener.try_clone()?)?;
+        }
 
-    match msg.bounce {
-        None => {
-            println!("Received bounce notification without bounce field: {:?}", msg);
-            HttpResponse::Ok().body("ok")
+        if let Some((path, listener)) = &bound.socket {
+            server = server.listen_uds(listener.try_clone()?)?;
+            println!("🚀 Listening on unix socket {}", path);
         }
-        Some(bounce) => {
-            let bounces = bounce
-                .bounced_recipients
-                .iter()
-                .map(|r| extract_email_address(r.email_address.as_str()))
-                .collect::<Vec<String>>();
-
-
-            for bounce in &bounces {
-                let query_result: Result<(), String> = match &data.db_type {
-                    DBType::MySQL(pool) => {
-
-                        let query_result =
-                            sqlx::query(r#"INSERT INTO blacklist (domain_id, email, reason) VALUES (?,?,?)"#)
-                                .bind(domain_id)
-                                .bind(bounce)
-                                .bind(&reason)
-                                .execute(pool)
-                                .await
-                                .map_err(|err: sqlx::Error| err.to_string());
-
-                        match query_result {
-                            Ok(_) => Ok(()),
-                            Err(err) => Err(err),
-                        }
-                    }
-                    DBType::Postgres => {
-                        match build_pg_pool(&data.db_url).await  {
-                            Ok(pg) => {
-
-                                let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
-                                let query_result =
-                                    pg
-                                    .execute(
-                                         &format!(
-                                            r#"INSERT INTO {table} (domain_id, email, reason) VALUES ($1,$2,$3)"#,
-                                            table = table
-                                        ),
-                                        &[&domain_id, &bounce, &reason],
-                                    )
-                                    .await
-                                    .map_err(|err| err.to_string());
-
-
-                                match query_result {
-                                    Ok(_) => Ok(()),
-                                    Err(err) => Err(err.to_string()),
-                                }
-                            },
-                            Err(err) => {
-                               Err(err.to_string())
-                            }
-
-                        }
-                    }
-                };
 
+        let server = server.run();
 
-                if let Err(err) = query_result {
-                    if err.contains("Duplicate entry") {
-                        println!("blacklist entry already exists for: {}", bounce);
-                        return HttpResponse::BadRequest().json(
-                            json!({"status": "fail","message": format!("blacklist entry already exists for: {}", bounce)}),
-                        );
-                    }
+        let startup = actix_web::rt::spawn(server::startup(startup_data, sqs_config, probes));
 
-                    println!("Failed to execute query: {:?}", err);
+        // when the database secret rotates the server is stopped gracefully and rebuilt with the new credentials
+        let rotated = match &secret_source {
+            Some(source) => secrets::watch(source.clone(), current_url, server.handle()),
+            None => Arc::new(AtomicBool::new(false)),
+        };
 
-                    return HttpResponse::InternalServerError()
-                        .json(json!({"status": "error","message": format!("{:?}", err)}));
-                }
-            }
+        server.await?;
 
-            println!(
-                "Got bounce notification: {:?} for domain: {}",
-                bounces, domain_id
-            );
+        for task in webhook_tasks {
+            task.abort();
+        }
 
+        // a server stopped during startup leaves no background tasks behind
+        if startup.is_finished() {
+            for task in startup.await.unwrap_or_default() {
+                task.abort();
+            }
+        } else {
+            startup.abort();
+        }
 
-            HttpResponse::Ok().json(json!({"status": "success"}))
+        if !rotated.load(Ordering::SeqCst) {
+            return Ok(());
         }
+
+        for pool in retired_pools {
+            pool.close().await;
+        }
+
+        println!("🚀 Restarting the server with the rotated database credentials");
     }
 }
-
@@ -0,0 +1,415 @@
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::blacklist::{self, NewEntry};
+use crate::handlers::is_admin;
+use crate::last_modified;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::ErrorResponse;
+use crate::AppState;
+
+const DEFAULT_BATCH_SIZE: i64 = 500;
+const CONFLICTS: &[&str] = &["skip", "overwrite", "newest"];
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct MergeRequest {
+    pub target_domain_id: i32,
+    // when the target already has the address: skip (default) keeps the target entry, overwrite replaces it with
+    // the source one, newest keeps the most recently created of the two
+    pub on_conflict: Option<String>,
+    // moves the entries instead of copying them, the source domain ends up empty of active entries
+    #[serde(default)]
+    pub remove_source: bool,
+    // only counts the entries and the conflicts
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct MergeSummary {
+    pub dry_run: bool,
+    // the active entries of the source domain
+    pub source_entries: i64,
+    // of those, the addresses the target domain already has an entry for
+    pub conflicts: i64,
+    pub copied: i64,
+    pub overwritten: i64,
+    pub skipped: i64,
+    pub removed_from_source: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MergeResponse {
+    pub success: bool,
+    pub data: MergeSummary,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct SourceRow {
+    id: i64,
+    email: String,
+    reason: String,
+    category: Option<String>,
+    expires_at: Option<NaiveDateTime>,
+    bounce_type: Option<String>,
+    bounce_sub_type: Option<String>,
+    diagnostic_code: Option<String>,
+    diagnostic_class: Option<String>,
+    reason_summary: Option<String>,
+    subject: Option<String>,
+    reporting_mta: Option<String>,
+    remote_mta_ip: Option<String>,
+    source_arn: Option<String>,
+    sending_account_id: Option<String>,
+    created_at: NaiveDateTime,
+    event_at: Option<NaiveDateTime>,
+}
+
+impl SourceRow {
+    fn entry_for(&self, domain_id: i32) -> NewEntry {
+        NewEntry {
+            domain_id,
+            email: self.email.clone(),
+            reason: self.reason.clone(),
+            category: self.category.clone().unwrap_or_default(),
+            expires_at: self.expires_at,
+            bounce_type: self.bounce_type.clone(),
+            bounce_sub_type: self.bounce_sub_type.clone(),
+            diagnostic_code: self.diagnostic_code.clone(),
+            diagnostic_class: self.diagnostic_class.clone(),
+            reason_summary: self.reason_summary.clone(),
+            subject: self.subject.clone(),
+            reporting_mta: self.reporting_mta.clone(),
+            remote_mta_ip: self.remote_mta_ip.clone(),
+            source_arn: self.source_arn.clone(),
+            sending_account_id: self.sending_account_id.clone(),
+            created_at: Some(self.created_at),
+            event_at: self.event_at,
+            pending_review: false,
+        }
+    }
+}
+
+const COLUMNS: &str = "id, email, reason, category, expires_at, bounce_type, bounce_sub_type, diagnostic_code, diagnostic_class, reason_summary, subject, reporting_mta, remote_mta_ip, source_arn, sending_account_id, created_at, event_at";
+
+// only active entries are merged, removed, expired or allowlisted ones stay decisions of the source domain
+const ACTIVE: &str = "status = 'active' AND (expires_at IS NULL OR expires_at > NOW())";
+const SOURCE_ACTIVE: &str = "s.status = 'active' AND (s.expires_at IS NULL OR s.expires_at > NOW())";
+
+fn table() -> String {
+    env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into())
+}
+
+// (active entries of the source, addresses of those the target has an entry for)
+async fn count(source: i32, target: i32, data: &AppState) -> Result<(i64, i64), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, (i64, i64)>(&format!(
+                r#"SELECT COUNT(*), CAST(COALESCE(SUM(t.id IS NOT NULL), 0) AS SIGNED) FROM blacklist s
+                   LEFT JOIN blacklist t ON t.domain_id = ? AND t.email = s.email
+                   WHERE s.domain_id = ? AND {}"#,
+                SOURCE_ACTIVE
+            ))
+                .bind(target)
+                .bind(source)
+                .fetch_one(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(
+                    &format!(
+                        r#"SELECT COUNT(*), COUNT(t.id) FROM {table} s
+                           LEFT JOIN {table} t ON t.domain_id = $1 AND t.email = s.email
+                           WHERE s.domain_id = $2 AND {active}"#,
+                        table = table(),
+                        active = SOURCE_ACTIVE
+                    ),
+                    &[&target, &source],
+                )
+                .await
+                .map(|row| (row.get(0), row.get(1)))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn fetch_batch(source: i32, after_id: i64, batch_size: i64, data: &AppState) -> Result<Vec<SourceRow>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, SourceRow>(&format!(
+                r#"SELECT {} FROM blacklist WHERE domain_id = ? AND id > ? AND {} ORDER BY id LIMIT ?"#,
+                COLUMNS, ACTIVE
+            ))
+                .bind(source)
+                .bind(after_id)
+                .bind(batch_size)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT {} FROM {} WHERE domain_id = $1 AND id > $2 AND {} ORDER BY id LIMIT $3"#,
+                        COLUMNS,
+                        table(),
+                        ACTIVE
+                    ),
+                    &[&source, &after_id, &batch_size],
+                )
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| SourceRow {
+                            id: row.get("id"),
+                            email: row.get("email"),
+                            reason: row.get("reason"),
+                            category: row.get("category"),
+                            expires_at: row.get("expires_at"),
+                            bounce_type: row.get("bounce_type"),
+                            bounce_sub_type: row.get("bounce_sub_type"),
+                            diagnostic_code: row.get("diagnostic_code"),
+                            diagnostic_class: row.get("diagnostic_class"),
+                            reason_summary: row.get("reason_summary"),
+                            subject: row.get("subject"),
+                            reporting_mta: row.get("reporting_mta"),
+                            remote_mta_ip: row.get("remote_mta_ip"),
+                            source_arn: row.get("source_arn"),
+                            sending_account_id: row.get("sending_account_id"),
+                            created_at: row.get("created_at"),
+                            event_at: row.get("event_at"),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// replaces the target entry of the address with the source one, active again. With `only_older` the target entry
+// is kept when it was created after the source one. Returns whether it was replaced.
+async fn overwrite(entry: &NewEntry, only_older: bool, data: &AppState) -> Result<bool, String> {
+    let older = if only_older { " AND created_at < {created_at}" } else { "" };
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let sql = format!(
+                r#"UPDATE blacklist SET reason = ?, category = ?, expires_at = ?, bounce_type = ?, bounce_sub_type = ?, diagnostic_code = ?, diagnostic_class = ?, reason_summary = ?, subject = ?, reporting_mta = ?, remote_mta_ip = ?, source_arn = ?, sending_account_id = ?, event_at = ?, status = 'active', status_changed_at = NOW(), reason_archived_at = NULL
+                   WHERE domain_id = ? AND email = ?{}"#,
+                older.replace("{created_at}", "?")
+            );
+            let mut query = sqlx::query(&sql)
+                .bind(&entry.reason)
+                .bind(&entry.category)
+                .bind(entry.expires_at)
+                .bind(&entry.bounce_type)
+                .bind(&entry.bounce_sub_type)
+                .bind(&entry.diagnostic_code)
+                .bind(&entry.diagnostic_class)
+                .bind(&entry.reason_summary)
+                .bind(&entry.subject)
+                .bind(&entry.reporting_mta)
+                .bind(&entry.remote_mta_ip)
+                .bind(&entry.source_arn)
+                .bind(&entry.sending_account_id)
+                .bind(entry.event_at)
+                .bind(entry.domain_id)
+                .bind(&entry.email);
+            if only_older {
+                query = query.bind(entry.created_at);
+            }
+
+            query
+                .execute(pool)
+                .await
+                .map(|result| result.rows_affected() > 0)
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let sql = format!(
+                r#"UPDATE {table} SET reason = $1, category = $2, expires_at = $3, bounce_type = $4, bounce_sub_type = $5, diagnostic_code = $6, diagnostic_class = $7, reason_summary = $8, subject = $9, reporting_mta = $10, remote_mta_ip = $11, source_arn = $12, sending_account_id = $13, event_at = $14, status = 'active', status_changed_at = NOW(), reason_archived_at = NULL
+                   WHERE domain_id = $15 AND email = $16{older}"#,
+                table = table(),
+                older = older.replace("{created_at}", "$17")
+            );
+            let created_at = entry.created_at.unwrap_or_default();
+            let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![
+                &entry.reason,
+                &entry.category,
+                &entry.expires_at,
+                &entry.bounce_type,
+                &entry.bounce_sub_type,
+                &entry.diagnostic_code,
+                &entry.diagnostic_class,
+                &entry.reason_summary,
+                &entry.subject,
+                &entry.reporting_mta,
+                &entry.remote_mta_ip,
+                &entry.source_arn,
+                &entry.sending_account_id,
+                &entry.event_at,
+                &entry.domain_id,
+                &entry.email,
+            ];
+            if only_older {
+                params.push(&created_at);
+            }
+
+            client
+                .execute(&sql, &params)
+                .await
+                .map(|updated| updated > 0)
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn delete_source(id: i64, data: &AppState) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"DELETE FROM blacklist WHERE id = ?"#)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(&format!(r#"DELETE FROM {} WHERE id = $1"#, table()), &[&id])
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// merges one source entry into the target domain
+async fn merge_entry(row: &SourceRow, source: i32, request: &MergeRequest, conflict: &str, data: &web::Data<AppState>, summary: &mut MergeSummary) -> Result<(), String> {
+    let target = request.target_domain_id;
+    let entry = row.entry_for(target);
+    // ordered with the notifications of the recipient on the target domain, see RecipientShards
+    let _recipient = data.shards.lock(target, &row.email).await;
+
+    match blacklist::insert(&entry, "blacklist", data).await {
+        Ok(()) => summary.copied += 1,
+        Err(err) if blacklist::is_duplicate(&err) => match conflict {
+            "skip" => summary.skipped += 1,
+            _ => {
+                if overwrite(&entry, conflict == "newest", data).await? {
+                    data.cache.evict(target, &entry.email);
+                    summary.overwritten += 1;
+                } else {
+                    summary.skipped += 1;
+                }
+            }
+        },
+        Err(err) => return Err(err),
+    }
+
+    if request.remove_source {
+        delete_source(row.id, data).await?;
+        data.cache.evict(source, &row.email);
+        summary.removed_from_source += 1;
+    }
+
+    Ok(())
+}
+
+// copies (or moves with remove_source) the active entries of a domain to another, for customers consolidating
+// sending domains or a tenant split in several. Copies go through the regular insert, so they are published and
+// dual written like any new entry.
+pub async fn merge_handler(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<MergeRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let source = path.into_inner();
+    let request = body.into_inner();
+    let conflict = request.on_conflict.clone().unwrap_or_else(|| "skip".into());
+
+    if !CONFLICTS.contains(&conflict.as_str()) {
+        return HttpResponse::BadRequest()
+            .json(ErrorResponse::new(format!("on_conflict must be one of {}", CONFLICTS.join(", "))));
+    }
+
+    if request.target_domain_id == source {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("target_domain_id must differ from the source domain"));
+    }
+
+    let mut summary = match count(source, request.target_domain_id, &data).await {
+        Ok((source_entries, conflicts)) => MergeSummary { dry_run: request.dry_run, source_entries, conflicts, ..Default::default() },
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    if request.dry_run {
+        return HttpResponse::Ok().json(MergeResponse { success: true, data: summary });
+    }
+
+    let batch_size = env::var("MERGE_BATCH_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+        .max(1);
+    let mut after_id = 0;
+
+    loop {
+        let rows = match fetch_batch(source, after_id, batch_size, &data).await {
+            Ok(rows) => rows,
+            Err(err) => {
+                return HttpResponse::InternalServerError()
+                    .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+            }
+        };
+
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        for row in &rows {
+            if let Err(err) = merge_entry(row, source, &request, &conflict, &data, &mut summary).await {
+                println!("🔥 Merge of domain {} into {} stopped at entry {}: {}", source, request.target_domain_id, row.id, err);
+                return HttpResponse::InternalServerError().json(ErrorResponse::new(format!(
+                    "🔥 Failed to merge entry {} after {:?}: {:?}",
+                    row.id, summary, err
+                )));
+            }
+        }
+    }
+
+    last_modified::touch(request.target_domain_id, &data).await;
+    if request.remove_source {
+        last_modified::touch(source, &data).await;
+    }
+
+    println!("✅ Merged domain {} into {} ({}): {:?}", source, request.target_domain_id, conflict, summary);
+
+    HttpResponse::Ok().json(MergeResponse { success: true, data: summary })
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse, Responder};
+use tokio::sync::broadcast;
+
+use crate::events::{self, Event};
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Counter { name, help, value: AtomicU64::new(0) }
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+// a value that goes up and down, stored as f64 bits
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Gauge {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Gauge { name, help, value: AtomicU64::new(0) }
+    }
+
+    pub fn set(&self, value: f64) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.value.load(Ordering::Relaxed))
+    }
+}
+
+pub static SNS_BOM_STRIPPED: Counter =
+    Counter::new("sns_payload_bom_stripped_total", "SNS payloads received with a UTF-8 byte order mark");
+pub static SNS_LOSSY_DECODED: Counter =
+    Counter::new("sns_payload_lossy_decoded_total", "SNS payloads with invalid UTF-8 decoded lossily");
+
+pub static EMAILS_SUPPRESSED: Counter = Counter::new("emails_suppressed_total", "Addresses added to the blacklist");
+pub static COMPLAINTS_RECEIVED: Counter = Counter::new("complaints_received_total", "Complaint notifications received");
+pub static LOOKUPS_PERFORMED: Counter = Counter::new("lookups_performed_total", "Addresses looked up");
+pub static LOOKUPS_BLACKLISTED: Counter =
+    Counter::new("lookups_blacklisted_total", "Addresses looked up and found blacklisted");
+pub static DOMAINS_PAUSED: Counter =
+    Counter::new("domains_paused_total", "Domains paused for exceeding the complaint rate threshold");
+
+pub static SES_TIMESTAMPS_SKEWED: Counter =
+    Counter::new("ses_timestamps_skewed_total", "SES events stamped too far in the future or the past");
+
+pub static SES_UNKNOWN_FIELDS: Counter =
+    Counter::new("ses_unknown_fields_total", "SES payloads with fields the models do not know (SES_PARSE_MODE=report or strict)");
+
+pub static AUTH_FAILURES: Counter =
+    Counter::new("auth_failures_total", "Requests refused for an unknown API key, a key used on another domain or a wrong admin token");
+pub static AUTH_LOCKOUTS: Counter =
+    Counter::new("auth_lockouts_total", "Source IPs or API key prefixes locked out after repeated authentication failures");
+
+pub static SNS_SOURCES_REFUSED: Counter =
+    Counter::new("sns_sources_refused_total", "SNS notifications from sources outside SNS_IP_ALLOWLIST");
+
+pub static NOTIFICATIONS_IN_FLIGHT: Gauge =
+    Gauge::new("notifications_in_flight", "Notifications being written, holding a slot of their domain's concurrency limit");
+pub static NOTIFICATIONS_WAITING: Gauge =
+    Gauge::new("notifications_waiting", "Notifications waiting for a slot of their domain's concurrency limit");
+pub static WORKER_UTILIZATION: Gauge =
+    Gauge::new("notification_worker_utilization", "Share of the concurrency slots of the active domains in use, 0 to 1");
+pub static WEBHOOKS_IN_FLIGHT: Gauge =
+    Gauge::new("webhook_deliveries_in_flight", "Webhook deliveries being sent or waiting for a retry");
+pub static OUTBOX_PENDING: Gauge = Gauge::new("outbox_pending", "Suppression events committed and not yet published");
+pub static OUTBOX_LAG: Gauge = Gauge::new("outbox_lag_seconds", "Age of the oldest unpublished outbox event");
+pub static PROCESSING_LAG: Gauge =
+    Gauge::new("notification_processing_lag_seconds", "Age of the SES event most recently processed, from its SES timestamp");
+pub static SQS_VISIBLE: Gauge = Gauge::new("sqs_messages_visible", "Messages waiting in SQS_QUEUE_URL (NOTIFICATION_SOURCE=sqs)");
+pub static SQS_NOT_VISIBLE: Gauge =
+    Gauge::new("sqs_messages_not_visible", "Messages of SQS_QUEUE_URL received and not yet deleted (NOTIFICATION_SOURCE=sqs)");
+
+static GAUGES: [&Gauge; 9] = [
+    &NOTIFICATIONS_IN_FLIGHT,
+    &NOTIFICATIONS_WAITING,
+    &WORKER_UTILIZATION,
+    &WEBHOOKS_IN_FLIGHT,
+    &OUTBOX_PENDING,
+    &OUTBOX_LAG,
+    &PROCESSING_LAG,
+    &SQS_VISIBLE,
+    &SQS_NOT_VISIBLE,
+];
+
+pub static SNS_CERT_URLS_REJECTED: Counter =
+    Counter::new("sns_cert_urls_rejected_total", "SNS messages refused for a SigningCertURL outside the trusted SNS endpoints");
+pub static SNS_CERT_FETCH_FAILURES: Counter =
+    Counter::new("sns_cert_fetch_failures_total", "SNS signing certificates that could not be fetched or were not valid");
+
+pub static SHADOW_COMPARISONS: Counter =
+    Counter::new("shadow_comparisons_total", "Notifications run through the candidate pipeline (SHADOW_MODE=true)");
+pub static SHADOW_DISCREPANCIES: Counter =
+    Counter::new("shadow_discrepancies_total", "Notifications the candidate pipeline read differently from the live one");
+
+static COUNTERS: [&Counter; 16] = [
+    &SNS_BOM_STRIPPED,
+    &SNS_LOSSY_DECODED,
+    &SNS_SOURCES_REFUSED,
+    &SNS_CERT_URLS_REJECTED,
+    &SNS_CERT_FETCH_FAILURES,
+    &SES_TIMESTAMPS_SKEWED,
+    &SES_UNKNOWN_FIELDS,
+    &SHADOW_COMPARISONS,
+    &SHADOW_DISCREPANCIES,
+    &EMAILS_SUPPRESSED,
+    &COMPLAINTS_RECEIVED,
+    &LOOKUPS_PERFORMED,
+    &LOOKUPS_BLACKLISTED,
+    &DOMAINS_PAUSED,
+    &AUTH_FAILURES,
+    &AUTH_LOCKOUTS,
+];
+
+// counts the events of the bus for the whole life of the process
+pub fn spawn(events: &broadcast::Sender<Event>) {
+    events::subscribe(events, "metrics", |event| match event {
+        Event::EmailSuppressed(_) => EMAILS_SUPPRESSED.inc(),
+        Event::ComplaintReceived { .. } => COMPLAINTS_RECEIVED.inc(),
+        Event::LookupPerformed { lookups, blacklisted, .. } => {
+            LOOKUPS_PERFORMED.add(lookups as u64);
+            LOOKUPS_BLACKLISTED.add(blacklisted as u64);
+        }
+        Event::DomainPaused(_) => DOMAINS_PAUSED.inc(),
+    });
+}
+
+// upper bounds in seconds of the request duration buckets, +Inf is implied
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const DEFAULT_MAX_SERIES: usize = 5000;
+
+const REQUEST_DURATION: &str = "http_request_duration_seconds";
+const REQUEST_DURATION_HELP: &str = "Duration of the HTTP requests until the response head, by route, method, status and domain";
+
+// the labels of a request: the route pattern, not the path, so addresses and ids do not make new series
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RequestLabels {
+    route: String,
+    method: String,
+    status: u16,
+    // the {domain_id} of the route, empty for routes without one and "other" past METRICS_MAX_SERIES
+    domain: String,
+}
+
+// the trace of the latest request that landed in a bucket, so a slow bucket links to a trace to open
+#[derive(Debug, Clone)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // per bucket, not cumulative, the last one is +Inf
+    counts: [u64; BUCKETS.len() + 1],
+    exemplars: [Option<Exemplar>; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64, trace_id: Option<String>) {
+        let bucket = BUCKETS.iter().position(|bound| value <= *bound).unwrap_or(BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+
+        if let Some(trace_id) = trace_id {
+            let timestamp = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+            self.exemplars[bucket] = Some(Exemplar { trace_id, value, timestamp });
+        }
+    }
+}
+
+fn histograms() -> &'static Mutex<HashMap<RequestLabels, Histogram>> {
+    static HISTOGRAMS: OnceLock<Mutex<HashMap<RequestLabels, Histogram>>> = OnceLock::new();
+    HISTOGRAMS.get_or_init(Default::default)
+}
+
+// METRICS_MAX_SERIES bounds the label sets kept, once reached new domains are counted as "other"
+fn max_series() -> usize {
+    static MAX_SERIES: OnceLock<usize> = OnceLock::new();
+    *MAX_SERIES.get_or_init(|| {
+        env::var("METRICS_MAX_SERIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_SERIES)
+    })
+}
+
+// the value of the {domain_id} segment of the pattern in the path
+fn domain_label(pattern: &str, path: &str) -> String {
+    pattern
+        .split('/')
+        .zip(path.split('/'))
+        .find(|(segment, _)| *segment == "{domain_id}")
+        .and_then(|(_, value)| value.parse::<i32>().ok())
+        .map(|domain_id| domain_id.to_string())
+        .unwrap_or_default()
+}
+
+// the W3C traceparent trace id, or the root of the X-Amzn-Trace-Id an ALB or X-Ray adds
+fn trace_id(req: &HttpRequest) -> Option<String> {
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(trace_id) = header("traceparent").and_then(|value| value.split('-').nth(1)) {
+        if trace_id.len() == 32 && trace_id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Some(trace_id.to_string());
+        }
+    }
+
+    header("X-Amzn-Trace-Id")?
+        .split(';')
+        .find_map(|part| part.trim().strip_prefix("Root="))
+        .filter(|root| root.len() <= 64 && root.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        .map(str::to_string)
+}
+
+fn record(req: &HttpRequest, status: u16, seconds: f64) {
+    let pattern = req.match_pattern();
+    let domain = pattern.as_deref().map(|pattern| domain_label(pattern, req.path())).unwrap_or_default();
+
+    let mut labels = RequestLabels {
+        // unmatched paths (scanners, typos) would each make a series
+        route: pattern.unwrap_or_else(|| "unmatched".into()),
+        method: req.method().to_string(),
+        status,
+        domain,
+    };
+
+    let mut histograms = histograms().lock().unwrap();
+
+    if !histograms.contains_key(&labels) && histograms.len() >= max_series() && !labels.domain.is_empty() {
+        labels.domain = "other".into();
+    }
+
+    histograms.entry(labels).or_default().observe(seconds, trace_id(req));
+}
+
+// times every request, to tell which route and which domain a latency spike comes from
+pub async fn track(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started_at = Instant::now();
+    let response = next.call(req).await?;
+
+    record(response.request(), response.status().as_u16(), started_at.elapsed().as_secs_f64());
+
+    Ok(response)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn bound(index: usize) -> String {
+    BUCKETS.get(index).map(|bound| bound.to_string()).unwrap_or_else(|| "+Inf".into())
+}
+
+// the Prometheus text format, or OpenMetrics (which Prometheus asks for when exemplar storage is on): the counter
+// families lose their _total suffix there and the buckets carry their exemplar
+pub fn render(open_metrics: bool) -> String {
+    let mut body = String::new();
+
+    for counter in COUNTERS {
+        let family = if open_metrics { counter.name.trim_end_matches("_total") } else { counter.name };
+        body.push_str(&format!("# HELP {} {}\n", family, counter.help));
+        body.push_str(&format!("# TYPE {} counter\n", family));
+        body.push_str(&format!("{} {}\n", counter.name, counter.get()));
+    }
+
+    for gauge in GAUGES {
+        body.push_str(&format!("# HELP {} {}\n", gauge.name, gauge.help));
+        body.push_str(&format!("# TYPE {} gauge\n", gauge.name));
+        body.push_str(&format!("{} {}\n", gauge.name, gauge.get()));
+    }
+
+    body.push_str(&format!("# HELP {} {}\n", REQUEST_DURATION, REQUEST_DURATION_HELP));
+    body.push_str(&format!("# TYPE {} histogram\n", REQUEST_DURATION));
+
+    let histograms = histograms().lock().unwrap();
+    let mut series: Vec<_> = histograms.iter().collect();
+    series.sort_by(|(a, _), (b, _)| (&a.route, &a.method, a.status, &a.domain).cmp(&(&b.route, &b.method, b.status, &b.domain)));
+
+    for (labels, histogram) in series {
+        let labels = format!(
+            r#"route="{}",method="{}",status="{}",domain_id="{}""#,
+            escape(&labels.route),
+            escape(&labels.method),
+            labels.status,
+            escape(&labels.domain)
+        );
+        let mut cumulative = 0;
+
+        for (index, count) in histogram.counts.iter().enumerate() {
+            cumulative += count;
+            body.push_str(&format!(r#"{}_bucket{{{},le="{}"}} {}"#, REQUEST_DURATION, labels, bound(index), cumulative));
+
+            if let (true, Some(exemplar)) = (open_metrics, &histogram.exemplars[index]) {
+                body.push_str(&format!(
+                    r#" # {{trace_id="{}"}} {} {}"#,
+                    exemplar.trace_id, exemplar.value, exemplar.timestamp
+                ));
+            }
+            body.push('\n');
+        }
+
+        body.push_str(&format!("{}_sum{{{}}} {}\n", REQUEST_DURATION, labels, histogram.sum));
+        body.push_str(&format!("{}_count{{{}}} {}\n", REQUEST_DURATION, labels, histogram.count));
+    }
+
+    if open_metrics {
+        body.push_str("# EOF\n");
+    }
+
+    body
+}
+
+pub async fn metrics_handler(req: HttpRequest) -> impl Responder {
+    let open_metrics = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+
+    if open_metrics {
+        HttpResponse::Ok()
+            .content_type("application/openmetrics-text; version=1.0.0; charset=utf-8")
+            .body(render(true))
+    } else {
+        HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(render(false))
+    }
+}
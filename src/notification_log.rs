@@ -0,0 +1,189 @@
+use std::collections::BTreeSet;
+use std::env;
+
+use actix_web::web;
+use chrono::NaiveDateTime;
+
+use crate::domain::Message;
+use crate::privacy;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::services::notifications::extract_email_address;
+use crate::AppState;
+
+// (id, SES message JSON, received_at)
+pub type LoggedNotification = (i64, String, NaiveDateTime);
+
+pub fn table() -> String {
+    env::var("PG_NOTIFICATION_LOG_TABLE").unwrap_or_else(|_| "notification_log".into())
+}
+
+pub fn recipients_table() -> String {
+    env::var("PG_NOTIFICATION_RECIPIENTS_TABLE").unwrap_or_else(|_| "notification_recipients".into())
+}
+
+// SES names at most 50 destinations, the cap only guards against a forged message
+const MAX_RECIPIENTS: usize = 100;
+
+// the addresses the message names, as stored in the blacklist so a search goes through privacy::stored_email too
+fn recipients(message: &Message) -> BTreeSet<String> {
+    let destination = message.mail.iter().flat_map(|mail| mail.destination.iter());
+    let bounced = message.bounce.iter().flat_map(|bounce| bounce.bounced_recipients.iter().map(|recipient| &recipient.email_address));
+    let complained = message
+        .complaint
+        .iter()
+        .flat_map(|complaint| complaint.complained_recipients.iter().map(|recipient| &recipient.email_address));
+
+    destination
+        .chain(bounced)
+        .chain(complained)
+        .map(|address| privacy::stored_email(&extract_email_address(address)))
+        .filter(|email| !email.is_empty())
+        .take(MAX_RECIPIENTS)
+        .collect()
+}
+
+// the id of the logged notification
+async fn insert(domain_id: i32, message: &Message, payload: &str, data: &web::Data<AppState>) -> Result<i64, String> {
+    let notification_type = message.notification_type.as_str();
+    let message_id = message.mail.as_ref().map(|mail| mail.message_id.as_str());
+    let bounce_sub_type = message.bounce.as_ref().map(|bounce| bounce.bounce_sub_type.as_str());
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"INSERT INTO notification_log (domain_id, notification_type, message_id, payload, bounce_sub_type) VALUES (?,?,?,?,?)"#,
+            )
+                .bind(domain_id)
+                .bind(notification_type)
+                .bind(message_id)
+                .bind(payload)
+                .bind(bounce_sub_type)
+                .execute(pool)
+                .await
+                .map(|result| result.last_insert_id() as i64)
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, notification_type, message_id, payload, bounce_sub_type) VALUES ($1,$2,$3,$4,$5)
+                           RETURNING id"#,
+                        table = table()
+                    ),
+                    &[&domain_id, &notification_type, &message_id, &payload, &bounce_sub_type],
+                )
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn insert_recipients(id: i64, domain_id: i32, recipients: &BTreeSet<String>, data: &web::Data<AppState>) -> Result<(), String> {
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let sql = format!(
+                r#"INSERT IGNORE INTO notification_recipients (notification_id, domain_id, email) VALUES {values}"#,
+                values = vec!["(?,?,?)"; recipients.len()].join(",")
+            );
+            let mut query = sqlx::query(&sql);
+
+            for email in recipients {
+                query = query.bind(id).bind(domain_id).bind(email);
+            }
+
+            query.execute(pool).await.map(|_| ()).map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let emails: Vec<&String> = recipients.iter().collect();
+
+            client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (notification_id, domain_id, email) SELECT $1, $2, UNNEST($3::text[])
+                           ON CONFLICT DO NOTHING"#,
+                        table = recipients_table()
+                    ),
+                    &[&id, &domain_id, &emails],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// keeps every parsed SES message, so suppressions can be rebuilt after a rules or parsing fix, with the addresses
+// it names for the event search
+pub async fn record(domain_id: i32, message: &Message, payload: &str, data: &web::Data<AppState>) {
+    if matches!(data.db_type, DBType::DynamoDB(_)) {
+        return;
+    }
+
+    let result = match insert(domain_id, message, &privacy::stored_text(payload), data).await {
+        Ok(id) => insert_recipients(id, domain_id, &recipients(message), data).await,
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = result {
+        println!("🔥 Failed to log notification for domain {}: {:?}", domain_id, err);
+    }
+}
+
+// the next page of logged notifications of a type in [from, to), ordered by id
+pub async fn fetch_window(
+    domain_id: i32,
+    notification_type: &str,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    after_id: i64,
+    limit: i64,
+    data: &web::Data<AppState>,
+) -> Result<Vec<LoggedNotification>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, LoggedNotification>(
+                r#"SELECT id, payload, received_at FROM notification_log
+                   WHERE domain_id = ? AND notification_type = ? AND received_at >= ? AND received_at < ? AND id > ?
+                   ORDER BY id LIMIT ?"#,
+            )
+                .bind(domain_id)
+                .bind(notification_type)
+                .bind(from)
+                .bind(to)
+                .bind(after_id)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, payload, received_at FROM {table}
+                           WHERE domain_id = $1 AND notification_type = $2 AND received_at >= $3 AND received_at < $4 AND id > $5
+                           ORDER BY id LIMIT $6"#,
+                        table = table()
+                    ),
+                    &[&domain_id, &notification_type, &from, &to, &after_id, &limit],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono_tz::Tz;
+use openssl::rand::rand_bytes;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api_keys;
+use crate::blacklist;
+use crate::domains;
+use crate::handlers::is_admin;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::ErrorResponse;
+use crate::rules::{self, BounceRule};
+use crate::topic_mappings;
+use crate::AppState;
+
+// a new tenant in one call: the domain row with its rules, an API key scoped to it and optionally the SNS topic
+// mapping, written in one transaction so a failure leaves nothing half provisioned
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct NewTenant {
+    // the next free id when absent
+    pub id: Option<i32>,
+    pub name: String,
+    // TENANT_DEFAULT_BOUNCE_RULES (a JSON array) when absent
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub bounce_rules: Option<Vec<BounceRule>>,
+    // TENANT_DEFAULT_SUPPRESSION_DAYS (a JSON object) when absent
+    pub suppression_days: Option<HashMap<String, i64>>,
+    // IANA name, e.g. "Europe/Berlin"
+    pub reporting_timezone: Option<String>,
+    // mapped to the domain for the shared /api/sns-endpoint, refused when another domain has it
+    pub topic_arn: Option<String>,
+    // the name of the key, "<name> (onboarding)" by default
+    pub api_key_name: Option<String>,
+    pub daily_quota: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Tenant {
+    pub domain_id: i32,
+    pub name: String,
+    // shown once, only its hash is stored
+    pub api_key: String,
+    pub api_key_id: i64,
+    pub topic_arn: Option<String>,
+    // where to subscribe the SES notifications of the tenant: the shared endpoint with a mapped topic
+    pub sns_endpoint: String,
+    #[schema(value_type = Vec<Object>)]
+    pub bounce_rules: Vec<BounceRule>,
+    pub suppression_days: HashMap<String, i64>,
+    pub reporting_timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TenantResponse {
+    pub success: bool,
+    pub data: Tenant,
+}
+
+// domain ids allocated before a concurrent onboarding taking the same one answers 409
+const ALLOCATION_ATTEMPTS: usize = 5;
+
+enum OnboardingError {
+    // the domain id is taken
+    DomainTaken(i32),
+    // the topic is taken
+    Conflict(String),
+    Database(String),
+}
+
+fn pg_table(var: &str, default: &str) -> String {
+    env::var(var).unwrap_or_else(|_| default.into())
+}
+
+fn default_rules() -> Vec<BounceRule> {
+    env::var("TENANT_DEFAULT_BOUNCE_RULES")
+        .ok()
+        .filter(|raw| !raw.is_empty())
+        .map(|raw| rules::parse_rules(&raw))
+        .unwrap_or_default()
+}
+
+fn default_suppression_days() -> HashMap<String, i64> {
+    let Some(raw) = env::var("TENANT_DEFAULT_SUPPRESSION_DAYS").ok().filter(|raw| !raw.is_empty()) else {
+        return HashMap::new();
+    };
+
+    let parsed = serde_json::from_str(&raw).map_err(|err| format!("{:?}", err)).and_then(|days| {
+        domains::check_suppression_days(&days)?;
+        Ok(days)
+    });
+
+    parsed.unwrap_or_else(|err| {
+        println!("🔥 Invalid TENANT_DEFAULT_SUPPRESSION_DAYS, onboarding without suppression days: {}", err);
+        HashMap::new()
+    })
+}
+
+fn generate_key() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    rand_bytes(&mut bytes).map_err(|err| err.to_string())?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// JSON columns stay NULL without a value, as for the domains created by hand
+fn json_column<T: Serialize>(value: &T, empty: bool) -> Option<String> {
+    (!empty).then(|| serde_json::to_string(value).unwrap_or_default())
+}
+
+fn conflict_or(err: String, conflict: impl FnOnce() -> OnboardingError) -> OnboardingError {
+    if blacklist::is_duplicate(&err) {
+        conflict()
+    } else {
+        OnboardingError::Database(err)
+    }
+}
+
+// (domain id, api key id). Without `id` the next free one is taken, a concurrent onboarding may take it first.
+async fn provision(tenant: &Tenant, id: Option<i32>, key_name: &str, daily_quota: Option<i64>, data: &web::Data<AppState>) -> Result<(i32, i64), OnboardingError> {
+    let bounce_rules = json_column(&tenant.bounce_rules, tenant.bounce_rules.is_empty());
+    let suppression_days = json_column(&tenant.suppression_days, tenant.suppression_days.is_empty());
+    let key_hash = api_keys::hash_key(&tenant.api_key);
+    let taken = |id: i32| move || OnboardingError::DomainTaken(id);
+    let mapped = || {
+        OnboardingError::Conflict(format!("topic {} is already mapped to a domain", tenant.topic_arn.as_deref().unwrap_or_default()))
+    };
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let mut tx = pool.begin().await.map_err(|err| OnboardingError::Database(err.to_string()))?;
+
+            let domain_id = match id {
+                Some(id) => id,
+                None => sqlx::query_scalar::<_, i64>(r#"SELECT CAST(COALESCE(MAX(id), 0) + 1 AS SIGNED) FROM domains"#)
+                    .fetch_one(&mut tx)
+                    .await
+                    .map(|id| id as i32)
+                    .map_err(|err| OnboardingError::Database(err.to_string()))?,
+            };
+
+            sqlx::query(r#"INSERT INTO domains (id, name, bounce_rules, suppression_days, reporting_timezone) VALUES (?, ?, ?, ?, ?)"#)
+                .bind(domain_id)
+                .bind(&tenant.name)
+                .bind(&bounce_rules)
+                .bind(&suppression_days)
+                .bind(&tenant.reporting_timezone)
+                .execute(&mut tx)
+                .await
+                .map_err(|err| conflict_or(err.to_string(), taken(domain_id)))?;
+
+            let api_key_id = sqlx::query(r#"INSERT INTO api_keys (name, key_hash, daily_quota, domain_id) VALUES (?, ?, ?, ?)"#)
+                .bind(key_name)
+                .bind(&key_hash)
+                .bind(daily_quota)
+                .bind(domain_id)
+                .execute(&mut tx)
+                .await
+                .map(|result| result.last_insert_id() as i64)
+                .map_err(|err| OnboardingError::Database(err.to_string()))?;
+
+            if let Some(topic_arn) = &tenant.topic_arn {
+                sqlx::query(r#"INSERT INTO topic_mappings (topic_arn, domain_id) VALUES (?, ?)"#)
+                    .bind(topic_arn)
+                    .bind(domain_id)
+                    .execute(&mut tx)
+                    .await
+                    .map_err(|err| conflict_or(err.to_string(), mapped))?;
+            }
+
+            tx.commit().await.map_err(|err| OnboardingError::Database(err.to_string()))?;
+            Ok((domain_id, api_key_id))
+        }
+        DBType::Postgres => {
+            let mut client = build_pg_pool(&data.db_url).await.map_err(|err| OnboardingError::Database(err.to_string()))?;
+            let tx = client.transaction().await.map_err(|err| OnboardingError::Database(err.to_string()))?;
+            let domains = pg_table("PG_DOMAINS_TABLE", "domains");
+
+            let domain_id = match id {
+                Some(id) => id,
+                None => tx
+                    .query_one(&format!(r#"SELECT COALESCE(MAX(id), 0) + 1 FROM {}"#, domains), &[])
+                    .await
+                    .map(|row| row.get::<_, i32>(0))
+                    .map_err(|err| OnboardingError::Database(err.to_string()))?,
+            };
+
+            tx.execute(
+                &format!(
+                    r#"INSERT INTO {} (id, name, bounce_rules, suppression_days, reporting_timezone) VALUES ($1, $2, $3, $4, $5)"#,
+                    domains
+                ),
+                &[&domain_id, &tenant.name, &bounce_rules, &suppression_days, &tenant.reporting_timezone],
+            )
+                .await
+                .map_err(|err| conflict_or(err.to_string(), taken(domain_id)))?;
+
+            let api_key_id = tx
+                .query_one(
+                    &format!(
+                        r#"INSERT INTO {} (name, key_hash, daily_quota, domain_id) VALUES ($1, $2, $3, $4) RETURNING id"#,
+                        pg_table("PG_API_KEYS_TABLE", "api_keys")
+                    ),
+                    &[&key_name, &key_hash, &daily_quota, &domain_id],
+                )
+                .await
+                .map(|row| row.get::<_, i64>(0))
+                .map_err(|err| OnboardingError::Database(err.to_string()))?;
+
+            if let Some(topic_arn) = &tenant.topic_arn {
+                tx.execute(
+                    &format!(
+                        r#"INSERT INTO {} (topic_arn, domain_id) VALUES ($1, $2)"#,
+                        pg_table("PG_TOPIC_MAPPINGS_TABLE", "topic_mappings")
+                    ),
+                    &[topic_arn, &domain_id],
+                )
+                    .await
+                    .map_err(|err| conflict_or(err.to_string(), mapped))?;
+            }
+
+            tx.commit().await.map_err(|err| OnboardingError::Database(err.to_string()))?;
+            Ok((domain_id, api_key_id))
+        }
+        DBType::DynamoDB(_) => Err(OnboardingError::Database(dynamodb::UNSUPPORTED.into())),
+    }
+}
+
+// replaces the manual SQL of a new tenant, the answer carries the API key in clear once
+pub async fn onboard_handler(req: HttpRequest, body: web::Json<NewTenant>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    let topic_arn = body.topic_arn.map(|topic_arn| topic_arn.trim().to_string()).filter(|topic_arn| !topic_arn.is_empty());
+    let reporting_timezone = body.reporting_timezone.map(|tz| tz.trim().to_string()).filter(|tz| !tz.is_empty());
+
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("name is required"));
+    }
+    if body.id.is_some_and(|id| id <= 0) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("id must be positive"));
+    }
+    if topic_arn.as_deref().is_some_and(|topic_arn| !topic_mappings::is_topic_arn(topic_arn)) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("topic_arn must be an SNS topic ARN"));
+    }
+    if let Some(Err(err)) = reporting_timezone.as_deref().map(|tz| tz.parse::<Tz>()) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("invalid reporting_timezone: {}", err)));
+    }
+    if let Some(Err(err)) = body.suppression_days.as_ref().map(domains::check_suppression_days) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(err));
+    }
+
+    let api_key = match generate_key() {
+        Ok(api_key) => api_key,
+        Err(err) => return HttpResponse::InternalServerError().json(ErrorResponse::new(err)),
+    };
+    let key_name = body.api_key_name.unwrap_or_else(|| format!("{} (onboarding)", name));
+
+    let mut tenant = Tenant {
+        domain_id: 0,
+        name,
+        api_key,
+        api_key_id: 0,
+        sns_endpoint: String::new(),
+        topic_arn,
+        bounce_rules: body.bounce_rules.unwrap_or_else(default_rules),
+        suppression_days: body.suppression_days.unwrap_or_else(default_suppression_days),
+        reporting_timezone,
+    };
+
+    // an allocated id taken by a concurrent onboarding is allocated again, only an id asked for conflicts
+    let mut provisioned = provision(&tenant, body.id, &key_name, body.daily_quota, &data).await;
+    for _ in 1..ALLOCATION_ATTEMPTS {
+        match provisioned {
+            Err(OnboardingError::DomainTaken(id)) if body.id.is_none() => {
+                println!("Domain id {} was taken by a concurrent onboarding, allocating another", id);
+                provisioned = provision(&tenant, None, &key_name, body.daily_quota, &data).await;
+            }
+            _ => break,
+        }
+    }
+
+    match provisioned {
+        Ok((domain_id, api_key_id)) => {
+            tenant.domain_id = domain_id;
+            tenant.api_key_id = api_key_id;
+            tenant.sns_endpoint = match &tenant.topic_arn {
+                Some(topic_arn) => {
+                    topic_mappings::forget(topic_arn);
+                    "/api/sns-endpoint".into()
+                }
+                None => format!("/api/{}/sns-endpoint", domain_id),
+            };
+
+            println!("✅ Onboarded domain {} ({}) with API key {}", domain_id, tenant.name, api_key_id);
+            HttpResponse::Created().json(TenantResponse { success: true, data: tenant })
+        }
+        Err(OnboardingError::DomainTaken(id)) => {
+            HttpResponse::Conflict().json(ErrorResponse::new(format!("domain {} already exists", id)))
+        }
+        Err(OnboardingError::Conflict(err)) => HttpResponse::Conflict().json(ErrorResponse::new(err)),
+        Err(OnboardingError::Database(err)) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
@@ -0,0 +1,81 @@
+use std::env;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use aws_config::{AppName, BehaviorVersion, SdkConfig};
+
+// an unreachable host fails fast, the calls set their own overall timeouts
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// identifies the service on every outbound request, OUTBOUND_USER_AGENT replaces it entirely
+// and SERVICE_ENVIRONMENT (e.g. production) is appended as a tag
+pub fn user_agent() -> String {
+    if let Some(user_agent) = env::var("OUTBOUND_USER_AGENT").ok().filter(|value| !value.is_empty()) {
+        return user_agent;
+    }
+
+    let base = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    match environment() {
+        Some(environment) => format!("{} ({})", base, environment),
+        None => base,
+    }
+}
+
+fn environment() -> Option<String> {
+    env::var("SERVICE_ENVIRONMENT").ok().filter(|value| !value.is_empty())
+}
+
+// OUTBOUND_SOURCE_IP binds outbound connections to one local address, for egress controlled networks
+fn source_ip() -> Option<IpAddr> {
+    let value = env::var("OUTBOUND_SOURCE_IP").ok().filter(|value| !value.is_empty())?;
+
+    match value.parse() {
+        Ok(ip) => Some(ip),
+        Err(err) => {
+            println!("🔥 Invalid OUTBOUND_SOURCE_IP {:?}, using the default route: {}", value, err);
+            None
+        }
+    }
+}
+
+// every HTTP client of the service starts from here
+pub fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent(user_agent())
+        .local_address(source_ip())
+        .connect_timeout(CONNECT_TIMEOUT)
+}
+
+// shared client for one-off calls like subscription confirmations and certificate downloads
+pub fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| client_builder().build().unwrap_or_default())
+}
+
+// the AWS SDK adds the app name to its own User-Agent, it only takes letters, digits and a few symbols
+fn app_name() -> Option<AppName> {
+    let name = match environment() {
+        Some(environment) => format!("{}-{}", env!("CARGO_PKG_NAME"), environment),
+        None => env!("CARGO_PKG_NAME").to_string(),
+    };
+
+    match AppName::new(name.clone()) {
+        Ok(app_name) => Some(app_name),
+        Err(err) => {
+            println!("🔥 Cannot use {:?} as AWS app name: {}", name, err);
+            None
+        }
+    }
+}
+
+pub async fn aws_config() -> SdkConfig {
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+
+    if let Some(app_name) = app_name() {
+        loader = loader.app_name(app_name);
+    }
+
+    loader.load().await
+}
@@ -0,0 +1,246 @@
+use std::env;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use actix_web::web;
+use sqlx::{MySql, Transaction};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::events::{self, Event, LiveEvent};
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::{webhooks, AppState};
+
+const PURGE_EVERY: Duration = Duration::from_secs(600);
+
+// (id, payload) of a pending event
+type OutboxRow = (i64, String);
+
+// the suppression events are written to the outbox with their entry (see blacklist::insert) and handed on by this
+// publisher once committed, so no consumer sees an entry that was rolled back. The webhook deliveries are stored in
+// the transaction marking the rows published, so a webhook never misses an event. The event bus (SSE stream,
+// ClickHouse, EventBridge) gets them at least once, but is in memory: a lagging subscriber or a restart loses some.
+// Other instances skip the rows being published.
+struct Config {
+    poll: Duration,
+    batch_size: i64,
+    retention_hours: i64,
+}
+
+fn config() -> Config {
+    let number = |var: &str, default: u64| env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+
+    Config {
+        poll: Duration::from_millis(number("OUTBOX_POLL_MS", 1000).max(10)),
+        batch_size: number("OUTBOX_BATCH_SIZE", 100).max(1) as i64,
+        retention_hours: number("OUTBOX_RETENTION_HOURS", 24) as i64,
+    }
+}
+
+pub fn table() -> String {
+    env::var("PG_OUTBOX_TABLE").unwrap_or_else(|_| "outbox".into())
+}
+
+fn notify() -> &'static Notify {
+    static NOTIFY: OnceLock<Notify> = OnceLock::new();
+    NOTIFY.get_or_init(Notify::new)
+}
+
+// wakes the publisher of this instance after a commit instead of waiting for the next poll
+pub fn wake() {
+    notify().notify_one();
+}
+
+pub fn payload(event: &LiveEvent) -> String {
+    serde_json::to_string(event).unwrap_or_default()
+}
+
+pub async fn enqueue(tx: &mut Transaction<'_, MySql>, event: &LiveEvent) -> Result<(), String> {
+    sqlx::query(r#"INSERT INTO outbox (domain_id, event_type, payload) VALUES (?, ?, ?)"#)
+        .bind(event.domain_id)
+        .bind(&event.event_type)
+        .bind(payload(event))
+        .execute(tx)
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+// an event of a change already committed on its own, e.g. a status change
+pub async fn enqueue_committed(data: &web::Data<AppState>, event: &LiveEvent) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let mut tx = pool.begin().await.map_err(|err| err.to_string())?;
+            enqueue(&mut tx, event).await?;
+            tx.commit().await.map_err(|err| err.to_string())?;
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(r#"INSERT INTO {} (domain_id, event_type, payload) VALUES ($1, $2, $3)"#, table()),
+                    &[&event.domain_id, &event.event_type, &payload(event)],
+                )
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        // nothing to enqueue into, the event is published at once (at most once)
+        DBType::DynamoDB(_) => {
+            events::publish(data, Event::EmailSuppressed(event.clone()));
+            return Ok(());
+        }
+    }
+
+    wake();
+    Ok(())
+}
+
+// the Postgres insert of an entry extended to write its outbox row in the same statement, the event type and the
+// payload are the two parameters after the entry's 18
+pub fn pg_with_entry(insert: &str) -> String {
+    format!(
+        r#"WITH entry AS ({insert} RETURNING id) INSERT INTO {table} (domain_id, event_type, payload) SELECT $1, $19, $20 FROM entry"#,
+        insert = insert,
+        table = table()
+    )
+}
+
+fn parse(id: i64, payload: &str) -> Option<LiveEvent> {
+    serde_json::from_str::<LiveEvent>(payload)
+        // marked published anyway, an unreadable row must not hold back the ones after it
+        .map_err(|err| println!("🔥 Skipped outbox event {}, unreadable payload: {:?}", id, err))
+        .ok()
+}
+
+// publishes one batch of pending events in id order, returns how many were published
+async fn publish_batch(data: &web::Data<AppState>, batch_size: i64) -> Result<usize, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let mut tx = pool.begin().await.map_err(|err| err.to_string())?;
+
+            let rows = sqlx::query_as::<_, OutboxRow>(
+                r#"SELECT id, payload FROM outbox WHERE published_at IS NULL ORDER BY id LIMIT ? FOR UPDATE SKIP LOCKED"#,
+            )
+                .bind(batch_size)
+                .fetch_all(&mut tx)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            if rows.is_empty() {
+                return Ok(0);
+            }
+
+            for event in rows.iter().filter_map(|(id, payload)| parse(*id, payload)) {
+                webhooks::enqueue_in_mysql(&mut tx, &event, data).await?;
+                events::publish(data, Event::EmailSuppressed(event));
+            }
+
+            let ids = rows.iter().map(|(id, _)| id.to_string()).collect::<Vec<_>>().join(",");
+            sqlx::query(&format!("UPDATE outbox SET published_at = NOW() WHERE id IN ({})", ids))
+                .execute(&mut tx)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            tx.commit().await.map_err(|err| err.to_string())?;
+            webhooks::wake();
+
+            Ok(rows.len())
+        }
+        DBType::Postgres => {
+            let mut client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let tx = client.transaction().await.map_err(|err| err.to_string())?;
+
+            let rows: Vec<OutboxRow> = tx
+                .query(
+                    &format!(
+                        r#"SELECT id, payload FROM {} WHERE published_at IS NULL ORDER BY id LIMIT $1 FOR UPDATE SKIP LOCKED"#,
+                        table()
+                    ),
+                    &[&batch_size],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                .map_err(|err| err.to_string())?;
+
+            if rows.is_empty() {
+                return Ok(0);
+            }
+
+            for event in rows.iter().filter_map(|(id, payload)| parse(*id, payload)) {
+                webhooks::enqueue_in_pg(&tx, &event, data).await?;
+                events::publish(data, Event::EmailSuppressed(event));
+            }
+
+            let ids: Vec<i64> = rows.iter().map(|(id, _)| *id).collect();
+            tx.execute(&format!("UPDATE {} SET published_at = NOW() WHERE id = ANY($1)", table()), &[&ids])
+                .await
+                .map_err(|err| err.to_string())?;
+
+            tx.commit().await.map_err(|err| err.to_string())?;
+            webhooks::wake();
+
+            Ok(rows.len())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// published events are kept OUTBOX_RETENTION_HOURS for investigations
+async fn purge(data: &web::Data<AppState>, retention_hours: i64) -> Result<u64, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"DELETE FROM outbox WHERE published_at < NOW() - INTERVAL ? HOUR"#)
+                .bind(retention_hours)
+                .execute(pool)
+                .await
+                .map(|result| result.rows_affected())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let retention_hours = retention_hours as i32;
+
+            client
+                .execute(
+                    &format!(r#"DELETE FROM {} WHERE published_at < NOW() - make_interval(hours => $1)"#, table()),
+                    &[&retention_hours],
+                )
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// polls the outbox every OUTBOX_POLL_MS, or at once when this instance committed an event
+pub fn start(data: web::Data<AppState>) -> Option<JoinHandle<()>> {
+    let config = config();
+    println!("🚀 Outbox publisher started, polling every {:?}", config.poll);
+
+    Some(tokio::spawn(async move {
+        let mut purged_at = Instant::now();
+
+        loop {
+            match publish_batch(&data, config.batch_size).await {
+                // a full batch, more are likely pending
+                Ok(published) if published as i64 == config.batch_size => continue,
+                Ok(_) => {}
+                Err(err) => println!("🔥 Failed to publish the outbox: {}", err),
+            }
+
+            if purged_at.elapsed() >= PURGE_EVERY {
+                purged_at = Instant::now();
+
+                if let Err(err) = purge(&data, config.retention_hours).await {
+                    println!("🔥 Failed to purge the outbox: {}", err);
+                }
+            }
+
+            tokio::select! {
+                _ = notify().notified() => {}
+                _ = tokio::time::sleep(config.poll) => {}
+            }
+        }
+    }))
+}
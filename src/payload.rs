@@ -0,0 +1,380 @@
+use std::env;
+use std::io::Read;
+
+use actix_web::web::Bytes;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::domain::{Message, SnsNotification};
+
+// SNS messages are at most 256 KiB, the envelope and its escaping add to that
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+// SES messages nest a handful of levels, serde_json alone would accept 128
+const DEFAULT_MAX_JSON_DEPTH: usize = 32;
+
+// SNS_MAX_BODY_BYTES bounds the body, also once decompressed
+pub fn max_body_bytes() -> usize {
+    env::var("SNS_MAX_BODY_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+fn max_json_depth() -> usize {
+    env::var("SNS_MAX_JSON_DEPTH")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_JSON_DEPTH)
+}
+
+// nesting depth of a JSON document, counted without parsing so hostile input is refused cheaply
+fn json_depth(bytes: &[u8]) -> usize {
+    let (mut depth, mut max) = (0usize, 0usize);
+    let (mut in_string, mut escaped) = (false, false);
+
+    for &byte in bytes {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    max
+}
+
+// applied to the SNS body and to the SES message it carries before either is parsed
+pub fn check_limits(bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() > max_body_bytes() {
+        return Err(format!("payload of {} bytes exceeds the limit of {}", bytes.len(), max_body_bytes()));
+    }
+
+    let depth = json_depth(bytes);
+
+    if depth > max_json_depth() {
+        return Err(format!("payload nests {} levels deep, the limit is {}", depth, max_json_depth()));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub enum SnsPayload {
+    Envelope(Box<SnsNotification>),
+    // the SES message JSON delivered without the SNS envelope
+    Raw(String),
+}
+
+// bodies with a Content-Encoding header are already decompressed by actix, but some gateways forward
+// compressed bodies without it, so detect gzip/zlib by their magic bytes. A JSON body never starts with those.
+pub fn decode_body(bytes: Bytes) -> Bytes {
+    let mut decoded = Vec::new();
+    // one byte over the limit is enough for check_limits to refuse a decompression bomb
+    let limit = max_body_bytes() as u64 + 1;
+
+    let result = match bytes.as_ref() {
+        [0x1f, 0x8b, ..] => GzDecoder::new(bytes.as_ref()).take(limit).read_to_end(&mut decoded),
+        [0x78, 0x01 | 0x5e | 0x9c | 0xda, ..] => ZlibDecoder::new(bytes.as_ref()).take(limit).read_to_end(&mut decoded),
+        _ => return bytes,
+    };
+
+    match result {
+        Ok(_) => Bytes::from(decoded),
+        Err(err) => {
+            println!("Failed to decompress SNS notification body: {:?}", err);
+            bytes
+        }
+    }
+}
+
+// what decode_text repaired, counted by the SNS extractor
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TextRepairs {
+    pub bom_stripped: bool,
+    pub lossy: bool,
+}
+
+// some middleboxes prefix the body with a UTF-8 BOM or mangle a few bytes, serde rejects both
+pub fn decode_text(bytes: Bytes) -> (Bytes, TextRepairs) {
+    let mut repairs = TextRepairs::default();
+
+    let bytes = match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        Some(stripped) => {
+            repairs.bom_stripped = true;
+            Bytes::copy_from_slice(stripped)
+        }
+        None => bytes,
+    };
+
+    if std::str::from_utf8(&bytes).is_ok() {
+        return (bytes, repairs);
+    }
+
+    repairs.lossy = true;
+    println!("⚠️ SNS notification body is not valid UTF-8, decoding it lossily");
+
+    (Bytes::from(String::from_utf8_lossy(&bytes).into_owned()), repairs)
+}
+
+// raw deliveries are recognized by the SNS header or by an SES message at the top level of the body
+pub fn parse(bytes: &[u8], raw_delivery: bool) -> Result<SnsPayload, String> {
+    check_limits(bytes).map_err(|err| format!("invalid SNS notification: {}", err))?;
+
+    let value: Value = serde_json::from_slice(bytes).map_err(|err| {
+        log::debug!("unparsable SNS notification body: {}", String::from_utf8_lossy(bytes));
+        format!("invalid SNS notification: {}", err)
+    })?;
+
+    if raw_delivery || value.get("notificationType").is_some() || value.get("eventType").is_some() {
+        return Ok(SnsPayload::Raw(value.to_string()));
+    }
+
+    SnsNotification::deserialize(&value)
+        .map(|notification| SnsPayload::Envelope(Box::new(notification)))
+        .map_err(|err| {
+            log::debug!("SNS envelope not matching the model: {}", value);
+            format!("invalid SNS notification: {}", err)
+        })
+}
+
+// the SES message of a notification, the limits are checked before it is parsed
+pub fn parse_message(raw: &str) -> Result<Message, String> {
+    check_limits(raw.as_bytes()).map_err(|err| format!("invalid SES message: {}", err))?;
+
+    serde_json::from_str(raw).map_err(|err| format!("invalid SES message: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::domain::tests::{assert_kept, object, optional, required, ses_message, text};
+    use crate::domain::Message;
+
+    const SNS_BOUNCE: &str = include_str!("../bench/samples/sns_bounce.json");
+    const BODY: &[u8] = br#"{"Type":"Notification","Message":"{}"}"#;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zlib(bytes: &[u8], level: Compression) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decode_body_inflates_gzip() {
+        let compressed = gzip(BODY);
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+
+        assert_eq!(decode_body(Bytes::from(compressed)), Bytes::from_static(BODY));
+    }
+
+    #[test]
+    fn decode_body_inflates_every_zlib_level() {
+        // the second header byte tells the compression level: 0x01, 0x5e, 0x9c and 0xda
+        for level in [Compression::none(), Compression::fast(), Compression::default(), Compression::best()] {
+            let compressed = zlib(BODY, level);
+            assert_eq!(compressed[0], 0x78);
+
+            assert_eq!(decode_body(Bytes::from(compressed)), Bytes::from_static(BODY), "level {:?}", level);
+        }
+    }
+
+    #[test]
+    fn decode_body_keeps_plain_json() {
+        assert_eq!(decode_body(Bytes::from_static(BODY)), Bytes::from_static(BODY));
+    }
+
+    #[test]
+    fn decode_body_keeps_a_corrupt_stream_as_received() {
+        let mut corrupt = gzip(BODY);
+        let middle = corrupt.len() / 2;
+        corrupt[middle..].iter_mut().for_each(|byte| *byte ^= 0xff);
+
+        assert_eq!(decode_body(Bytes::from(corrupt.clone())), Bytes::from(corrupt));
+    }
+
+    #[test]
+    fn decode_body_stops_one_byte_over_the_limit() {
+        let bomb = gzip(&vec![b' '; max_body_bytes() * 4]);
+        assert!(bomb.len() < max_body_bytes());
+
+        let decoded = decode_body(Bytes::from(bomb));
+
+        assert_eq!(decoded.len(), max_body_bytes() + 1);
+        assert!(check_limits(&decoded).is_err());
+    }
+
+    // the body as the extractor hands it to parse
+    fn received(body: Vec<u8>) -> Result<SnsPayload, String> {
+        parse(&decode_text(decode_body(Bytes::from(body))).0, false)
+    }
+
+    fn envelope() -> BoxedStrategy<Value> {
+        let message = prop_oneof![ses_message().prop_map(|message| Value::from(message.to_string())), text()];
+
+        object(vec![
+            required("Type", prop_oneof![Just("Notification".into()), Just("SubscriptionConfirmation".into())]),
+            optional("Message", message),
+            optional("SubscribeURL", text()),
+            optional("MessageId", text()),
+            optional("TopicArn", text()),
+            optional("Subject", text()),
+            optional("Timestamp", text()),
+            optional("Token", text()),
+            optional("SignatureVersion", text()),
+            optional("Signature", text()),
+            optional("SigningCertURL", text()),
+        ])
+    }
+
+    // as sent, with a BOM, gzipped or deflated
+    fn encoded(json: String, encoding: usize) -> Vec<u8> {
+        match encoding {
+            0 => json.into_bytes(),
+            1 => [b"\xEF\xBB\xBF".as_slice(), json.as_bytes()].concat(),
+            2 => gzip(json.as_bytes()),
+            _ => zlib(json.as_bytes(), Compression::default()),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn envelopes_are_parsed_without_loss(input in envelope(), encoding in 0..4usize) {
+            let Ok(SnsPayload::Envelope(notification)) = received(encoded(input.to_string(), encoding)) else {
+                panic!("not parsed as an envelope: {}", input);
+            };
+
+            assert_kept(&input, &serde_json::to_value(&notification).unwrap(), "envelope");
+        }
+
+        #[test]
+        fn raw_messages_are_passed_on_whole(input in ses_message(), encoding in 0..4usize) {
+            let Ok(SnsPayload::Raw(raw)) = received(encoded(input.to_string(), encoding)) else {
+                panic!("not parsed as a raw message: {}", input);
+            };
+
+            prop_assert_eq!(
+                serde_json::from_str::<Message>(&raw).unwrap(),
+                serde_json::from_value::<Message>(input).unwrap()
+            );
+        }
+
+        #[test]
+        fn arbitrary_bodies_never_panic(body in prop::collection::vec(any::<u8>(), 0..512), raw_delivery in any::<bool>()) {
+            let _ = parse(&decode_text(decode_body(Bytes::from(body))).0, raw_delivery);
+        }
+    }
+
+    #[test]
+    fn sns_envelope_is_normalized() {
+        let Ok(SnsPayload::Envelope(notification)) = received(SNS_BOUNCE.into()) else {
+            panic!("the sample is not an envelope");
+        };
+
+        // the SES message inside is the one of domain's bounce snapshot
+        insta::assert_json_snapshot!("sns_bounce", notification);
+    }
+
+    #[test]
+    fn subscription_confirmation_is_normalized() {
+        let body = r#"{
+            "Type": "SubscriptionConfirmation",
+            "MessageId": "165545c9-2a5c-472c-8df2-7ff2be2b3b1b",
+            "Token": "2336412f37fb687f5d51e6e241d09c805a5a57b30d712f794cc5f6a988666d92768dd60a747ba6f3beb71854e285d6ad02428b09ceece29417f1f02d609c582afbacc99c583a916b9981dd2728f4ae6fdb82efd087cc3b7849e05798d2d2785c03b0879594eeac82c01f235d0e717736",
+            "TopicArn": "arn:aws:sns:us-east-1:123456789012:ses-bounces",
+            "Message": "You have chosen to subscribe to the topic arn:aws:sns:us-east-1:123456789012:ses-bounces.",
+            "SubscribeURL": "https://sns.us-east-1.amazonaws.com/?Action=ConfirmSubscription&TopicArn=arn:aws:sns:us-east-1:123456789012:ses-bounces&Token=2336412f37",
+            "Timestamp": "2024-01-15T10:00:00.000Z",
+            "SignatureVersion": "2",
+            "Signature": "unsigned",
+            "SigningCertURL": "https://sns.us-east-1.amazonaws.com/SimpleNotificationService-0000000000000000000000.pem"
+        }"#;
+        let Ok(SnsPayload::Envelope(notification)) = received(gzip(body.as_bytes())) else {
+            panic!("the confirmation is not an envelope");
+        };
+
+        insta::assert_json_snapshot!("subscription_confirmation", notification);
+    }
+
+
+    #[test]
+    fn decode_text_reports_its_repairs() {
+        assert_eq!(decode_text(Bytes::from_static(BODY)), (Bytes::from_static(BODY), TextRepairs::default()));
+
+        let (text, repairs) = decode_text(Bytes::from([b"\xEF\xBB\xBF".as_slice(), BODY].concat()));
+        assert_eq!(&text[..], BODY);
+        assert_eq!(repairs, TextRepairs { bom_stripped: true, lossy: false });
+
+        let (text, repairs) = decode_text(Bytes::from_static(b"{\"a\":\"\xff\"}"));
+        assert_eq!(&text[..], "{\"a\":\"\u{fffd}\"}".as_bytes());
+        assert_eq!(repairs, TextRepairs { bom_stripped: false, lossy: true });
+    }
+
+    #[test]
+    fn json_depth_counts_the_deepest_nesting() {
+        assert_eq!(json_depth(b""), 0);
+        assert_eq!(json_depth(br#""text""#), 0);
+        assert_eq!(json_depth(br#"{"a":[1,{"b":[]}],"c":{}}"#), 4);
+        // brackets inside strings are text, also after an escaped quote or backslash
+        assert_eq!(json_depth(br#"{"a":"[[[{\"[[","b":"\\","c":"{{"}"#), 1);
+        // closing brackets without an opening one make no room for more nesting
+        assert_eq!(json_depth(b"]]]]{[{[}"), 4);
+    }
+
+    #[test]
+    fn check_limits_refuses_one_level_over_the_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+        assert!(check_limits(nested(DEFAULT_MAX_JSON_DEPTH).as_bytes()).is_ok());
+        assert_eq!(
+            check_limits(nested(DEFAULT_MAX_JSON_DEPTH + 1).as_bytes()),
+            Err(format!("payload nests {} levels deep, the limit is {}", DEFAULT_MAX_JSON_DEPTH + 1, DEFAULT_MAX_JSON_DEPTH))
+        );
+    }
+
+    #[test]
+    fn check_limits_refuses_one_byte_over_the_size() {
+        let string = |len: usize| format!("\"{}\"", " ".repeat(len - 2));
+
+        assert!(check_limits(string(max_body_bytes()).as_bytes()).is_ok());
+        assert!(check_limits(string(max_body_bytes() + 1).as_bytes()).unwrap_err().contains("exceeds the limit"));
+    }
+
+    // far beyond serde_json's own recursion limit, refused before either parser recurses into it
+    #[test]
+    fn hostile_nesting_is_refused_before_parsing() {
+        let hostile = "[".repeat(100_000);
+
+        assert!(parse(hostile.as_bytes(), false).unwrap_err().contains("levels deep"));
+        assert!(parse(hostile.as_bytes(), true).unwrap_err().contains("levels deep"));
+
+        let message = format!(r#"{{"notificationType":"Bounce","bounce":{}}}"#, hostile);
+        assert!(parse_message(&message).unwrap_err().starts_with("invalid SES message: payload nests"));
+    }
+}
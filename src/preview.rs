@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::blacklist::{self, NewEntry};
+use crate::domain::SnsNotificationType::{Notification, SubscriptionConfirmation};
+use crate::domain::{Message, NotificationType};
+use crate::domains::{self, DomainSettings};
+use crate::handlers::is_admin;
+use crate::responses::ErrorResponse;
+use crate::rules::{self, BounceRule};
+use crate::services::notifications::extract_email_address;
+use crate::simulator;
+use crate::payload::{self, SnsPayload};
+use crate::strict::{self, ParseMode};
+use crate::timestamps;
+use crate::AppState;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PreviewQuery {
+    // applies the bounce rules and suppression days of the domain, the defaults without it
+    pub domain_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MatchedRule {
+    // position in the domain's bounce_rules, from 0
+    pub index: usize,
+    #[schema(value_type = Object)]
+    pub rule: BounceRule,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PreviewEntry {
+    pub email: String,
+    // what the blacklist would hold, a keyed hash with EMAIL_HASH_KEY
+    pub stored_email: String,
+    // active or pending_review
+    pub status: String,
+    pub category: String,
+    pub expires_at: Option<NaiveDateTime>,
+    pub reason_summary: Option<String>,
+    pub diagnostic_class: Option<String>,
+    pub rule: Option<MatchedRule>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SkippedRecipient {
+    pub email: String,
+    pub reason: String,
+    pub rule: Option<MatchedRule>,
+}
+
+// how the service reads a payload, nothing is written, published or counted
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct ParsePreview {
+    // envelope (the SNS JSON) or raw (the SES message without the envelope)
+    pub payload: String,
+    // Notification or SubscriptionConfirmation, for envelopes
+    pub sns_type: Option<String>,
+    pub topic_arn: Option<String>,
+    // Bounce, Complaint, Delivery, ...
+    pub notification_type: Option<String>,
+    pub message_id: Option<String>,
+    pub feedback_id: Option<String>,
+    pub bounce_type: Option<String>,
+    pub bounce_sub_type: Option<String>,
+    pub complaint_feedback_type: Option<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub suppressed: Vec<PreviewEntry>,
+    pub skipped: Vec<SkippedRecipient>,
+    // what would be logged without failing the notification, e.g. unknown fields or a skewed timestamp
+    pub warnings: Vec<String>,
+    // why the notification would fail, it is then dead-lettered; None when it would be accepted
+    pub error: Option<String>,
+}
+
+fn preview_entry(email: String, entry: NewEntry, rule: Option<MatchedRule>) -> PreviewEntry {
+    PreviewEntry {
+        email,
+        status: entry.status().as_str().into(),
+        stored_email: entry.email,
+        category: entry.category,
+        expires_at: entry.expires_at,
+        reason_summary: entry.reason_summary,
+        diagnostic_class: entry.diagnostic_class,
+        rule,
+    }
+}
+
+// the checks process_message runs on the SES message, reported instead of logged and counted
+fn check_message(raw: &str, message: &Message, preview: &mut ParsePreview) -> Result<(), String> {
+    payload::check_limits(raw.as_bytes()).map_err(|err| format!("invalid SES message: {}", err))?;
+
+    if matches!(message.notification_type, NotificationType::Bounce | NotificationType::Complaint) {
+        let unknown = strict::unknown_fields(raw, message);
+
+        if !unknown.is_empty() {
+            let problem = format!("unknown fields in SES message payload: {}", unknown.join(", "));
+            match strict::mode() {
+                ParseMode::Strict => return Err(problem),
+                ParseMode::Report => preview.warnings.push(problem),
+                ParseMode::Permissive => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_timestamp(kind: &str, id: &str, timestamp: DateTime<Utc>, preview: &mut ParsePreview) -> Result<(), String> {
+    match timestamps::problem(kind, id, timestamp) {
+        Some(problem) if timestamps::rejects() => Err(problem),
+        Some(problem) => {
+            preview.warnings.push(problem);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+// the same steps as services::notifications, up to the entries it would write
+fn preview_message(
+    domain_id: i32,
+    raw: &str,
+    settings: &DomainSettings,
+    preview: &mut ParsePreview,
+) -> Result<(), String> {
+    let message: Message = serde_json::from_str(raw).map_err(|err| format!("invalid SES message: {}", err))?;
+
+    preview.notification_type = Some(message.notification_type.as_str().into());
+    preview.message_id = message.mail.as_ref().map(|mail| mail.message_id.clone());
+
+    check_message(raw, &message, preview)?;
+
+    let reason = serde_json::to_string(&message).unwrap_or_default();
+    let mail = message.mail.as_ref();
+
+    match &message.notification_type {
+        NotificationType::Bounce => {
+            let Some(bounce) = &message.bounce else {
+                preview.warnings.push("bounce notification without bounce field, acknowledged and ignored".into());
+                return Ok(());
+            };
+
+            preview.feedback_id = Some(bounce.feedback_id.clone());
+            preview.bounce_type = Some(bounce.bounce_type.clone());
+            preview.bounce_sub_type = Some(bounce.bounce_sub_type.clone());
+            preview.timestamp = Some(bounce.timestamp);
+            check_timestamp("bounce", &bounce.feedback_id, bounce.timestamp, preview)?;
+
+            // the addresses already suppressed by the notification, see blacklist::unique
+            let mut seen = HashSet::new();
+
+            for recipient in &bounce.bounced_recipients {
+                let email = extract_email_address(&recipient.email_address);
+                let rule = rules::matching(&settings.bounce_rules, bounce, recipient, mail)
+                    .map(|(index, rule)| MatchedRule { index, rule: rule.clone() });
+
+                match blacklist::bounce_entry(domain_id, bounce, recipient, mail, &reason, settings, Utc::now().naive_utc()) {
+                    Some(entry) if !seen.insert(entry.email.to_lowercase()) => preview.skipped.push(SkippedRecipient {
+                        email,
+                        reason: "named earlier in the notification".into(),
+                        rule,
+                    }),
+                    Some(entry) => preview.suppressed.push(preview_entry(email, entry, rule)),
+                    None if simulator::excluded(&email) => preview.skipped.push(SkippedRecipient {
+                        email,
+                        reason: "SES mailbox simulator address".into(),
+                        rule,
+                    }),
+                    None => preview.skipped.push(SkippedRecipient { email, reason: "ignored by a bounce rule".into(), rule }),
+                }
+            }
+        }
+        NotificationType::Complaint => {
+            let Some(complaint) = &message.complaint else {
+                return Err("complaint notification without complaint field".into());
+            };
+
+            preview.feedback_id = Some(complaint.feedback_id.clone());
+            preview.complaint_feedback_type = complaint.complaint_feedback_type.clone();
+            preview.timestamp = Some(complaint.timestamp);
+            check_timestamp("complaint", &complaint.feedback_id, complaint.timestamp, preview)?;
+
+            // with EMAIL_HASH_KEY set, complaint entries only know the hashed address
+            for entry in blacklist::complaint_entries(domain_id, complaint, mail, &reason, settings)? {
+                preview.suppressed.push(preview_entry(entry.email.clone(), entry, None));
+            }
+
+            // the recipients complaint_entries left out, mail.destination stands in when the complaint names none
+            let recipients: Vec<String> = match complaint.complained_recipients.is_empty() {
+                true => mail.map(|mail| mail.destination.clone()).unwrap_or_default(),
+                false => complaint.complained_recipients.iter().map(|recipient| recipient.email_address.clone()).collect(),
+            };
+            for email in recipients.iter().map(|recipient| extract_email_address(recipient)) {
+                if simulator::excluded(&email) {
+                    preview.skipped.push(SkippedRecipient { email, reason: "SES mailbox simulator address".into(), rule: None });
+                }
+            }
+        }
+        NotificationType::Other(other) => return Err(format!("unknown notification type: {}", other)),
+        other => preview.warnings.push(format!("{} notifications are acknowledged and ignored", other.as_str())),
+    }
+
+    Ok(())
+}
+
+fn preview(domain_id: i32, body: &[u8], settings: &DomainSettings) -> ParsePreview {
+    let mut preview = ParsePreview::default();
+
+    let result = match payload::parse(body, false) {
+        Ok(SnsPayload::Raw(message)) => {
+            preview.payload = "raw".into();
+            preview_message(domain_id, &message, settings, &mut preview)
+        }
+        Ok(SnsPayload::Envelope(notification)) => {
+            preview.payload = "envelope".into();
+            preview.topic_arn = notification.topic_arn.clone();
+
+            match notification.type_field {
+                SubscriptionConfirmation => {
+                    preview.sns_type = Some("SubscriptionConfirmation".into());
+                    match notification.subscribe_url {
+                        Some(url) => {
+                            preview.warnings.push(format!("the subscription would be confirmed with {}", url));
+                            Ok(())
+                        }
+                        None => Err("subscription confirmation without SubscribeURL".into()),
+                    }
+                }
+                Notification => {
+                    preview.sns_type = Some("Notification".into());
+                    match notification.message {
+                        Some(message) => preview_message(domain_id, &message, settings, &mut preview),
+                        None => Err("notification without Message".into()),
+                    }
+                }
+            }
+        }
+        Err(err) => Err(err),
+    };
+
+    preview.error = result.err();
+    preview
+}
+
+// runs an SNS delivery or a raw SES message through the parsing, the checks and the domain rules without writing
+// anything, to debug a topic configuration. The signature is not verified, a payload edited by hand still parses.
+pub async fn parse_preview_handler(
+    req: HttpRequest,
+    query: web::Query<PreviewQuery>,
+    body: Bytes,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let (domain_id, settings) = match query.domain_id {
+        Some(domain_id) => (domain_id, domains::load(domain_id, &data).await),
+        None => (0, DomainSettings::default()),
+    };
+
+    HttpResponse::Ok().json(preview(domain_id, &body, &settings))
+}
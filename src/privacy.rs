@@ -0,0 +1,210 @@
+use std::env;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::blacklist;
+use crate::config::arg_value;
+use crate::redaction;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+
+const DEFAULT_BATCH_SIZE: i64 = 500;
+
+// (id, email) of a row still storing the plaintext address
+type EmailRow = (i64, String);
+
+// with EMAIL_HASH_KEY set only HMAC-SHA256(key, lowercased email) is stored and looked up, bounce reasons keep
+// the bounce type instead of the SES message, which carries the addresses, and the logged payloads, dead letters
+// and diagnostic codes keep the hash in place of each address
+fn hash_key() -> Option<String> {
+    env::var("EMAIL_HASH_KEY").ok().filter(|key| !key.is_empty())
+}
+
+pub fn hashing_enabled() -> bool {
+    hash_key().is_some()
+}
+
+// addresses are stored and looked up trimmed and lowercased, so a lookup does not depend on the collation of the
+// email column: MySQL's default ones ignore case and accents, Postgres compares bytes
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn hmac_hex(key: &str, email: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(normalize(email).as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn is_hashed(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// the value written to and looked up in the email column. A value already hashed is kept, the addresses of a
+// payload stored by stored_text are then suppressed as they were when it is replayed.
+pub fn stored_email(email: &str) -> String {
+    match hash_key() {
+        Some(_) if is_hashed(email.trim()) => email.trim().into(),
+        Some(key) => hmac_hex(&key, email),
+        None => normalize(email),
+    }
+}
+
+// a text kept beside the blacklist (a logged or dead-lettered payload, a diagnostic code) with every address
+// replaced by its stored_email when hashing, so no store keeps the plaintext addresses
+pub fn stored_text(text: &str) -> String {
+    if !hashing_enabled() {
+        return text.into();
+    }
+
+    redaction::email_regex()
+        .replace_all(text, |caps: &regex::Captures| stored_email(&caps[0]))
+        .into_owned()
+}
+
+pub fn stored_reason(reason: &str, bounce_type: &str, bounce_sub_type: &str) -> String {
+    if hashing_enabled() {
+        format!("{}/{}", bounce_type, bounce_sub_type)
+    } else {
+        reason.into()
+    }
+}
+
+async fn fetch_batch(db_type: &DBType, db_url: &str, after_id: i64, batch_size: i64) -> Result<Vec<EmailRow>, String> {
+    let table = blacklist::table_for(db_type);
+
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, EmailRow>(&format!(
+                r#"SELECT id, email FROM {table} WHERE id > ? AND email LIKE '%@%' ORDER BY id LIMIT ?"#,
+                table = table
+            ))
+                .bind(after_id)
+                .bind(batch_size)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, email FROM {table} WHERE id > $1 AND email LIKE '%@%' ORDER BY id LIMIT $2"#,
+                        table = table
+                    ),
+                    &[&after_id, &batch_size],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// hashes the address and replaces the reason by the normalized bounce type, manual rows keep their free text reason
+async fn hash_row(db_type: &DBType, db_url: &str, id: i64, hashed: &str) -> Result<(), String> {
+    let table = blacklist::table_for(db_type);
+
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(&format!(
+                r#"UPDATE {table} SET email = ?, reason = CASE WHEN bounce_type IS NULL THEN reason ELSE CONCAT(bounce_type, '/', COALESCE(bounce_sub_type, '')) END WHERE id = ?"#,
+                table = table
+            ))
+                .bind(hashed)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET email = $1, reason = CASE WHEN bounce_type IS NULL THEN reason ELSE bounce_type || '/' || COALESCE(bounce_sub_type, '') END WHERE id = $2"#,
+                        table = table
+                    ),
+                    &[&hashed, &id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn delete_row(db_type: &DBType, db_url: &str, id: i64) -> Result<(), String> {
+    let table = blacklist::table_for(db_type);
+
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(&format!(r#"DELETE FROM {table} WHERE id = ?"#, table = table))
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(&format!(r#"DELETE FROM {table} WHERE id = $1"#, table = table), &[&id])
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// `hash-emails [--batch-size N] [--after-id ID]` hashes the plaintext addresses of existing rows with EMAIL_HASH_KEY.
+// Run backfill-reasons first, the normalized columns replace the SES message kept as reason.
+pub async fn run(db_type: &DBType, db_url: &str, args: &[String]) -> Result<(), String> {
+    let key = hash_key().ok_or("EMAIL_HASH_KEY is not set")?;
+    let batch_size = arg_value(args, "--batch-size").and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+    let mut after_id = arg_value(args, "--after-id").and_then(|value| value.parse().ok()).unwrap_or(0);
+    let (mut hashed, mut merged) = (0, 0);
+
+    println!("🚀 Hashing stored email addresses after id {} in batches of {}", after_id, batch_size);
+
+    loop {
+        let rows = fetch_batch(db_type, db_url, after_id, batch_size).await?;
+
+        let Some((last_id, _)) = rows.last() else {
+            break;
+        };
+        let last_id = *last_id;
+
+        for (id, email) in &rows {
+            match hash_row(db_type, db_url, *id, &hmac_hex(&key, email)).await {
+                Ok(()) => hashed += 1,
+                // addresses differing only in case hash to the same value, the row hashed first is kept
+                Err(err) if blacklist::is_duplicate(&err) => {
+                    delete_row(db_type, db_url, *id).await?;
+                    merged += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        after_id = last_id;
+        println!("Processed up to id {}: {} hashed, {} merged", after_id, hashed, merged);
+    }
+
+    println!("✅ Hashing done: {} hashed, {} merged into an existing row", hashed, merged);
+
+    Ok(())
+}
@@ -0,0 +1,327 @@
+use actix_web::web;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+use crate::domain::Message;
+use crate::config::arg_value;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::{blacklist, domains, notification_log, schema, AppState};
+
+const BATCH_SIZE: i64 = 500;
+
+async fn execute(data: &web::Data<AppState>, sql: &str) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(sql)
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("{}: {}", sql, err)),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            client.batch_execute(sql).await.map_err(|err| format!("{}: {}", sql, err))
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn max_id(data: &web::Data<AppState>, table: &str) -> Result<i64, String> {
+    let sql = format!("SELECT COALESCE(MAX(id), 0) FROM {table}", table = table);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query_scalar::<_, i64>(&sql)
+            .fetch_one(pool)
+            .await
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            client
+                .query_one(&sql, &[])
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// the statements bringing the copy up to date with the live table once writes are blocked. `live_max` is the highest
+// live id by then: the replayed rows move above it, so the rows written while replaying keep their ids, and the live
+// rows are matched on (domain_id, email) against the replayed ones.
+fn catch_up_sql(db_type: &DBType, live: &str, fresh: &str, copied_up_to: i64, replaced: &str, live_max: i64) -> Vec<String> {
+    let columns = schema::expected_columns("blacklist");
+    let same: Vec<String> = columns
+        .iter()
+        .map(|column| match db_type {
+            DBType::Postgres => format!("f.{column} IS NOT DISTINCT FROM l.{column}", column = column),
+            _ => format!("f.{column} <=> l.{column}", column = column),
+        })
+        .collect();
+
+    let (removed, upsert) = match db_type {
+        DBType::Postgres => (
+            format!(
+                "DELETE FROM {fresh} AS f WHERE f.id <= {copied_up_to} AND NOT EXISTS (SELECT 1 FROM {live} AS l WHERE l.id = f.id AND {same})",
+                fresh = fresh,
+                live = live,
+                copied_up_to = copied_up_to,
+                same = same.join(" AND ")
+            ),
+            format!(
+                "ON CONFLICT (domain_id, email) DO UPDATE SET {}",
+                columns.iter().map(|column| format!("{column} = EXCLUDED.{column}", column = column)).collect::<Vec<_>>().join(", ")
+            ),
+        ),
+        _ => (
+            format!(
+                "DELETE f FROM {fresh} AS f LEFT JOIN {live} AS l ON l.id = f.id AND {same} WHERE f.id <= {copied_up_to} AND l.id IS NULL",
+                fresh = fresh,
+                live = live,
+                copied_up_to = copied_up_to,
+                same = same.join(" AND ")
+            ),
+            format!(
+                "ON DUPLICATE KEY UPDATE {}",
+                columns.iter().map(|column| format!("{column} = VALUES({column})", column = column)).collect::<Vec<_>>().join(", ")
+            ),
+        ),
+    };
+
+    vec![
+        // negated first, a shift in place would collide with the next replayed id
+        format!("UPDATE {fresh} SET id = -id WHERE id > {copied_up_to}", fresh = fresh, copied_up_to = copied_up_to),
+        format!("UPDATE {fresh} SET id = {live_max} - id WHERE id < 0", fresh = fresh, live_max = live_max),
+        // copied rows deleted or changed since, the changed ones come back with the live rows below
+        removed,
+        // rows written while replaying, over a replayed row of the same address
+        format!(
+            "INSERT INTO {fresh} SELECT * FROM {live} AS l WHERE NOT EXISTS (SELECT 1 FROM {fresh} AS f WHERE f.id = l.id) AND NOT (l.id <= {copied_up_to} AND {replaced}) {upsert}",
+            fresh = fresh,
+            live = live,
+            copied_up_to = copied_up_to,
+            replaced = replaced,
+            upsert = upsert
+        ),
+    ]
+}
+
+// catches up and swaps the copy in while writes to the live table wait, they then land in the new table
+async fn swap(
+    data: &web::Data<AppState>,
+    live: &str,
+    fresh: &str,
+    old: &str,
+    copied_up_to: i64,
+    replaced: &str,
+) -> Result<(), String> {
+    let max_id_sql = |table: &str| format!("SELECT COALESCE(MAX(id), 0) FROM {table}", table = table);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            // LOCK TABLES holds for the session, so everything runs on one connection
+            let mut conn = pool.acquire().await.map_err(|err| err.to_string())?;
+            let lock = format!(
+                "LOCK TABLES {live} WRITE, {live} AS l READ, {fresh} WRITE, {fresh} AS f WRITE",
+                live = live,
+                fresh = fresh
+            );
+            sqlx::query(&lock).execute(&mut *conn).await.map_err(|err| format!("{}: {}", lock, err))?;
+
+            let swapped = async {
+                let live_max: i64 =
+                    sqlx::query_scalar(&max_id_sql(live)).fetch_one(&mut *conn).await.map_err(|err| err.to_string())?;
+
+                for sql in catch_up_sql(&data.db_type, live, fresh, copied_up_to, replaced, live_max) {
+                    sqlx::query(&sql).execute(&mut *conn).await.map_err(|err| format!("{}: {}", sql, err))?;
+                }
+
+                let fresh_max: i64 =
+                    sqlx::query_scalar(&max_id_sql(fresh)).fetch_one(&mut *conn).await.map_err(|err| err.to_string())?;
+                let statements = [
+                    format!("ALTER TABLE {fresh} AUTO_INCREMENT = {next}", fresh = fresh, next = fresh_max + 1),
+                    format!("RENAME TABLE {live} TO {old}, {fresh} TO {live}", live = live, old = old, fresh = fresh),
+                ];
+                for sql in statements {
+                    sqlx::query(&sql).execute(&mut *conn).await.map_err(|err| format!("{}: {}", sql, err))?;
+                }
+
+                Ok::<_, String>(())
+            }
+                .await;
+
+            // released on failure too, before the connection goes back to the pool
+            let unlocked = sqlx::query("UNLOCK TABLES").execute(&mut *conn).await.map_err(|err| err.to_string());
+            if unlocked.is_err() {
+                conn.detach();
+            }
+
+            swapped.and(unlocked.map(|_| ()))
+        }
+        DBType::Postgres => {
+            let mut client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+            let tx = client.transaction().await.map_err(|err| err.to_string())?;
+
+            // reads go on, writes wait until the commit
+            tx.batch_execute(&format!("LOCK TABLE {live} IN EXCLUSIVE MODE", live = live))
+                .await
+                .map_err(|err| err.to_string())?;
+            let live_max: i64 =
+                tx.query_one(&max_id_sql(live), &[]).await.map(|row| row.get(0)).map_err(|err| err.to_string())?;
+
+            for sql in catch_up_sql(&data.db_type, live, fresh, copied_up_to, replaced, live_max) {
+                tx.batch_execute(&sql).await.map_err(|err| format!("{}: {}", sql, err))?;
+            }
+
+            let live_sequence: Option<String> = tx
+                .query_one("SELECT pg_get_serial_sequence($1, 'id')", &[&live])
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())?;
+
+            // the sequences follow their tables, so the next rebuild can create <table>_rebuild_id_seq again
+            let mut statements = vec![format!(
+                "SELECT setval('{fresh}_id_seq', COALESCE(MAX(id), 0) + 1, false) FROM {fresh}",
+                fresh = fresh
+            )];
+            if let Some(live_sequence) = live_sequence {
+                statements.push(format!("ALTER SEQUENCE {} RENAME TO {}_id_seq", live_sequence, old));
+                statements.push(format!("ALTER SEQUENCE {fresh}_id_seq RENAME TO {live}_id_seq", fresh = fresh, live = live));
+            }
+            statements.push(format!("ALTER TABLE {live} RENAME TO {old}", live = live, old = old));
+            statements.push(format!("ALTER TABLE {fresh} RENAME TO {live}", fresh = fresh, live = live));
+
+            for sql in statements {
+                tx.batch_execute(&sql).await.map_err(|err| format!("{}: {}", sql, err))?;
+            }
+
+            tx.commit().await.map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+fn parse_date(args: &[String], name: &str) -> Result<NaiveDateTime, String> {
+    arg_value(args, name)
+        .and_then(|value| NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok())
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .ok_or_else(|| format!("{} YYYY-MM-DD is required", name))
+}
+
+// `rebuild-blacklist --domain ID --from YYYY-MM-DD --to YYYY-MM-DD` replays the logged bounces of the window
+// through the current rules into a copy of the blacklist, then swaps the copy in. Writes wait while the copy catches
+// up with the live table and replaces it. The replaced table is kept as <table>_old until the next rebuild.
+pub async fn run(data: &web::Data<AppState>, args: &[String]) -> Result<(), String> {
+    let domain_id: i32 = arg_value(args, "--domain")
+        .and_then(|value| value.parse().ok())
+        .ok_or("--domain ID is required")?;
+    let from = parse_date(args, "--from")?;
+    // the end date is inclusive
+    let to = parse_date(args, "--to")? + Duration::days(1);
+
+    let live = blacklist::table();
+    let fresh = format!("{}_rebuild", live);
+    let old = format!("{}_old", live);
+
+    println!("🚀 Rebuilding {} for domain {} from {} to {}", live, domain_id, from, to);
+
+    execute(data, &format!("DROP TABLE IF EXISTS {fresh}", fresh = fresh)).await?;
+    match &data.db_type {
+        DBType::MySQL(_) => execute(data, &format!("CREATE TABLE {fresh} LIKE {live}", fresh = fresh, live = live)).await?,
+        DBType::Postgres => {
+            execute(data, &format!("CREATE TABLE {fresh} (LIKE {live} INCLUDING ALL)", fresh = fresh, live = live)).await?
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    }
+
+    // everything except the bounce suppressions of the window, which are replayed below
+    let copied_up_to = max_id(data, &live).await?;
+    let window = match &data.db_type {
+        DBType::MySQL(_) => format!("'{}' AND created_at < '{}'", from, to),
+        DBType::Postgres => format!("'{}'::timestamp AND created_at < '{}'::timestamp", from, to),
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+    let replaced = format!(
+        "domain_id = {domain_id} AND bounce_type IS NOT NULL AND created_at >= {window}",
+        domain_id = domain_id,
+        window = window
+    );
+
+    // the replayed rows are numbered after the copied ones, the PG copy gets its own sequence as LIKE would
+    // otherwise share the live one, and the next rebuild could not drop the replaced table
+    match &data.db_type {
+        DBType::MySQL(_) => {
+            execute(data, &format!("ALTER TABLE {fresh} AUTO_INCREMENT = {next}", fresh = fresh, next = copied_up_to + 1))
+                .await?
+        }
+        DBType::Postgres => {
+            execute(
+                data,
+                &format!(
+                    "CREATE SEQUENCE {fresh}_id_seq START WITH {next} OWNED BY {fresh}.id; ALTER TABLE {fresh} ALTER COLUMN id SET DEFAULT nextval('{fresh}_id_seq')",
+                    fresh = fresh,
+                    next = copied_up_to + 1
+                ),
+            )
+                .await?
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    }
+
+    execute(
+        data,
+        &format!(
+            "INSERT INTO {fresh} SELECT * FROM {live} WHERE id <= {copied_up_to} AND NOT ({replaced})",
+            fresh = fresh,
+            live = live,
+            copied_up_to = copied_up_to,
+            replaced = replaced,
+        ),
+    )
+        .await?;
+
+    let settings = domains::load(domain_id, data).await;
+    let (mut replayed, mut inserted, mut duplicates) = (0, 0, 0);
+    let mut after_id = 0;
+
+    loop {
+        let notifications =
+            notification_log::fetch_window(domain_id, "Bounce", from, to, after_id, BATCH_SIZE, data).await?;
+
+        let Some((last_id, _, _)) = notifications.last() else {
+            break;
+        };
+        after_id = *last_id;
+
+        for (id, payload, received_at) in &notifications {
+            let Ok(message) = serde_json::from_str::<Message>(payload) else {
+                println!("Skipping notification {}: payload does not parse", id);
+                continue;
+            };
+            let Some(bounce) = &message.bounce else {
+                continue;
+            };
+
+            replayed += 1;
+
+            for mut entry in blacklist::bounce_entries(domain_id, bounce, message.mail.as_ref(), payload, &settings, *received_at) {
+                entry.created_at = Some(*received_at);
+
+                match blacklist::insert_into(&fresh, &entry, data).await {
+                    Ok(_) => inserted += 1,
+                    Err(err) if blacklist::is_duplicate(&err) => duplicates += 1,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        println!("Replayed up to notification {}: {} entries, {} duplicates", after_id, inserted, duplicates);
+    }
+
+    execute(data, &format!("DROP TABLE IF EXISTS {old}", old = old)).await?;
+    swap(data, &live, &fresh, &old, copied_up_to, &replaced).await?;
+
+    println!(
+        "✅ Rebuild done: {} notifications replayed, {} entries, {} duplicates, previous table kept as {}",
+        replayed, inserted, duplicates, old
+    );
+
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::sync::OnceLock;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use openssl::pkey::{PKey, Private, Public};
+use openssl::rand::rand_bytes;
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::config::arg_value;
+use crate::privacy;
+
+const DEFAULT_MAX_TEXT: usize = 128;
+// a payload that is not JSON is kept as one text, longer than the strings inside a JSON payload
+const RAW_TEXT_LIMIT: usize = 1024;
+
+const ALGORITHM: &str = "RSA-OAEP+A256GCM";
+
+// with DEAD_LETTER_PUBLIC_KEY set (a PEM RSA public key, or the path of one) a dead letter keeps a redacted copy of
+// its payload to debug from, with the addresses masked to a hash and texts cut at REDACT_MAX_TEXT characters, and
+// the original sealed with the key. Only the holders of the private key can open it, see `decrypt-payload`.
+fn public_key() -> Result<Option<Rsa<Public>>, String> {
+    let Some(value) = env::var("DEAD_LETTER_PUBLIC_KEY").ok().filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+
+    let pem = read_pem(&value)?;
+    let key = PKey::public_key_from_pem(pem.as_bytes()).map_err(|err| format!("invalid DEAD_LETTER_PUBLIC_KEY: {}", err))?;
+
+    key.rsa().map(Some).map_err(|err| format!("DEAD_LETTER_PUBLIC_KEY is not an RSA key: {}", err))
+}
+
+fn read_pem(value: &str) -> Result<String, String> {
+    if value.trim_start().starts_with("-----BEGIN") {
+        Ok(value.into())
+    } else {
+        fs::read_to_string(value).map_err(|err| format!("failed to read {}: {}", value, err))
+    }
+}
+
+fn max_text() -> usize {
+    env::var("REDACT_MAX_TEXT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_TEXT)
+}
+
+pub fn email_regex() -> &'static Regex {
+    static EMAIL: OnceLock<Regex> = OnceLock::new();
+    EMAIL.get_or_init(|| Regex::new(r"[A-Za-z0-9._%+\-]+@[A-Za-z0-9\-]+(\.[A-Za-z0-9\-]+)+").expect("valid regex"))
+}
+
+// the same address always masks to the same hash, keyed with EMAIL_HASH_KEY when set
+fn mask(email: &str) -> String {
+    let email = email.to_lowercase();
+
+    let digest: Vec<u8> = match env::var("EMAIL_HASH_KEY").ok().filter(|key| !key.is_empty()) {
+        Some(key) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+            mac.update(email.as_bytes());
+            mac.finalize().into_bytes().to_vec()
+        }
+        None => Sha256::digest(email.as_bytes()).to_vec(),
+    };
+
+    let hex: String = digest.iter().take(6).map(|byte| format!("{:02x}", byte)).collect();
+    format!("<email:{}>", hex)
+}
+
+fn redact_text(text: &str, limit: usize) -> String {
+    let masked = email_regex().replace_all(text, |caps: &regex::Captures| mask(&caps[0]));
+    let length = masked.chars().count();
+
+    if length <= limit {
+        return masked.into_owned();
+    }
+
+    let kept: String = masked.chars().take(limit).collect();
+    format!("{}…[{} more characters]", kept, length - limit)
+}
+
+fn redact_value(value: &mut Value, limit: usize) {
+    match value {
+        Value::String(text) => {
+            // the SES message travels as a JSON string inside the SNS envelope
+            *text = match serde_json::from_str::<Value>(text) {
+                Ok(mut inner @ (Value::Object(_) | Value::Array(_))) => {
+                    redact_value(&mut inner, limit);
+                    inner.to_string()
+                }
+                _ => redact_text(text, limit),
+            };
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| redact_value(value, limit)),
+        Value::Object(fields) => fields.values_mut().for_each(|value| redact_value(value, limit)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+// the payload with every address masked and every text cut, keeping the JSON structure to debug the parsing from
+pub fn redact(payload: &str) -> String {
+    match serde_json::from_str::<Value>(payload) {
+        Ok(mut value) => {
+            redact_value(&mut value, max_text());
+            value.to_string()
+        }
+        Err(_) => redact_text(payload, RAW_TEXT_LIMIT),
+    }
+}
+
+// a random AES-256-GCM key encrypts the payload and is itself encrypted with the RSA key
+#[derive(Debug, Serialize, Deserialize)]
+struct Sealed {
+    alg: String,
+    key: String,
+    iv: String,
+    tag: String,
+    ciphertext: String,
+}
+
+fn seal(public_key: &Rsa<Public>, plaintext: &str) -> Result<String, String> {
+    let mut key = [0u8; 32];
+    let mut iv = [0u8; 12];
+    rand_bytes(&mut key).map_err(|err| err.to_string())?;
+    rand_bytes(&mut iv).map_err(|err| err.to_string())?;
+
+    let mut tag = [0u8; 16];
+    let ciphertext = encrypt_aead(Cipher::aes_256_gcm(), &key, Some(&iv), &[], plaintext.as_bytes(), &mut tag)
+        .map_err(|err| err.to_string())?;
+
+    let mut wrapped = vec![0u8; public_key.size() as usize];
+    let length = public_key
+        .public_encrypt(&key, &mut wrapped, Padding::PKCS1_OAEP)
+        .map_err(|err| err.to_string())?;
+    wrapped.truncate(length);
+
+    serde_json::to_string(&Sealed {
+        alg: ALGORITHM.into(),
+        key: BASE64.encode(wrapped),
+        iv: BASE64.encode(iv),
+        tag: BASE64.encode(tag),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+        .map_err(|err| err.to_string())
+}
+
+fn open(private_key: &Rsa<Private>, sealed: &str) -> Result<String, String> {
+    let sealed: Sealed = serde_json::from_str(sealed.trim()).map_err(|err| format!("not a sealed payload: {}", err))?;
+
+    if sealed.alg != ALGORITHM {
+        return Err(format!("unsupported algorithm {}", sealed.alg));
+    }
+
+    let decode = |field: &str, value: &str| BASE64.decode(value).map_err(|err| format!("invalid {}: {}", field, err));
+    let (wrapped, iv, tag, ciphertext) = (
+        decode("key", &sealed.key)?,
+        decode("iv", &sealed.iv)?,
+        decode("tag", &sealed.tag)?,
+        decode("ciphertext", &sealed.ciphertext)?,
+    );
+
+    let mut key = vec![0u8; private_key.size() as usize];
+    let length = private_key
+        .private_decrypt(&wrapped, &mut key, Padding::PKCS1_OAEP)
+        .map_err(|_| "the private key does not open this payload".to_string())?;
+    key.truncate(length);
+
+    let plaintext = decrypt_aead(Cipher::aes_256_gcm(), &key, Some(&iv), &[], &ciphertext, &tag)
+        .map_err(|_| "the payload was altered".to_string())?;
+
+    String::from_utf8(plaintext).map_err(|err| err.to_string())
+}
+
+// (payload, encrypted_payload) to store for a dead letter: the redacted copy and the sealed original with
+// DEAD_LETTER_PUBLIC_KEY, the payload as received without it (with the addresses hashed in EMAIL_HASH_KEY mode)
+pub fn dead_letter_payload(payload: &str) -> (String, Option<String>) {
+    let sealed = match public_key() {
+        Ok(Some(key)) => seal(&key, payload),
+        Ok(None) => return (privacy::stored_text(payload), None),
+        Err(err) => Err(err),
+    };
+
+    match sealed {
+        Ok(sealed) => (redact(payload), Some(sealed)),
+        // the original is dropped rather than stored in clear against the configuration, the error left in its
+        // place still marks the payload as redacted so a replay asks for the original
+        Err(err) => {
+            println!("🔥 Failed to seal a dead letter payload, keeping the redacted copy only: {}", err);
+            (redact(payload), Some(serde_json::json!({ "error": format!("sealing failed: {}", err) }).to_string()))
+        }
+    }
+}
+
+// `decrypt-payload --private-key key.pem`, reads an encrypted_payload on stdin and prints the original
+pub fn run(args: &[String]) -> Result<(), String> {
+    let path = arg_value(args, "--private-key").ok_or("--private-key PATH is required")?;
+    let pem = fs::read(&path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let key = PKey::private_key_from_pem(&pem)
+        .and_then(|key| key.rsa())
+        .map_err(|err| format!("invalid RSA private key {}: {}", path, err))?;
+
+    let mut sealed = String::new();
+    std::io::stdin().read_to_string(&mut sealed).map_err(|err| err.to_string())?;
+
+    println!("{}", open(&key, &sealed)?);
+    Ok(())
+}
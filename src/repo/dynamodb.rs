@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::time::Duration;
+
+use aws_sdk_dynamodb::types::{AttributeValue, KeysAndAttributes};
+use aws_sdk_dynamodb::Client;
+use chrono::{NaiveDateTime, Utc};
+use rand::Rng;
+
+use crate::blacklist::NewEntry;
+use crate::outbound;
+use crate::repo::EntryStatus;
+
+// BatchGetItem takes at most 100 keys per request
+const BATCH_GET_LIMIT: usize = 100;
+// requests of one chunk before its unprocessed keys fail the lookup
+const UNPROCESSED_MAX_ATTEMPTS: u32 = 8;
+const UNPROCESSED_BASE_DELAY: Duration = Duration::from_millis(50);
+const UNPROCESSED_MAX_DELAY: Duration = Duration::from_secs(2);
+
+// answered by the features built on SQL (domains, stats, API keys, the audit, ...) with DB_TYPE=DYNAMODB
+pub const UNSUPPORTED: &str = "not supported with DB_TYPE=DYNAMODB";
+
+// the suppression store of DB_TYPE=DYNAMODB, for deployments without a relational database: one item per entry in
+// DYNAMODB_TABLE (blacklist), partition key domain_id (N) and sort key email (S), as created by
+// `aws dynamodb create-table --cli-input-json file://migrations/dynamodb/blacklist.json`. expires_at is in epoch
+// seconds, so the table's TTL can be pointed at it to delete the expired entries. DYNAMODB_ENDPOINT_URL targets
+// e.g. DynamoDB Local.
+#[derive(Debug, Clone)]
+pub struct DynamoStore {
+    client: Client,
+    table: String,
+}
+
+impl DynamoStore {
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+}
+
+pub async fn connect() -> DynamoStore {
+    let config = outbound::aws_config().await;
+    let mut builder = aws_sdk_dynamodb::config::Builder::from(&config);
+
+    if let Some(endpoint) = env::var("DYNAMODB_ENDPOINT_URL").ok().filter(|url| !url.is_empty()) {
+        builder = builder.endpoint_url(endpoint);
+    }
+
+    let table = env::var("DYNAMODB_TABLE").unwrap_or_else(|_| "blacklist".into());
+    println!("🚀 Using the DynamoDB table {}", table);
+
+    DynamoStore { client: Client::from_conf(builder.build()), table }
+}
+
+// the readiness check: the table exists and the credentials can describe it
+pub async fn ping(store: &DynamoStore) -> Result<(), String> {
+    store
+        .client
+        .describe_table()
+        .table_name(&store.table)
+        .send()
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("DynamoDB describe table failed: {}", err))
+}
+
+fn key(domain_id: i32, email: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("domain_id".to_string(), AttributeValue::N(domain_id.to_string())),
+        ("email".to_string(), AttributeValue::S(email.to_string())),
+    ])
+}
+
+fn string(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name).and_then(|value| value.as_s().ok()).cloned()
+}
+
+fn epoch(at: NaiveDateTime) -> AttributeValue {
+    AttributeValue::N(at.and_utc().timestamp().to_string())
+}
+
+// the status as stored and the effective one: an active entry past expires_at reads as expired, as in SQL
+fn statuses(item: &HashMap<String, AttributeValue>) -> (String, EntryStatus) {
+    let stored = string(item, "status").unwrap_or_else(|| EntryStatus::Active.as_str().into());
+    let expired = item
+        .get("expires_at")
+        .and_then(|value| value.as_n().ok())
+        .and_then(|value| value.parse::<i64>().ok())
+        .is_some_and(|expires_at| expires_at <= Utc::now().timestamp());
+
+    let status = match EntryStatus::parse(&stored).unwrap_or(EntryStatus::Active) {
+        EntryStatus::Active if expired => EntryStatus::Expired,
+        status => status,
+    };
+
+    (stored, status)
+}
+
+// the entry, refused when the address already has one in the domain: the error reads as a duplicate key. With
+// `reactivate` an entry that no longer suppresses is replaced, as blacklist::insert_or_reactivate describes.
+pub async fn put_entry(store: &DynamoStore, entry: &NewEntry, reactivate: bool) -> Result<(), String> {
+    let now = Utc::now().naive_utc();
+    let mut item = key(entry.domain_id, &entry.email);
+
+    item.insert("status".into(), AttributeValue::S(if entry.pending_review { "pending_review" } else { "active" }.into()));
+    item.insert("reason".into(), AttributeValue::S(entry.reason.clone()));
+    item.insert("category".into(), AttributeValue::S(entry.category.clone()));
+    item.insert("created_at".into(), AttributeValue::S(entry.created_at.unwrap_or(now).to_string()));
+
+    if let Some(expires_at) = entry.expires_at {
+        item.insert("expires_at".into(), epoch(expires_at));
+    }
+
+    let optional = [
+        ("bounce_type", &entry.bounce_type),
+        ("bounce_sub_type", &entry.bounce_sub_type),
+        ("diagnostic_code", &entry.diagnostic_code),
+        ("diagnostic_class", &entry.diagnostic_class),
+        ("reason_summary", &entry.reason_summary),
+        ("subject", &entry.subject),
+        ("reporting_mta", &entry.reporting_mta),
+        ("remote_mta_ip", &entry.remote_mta_ip),
+        ("source_arn", &entry.source_arn),
+        ("sending_account_id", &entry.sending_account_id),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            item.insert(name.into(), AttributeValue::S(value.clone()));
+        }
+    }
+    if let Some(event_at) = entry.event_at {
+        item.insert("event_at".into(), AttributeValue::S(event_at.to_string()));
+    }
+
+    let request = store
+        .client
+        .put_item()
+        .table_name(&store.table)
+        .set_item(Some(item))
+        .condition_expression("attribute_not_exists(email)");
+
+    let request = match reactivate {
+        false => request,
+        true => {
+            let mut condition = "attribute_not_exists(email) OR #status IN (:expired, :removed) OR (#status = :active AND expires_at <= :now)".to_string();
+            if !entry.pending_review {
+                condition.push_str(" OR #status = :pending_review");
+            }
+
+            let mut request = request
+                .condition_expression(condition)
+                .expression_attribute_names("#status", "status")
+                .expression_attribute_values(":expired", AttributeValue::S(EntryStatus::Expired.as_str().into()))
+                .expression_attribute_values(":removed", AttributeValue::S(EntryStatus::Removed.as_str().into()))
+                .expression_attribute_values(":active", AttributeValue::S(EntryStatus::Active.as_str().into()))
+                .expression_attribute_values(":now", epoch(now));
+            if !entry.pending_review {
+                request = request
+                    .expression_attribute_values(":pending_review", AttributeValue::S(EntryStatus::PendingReview.as_str().into()));
+            }
+            request
+        }
+    };
+
+    let result = request.send().await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) if err.as_service_error().is_some_and(|err| err.is_conditional_check_failed_exception()) => {
+            Err(format!("duplicate key: {} in domain {}", entry.email, entry.domain_id))
+        }
+        Err(err) => Err(format!("DynamoDB put failed: {}", err)),
+    }
+}
+
+async fn get(store: &DynamoStore, domain_id: i32, email: &str, consistent: bool) -> Result<Option<HashMap<String, AttributeValue>>, String> {
+    store
+        .client
+        .get_item()
+        .table_name(&store.table)
+        .set_key(Some(key(domain_id, email)))
+        .consistent_read(consistent)
+        .send()
+        .await
+        .map(|output| output.item)
+        .map_err(|err| format!("DynamoDB get failed: {}", err))
+}
+
+// (stored status, effective status) of the entry, read consistently before a status change
+pub async fn get_status(store: &DynamoStore, domain_id: i32, email: &str, consistent: bool) -> Result<Option<(String, EntryStatus)>, String> {
+    Ok(get(store, domain_id, email, consistent).await?.as_ref().map(statuses))
+}
+
+// (reason_summary, subject) of the entry
+pub async fn get_details(store: &DynamoStore, domain_id: i32, email: &str) -> Result<Option<(Option<String>, Option<String>)>, String> {
+    Ok(get(store, domain_id, email, false)
+        .await?
+        .map(|item| (string(&item, "reason_summary"), string(&item, "subject"))))
+}
+
+// random delay in [0, min(UNPROCESSED_MAX_DELAY, UNPROCESSED_BASE_DELAY * 2^attempt)) before asking for the
+// unprocessed keys again, as the AWS guidance for BatchGetItem asks
+fn unprocessed_backoff(attempt: u32) -> Duration {
+    let ceiling = UNPROCESSED_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(UNPROCESSED_MAX_DELAY);
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64))
+}
+
+// the addresses among `emails` with an active suppression, BATCH_GET_LIMIT keys per request
+pub async fn active_among(store: &DynamoStore, domain_id: i32, emails: &[String]) -> Result<HashSet<String>, String> {
+    let mut active = HashSet::new();
+
+    // BatchGetItem refuses a request naming the same key twice
+    let mut seen = HashSet::new();
+    let emails: Vec<&String> = emails.iter().filter(|email| seen.insert(email.as_str())).collect();
+
+    for chunk in emails.chunks(BATCH_GET_LIMIT) {
+        let mut keys: Vec<_> = chunk.iter().map(|email| key(domain_id, email)).collect();
+        let mut attempt = 0;
+
+        // throttled reads come back as unprocessed keys, asked again with backoff until none are left
+        while !keys.is_empty() {
+            if attempt > 0 {
+                if attempt >= UNPROCESSED_MAX_ATTEMPTS {
+                    return Err(format!("DynamoDB batch get left {} keys unprocessed after {} attempts", keys.len(), attempt));
+                }
+                tokio::time::sleep(unprocessed_backoff(attempt - 1)).await;
+            }
+            attempt += 1;
+
+            let request = KeysAndAttributes::builder()
+                .set_keys(Some(keys))
+                .projection_expression("email, #status, expires_at")
+                .expression_attribute_names("#status", "status")
+                .build()
+                .map_err(|err| err.to_string())?;
+
+            let output = store
+                .client
+                .batch_get_item()
+                .request_items(&store.table, request)
+                .send()
+                .await
+                .map_err(|err| format!("DynamoDB batch get failed: {}", err))?;
+
+            for item in output.responses.unwrap_or_default().remove(&store.table).unwrap_or_default() {
+                if statuses(&item).1.suppresses() {
+                    active.extend(string(&item, "email"));
+                }
+            }
+
+            keys = output
+                .unprocessed_keys
+                .unwrap_or_default()
+                .remove(&store.table)
+                .map(|unprocessed| unprocessed.keys)
+                .unwrap_or_default();
+        }
+    }
+
+    Ok(active)
+}
+
+// moves the entry from the stored status `from` to `to`, conditional on it, returns how many items changed (0 or 1)
+pub async fn update_status(store: &DynamoStore, domain_id: i32, email: &str, from: &str, to: EntryStatus) -> Result<u64, String> {
+    // reactivated entries suppress until they are moved out of active again
+    let expression = match to {
+        EntryStatus::Active => "SET #status = :to, status_changed_at = :now REMOVE expires_at",
+        _ => "SET #status = :to, status_changed_at = :now",
+    };
+
+    let result = store
+        .client
+        .update_item()
+        .table_name(&store.table)
+        .set_key(Some(key(domain_id, email)))
+        .update_expression(expression)
+        .condition_expression("#status = :from")
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(":to", AttributeValue::S(to.as_str().into()))
+        .expression_attribute_values(":from", AttributeValue::S(from.into()))
+        .expression_attribute_values(":now", AttributeValue::S(Utc::now().naive_utc().to_string()))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(1),
+        Err(err) if err.as_service_error().is_some_and(|err| err.is_conditional_check_failed_exception()) => Ok(0),
+        Err(err) => Err(format!("DynamoDB update failed: {}", err)),
+    }
+}
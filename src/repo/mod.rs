@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::env;
+use std::sync::OnceLock;
+
+use sqlx::mysql::MySqlPool;
+
+use crate::faults;
+use crate::privacy;
+use crate::AppState;
+
+pub mod dynamodb;
+pub mod mysql;
+pub mod pool;
+pub mod postgres;
+pub mod status;
+
+pub use mysql::{build_mysql_pool, build_mysql_read_pool, mysql_connect_options, mysql_pool_options, read_mysql};
+pub use postgres::{build_pg_pool, build_pg_read_client, prepared_client, prepared_read_client};
+pub use status::{effective_status_sql, EntryStatus};
+
+#[derive(Debug, Clone)]
+pub enum DBType {
+    Postgres,
+    MySQL(MySqlPool),
+    // the suppressions only, see dynamodb.rs
+    DynamoDB(dynamodb::DynamoStore),
+}
+
+// whether the address has an active suppression in the domain. Every lookup binds the domain it is asked for,
+// together with the domain scope of API keys this keeps tenants from reading each other's entries.
+pub async fn lookup(domain_id: i32, email: &str, data: &AppState) -> Result<bool, String> {
+    lookup_status(domain_id, email, data)
+        .await
+        .map(|status| status.is_some_and(|status| status.suppresses()))
+}
+
+// the effective status of the address' entry in the domain, None without an entry
+pub async fn lookup_status(domain_id: i32, email: &str, data: &AppState) -> Result<Option<EntryStatus>, String> {
+    let email = privacy::stored_email(email);
+
+    if let Some(status) = data.cache.get(domain_id, &email) {
+        return Ok(status);
+    }
+
+    faults::db_latency().await;
+    let result = query_status(domain_id, &email, data).await;
+
+    if let Ok(status) = result {
+        data.cache.put(domain_id, &email, status);
+    }
+
+    result
+}
+
+// the lookup query is built once, its text is the key of the prepared statement on both backends
+fn status_sql(postgres: bool) -> &'static str {
+    static MYSQL: OnceLock<String> = OnceLock::new();
+    static POSTGRES: OnceLock<String> = OnceLock::new();
+
+    if postgres {
+        POSTGRES.get_or_init(|| {
+            format!(
+                r#"SELECT {effective} FROM {table} WHERE domain_id = $1 AND email = $2"#,
+                effective = effective_status_sql(""),
+                table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into())
+            )
+        })
+    } else {
+        MYSQL.get_or_init(|| {
+            format!(
+                r#"SELECT {effective} FROM blacklist WHERE domain_id = ? AND email = ?"#,
+                effective = effective_status_sql("")
+            )
+        })
+    }
+}
+
+async fn query_status(domain_id: i32, email: &str, data: &AppState) -> Result<Option<EntryStatus>, String> {
+    let query_result: Result<Option<String>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| {
+                let email = email.to_string();
+                async move {
+                    sqlx::query_scalar::<_, String>(status_sql(false))
+                        .bind(domain_id)
+                        .bind(email)
+                        .fetch_optional(&pool)
+                        .await
+                }
+            })
+                .await
+                .map_err(|err| format!("🔥 Failed to query the database: {:?}", err))
+        }
+        DBType::Postgres => {
+            let Ok(client) = prepared_read_client(data).await else {
+                return Err("Failed to connect to the database".into());
+            };
+
+            client
+                .query_opt(status_sql(true), &[&domain_id, &email])
+                .await
+                .map(|row| row.map(|row| row.get(0)))
+                .map_err(|err| format!("🔥 Failed to query the database: {:?}", err))
+        }
+        DBType::DynamoDB(store) => dynamodb::get_status(store, domain_id, email, false)
+            .await
+            .map(|found| found.map(|(_, status)| status.as_str().to_string())),
+    };
+
+    query_result.map(|status| status.as_deref().and_then(EntryStatus::parse))
+}
+
+// (reason_summary, subject) of an entry
+type DetailsRow = (Option<String>, Option<String>);
+
+// the reason_summary and subject of the address' entry in the domain, for detail views next to the cached status
+pub async fn entry_details(domain_id: i32, email: &str, data: &AppState) -> Result<DetailsRow, String> {
+    let email = privacy::stored_email(email);
+
+    let query_result: Result<Option<DetailsRow>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| {
+                let email = email.clone();
+                async move {
+                    sqlx::query_as::<_, DetailsRow>(r#"SELECT reason_summary, subject FROM blacklist WHERE domain_id = ? AND email = ?"#)
+                        .bind(domain_id)
+                        .bind(email)
+                        .fetch_optional(&pool)
+                        .await
+                }
+            })
+                .await
+                .map_err(|err| format!("🔥 Failed to query the database: {:?}", err))
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            client
+                .query_opt(
+                    &format!(r#"SELECT reason_summary, subject FROM {table} WHERE domain_id = $1 AND email = $2"#, table = table),
+                    &[&domain_id, &email],
+                )
+                .await
+                .map(|row| row.map(|row| (row.get(0), row.get(1))))
+                .map_err(|err| format!("🔥 Failed to query the database: {:?}", err))
+        }
+        DBType::DynamoDB(store) => dynamodb::get_details(store, domain_id, &email).await,
+    };
+
+    query_result.map(Option::unwrap_or_default)
+}
+
+// the addresses among `emails`, given as stored, with an active suppression in the domain, in a single query
+pub async fn lookup_many(domain_id: i32, emails: &[String], data: &AppState) -> Result<HashSet<String>, String> {
+    if emails.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let placeholders = vec!["?"; emails.len()].join(",");
+            let sql = format!(
+                r#"SELECT email FROM blacklist WHERE domain_id = ? AND email IN ({placeholders}) AND status = 'active' AND (expires_at IS NULL OR expires_at > NOW())"#,
+                placeholders = placeholders
+            );
+
+            read_mysql(data, pool, |pool| {
+                let sql = sql.clone();
+                async move {
+                    // one statement per batch size would evict the hot lookup and insert from the statement cache
+                    let mut query = sqlx::query_scalar::<_, String>(&sql).persistent(false).bind(domain_id);
+                    for email in emails {
+                        query = query.bind(email);
+                    }
+                    query.fetch_all(&pool).await
+                }
+            })
+                .await
+                .map(|rows| rows.into_iter().collect())
+                .map_err(|err| format!("🔥 Failed to query the database: {:?}", err))
+        }
+        DBType::Postgres => {
+            let Ok(client) = prepared_read_client(data).await else {
+                return Err("Failed to connect to the database".into());
+            };
+
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT email FROM {table} WHERE domain_id = $1 AND email = ANY($2) AND status = 'active' AND (expires_at IS NULL OR expires_at > NOW())"#,
+                        table = table
+                    ),
+                    &[&domain_id, &emails],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| row.get(0)).collect())
+                .map_err(|err| format!("🔥 Failed to query the database: {:?}", err))
+        }
+        DBType::DynamoDB(store) => dynamodb::active_among(store, domain_id, emails).await,
+    }
+}
@@ -0,0 +1,118 @@
+use std::env;
+use std::future::Future;
+use std::str::FromStr;
+
+use sqlx::mysql::{MySqlConnectOptions, MySqlPool, MySqlPoolOptions};
+use sqlx::Executor;
+
+use crate::config::init_statements;
+use crate::deadline;
+use crate::repo::pool;
+use crate::AppState;
+
+pub fn mysql_pool_options() -> MySqlPoolOptions {
+    let init_sql = init_statements("MYSQL_INIT_SQL");
+    let settings = pool::settings();
+
+    MySqlPoolOptions::new()
+        .min_connections(settings.min_connections)
+        .max_connections(settings.max_connections)
+        .acquire_timeout(settings.acquire_timeout)
+        .idle_timeout(settings.idle_timeout)
+        .max_lifetime(settings.max_lifetime)
+        .after_connect(move |conn, _meta| {
+            let init_sql = init_sql.clone();
+            Box::pin(async move {
+                for stmt in &init_sql {
+                    conn.execute(stmt.as_str()).await?;
+                }
+                if let Some(stmt) = deadline::mysql_session_statement() {
+                    conn.execute(stmt.as_str()).await?;
+                }
+                Ok(())
+            })
+        })
+}
+
+// sqlx prepares every persistent query once per connection and keeps it in an LRU cache,
+// MYSQL_STATEMENT_CACHE_SIZE bounds it per connection (default 100, like sqlx)
+pub fn mysql_connect_options(database_url: &str) -> Result<MySqlConnectOptions, sqlx::Error> {
+    let capacity = env::var("MYSQL_STATEMENT_CACHE_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(100);
+
+    Ok(MySqlConnectOptions::from_str(database_url)?.statement_cache_capacity(capacity))
+}
+
+pub async fn build_mysql_pool(database_url: &str) -> Result<MySqlPool, Box<dyn std::error::Error + Send + Sync>> {
+    let settings = pool::settings();
+    println!(
+        "🚀 Connecting to the MySQL database (pool of {} to {} connections)...",
+        settings.min_connections, settings.max_connections
+    );
+
+    let options = match mysql_connect_options(database_url) {
+        Ok(options) => options,
+        Err(err) => {
+            println!("🔥 Failed to connect to the database: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let pool = match mysql_pool_options()
+        .connect_with(options)
+        .await
+    {
+        Ok(pool) => {
+            println!("✅Connection to the database is successful!");
+            pool
+        }
+        Err(err) => {
+            println!("🔥 Failed to connect to the database: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+
+    Ok(pool)
+}
+
+// the replica pool connects lazily and gives up quickly, so an unavailable replica never blocks startup or lookups
+pub fn build_mysql_read_pool(read_url: &str) -> Option<MySqlPool> {
+    match mysql_connect_options(read_url) {
+        Ok(options) => Some(
+            mysql_pool_options()
+                .acquire_timeout(std::time::Duration::from_secs(3))
+                .connect_lazy_with(options),
+        ),
+        Err(err) => {
+            println!("🔥 Invalid READ_DATABASE_URL, reads will use the primary: {:?}", err);
+            None
+        }
+    }
+}
+
+fn is_unavailable(err: &sqlx::Error) -> bool {
+    matches!(
+        err,
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed
+    )
+}
+
+// runs a read only query on the replica, falling back to the primary when the replica is unavailable
+pub async fn read_mysql<T, F, Fut>(data: &AppState, primary: &MySqlPool, query: F) -> Result<T, sqlx::Error>
+where
+    F: Fn(MySqlPool) -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    if let Some(replica) = &data.read_pool {
+        match query(replica.clone()).await {
+            Err(err) if is_unavailable(&err) => {
+                println!("🔥 Read replica unavailable, falling back to the primary: {:?}", err);
+            }
+            result => return result,
+        }
+    }
+
+    query(primary.clone()).await
+}
@@ -0,0 +1,100 @@
+use std::env;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use sqlx::mysql::MySqlPool;
+use sqlx::Connection;
+use tokio::task::JoinHandle;
+
+// the connection settings of both backends, the defaults are the former fixed ones (sqlx's for MySQL):
+// DB_POOL_MIN_CONNECTIONS (0) and DB_POOL_MAX_CONNECTIONS (10) bound the MySQL pool, DB_POOL_ACQUIRE_TIMEOUT_SECS (30)
+// is how long a query waits for a MySQL connection or a Postgres connect, DB_POOL_IDLE_TIMEOUT_SECS (600) and
+// DB_POOL_MAX_LIFETIME_SECS (1800) retire MySQL connections and the shared Postgres prepared connection, 0 never does
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub min_connections: u32,
+    pub max_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    // DB_POOL_SHRINK_AFTER_SECS, 0 disables the adaptive shrinking
+    pub shrink_after: Option<Duration>,
+}
+
+fn number(var: &str, default: u64) -> u64 {
+    env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+fn seconds(var: &str, default: u64) -> Option<Duration> {
+    Some(number(var, default)).filter(|secs| *secs > 0).map(Duration::from_secs)
+}
+
+pub fn settings() -> &'static PoolSettings {
+    static SETTINGS: OnceLock<PoolSettings> = OnceLock::new();
+    SETTINGS.get_or_init(|| {
+        let max_connections = number("DB_POOL_MAX_CONNECTIONS", 10).max(1) as u32;
+
+        PoolSettings {
+            min_connections: (number("DB_POOL_MIN_CONNECTIONS", 0) as u32).min(max_connections),
+            max_connections,
+            acquire_timeout: Duration::from_secs(number("DB_POOL_ACQUIRE_TIMEOUT_SECS", 30).max(1)),
+            idle_timeout: seconds("DB_POOL_IDLE_TIMEOUT_SECS", 600),
+            max_lifetime: seconds("DB_POOL_MAX_LIFETIME_SECS", 1800),
+            shrink_after: seconds("DB_POOL_SHRINK_AFTER_SECS", 120),
+        }
+    })
+}
+
+const SAMPLE_EVERY: Duration = Duration::from_secs(10);
+
+// sqlx hands out its idle connections in turn, so a trickle of queries keeps every connection of the last peak
+// from ever reaching the idle timeout. Every SAMPLE_EVERY this closes the idle connections beyond the most in use
+// over the last DB_POOL_SHRINK_AFTER_SECS (plus one spare), never below DB_POOL_MIN_CONNECTIONS; the pool grows
+// back on demand up to DB_POOL_MAX_CONNECTIONS.
+pub fn start_shrinking(name: &'static str, pool: MySqlPool) -> Option<JoinHandle<()>> {
+    let window = settings().shrink_after?;
+    let min_connections = settings().min_connections;
+
+    Some(tokio::spawn(async move {
+        // (when, connections in use)
+        let mut samples: Vec<(Instant, u32)> = vec![];
+        let started_at = Instant::now();
+
+        loop {
+            tokio::time::sleep(SAMPLE_EVERY).await;
+
+            if pool.is_closed() {
+                return;
+            }
+
+            let size = pool.size();
+            let in_use = size.saturating_sub(pool.num_idle() as u32);
+            samples.retain(|(at, _)| at.elapsed() < window);
+            samples.push((Instant::now(), in_use));
+
+            // not enough history yet to call the pool idle
+            if started_at.elapsed() < window {
+                continue;
+            }
+
+            let peak = samples.iter().map(|(_, in_use)| *in_use).max().unwrap_or(0);
+            let target = (peak + 1).max(min_connections);
+            let mut closed = 0;
+
+            while pool.size() > target {
+                let Some(conn) = pool.try_acquire() else {
+                    break;
+                };
+
+                if let Err(err) = conn.detach().close().await {
+                    log::debug!("closing an idle {} connection failed: {}", name, err);
+                }
+                closed += 1;
+            }
+
+            if closed > 0 {
+                log::debug!("closed {} idle {} connection(s), {} left, at most {} in use recently", closed, name, pool.size(), peak);
+            }
+        }
+    }))
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls, Row, Statement};
+
+use crate::config::init_statements;
+use crate::deadline;
+use crate::repo::pool;
+use crate::AppState;
+
+pub async fn build_pg_pool(database_url: &str) -> Result<Client, Box<dyn std::error::Error + Send + Sync>> {
+    println!("🚀 Connecting to the PG database...");
+
+    let timeout = pool::settings().acquire_timeout;
+    let (client, connection) = tokio::time::timeout(timeout, tokio_postgres::connect(database_url, NoTls))
+        .await
+        .map_err(|_| format!("connecting took longer than {:?}", timeout))??;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    for stmt in init_statements("PG_INIT_SQL") {
+        client.batch_execute(&stmt).await?;
+    }
+    if let Some(stmt) = deadline::pg_session_statement() {
+        client.batch_execute(&stmt).await?;
+    }
+
+    println!("✅Connection to the database is successful!");
+
+    Ok(client)
+}
+
+pub async fn build_pg_read_client(data: &AppState) -> Result<Client, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(read_url) = &data.read_db_url {
+        match build_pg_pool(read_url).await {
+            Ok(client) => return Ok(client),
+            Err(err) => println!("🔥 Read replica unavailable, falling back to the primary: {:?}", err),
+        }
+    }
+
+    build_pg_pool(&data.db_url).await
+}
+
+// a long lived connection per database URL with the statements prepared on it, for the hot lookup and insert
+// queries. tokio-postgres pipelines concurrent queries on one connection, so requests share it instead of
+// connecting and planning the same query each time. PG_PREPARED_STATEMENTS=false goes back to a connection per call.
+pub struct PreparedClient {
+    client: Client,
+    statements: Mutex<HashMap<String, Statement>>,
+    connected_at: Instant,
+    used_at: Mutex<Instant>,
+}
+
+type Shared = tokio::sync::Mutex<HashMap<String, Arc<PreparedClient>>>;
+
+fn prepared_enabled() -> bool {
+    env::var("PG_PREPARED_STATEMENTS").map(|value| value != "false").unwrap_or(true)
+}
+
+impl PreparedClient {
+    fn new(client: Client) -> Self {
+        PreparedClient {
+            client,
+            statements: Mutex::default(),
+            connected_at: Instant::now(),
+            used_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    // retired like a pooled MySQL connection, after DB_POOL_MAX_LIFETIME_SECS or DB_POOL_IDLE_TIMEOUT_SECS unused
+    fn expired(&self) -> bool {
+        let settings = pool::settings();
+
+        settings.max_lifetime.is_some_and(|lifetime| self.connected_at.elapsed() >= lifetime)
+            || settings.idle_timeout.is_some_and(|timeout| self.used_at.lock().unwrap().elapsed() >= timeout)
+    }
+
+    async fn statement(&self, sql: &str) -> Result<Statement, tokio_postgres::Error> {
+        *self.used_at.lock().unwrap() = Instant::now();
+
+        if let Some(statement) = self.statements.lock().unwrap().get(sql) {
+            return Ok(statement.clone());
+        }
+
+        let statement = self.client.prepare(sql).await?;
+        self.statements.lock().unwrap().insert(sql.to_string(), statement.clone());
+
+        Ok(statement)
+    }
+
+    pub async fn query(&self, sql: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, tokio_postgres::Error> {
+        let statement = self.statement(sql).await?;
+        self.client.query(&statement, params).await
+    }
+
+    pub async fn query_opt(&self, sql: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, tokio_postgres::Error> {
+        let statement = self.statement(sql).await?;
+        self.client.query_opt(&statement, params).await
+    }
+
+    pub async fn execute(&self, sql: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, tokio_postgres::Error> {
+        let statement = self.statement(sql).await?;
+        self.client.execute(&statement, params).await
+    }
+}
+
+pub async fn prepared_client(database_url: &str) -> Result<Arc<PreparedClient>, Box<dyn std::error::Error + Send + Sync>> {
+    if !prepared_enabled() {
+        let client = build_pg_pool(database_url).await?;
+        return Ok(Arc::new(PreparedClient::new(client)));
+    }
+
+    static CLIENTS: OnceLock<Shared> = OnceLock::new();
+    let mut clients = CLIENTS.get_or_init(Shared::default).lock().await;
+
+    // a dropped connection (restart, failover) is replaced, its statements go with it, and so is an expired one,
+    // requests still holding it finish on it
+    if let Some(prepared) = clients.get(database_url).filter(|prepared| !prepared.client.is_closed() && !prepared.expired()) {
+        return Ok(prepared.clone());
+    }
+
+    let client = build_pg_pool(database_url).await?;
+    let prepared = Arc::new(PreparedClient::new(client));
+    clients.insert(database_url.to_string(), prepared.clone());
+
+    Ok(prepared)
+}
+
+pub async fn prepared_read_client(data: &AppState) -> Result<Arc<PreparedClient>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(read_url) = &data.read_db_url {
+        match prepared_client(read_url).await {
+            Ok(client) => return Ok(client),
+            Err(err) => println!("🔥 Read replica unavailable, falling back to the primary: {:?}", err),
+        }
+    }
+
+    prepared_client(&data.db_url).await
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::blacklist;
+use crate::last_modified;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::AppState;
+
+// lifecycle of a blacklist entry, only active entries suppress sending
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    Active,
+    PendingReview,
+    Expired,
+    Removed,
+    Allowlisted,
+}
+
+// (stored status, effective status)
+type StatusRow = (String, String);
+
+// the status as seen by lookups, active entries past expires_at are expired. `alias` qualifies the columns, e.g. "b."
+pub fn effective_status_sql(alias: &str) -> String {
+    format!(
+        "CASE WHEN {a}status = 'active' AND {a}expires_at IS NOT NULL AND {a}expires_at <= NOW() THEN 'expired' ELSE {a}status END",
+        a = alias
+    )
+}
+
+impl EntryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryStatus::Active => "active",
+            EntryStatus::PendingReview => "pending_review",
+            EntryStatus::Expired => "expired",
+            EntryStatus::Removed => "removed",
+            EntryStatus::Allowlisted => "allowlisted",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(EntryStatus::Active),
+            "pending_review" => Some(EntryStatus::PendingReview),
+            "expired" => Some(EntryStatus::Expired),
+            "removed" => Some(EntryStatus::Removed),
+            "allowlisted" => Some(EntryStatus::Allowlisted),
+            _ => None,
+        }
+    }
+
+    pub fn suppresses(&self) -> bool {
+        *self == EntryStatus::Active
+    }
+
+    // the lifecycle, e.g. a removed entry can only be reactivated and a pending one not expire before review
+    pub fn can_transition(&self, to: EntryStatus) -> bool {
+        use EntryStatus::*;
+
+        matches!(
+            (self, to),
+            (Active, PendingReview | Expired | Removed | Allowlisted)
+                | (PendingReview, Active | Removed | Allowlisted)
+                | (Expired, Active | Removed)
+                | (Removed, Active)
+                | (Allowlisted, Active | Removed)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum TransitionError {
+    NotFound,
+    Invalid { from: EntryStatus, to: EntryStatus },
+    Database(String),
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionError::NotFound => write!(f, "blacklist entry not found"),
+            TransitionError::Invalid { from, to } => {
+                write!(f, "invalid status transition from {} to {}", from.as_str(), to.as_str())
+            }
+            TransitionError::Database(err) => write!(f, "🔥 Failed to query the database: {}", err),
+        }
+    }
+}
+
+async fn current(domain_id: i32, email: &str, data: &AppState) -> Result<Option<(String, EntryStatus)>, String> {
+    let table = blacklist::table_for(&data.db_type);
+    let sql = |placeholders: (&str, &str)| {
+        format!(
+            r#"SELECT status, {effective} FROM {table} WHERE domain_id = {} AND email = {}"#,
+            placeholders.0,
+            placeholders.1,
+            effective = effective_status_sql(""),
+            table = table
+        )
+    };
+
+    let row: Option<StatusRow> = match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, StatusRow>(&sql(("?", "?")))
+            .bind(domain_id)
+            .bind(email)
+            .fetch_optional(pool)
+            .await
+            .map_err(|err| err.to_string())?,
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(&sql(("$1", "$2")), &[&domain_id, &email])
+                .await
+                .map_err(|err| err.to_string())?
+                .map(|row| (row.get(0), row.get(1)))
+        }
+        // read consistently, the update below is conditional on it
+        DBType::DynamoDB(store) => return dynamodb::get_status(store, domain_id, email, true).await,
+    };
+
+    Ok(row.map(|(stored, effective)| {
+        let status = EntryStatus::parse(&effective).unwrap_or(EntryStatus::Active);
+        (stored, status)
+    }))
+}
+
+// moves the entry of an address (as stored) to `to` when the lifecycle allows it, returns the previous status.
+// The update is conditional on the stored status, so concurrent changes cannot skip a check.
+pub async fn transition(domain_id: i32, email: &str, to: EntryStatus, data: &AppState) -> Result<EntryStatus, TransitionError> {
+    // ordered with the notifications of the recipient, see RecipientShards
+    let _recipient = data.shards.lock(domain_id, email).await;
+
+    transition_locked(domain_id, email, to, data).await
+}
+
+// transition for a caller already holding the queue of the recipient, e.g. to write more under it
+pub async fn transition_locked(domain_id: i32, email: &str, to: EntryStatus, data: &AppState) -> Result<EntryStatus, TransitionError> {
+    let (stored, from) = current(domain_id, email, data)
+        .await
+        .map_err(TransitionError::Database)?
+        .ok_or(TransitionError::NotFound)?;
+
+    if !from.can_transition(to) {
+        return Err(TransitionError::Invalid { from, to });
+    }
+
+    let table = blacklist::table_for(&data.db_type);
+    // reactivated entries suppress until they are moved out of active again
+    let expires_at = match to {
+        EntryStatus::Active => "NULL",
+        _ => "expires_at",
+    };
+
+    let updated = match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(&format!(
+            r#"UPDATE {table} SET status = ?, status_changed_at = NOW(), expires_at = {expires_at} WHERE domain_id = ? AND email = ? AND status = ?"#,
+            table = table,
+            expires_at = expires_at
+        ))
+            .bind(to.as_str())
+            .bind(domain_id)
+            .bind(email)
+            .bind(&stored)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(|err| TransitionError::Database(err.to_string()))?,
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url)
+                .await
+                .map_err(|err| TransitionError::Database(err.to_string()))?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET status = $1, status_changed_at = NOW(), expires_at = {expires_at} WHERE domain_id = $2 AND email = $3 AND status = $4"#,
+                        table = table,
+                        expires_at = expires_at
+                    ),
+                    &[&to.as_str(), &domain_id, &email, &stored],
+                )
+                .await
+                .map_err(|err| TransitionError::Database(err.to_string()))?
+        }
+        DBType::DynamoDB(store) => dynamodb::update_status(store, domain_id, email, &stored, to)
+            .await
+            .map_err(TransitionError::Database)?,
+    };
+
+    if updated == 0 {
+        return Err(TransitionError::Database("the entry changed concurrently, retry".into()));
+    }
+
+    data.cache.evict(domain_id, email);
+    last_modified::touch(domain_id, data).await;
+
+    Ok(from)
+}
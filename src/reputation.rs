@@ -0,0 +1,267 @@
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::api_keys;
+use crate::events::{self, Event};
+use crate::handlers::is_admin;
+use crate::notification_log;
+use crate::repo::{build_pg_pool, build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::responses::{ErrorResponse, StatusResponse};
+use crate::AppState;
+
+// SES puts accounts under review from a 0.1% complaint rate
+const DEFAULT_THRESHOLD: f64 = 0.001;
+const DEFAULT_MIN_DELIVERIES: i64 = 1000;
+const DEFAULT_WINDOW_HOURS: i64 = 24;
+
+// (complaints, deliveries) in the window
+type CountsRow = (i64, i64);
+
+// (sending_paused, paused_at, pause_reason)
+type PauseRow = (bool, Option<NaiveDateTime>, Option<String>);
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Reputation {
+    pub domain_id: i32,
+    pub window_hours: i64,
+    pub complaints: i64,
+    // Delivery notifications logged in the window, SES only sends them when enabled on the identity
+    pub deliveries: i64,
+    pub complaint_rate: f64,
+    pub threshold: f64,
+    // set automatically once the complaint rate exceeds the threshold, senders should halt their campaigns
+    pub sending_paused: bool,
+    pub paused_at: Option<NaiveDateTime>,
+    pub pause_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReputationResponse {
+    pub success: bool,
+    pub data: Reputation,
+}
+
+// COMPLAINT_RATE_THRESHOLD over COMPLAINT_RATE_WINDOW_HOURS pauses the domain, once at least
+// COMPLAINT_RATE_MIN_DELIVERIES deliveries make the rate meaningful
+struct Config {
+    threshold: f64,
+    min_deliveries: i64,
+    window_hours: i64,
+}
+
+fn config() -> Config {
+    Config {
+        threshold: env::var("COMPLAINT_RATE_THRESHOLD").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_THRESHOLD),
+        min_deliveries: env::var("COMPLAINT_RATE_MIN_DELIVERIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MIN_DELIVERIES),
+        window_hours: env::var("COMPLAINT_RATE_WINDOW_HOURS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_WINDOW_HOURS)
+            .max(1),
+    }
+}
+
+fn domains_table() -> String {
+    env::var("PG_DOMAINS_TABLE").unwrap_or_else(|_| "domains".into())
+}
+
+async fn counts(domain_id: i32, since: NaiveDateTime, data: &web::Data<AppState>) -> Result<CountsRow, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, CountsRow>(
+                    r#"SELECT CAST(COALESCE(SUM(CASE WHEN notification_type = 'Complaint' THEN 1 ELSE 0 END), 0) AS SIGNED),
+                              CAST(COALESCE(SUM(CASE WHEN notification_type = 'Delivery' THEN 1 ELSE 0 END), 0) AS SIGNED)
+                       FROM notification_log WHERE domain_id = ? AND received_at >= ?"#,
+                )
+                    .bind(domain_id)
+                    .bind(since)
+                    .fetch_one(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(
+                    &format!(
+                        r#"SELECT COUNT(*) FILTER (WHERE notification_type = 'Complaint'),
+                                  COUNT(*) FILTER (WHERE notification_type = 'Delivery')
+                           FROM {table} WHERE domain_id = $1 AND received_at >= $2"#,
+                        table = notification_log::table()
+                    ),
+                    &[&domain_id, &since],
+                )
+                .await
+                .map(|row| (row.get(0), row.get(1)))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn pause_state(domain_id: i32, data: &web::Data<AppState>) -> Result<Option<PauseRow>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, PauseRow>(r#"SELECT sending_paused, paused_at, pause_reason FROM domains WHERE id = ?"#)
+                .bind(domain_id)
+                .fetch_optional(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(
+                    &format!(r#"SELECT sending_paused, paused_at, pause_reason FROM {table} WHERE id = $1"#, table = domains_table()),
+                    &[&domain_id],
+                )
+                .await
+                .map(|row| row.map(|row| (row.get(0), row.get(1), row.get(2))))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// domains without a row get one named after their id, like the settings defaults they stand for
+async fn set_paused(domain_id: i32, reason: Option<&str>, data: &web::Data<AppState>) -> Result<(), String> {
+    let paused = reason.is_some();
+    let name = domain_id.to_string();
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"INSERT INTO domains (id, name, sending_paused, paused_at, pause_reason)
+                   VALUES (?, ?, ?, CASE WHEN ? THEN NOW() END, ?)
+                   ON DUPLICATE KEY UPDATE sending_paused = VALUES(sending_paused), paused_at = VALUES(paused_at), pause_reason = VALUES(pause_reason)"#,
+            )
+                .bind(domain_id)
+                .bind(&name)
+                .bind(paused)
+                .bind(paused)
+                .bind(reason)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (id, name, sending_paused, paused_at, pause_reason)
+                           VALUES ($1, $2, $3, CASE WHEN $3 THEN NOW() END, $4)
+                           ON CONFLICT (id) DO UPDATE SET sending_paused = EXCLUDED.sending_paused,
+                               paused_at = EXCLUDED.paused_at, pause_reason = EXCLUDED.pause_reason"#,
+                        table = domains_table()
+                    ),
+                    &[&domain_id, &name, &paused, &reason],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+pub async fn reputation(domain_id: i32, data: &web::Data<AppState>) -> Result<Reputation, String> {
+    let config = config();
+    let since = Utc::now().naive_utc() - Duration::hours(config.window_hours);
+    let (complaints, deliveries) = counts(domain_id, since, data).await?;
+    let (sending_paused, paused_at, pause_reason) = pause_state(domain_id, data).await?.unwrap_or((false, None, None));
+
+    Ok(Reputation {
+        domain_id,
+        window_hours: config.window_hours,
+        complaints,
+        deliveries,
+        complaint_rate: if deliveries > 0 { complaints as f64 / deliveries as f64 } else { 0.0 },
+        threshold: config.threshold,
+        sending_paused,
+        paused_at,
+        pause_reason,
+    })
+}
+
+// run after each complaint, pauses the domain and publishes a DomainPaused event when the rate crossed the threshold
+pub async fn check_complaint_rate(domain_id: i32, data: &web::Data<AppState>) {
+    // the rate comes from the SQL notification log
+    if let DBType::DynamoDB(_) = data.db_type {
+        return;
+    }
+
+    let min_deliveries = config().min_deliveries;
+
+    let reputation = match reputation(domain_id, data).await {
+        Ok(reputation) => reputation,
+        Err(err) => {
+            println!("🔥 Failed to compute the complaint rate of domain {}: {}", domain_id, err);
+            return;
+        }
+    };
+
+    if reputation.sending_paused || reputation.deliveries < min_deliveries || reputation.complaint_rate <= reputation.threshold {
+        return;
+    }
+
+    let reason = format!(
+        "complaint rate {:.4}% over {}h exceeds {:.4}%",
+        reputation.complaint_rate * 100.0,
+        reputation.window_hours,
+        reputation.threshold * 100.0
+    );
+
+    if let Err(err) = set_paused(domain_id, Some(&reason), data).await {
+        println!("🔥 Failed to pause domain {}: {}", domain_id, err);
+        return;
+    }
+
+    events::publish(data, Event::domain_paused(domain_id, &reason));
+}
+
+pub async fn reputation_handler(req: HttpRequest, path: web::Path<i32>, data: web::Data<AppState>) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    if let Err(response) = api_keys::meter(&req, &data, domain_id).await {
+        return response;
+    }
+
+    match reputation(domain_id, &data).await {
+        Ok(reputation) => HttpResponse::Ok().json(ReputationResponse { success: true, data: reputation }),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+// lifts the pause once the cause is dealt with, a rate still over the threshold pauses again on the next complaint
+pub async fn resume_handler(req: HttpRequest, path: web::Path<i32>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain_id = path.into_inner();
+
+    match set_paused(domain_id, None, &data).await {
+        Ok(()) => {
+            println!("✅ Resumed sending for domain {}", domain_id);
+            HttpResponse::Ok().json(StatusResponse::success())
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
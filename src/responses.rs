@@ -0,0 +1,225 @@
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::api_keys::ApiKeyUsage;
+use crate::archive::EntryReasonResponse;
+use crate::auth_failures::AuthFailuresResponse;
+use crate::merge::{MergeRequest, MergeResponse};
+use crate::logging::{LogSettingsResponse, LogSettingsUpdate};
+use crate::cache::{CacheInvalidationResponse, CacheStatsResponse};
+use crate::complaint_review::PendingComplaint;
+use crate::blacklist::{DomainSuppression, ImportResponse, ManualEntry, StatusChange};
+use crate::daily_stats::DailyStats;
+use crate::bulk_delete::{BulkDeleteFilter, BulkDeleteResponse};
+use crate::dns::DnsReport;
+use crate::domain::{DeadLetter, WebhookDeadLetter};
+use crate::event_search::{EventPage, LoggedEvent};
+use crate::events::LiveEvent;
+use crate::handlers::blacklist::BatchLookup;
+use crate::handlers::v2::{CreatedSuppression, StatusTransition, Suppression};
+use crate::imports::{ImportAccepted, ImportJob};
+use crate::onboarding::{NewTenant, TenantResponse};
+use crate::preview::ParsePreview;
+use crate::repo::EntryStatus;
+use crate::reputation::{Reputation, ReputationResponse};
+use crate::schema::IndexStatus;
+use crate::selftest::SelfTestReport;
+use crate::shadow::ShadowDiscrepancy;
+use crate::ses_reconcile::ReconcileResponse;
+use crate::subscriptions::{ConfirmationResponse, Subscription};
+use crate::topic_mappings::{NewTopicMapping, TopicMapping};
+use crate::unsubscribe::UnsubscribeLinkResponse;
+use crate::stats::{DiagnosticClassStats, IdentityStats, MtaStats, RecipientDomainStats};
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HealthResponse {
+    pub status: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Lookup {
+    pub blacklisted: bool,
+    // status of the entry, absent when the address has none
+    pub status: Option<EntryStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LookupResponse {
+    pub success: bool,
+    pub data: Lookup,
+}
+
+impl LookupResponse {
+    pub fn new(status: Option<EntryStatus>) -> Self {
+        LookupResponse {
+            success: true,
+            data: Lookup { blacklisted: status.is_some_and(|status| status.suppresses()), status },
+        }
+    }
+}
+
+// one NDJSON line of a batch lookup
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BatchLookupResult {
+    pub email: String,
+    pub blacklisted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub error: String,
+}
+
+impl ErrorResponse {
+    pub fn new(error: impl Into<String>) -> Self {
+        ErrorResponse { success: false, error: error.into() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ListResponse<T: ToSchema> {
+    pub success: bool,
+    pub data: Vec<T>,
+}
+
+impl<T: ToSchema> ListResponse<T> {
+    pub fn new(data: Vec<T>) -> Self {
+        ListResponse { success: true, data }
+    }
+}
+
+// response of the SNS endpoint, status is one of success, duplicate, fail or error
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StatusResponse {
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl StatusResponse {
+    pub fn success() -> Self {
+        StatusResponse { status: "success".into(), message: None }
+    }
+
+    pub fn fail(message: impl Into<String>) -> Self {
+        StatusResponse { status: "fail".into(), message: Some(message.into()) }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        StatusResponse { status: "error".into(), message: Some(message.into()) }
+    }
+
+    pub fn duplicate(message: impl Into<String>) -> Self {
+        StatusResponse { status: "duplicate".into(), message: Some(message.into()) }
+    }
+}
+
+// the error envelope of every /api/v2 response, `code` is stable and meant for programs, `message` for people
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiError {
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorEnvelope {
+    pub success: bool,
+    pub error: ApiError,
+}
+
+impl ErrorEnvelope {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        ErrorEnvelope {
+            success: false,
+            error: ApiError { code: code.into(), message: message.into() },
+        }
+    }
+}
+
+// the success envelope of every /api/v2 response
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Envelope<T: ToSchema> {
+    pub success: bool,
+    pub data: T,
+}
+
+impl<T: ToSchema> Envelope<T> {
+    pub fn new(data: T) -> Self {
+        Envelope { success: true, data }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReplayOutcome {
+    pub id: i64,
+    pub status: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReplayResponse {
+    pub success: bool,
+    pub data: ReplayOutcome,
+}
+
+#[derive(OpenApi)]
+#[openapi(components(schemas(
+    HealthResponse,
+    LookupResponse,
+    EntryStatus,
+    StatusChange,
+    BatchLookup,
+    BatchLookupResult,
+    ErrorResponse,
+    StatusResponse,
+    ReplayResponse,
+    SelfTestReport,
+    ParsePreview,
+    ShadowDiscrepancy,
+    DnsReport,
+    ManualEntry,
+    ImportResponse,
+    ImportAccepted,
+    ImportJob,
+    NewTenant,
+    TenantResponse,
+    BulkDeleteFilter,
+    BulkDeleteResponse,
+    ReputationResponse,
+    LiveEvent,
+    LoggedEvent,
+    EventPage,
+    ErrorEnvelope,
+    Envelope<Suppression>,
+    Envelope<CreatedSuppression>,
+    Envelope<StatusTransition>,
+    Envelope<Reputation>,
+    ListResponse<DeadLetter>,
+    ListResponse<WebhookDeadLetter>,
+    ListResponse<DomainSuppression>,
+    ListResponse<PendingComplaint>,
+    ListResponse<RecipientDomainStats>,
+    ListResponse<MtaStats>,
+    ListResponse<IdentityStats>,
+    ListResponse<DiagnosticClassStats>,
+    ListResponse<Subscription>,
+    ConfirmationResponse,
+    ReconcileResponse,
+    AuthFailuresResponse,
+    EntryReasonResponse,
+    MergeRequest,
+    MergeResponse,
+    UnsubscribeLinkResponse,
+    LogSettingsUpdate,
+    LogSettingsResponse,
+    CacheStatsResponse,
+    CacheInvalidationResponse,
+    NewTopicMapping,
+    ListResponse<TopicMapping>,
+    ListResponse<DailyStats>,
+    ListResponse<ApiKeyUsage>,
+    ListResponse<IndexStatus>,
+)))]
+pub struct ApiDoc;
@@ -0,0 +1,197 @@
+use regex::Regex;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::diagnostics;
+use crate::domain::{Bounce, BouncedRecipient, Mail};
+
+// action applied to a bounced recipient, rules are stored as JSON in domains.bounce_rules, e.g.
+// [{"bounce_type": "Transient", "action": "suppress_temporarily", "days": 7}, {"diagnostic_code": "5\\.1\\.1", "action": "alert"},
+//  {"diagnostic_class": "mailbox_full", "action": "suppress_temporarily", "days": 3}]
+// a hundred years, far beyond any sensible suppression and within the dates chrono can represent
+pub const MAX_DAYS: i64 = 36_500;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RuleAction {
+    SuppressPermanently,
+    SuppressTemporarily {
+        #[serde(deserialize_with = "days")]
+        days: i64,
+    },
+    Ignore,
+    Alert,
+}
+
+// rules with days outside 0..=MAX_DAYS are refused when parsed
+fn days<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    let days = i64::deserialize(deserializer)?;
+
+    if !(0..=MAX_DAYS).contains(&days) {
+        return Err(D::Error::custom(format!("days must be between 0 and {}, got {}", MAX_DAYS, days)));
+    }
+
+    Ok(days)
+}
+
+// a diagnostic code regex, compiled once when the rules are parsed; an invalid one is refused then
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+
+        Regex::new(&pattern)
+            .map(Pattern)
+            .map_err(|err| D::Error::custom(format!("invalid diagnostic code regex {:?}: {}", pattern, err)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BounceRule {
+    pub bounce_type: Option<String>,
+    pub bounce_sub_type: Option<String>,
+    // regex matched against the recipient diagnostic code
+    pub diagnostic_code: Option<Pattern>,
+    // the classified diagnostic, e.g. mailbox_full, see diagnostics::CLASSES
+    pub diagnostic_class: Option<String>,
+    // sending identity of the mail, e.g. arn:aws:ses:us-east-1:123456789012:identity/example.com
+    pub source_arn: Option<String>,
+    pub sending_account_id: Option<String>,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+impl BounceRule {
+    fn matches(&self, bounce: &Bounce, recipient: &BouncedRecipient, mail: Option<&Mail>) -> bool {
+        if let Some(source_arn) = &self.source_arn {
+            if mail.map(|mail| &mail.source_arn) != Some(source_arn) {
+                return false;
+            }
+        }
+
+        if let Some(sending_account_id) = &self.sending_account_id {
+            if mail.map(|mail| &mail.sending_account_id) != Some(sending_account_id) {
+                return false;
+            }
+        }
+
+        if let Some(bounce_type) = &self.bounce_type {
+            if !bounce_type.eq_ignore_ascii_case(&bounce.bounce_type) {
+                return false;
+            }
+        }
+
+        if let Some(bounce_sub_type) = &self.bounce_sub_type {
+            if !bounce_sub_type.eq_ignore_ascii_case(&bounce.bounce_sub_type) {
+                return false;
+            }
+        }
+
+        if let Some(class) = &self.diagnostic_class {
+            let actual = diagnostics::classify(recipient.status.as_deref(), recipient.diagnostic_code.as_deref());
+
+            if actual != Some(class.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &self.diagnostic_code {
+            match &recipient.diagnostic_code {
+                Some(code) if pattern.is_match(code) => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+pub fn parse_rules(raw: &str) -> Vec<BounceRule> {
+    match serde_json::from_str(raw) {
+        Ok(rules) => rules,
+        Err(err) => {
+            println!("🔥 Failed to parse bounce rules, falling back to defaults: {:?}", err);
+            vec![]
+        }
+    }
+}
+
+// the first matching rule wins, without a match the domain suppression defaults apply
+pub fn evaluate(
+    rules: &[BounceRule],
+    bounce: &Bounce,
+    recipient: &BouncedRecipient,
+    mail: Option<&Mail>,
+) -> Option<RuleAction> {
+    matching(rules, bounce, recipient, mail).map(|(_, rule)| rule.action.clone())
+}
+
+// the rule `evaluate` applies and its position in the list
+pub fn matching<'a>(
+    rules: &'a [BounceRule],
+    bounce: &Bounce,
+    recipient: &BouncedRecipient,
+    mail: Option<&Mail>,
+) -> Option<(usize, &'a BounceRule)> {
+    rules
+        .iter()
+        .enumerate()
+        .find(|(_, rule)| rule.matches(bounce, recipient, mail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient(diagnostic_code: &str) -> BouncedRecipient {
+        BouncedRecipient { diagnostic_code: Some(diagnostic_code.into()), ..BouncedRecipient::default() }
+    }
+
+    #[test]
+    fn rules_are_parsed_with_their_pattern_compiled() {
+        let rules = parse_rules(r#"[{"diagnostic_code": "5\\.1\\.1", "action": "suppress_temporarily", "days": 7}]"#);
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].action, RuleAction::SuppressTemporarily { days: 7 });
+
+        let bounce = Bounce::default();
+        assert_eq!(evaluate(&rules, &bounce, &recipient("smtp; 550 5.1.1 user unknown"), None), Some(rules[0].action.clone()));
+        assert_eq!(evaluate(&rules, &bounce, &recipient("smtp; 452 4.2.2 mailbox full"), None), None);
+    }
+
+    #[test]
+    fn out_of_range_days_are_refused() {
+        for days in ["-1", "36501", "9223372036854775807"] {
+            let raw = format!(r#"[{{"action": "suppress_temporarily", "days": {}}}]"#, days);
+            assert!(parse_rules(&raw).is_empty(), "days {}", days);
+        }
+
+        let raw = format!(r#"[{{"action": "suppress_temporarily", "days": {}}}]"#, MAX_DAYS);
+        assert_eq!(parse_rules(&raw).len(), 1);
+    }
+
+    #[test]
+    fn invalid_patterns_are_refused() {
+        assert!(parse_rules(r#"[{"diagnostic_code": "5\\.1\\.(", "action": "alert"}]"#).is_empty());
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::handlers::is_admin;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::{ErrorResponse, ListResponse};
+use crate::AppState;
+
+// a table as the service expects it after all migrations, `pg_var` is the PG_* table name override
+struct TableSpec {
+    name: &'static str,
+    pg_var: &'static str,
+    columns: &'static [&'static str],
+    // (columns, unique), matched by columns because PG generates the constraint index names
+    indexes: &'static [(&'static [&'static str], bool)],
+    // not needed for correctness but for the queries to stay fast on large tables, reported by the indexes command
+    recommended: &'static [(&'static [&'static str], bool)],
+}
+
+const TABLES: &[TableSpec] = &[
+    TableSpec {
+        name: "blacklist",
+        pg_var: "PG_TABLE",
+        columns: &[
+            "id", "domain_id", "email", "reason", "created_at", "expires_at", "bounce_type", "bounce_sub_type",
+            "diagnostic_code", "category", "reporting_mta", "remote_mta_ip", "source_arn", "sending_account_id",
+            "status", "status_changed_at", "event_at", "diagnostic_class", "reason_summary", "subject",
+            "reason_archived_at",
+        ],
+        // duplicate detection relies on the unique key
+        indexes: &[(&["domain_id", "email"], true)],
+        // cache warmup and stats by recency, lookups of an address across domains
+        recommended: &[
+            (&["domain_id", "created_at"], false),
+            (&["email"], false),
+            (&["domain_id", "event_at"], false),
+            (&["domain_id", "diagnostic_class"], false),
+            // keyset pages of the export
+            (&["domain_id", "id"], false),
+        ],
+    },
+    TableSpec {
+        name: "domains",
+        pg_var: "PG_DOMAINS_TABLE",
+        columns: &[
+            "id", "name", "bounce_rules", "suppression_days", "max_concurrency", "sending_paused", "paused_at",
+            "pause_reason", "webhook_template", "reporting_timezone",
+        ],
+        indexes: &[],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "dead_letters",
+        pg_var: "PG_DEAD_LETTERS_TABLE",
+        columns: &[
+            "id", "domain_id", "payload", "error", "created_at", "replayed_at", "replay_status", "replay_error",
+            "encrypted_payload",
+        ],
+        indexes: &[],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "api_keys",
+        pg_var: "PG_API_KEYS_TABLE",
+        columns: &["id", "name", "key_hash", "daily_quota", "domain_id", "created_at"],
+        indexes: &[(&["key_hash"], true)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "api_usage",
+        pg_var: "PG_API_USAGE_TABLE",
+        columns: &["api_key_id", "day", "requests"],
+        indexes: &[(&["api_key_id", "day"], true)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "notification_log",
+        pg_var: "PG_NOTIFICATION_LOG_TABLE",
+        columns: &["id", "domain_id", "notification_type", "message_id", "payload", "received_at", "bounce_sub_type"],
+        indexes: &[(&["domain_id", "received_at"], false)],
+        // rebuilds and complaint rates filter by type
+        recommended: &[(&["domain_id", "notification_type", "received_at"], false)],
+    },
+    TableSpec {
+        name: "notification_recipients",
+        pg_var: "PG_NOTIFICATION_RECIPIENTS_TABLE",
+        columns: &["notification_id", "domain_id", "email"],
+        indexes: &[(&["domain_id", "email", "notification_id"], false)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "webhook_dead_letters",
+        pg_var: "PG_WEBHOOK_DEAD_LETTERS_TABLE",
+        columns: &[
+            "id", "domain_id", "url", "payload", "attempts", "error", "created_at", "replayed_at", "replay_status",
+            "replay_error",
+        ],
+        indexes: &[],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "webhook_deliveries",
+        pg_var: "PG_WEBHOOK_DELIVERIES_TABLE",
+        columns: &[
+            "id", "domain_id", "url", "payload", "attempts", "next_attempt_at", "last_error", "created_at",
+        ],
+        // the poller claims the due deliveries
+        indexes: &[(&["next_attempt_at"], false)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "lookup_audit",
+        pg_var: "PG_LOOKUP_AUDIT_TABLE",
+        columns: &["id", "domain_id", "email", "blacklisted", "api_key_id", "requested_at"],
+        indexes: &[(&["domain_id", "email", "requested_at"], false)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "sns_subscriptions",
+        pg_var: "PG_SNS_SUBSCRIPTIONS_TABLE",
+        columns: &[
+            "id", "domain_id", "topic_arn", "subscribe_url", "status", "attempts", "last_error", "created_at", "confirmed_at",
+        ],
+        indexes: &[],
+        // the startup retry selects the pending confirmations
+        recommended: &[(&["status"], false)],
+    },
+    TableSpec {
+        name: "schema_version",
+        pg_var: "PG_SCHEMA_VERSION_TABLE",
+        columns: &["version", "min_compatible", "applied_at"],
+        indexes: &[],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "daily_stats",
+        pg_var: "PG_DAILY_STATS_TABLE",
+        columns: &[
+            "domain_id", "day", "blacklist_size", "hard_bounces", "soft_bounces", "complaints", "manual",
+            "bounce_notifications", "delivery_notifications", "bounce_rate", "computed_at",
+        ],
+        // the snapshots are upserted per domain and day
+        indexes: &[(&["domain_id", "day"], true)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "topic_mappings",
+        pg_var: "PG_TOPIC_MAPPINGS_TABLE",
+        columns: &["id", "topic_arn", "domain_id", "created_at", "updated_at"],
+        // one domain per topic, the admin endpoint upserts on it
+        indexes: &[(&["topic_arn"], true)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "blacklist_modifications",
+        pg_var: "PG_BLACKLIST_MODIFICATIONS_TABLE",
+        columns: &["domain_id", "modified_at"],
+        indexes: &[],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "outbox",
+        pg_var: "PG_OUTBOX_TABLE",
+        columns: &["id", "domain_id", "event_type", "payload", "created_at", "published_at"],
+        // the publisher scans the pending rows in id order
+        indexes: &[(&["published_at", "id"], false)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "reason_archive",
+        pg_var: "PG_REASON_ARCHIVE_TABLE",
+        columns: &["blacklist_id", "domain_id", "reason_gz", "s3_key", "archived_at"],
+        indexes: &[],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "idempotency_keys",
+        pg_var: "PG_IDEMPOTENCY_KEYS_TABLE",
+        columns: &["idempotency_key", "request_path", "fingerprint", "status_code", "response", "created_at"],
+        indexes: &[(&["idempotency_key", "request_path"], true)],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "import_jobs",
+        pg_var: "PG_IMPORT_JOBS_TABLE",
+        columns: &[
+            "id", "domain_id", "status", "total", "processed", "inserted", "skipped", "failed", "errors", "created_at",
+            "updated_at", "finished_at",
+        ],
+        indexes: &[],
+        recommended: &[],
+    },
+    TableSpec {
+        name: "shadow_discrepancies",
+        pg_var: "PG_SHADOW_DISCREPANCIES_TABLE",
+        columns: &[
+            "id", "domain_id", "notification_type", "message_id", "payload", "current_result", "candidate_result",
+            "created_at",
+        ],
+        indexes: &[],
+        recommended: &[],
+    },
+];
+
+// (index name, column, unique), one row per indexed column in index order
+type IndexRow = (String, String, bool);
+
+impl TableSpec {
+    fn table(&self, db_type: &DBType) -> String {
+        match db_type {
+            DBType::MySQL(_) => self.name.into(),
+            DBType::Postgres => env::var(self.pg_var).unwrap_or_else(|_| self.name.into()),
+            DBType::DynamoDB(_) => self.name.into(),
+        }
+    }
+}
+
+async fn columns(db_type: &DBType, db_url: &str, table: &str) -> Result<Vec<String>, String> {
+    match db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, (String,)>(
+            r#"SELECT CAST(column_name AS CHAR) FROM information_schema.columns
+               WHERE table_schema = DATABASE() AND table_name = ?"#,
+        )
+            .bind(table)
+            .fetch_all(pool)
+            .await
+            .map(|rows| rows.into_iter().map(|(column,)| column).collect())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            client
+                .query(
+                    r#"SELECT column_name::text FROM information_schema.columns
+                       WHERE table_schema = current_schema() AND table_name = $1"#,
+                    &[&table],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| row.get(0)).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn index_rows(db_type: &DBType, db_url: &str, table: &str) -> Result<Vec<IndexRow>, String> {
+    match db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, IndexRow>(
+            r#"SELECT CAST(index_name AS CHAR), CAST(column_name AS CHAR), non_unique = 0 FROM information_schema.statistics
+               WHERE table_schema = DATABASE() AND table_name = ?
+               ORDER BY index_name, seq_in_index"#,
+        )
+            .bind(table)
+            .fetch_all(pool)
+            .await
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            client
+                .query(
+                    r#"SELECT i.relname::text, a.attname::text, x.indisunique
+                       FROM pg_index x
+                       JOIN pg_class t ON t.oid = x.indrelid
+                       JOIN pg_class i ON i.oid = x.indexrelid
+                       CROSS JOIN LATERAL unnest(x.indkey) WITH ORDINALITY AS k(attnum, n)
+                       JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = k.attnum
+                       WHERE t.relname = $1 AND t.relnamespace = current_schema()::regnamespace
+                       ORDER BY i.relname, k.n"#,
+                    &[&table],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// column lists of the existing indexes, with their uniqueness
+async fn indexes(db_type: &DBType, db_url: &str, table: &str) -> Result<Vec<(Vec<String>, bool)>, String> {
+    let mut by_name: HashMap<String, (Vec<String>, bool)> = HashMap::new();
+
+    for (name, column, unique) in index_rows(db_type, db_url, table).await? {
+        let entry = by_name.entry(name).or_insert_with(|| (vec![], unique));
+        entry.0.push(column);
+    }
+
+    Ok(by_name.into_values().collect())
+}
+
+// the columns of a table after all migrations, by its default name
+pub fn expected_columns(name: &str) -> &'static [&'static str] {
+    TABLES.iter().find(|spec| spec.name == name).map(|spec| spec.columns).unwrap_or_default()
+}
+
+fn has_index(existing: &[(Vec<String>, bool)], columns: &[&str], unique: bool) -> bool {
+    existing.iter().any(|(existing, existing_unique)| {
+        (*existing_unique || !unique)
+            && existing.len() == columns.len()
+            && existing.iter().zip(columns.iter()).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    })
+}
+
+// everything the current code expects but the database lacks, one line per table, column or index
+pub async fn validate(db_type: &DBType, db_url: &str) -> Result<Vec<String>, String> {
+    let mut missing = vec![];
+
+    for spec in TABLES {
+        let table = spec.table(db_type);
+        let existing = columns(db_type, db_url, &table).await?;
+
+        if existing.is_empty() {
+            missing.push(format!("table {} (create it with the {} migrations)", table, spec.name));
+            continue;
+        }
+
+        for column in spec.columns {
+            if !existing.iter().any(|existing| existing.eq_ignore_ascii_case(column)) {
+                missing.push(format!("column {}.{}", table, column));
+            }
+        }
+
+        let existing = indexes(db_type, db_url, &table).await?;
+
+        for (columns, unique) in spec.indexes {
+            if !has_index(&existing, columns, *unique) {
+                let kind = if *unique { "unique index" } else { "index" };
+                missing.push(format!("{} on {} ({})", kind, table, columns.join(", ")));
+            }
+        }
+    }
+
+    Ok(missing)
+}
+
+// SCHEMA_CHECK=warn (default) logs what is missing, strict refuses to start, off skips the check.
+// The service never applies migrations itself, so this is the only place a stale schema shows up before the first bounce.
+// Returns whether the schema is complete, which holds back readiness otherwise.
+pub async fn check_on_boot(db_type: &DBType, db_url: &str) -> bool {
+    let mode = env::var("SCHEMA_CHECK").unwrap_or_else(|_| "warn".into());
+
+    if mode == "off" {
+        return true;
+    }
+
+    match validate(db_type, db_url).await {
+        Ok(missing) if missing.is_empty() => {
+            println!("✅ Database schema is up to date");
+            true
+        }
+        Ok(missing) => {
+            println!("🚨 Database schema is missing {} item(s), apply the pending migrations:", missing.len());
+            for item in &missing {
+                println!("🚨   - {}", item);
+            }
+
+            if mode == "strict" {
+                println!("🔥 Refusing to start with an incomplete schema (SCHEMA_CHECK=strict)");
+                std::process::exit(1);
+            }
+
+            false
+        }
+        Err(err) => {
+            println!("🔥 Failed to inspect the database schema: {}", err);
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IndexStatus {
+    pub table: String,
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+    // required by the code, otherwise recommended
+    pub required: bool,
+    pub present: bool,
+    // the failure of the last create attempt, e.g. duplicates blocking a unique index
+    pub error: Option<String>,
+}
+
+// every required and recommended index of the existing tables, with whether it is there
+pub async fn index_report(db_type: &DBType, db_url: &str) -> Result<Vec<IndexStatus>, String> {
+    let mut report = vec![];
+
+    for spec in TABLES {
+        let table = spec.table(db_type);
+
+        if columns(db_type, db_url, &table).await?.is_empty() {
+            continue;
+        }
+
+        let existing = indexes(db_type, db_url, &table).await?;
+        let wanted = spec.indexes.iter().map(|index| (index, true)).chain(spec.recommended.iter().map(|index| (index, false)));
+
+        for ((columns, unique), required) in wanted {
+            report.push(IndexStatus {
+                name: format!("{}_{}_{}", spec.name, columns.join("_"), if *unique { "key" } else { "idx" }),
+                table: table.clone(),
+                columns: columns.iter().map(|column| column.to_string()).collect(),
+                unique: *unique,
+                required,
+                present: has_index(&existing, columns, *unique),
+                error: None,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+// online index builds, so the tables stay writable while SNS keeps delivering
+async fn create_index(db_type: &DBType, db_url: &str, index: &IndexStatus) -> Result<(), String> {
+    let unique = if index.unique { "UNIQUE " } else { "" };
+    let columns = index.columns.join(", ");
+
+    match db_type {
+        DBType::MySQL(pool) => sqlx::query(&format!(
+            r#"ALTER TABLE {table} ADD {unique}INDEX {name} ({columns}), ALGORITHM=INPLACE, LOCK=NONE"#,
+            table = index.table,
+            unique = unique,
+            name = index.name,
+            columns = columns
+        ))
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            client
+                .batch_execute(&format!(
+                    r#"CREATE {unique}INDEX CONCURRENTLY IF NOT EXISTS {name} ON {table} ({columns})"#,
+                    unique = unique,
+                    name = index.name,
+                    table = index.table,
+                    columns = columns
+                ))
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// creates the missing indexes one by one, a failure is reported on the index and does not stop the others
+pub async fn create_missing_indexes(db_type: &DBType, db_url: &str) -> Result<Vec<IndexStatus>, String> {
+    let mut report = index_report(db_type, db_url).await?;
+
+    for index in report.iter_mut().filter(|index| !index.present) {
+        println!("🚀 Creating index {} on {} ({})", index.name, index.table, index.columns.join(", "));
+
+        match create_index(db_type, db_url, index).await {
+            Ok(()) => index.present = true,
+            Err(err) => {
+                println!("🔥 Failed to create index {}: {}", index.name, err);
+                index.error = Some(err);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// `indexes [--create]` prints the index report, --create builds the missing ones first. Exits 1 while any is missing.
+pub async fn run_indexes(db_type: &DBType, db_url: &str, args: &[String]) -> Result<bool, String> {
+    let report = if args.iter().any(|arg| arg == "--create") {
+        create_missing_indexes(db_type, db_url).await?
+    } else {
+        index_report(db_type, db_url).await?
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+
+    Ok(report.iter().all(|index| index.present))
+}
+
+pub async fn indexes_handler(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    match index_report(&data.db_type, &data.db_url).await {
+        Ok(report) => HttpResponse::Ok().json(ListResponse::new(report)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to inspect the database schema: {}", err))),
+    }
+}
+
+pub async fn create_indexes_handler(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    match create_missing_indexes(&data.db_type, &data.db_url).await {
+        Ok(report) => HttpResponse::Ok().json(ListResponse::new(report)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to inspect the database schema: {}", err))),
+    }
+}
@@ -0,0 +1,133 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::StatusResponse;
+
+// the last migration this build was written against, bumped by every migration that records itself in schema_version
+pub const SCHEMA_VERSION: i32 = 35;
+
+// set when the schema is incompatible and SCHEMA_VERSION_MISMATCH=read_only, writes are refused from then on
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// (version, min_compatible) of the newest applied migration
+type VersionRow = (i32, i32);
+
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+fn table() -> String {
+    env::var("PG_SCHEMA_VERSION_TABLE").unwrap_or_else(|_| "schema_version".into())
+}
+
+fn is_missing_table(err: &str) -> bool {
+    err.contains("doesn't exist") || err.contains("does not exist")
+}
+
+// None while the schema_version migration has not run
+async fn current(db_type: &DBType, db_url: &str) -> Result<Option<VersionRow>, String> {
+    let result = match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, VersionRow>("SELECT version, min_compatible FROM schema_version ORDER BY version DESC LIMIT 1")
+                .fetch_optional(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(
+                    &format!("SELECT version, min_compatible FROM {} ORDER BY version DESC LIMIT 1", table()),
+                    &[],
+                )
+                .await
+                .map(|row| row.map(|row| (row.get(0), row.get(1))))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    match result {
+        Err(err) if is_missing_table(&err) => Ok(None),
+        result => result,
+    }
+}
+
+// why this build cannot run against the schema, None when it can. Every migration records its version and the
+// oldest build version still safe to write with it, so additive migrations can go out before the new code
+// (blue/green) while a breaking one stops the old instances.
+fn incompatibility(row: Option<VersionRow>) -> Option<String> {
+    match row {
+        None => Some(format!(
+            "the database has no schema_version, apply the migrations up to {} first",
+            SCHEMA_VERSION
+        )),
+        Some((version, _)) if version < SCHEMA_VERSION => Some(format!(
+            "the database schema is at version {} but this build needs {}, apply the pending migrations first",
+            version, SCHEMA_VERSION
+        )),
+        Some((version, min_compatible)) if min_compatible > SCHEMA_VERSION => Some(format!(
+            "the database schema is at version {} which needs builds of version {} or newer, this build is at {}",
+            version, min_compatible, SCHEMA_VERSION
+        )),
+        Some(_) => None,
+    }
+}
+
+// SCHEMA_VERSION_MISMATCH=refuse (default) exits on an incompatible schema, read_only serves lookups but refuses
+// writes, ignore only logs. The maintenance commands skip the check.
+pub async fn check_on_boot(db_type: &DBType, db_url: &str) {
+    let mode = env::var("SCHEMA_VERSION_MISMATCH").unwrap_or_else(|_| "refuse".into());
+
+    // the DynamoDB items have no migrations to be compatible with
+    if let DBType::DynamoDB(_) = db_type {
+        return;
+    }
+
+    let row = match current(db_type, db_url).await {
+        Ok(row) => row,
+        Err(err) => {
+            println!("🔥 Failed to read the schema version: {}", err);
+            return;
+        }
+    };
+
+    let Some(reason) = incompatibility(row) else {
+        READ_ONLY.store(false, Ordering::Relaxed);
+        println!("✅ Database schema version {} is compatible", row.map(|(version, _)| version).unwrap_or_default());
+        return;
+    };
+
+    match mode.as_str() {
+        "ignore" => println!("🚨 Incompatible database schema, continuing anyway (SCHEMA_VERSION_MISMATCH=ignore): {}", reason),
+        "read_only" => {
+            println!("🚨 Incompatible database schema, serving read-only (SCHEMA_VERSION_MISMATCH=read_only): {}", reason);
+            READ_ONLY.store(true, Ordering::Relaxed);
+        }
+        _ => {
+            println!("🔥 Refusing to start with an incompatible database schema: {}", reason);
+            std::process::exit(1);
+        }
+    }
+}
+
+// refuses every request that may write while read-only, SNS redelivers the notifications once a compatible build serves them
+pub async fn guard(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    if read_only() && !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        let response = HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "60"))
+            .json(StatusResponse::error("read-only: the database schema is incompatible with this build"));
+
+        return Ok(req.into_response(response));
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+}
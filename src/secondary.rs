@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::env;
+
+use crate::blacklist;
+use crate::config::arg_value;
+use crate::repo::{build_pg_pool, dynamodb, mysql_connect_options, mysql_pool_options, DBType};
+
+// how many differing rows the consistency check prints per side
+const REPORT_LIMIT: usize = 20;
+
+// second backend written alongside the primary while migrating, e.g. SECONDARY_DB_TYPE=PG SECONDARY_DATABASE_URL=postgres://...
+// reads always go to the primary
+#[derive(Debug, Clone)]
+pub struct Secondary {
+    pub db_type: DBType,
+    pub db_url: String,
+}
+
+pub fn from_env() -> Option<Secondary> {
+    let db_url = env::var("SECONDARY_DATABASE_URL").ok().filter(|url| !url.is_empty())?;
+
+    let db_type = match env::var("SECONDARY_DB_TYPE").unwrap_or_else(|_| "PG".into()).as_str() {
+        "PG" => DBType::Postgres,
+        // lazy, so an unreachable secondary never blocks startup
+        "MYSQL" => match mysql_connect_options(&db_url) {
+            Ok(options) => DBType::MySQL(mysql_pool_options().connect_lazy_with(options)),
+            Err(err) => {
+                println!("🔥 Invalid SECONDARY_DATABASE_URL, dual writes are disabled: {:?}", err);
+                return None;
+            }
+        },
+        other => {
+            println!("🔥 Unsupported secondary database type: {}", other);
+            return None;
+        }
+    };
+
+    println!("🚀 Dual writes to the secondary {:?} database are enabled", env::var("SECONDARY_DB_TYPE").unwrap_or_else(|_| "PG".into()));
+
+    Some(Secondary { db_type, db_url })
+}
+
+async fn keys(db_type: &DBType, db_url: &str, domain_id: Option<i32>) -> Result<HashSet<(i64, String)>, String> {
+    let table = blacklist::table_for(db_type);
+
+    match db_type {
+        DBType::MySQL(pool) => {
+            let query = format!(
+                "SELECT domain_id, email FROM {table} WHERE ? IS NULL OR domain_id = ?",
+                table = table
+            );
+
+            sqlx::query_as::<_, (i64, String)>(&query)
+                .bind(domain_id)
+                .bind(domain_id)
+                .fetch_all(pool)
+                .await
+                .map(|rows| rows.into_iter().collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            let query = format!(
+                "SELECT domain_id, email FROM {table} WHERE $1::integer IS NULL OR domain_id = $1",
+                table = table
+            );
+
+            client
+                .query(&query, &[&domain_id])
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get::<_, i32>(0) as i64, row.get(1))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+fn report(label: &str, rows: &[&(i64, String)]) {
+    println!("{} {} row(s) only in the {} database", if rows.is_empty() { "✅" } else { "🚨" }, rows.len(), label);
+
+    for (domain_id, email) in rows.iter().take(REPORT_LIMIT) {
+        println!("🚨   - domain {} {}", domain_id, email);
+    }
+}
+
+// `consistency-check [--domain ID]` compares the suppressed (domain, email) pairs of both backends,
+// returns whether they match
+pub async fn consistency_check(
+    db_type: &DBType,
+    db_url: &str,
+    secondary: Option<&Secondary>,
+    args: &[String],
+) -> Result<bool, String> {
+    let secondary = secondary.ok_or("SECONDARY_DATABASE_URL is not configured")?;
+    let domain_id = match arg_value(args, "--domain") {
+        Some(value) => Some(value.parse::<i32>().map_err(|_| format!("invalid --domain: {}", value))?),
+        None => None,
+    };
+
+    let primary_keys = keys(db_type, db_url, domain_id).await?;
+    let secondary_keys = keys(&secondary.db_type, &secondary.db_url, domain_id).await?;
+
+    let mut only_primary: Vec<_> = primary_keys.difference(&secondary_keys).collect();
+    let mut only_secondary: Vec<_> = secondary_keys.difference(&primary_keys).collect();
+    only_primary.sort();
+    only_secondary.sort();
+
+    println!("Compared {} primary and {} secondary rows", primary_keys.len(), secondary_keys.len());
+    report("primary", &only_primary);
+    report("secondary", &only_secondary);
+
+    Ok(only_primary.is_empty() && only_secondary.is_empty())
+}
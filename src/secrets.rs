@@ -0,0 +1,163 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::ServerHandle;
+use serde::Deserialize;
+use url::Url;
+
+use crate::outbound;
+
+const DEFAULT_REFRESH_SECS: u64 = 300;
+// reading a local file costs nothing, Vault leases can be as short as an hour
+const DEFAULT_FILE_REFRESH_SECS: u64 = 30;
+
+// where the database credentials are kept instead of a plain DATABASE_URL
+#[derive(Debug, Clone)]
+pub enum SecretSource {
+    // DATABASE_SECRET_ID, a full URL or an RDS style JSON secret
+    SecretsManager(String),
+    // DATABASE_SSM_PARAMETER, a (SecureString) parameter holding the URL
+    Ssm(String),
+    // DATABASE_URL_FILE, a file kept current by e.g. a Vault Agent template or the secrets store CSI driver:
+    // a URL, an RDS style JSON or the {"username", "password"} of Vault dynamic credentials, bare or as the
+    // `data` of a Vault read response
+    File(String),
+}
+
+// Vault's read response wraps the credentials in `data`
+#[derive(Debug, Deserialize)]
+struct VaultResponse {
+    data: RdsSecret,
+}
+
+// the JSON written by the RDS managed rotation, a secret with only the password patches DATABASE_URL
+#[derive(Debug, Deserialize)]
+struct RdsSecret {
+    username: Option<String>,
+    password: String,
+    engine: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    dbname: Option<String>,
+}
+
+pub fn source() -> Option<SecretSource> {
+    if let Some(id) = env::var("DATABASE_SECRET_ID").ok().filter(|id| !id.is_empty()) {
+        return Some(SecretSource::SecretsManager(id));
+    }
+
+    if let Some(name) = env::var("DATABASE_SSM_PARAMETER").ok().filter(|name| !name.is_empty()) {
+        return Some(SecretSource::Ssm(name));
+    }
+
+    env::var("DATABASE_URL_FILE")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(SecretSource::File)
+}
+
+fn url_from_secret(secret: &str) -> Result<String, String> {
+    let secret = secret.trim();
+
+    if secret.contains("://") {
+        return Ok(secret.into());
+    }
+
+    let rds: RdsSecret = match serde_json::from_str::<VaultResponse>(secret) {
+        Ok(response) => response.data,
+        Err(_) => serde_json::from_str(secret).map_err(|err| format!("unsupported secret format: {}", err))?,
+    };
+
+    let mut url = match &rds.host {
+        Some(host) => {
+            let scheme = match rds.engine.as_deref() {
+                Some(engine) if engine.starts_with("postgres") => "postgres",
+                Some(_) => "mysql",
+                None if env::var("DB_TYPE").as_deref() == Ok("PG") => "postgres",
+                None => "mysql",
+            };
+            let port = rds.port.unwrap_or(if scheme == "postgres" { 5432 } else { 3306 });
+            let dbname = rds.dbname.clone().unwrap_or_default();
+
+            Url::parse(&format!("{}://{}:{}/{}", scheme, host, port, dbname)).map_err(|err| err.to_string())?
+        }
+        None => {
+            let base = env::var("DATABASE_URL").map_err(|_| "the secret has no host and DATABASE_URL is not set".to_string())?;
+            Url::parse(&base).map_err(|err| err.to_string())?
+        }
+    };
+
+    if let Some(username) = &rds.username {
+        url.set_username(username).map_err(|_| "invalid username in secret".to_string())?;
+    }
+    url.set_password(Some(&rds.password)).map_err(|_| "invalid password in secret".to_string())?;
+
+    Ok(url.to_string())
+}
+
+pub async fn resolve_database_url(source: &SecretSource) -> Result<String, String> {
+    let secret = match source {
+        SecretSource::SecretsManager(id) => aws_sdk_secretsmanager::Client::new(&outbound::aws_config().await)
+            .get_secret_value()
+            .secret_id(id)
+            .send()
+            .await
+            .map_err(|err| format!("failed to read secret {}: {}", id, err))?
+            .secret_string
+            .ok_or_else(|| format!("secret {} has no string value", id))?,
+        SecretSource::Ssm(name) => aws_sdk_ssm::Client::new(&outbound::aws_config().await)
+            .get_parameter()
+            .name(name)
+            .with_decryption(true)
+            .send()
+            .await
+            .map_err(|err| format!("failed to read parameter {}: {}", name, err))?
+            .parameter
+            .and_then(|parameter| parameter.value)
+            .ok_or_else(|| format!("parameter {} has no value", name))?,
+        SecretSource::File(path) => tokio::fs::read_to_string(path)
+            .await
+            .map_err(|err| format!("failed to read {}: {}", path, err))?,
+    };
+
+    url_from_secret(&secret)
+}
+
+// polls the secret every SECRET_REFRESH_SECS (300, 30 for a file) and stops the server gracefully once it rotated:
+// the requests in flight finish on the old pools, the listening socket stays open meanwhile so new connections
+// wait in its backlog, and the returned flag tells main to start again with the new credentials. A file that is
+// briefly missing or half written while it is rendered keeps the current credentials until the next poll.
+pub fn watch(source: SecretSource, current_url: String, handle: ServerHandle) -> Arc<AtomicBool> {
+    let rotated = Arc::new(AtomicBool::new(false));
+    let flag = rotated.clone();
+
+    let default_refresh = match source {
+        SecretSource::File(_) => DEFAULT_FILE_REFRESH_SECS,
+        _ => DEFAULT_REFRESH_SECS,
+    };
+    let refresh = env::var("SECRET_REFRESH_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(default_refresh);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(refresh)).await;
+
+            match resolve_database_url(&source).await {
+                Ok(url) if url != current_url => {
+                    println!("🔑 Database credentials rotated, restarting the server");
+                    flag.store(true, Ordering::SeqCst);
+                    handle.stop(true).await;
+                    break;
+                }
+                Ok(_) => {}
+                Err(err) => println!("🔥 Failed to refresh the database secret: {}", err),
+            }
+        }
+    });
+
+    rotated
+}
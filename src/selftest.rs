@@ -0,0 +1,130 @@
+use std::env;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::responses::ErrorResponse;
+use crate::schema;
+use crate::handlers::is_admin;
+use crate::outbound;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::AppState;
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CheckResult {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+fn check(name: &str, result: Result<String, String>) -> CheckResult {
+    match result {
+        Ok(detail) => CheckResult { name: name.into(), passed: true, detail },
+        Err(detail) => CheckResult { name: name.into(), passed: false, detail },
+    }
+}
+
+async fn check_database(db_type: &DBType, db_url: &str) -> Result<String, String> {
+    match db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query("SELECT 1").execute(pool).await.map_err(|err| err.to_string())?;
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            client.simple_query("SELECT 1").await.map_err(|err| err.to_string())?;
+        }
+        DBType::DynamoDB(store) => dynamodb::ping(store).await?,
+    }
+
+    Ok("connected".into())
+}
+
+async fn check_schema(db_type: &DBType, db_url: &str) -> Result<String, String> {
+    let missing = schema::validate(db_type, db_url).await?;
+
+    if missing.is_empty() {
+        Ok("all required tables, columns and indexes exist".into())
+    } else {
+        Err(format!("missing: {}", missing.join("; ")))
+    }
+}
+
+// the SigningCertURL of SNS messages points to the regional SNS endpoint
+async fn check_sns(client: &reqwest::Client) -> Result<String, String> {
+    let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".into());
+    let url = format!("https://sns.{}.amazonaws.com/", region);
+
+    client
+        .get(&url)
+        .send()
+        .await
+        .map(|response| format!("{} reachable ({})", url, response.status()))
+        .map_err(|err| format!("{} unreachable: {}", url, err))
+}
+
+async fn check_webhook(client: &reqwest::Client, url: &str) -> Result<String, String> {
+    let response = client
+        .post(url)
+        .json(&json!({"type": "self_test", "timestamp": Utc::now().to_rfc3339()}))
+        .send()
+        .await
+        .map_err(|err| format!("{} unreachable: {}", url, err))?;
+
+    if response.status().is_success() {
+        Ok(format!("{} answered {}", url, response.status()))
+    } else {
+        Err(format!("{} answered {}", url, response.status()))
+    }
+}
+
+pub async fn run(db_type: &DBType, db_url: &str) -> SelfTestReport {
+    let client = outbound::client_builder()
+        .timeout(CHECK_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+
+    let mut checks = vec![check("database", check_database(db_type, db_url).await)];
+
+    // DynamoDB only stores the suppressions, there are no SQL tables to check
+    if !matches!(db_type, DBType::DynamoDB(_)) {
+        checks.push(check("schema", check_schema(db_type, db_url).await));
+    }
+
+    checks.push(check("sns", check_sns(&client).await));
+
+    // the test webhook is only fired when a target is configured
+    if let Ok(url) = env::var("SELF_TEST_WEBHOOK_URL") {
+        checks.push(check("webhook", check_webhook(&client, &url).await));
+    }
+
+    SelfTestReport {
+        passed: checks.iter().all(|check| check.passed),
+        checks,
+    }
+}
+
+pub async fn self_test_handler(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let report = run(&data.db_type, &data.db_url).await;
+
+    if report.passed {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
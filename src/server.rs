@@ -0,0 +1,219 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpResponse, Responder};
+use tokio::task::JoinHandle;
+
+use crate::autoscaling;
+use crate::cache;
+use crate::complaint_review;
+use crate::daily_stats;
+use crate::lookup_audit;
+use crate::outbox;
+use crate::repo::{build_pg_pool, dynamodb, pool, DBType};
+use crate::responses::HealthResponse;
+use crate::schema;
+use crate::schema_version;
+use crate::ses_reconcile;
+use crate::sns_allowlist;
+use crate::sqs;
+use crate::subscriptions;
+use crate::AppState;
+
+// state behind the Kubernetes probes: /live answers as soon as the server is bound, /startup once the boot work
+// (schema check, audit partitions, cache warmup) is done and /ready once it is, READY_GRACE_SECS passed,
+// the schema is complete and the database answers
+#[derive(Debug, Default)]
+pub struct Probes {
+    started_at: OnceLock<Instant>,
+    schema_complete: AtomicBool,
+}
+
+impl Probes {
+    fn mark_started(&self) {
+        let _ = self.started_at.set(Instant::now());
+    }
+}
+
+// BIND_SOCKET serves on a Unix socket for a reverse proxy in the same pod, BIND_TCP=false then drops port 8000
+pub fn socket_path() -> Option<String> {
+    env::var("BIND_SOCKET").ok().filter(|path| !path.is_empty())
+}
+
+pub fn tcp_enabled() -> bool {
+    env::var("BIND_TCP").as_deref() != Ok("false") || socket_path().is_none()
+}
+
+// bound once by the first server and handed to the ones rebuilt after a credential rotation: while the old server
+// drains and the new one starts, connections wait in the backlog of the still open sockets instead of being refused
+pub struct Listeners {
+    pub tcp: Option<TcpListener>,
+    pub socket: Option<(String, UnixListener)>,
+}
+
+impl Listeners {
+    pub fn bind() -> io::Result<Self> {
+        let tcp = match tcp_enabled() {
+            true => Some(TcpListener::bind("0.0.0.0:8000")?),
+            false => None,
+        };
+
+        let socket = match socket_path() {
+            Some(path) => {
+                remove_stale_socket(&path)?;
+                let listener = UnixListener::bind(&path)?;
+                set_socket_mode(&path)?;
+                Some((path, listener))
+            }
+            None => None,
+        };
+
+        Ok(Listeners { tcp, socket })
+    }
+}
+
+// a socket left behind by a previous run or a restart blocks the bind, anything else at the path is kept
+pub fn remove_stale_socket(path: &str) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+// BIND_SOCKET_MODE, octal permissions of the socket, e.g. 660 to let the proxy's group connect
+pub fn set_socket_mode(path: &str) -> io::Result<()> {
+    let Ok(mode) = env::var("BIND_SOCKET_MODE") else {
+        return Ok(());
+    };
+
+    let mode = u32::from_str_radix(mode.trim(), 8)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid BIND_SOCKET_MODE {:?}: {}", mode, err)))?;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+fn grace_period() -> Duration {
+    Duration::from_secs(env::var("READY_GRACE_SECS").ok().and_then(|secs| secs.parse().ok()).unwrap_or(0))
+}
+
+fn probe_response(ok: bool, message: impl Into<String>) -> HttpResponse {
+    let body = HealthResponse {
+        status: if ok { "ok".into() } else { "unavailable".into() },
+        message: message.into(),
+    };
+
+    if ok {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+// the boot work, run once the server is bound so the probes can answer meanwhile.
+// Returns the background tasks to stop when the server restarts.
+pub async fn startup(data: web::Data<AppState>, sqs_config: Option<sqs::Config>, probes: Arc<Probes>) -> Vec<JoinHandle<()>> {
+    // DynamoDB only stores the suppressions, the work on the SQL tables is left out
+    let relational = !matches!(data.db_type, DBType::DynamoDB(_));
+
+    let schema_complete = !relational || schema::check_on_boot(&data.db_type, &data.db_url).await;
+    probes.schema_complete.store(schema_complete, Ordering::SeqCst);
+
+    // a read-only instance runs no writing background work and leaves the queue to a compatible build
+    let read_only = schema_version::read_only();
+    let mut tasks = vec![];
+    tasks.extend(sns_allowlist::start());
+    tasks.extend(autoscaling::start(data.clone()).await);
+
+    if let DBType::MySQL(pool) = &data.db_type {
+        tasks.extend(pool::start_shrinking("MySQL", pool.clone()));
+    }
+    if let Some(read_pool) = &data.read_pool {
+        tasks.extend(pool::start_shrinking("MySQL replica", read_pool.clone()));
+    }
+
+    if !read_only && relational {
+        tasks.extend(lookup_audit::start(&data.db_type, &data.db_url).await);
+        tasks.extend(daily_stats::start(&data.db_type, &data.db_url).await);
+        tasks.extend(ses_reconcile::start(data.clone()));
+        tasks.extend(outbox::start(data.clone()));
+        tasks.extend(complaint_review::start(data.clone()));
+    }
+
+    if relational {
+        cache::warm(&data.cache, &data.db_type, &data.db_url).await;
+    }
+
+    if !read_only && relational {
+        match subscriptions::confirm_pending(&data).await {
+            Ok(summary) if summary.confirmed + summary.failed + summary.expired > 0 => {
+                println!("Retried pending SNS subscription confirmations: {:?}", summary);
+            }
+            Ok(_) => {}
+            Err(err) => println!("🔥 Failed to retry the pending SNS subscription confirmations: {}", err),
+        }
+    }
+
+    if let Some(config) = sqs_config.filter(|_| !read_only) {
+        tasks.push(sqs::spawn(config, data.clone()).await);
+    }
+
+    probes.mark_started();
+    println!("✅ Startup complete");
+
+    tasks
+}
+
+async fn ping(db_type: &DBType, db_url: &str) -> Result<(), String> {
+    match db_type {
+        DBType::MySQL(pool) => sqlx::query("SELECT 1").execute(pool).await.map(|_| ()).map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(db_url).await.map_err(|err| err.to_string())?;
+            client.simple_query("SELECT 1").await.map(|_| ()).map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(store) => dynamodb::ping(store).await,
+    }
+}
+
+pub async fn live_handler() -> impl Responder {
+    probe_response(true, "alive")
+}
+
+pub async fn startup_handler(probes: web::Data<Probes>) -> impl Responder {
+    match probes.started_at.get() {
+        Some(_) => probe_response(true, "started"),
+        None => probe_response(false, "starting"),
+    }
+}
+
+pub async fn ready_handler(probes: web::Data<Probes>, data: web::Data<AppState>) -> impl Responder {
+    let Some(started_at) = probes.started_at.get() else {
+        return probe_response(false, "starting");
+    };
+
+    if started_at.elapsed() < grace_period() {
+        return probe_response(false, "in grace period");
+    }
+
+    // the migrations run outside the service, so a stale schema is checked again until it is complete
+    if !probes.schema_complete.load(Ordering::SeqCst) {
+        match schema::validate(&data.db_type, &data.db_url).await {
+            Ok(missing) if missing.is_empty() => probes.schema_complete.store(true, Ordering::SeqCst),
+            Ok(missing) => return probe_response(false, format!("schema is missing {} item(s)", missing.len())),
+            Err(err) => return probe_response(false, format!("failed to inspect the schema: {}", err)),
+        }
+    }
+
+    match ping(&data.db_type, &data.db_url).await {
+        Ok(()) => probe_response(true, "ready"),
+        Err(err) => probe_response(false, format!("database unavailable: {}", err)),
+    }
+}
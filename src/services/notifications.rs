@@ -0,0 +1,249 @@
+use std::future::Future;
+use std::sync::OnceLock;
+
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use regex::Regex;
+
+use crate::autoscaling;
+use crate::blacklist::{self, NewEntry};
+use crate::domain::SnsNotificationType::{Notification, SubscriptionConfirmation};
+use crate::domain::{Message, NotificationType};
+use crate::domains::{self, DomainSettings};
+use crate::events::{self, Event};
+use crate::notification_log;
+use crate::reputation;
+use crate::responses::StatusResponse;
+use crate::shadow;
+use crate::payload::{self, SnsPayload};
+use crate::strict;
+use crate::subscriptions;
+use crate::timestamps;
+use crate::AppState;
+
+const LIMITER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// runs `work` in a task of its own on the current worker and waits for it. Actix drops the future of a request
+// whose client disconnects, as SNS does after 15 seconds; in a task the notification is processed to the end
+// instead of leaving some of its recipients written and the others untracked. Err means the task panicked.
+pub async fn to_completion<T: 'static>(work: impl Future<Output = T> + 'static) -> Result<T, String> {
+    // the processing pipeline builds actix responses, which are not Send
+    actix_web::rt::spawn(work).await.map_err(|err| format!("notification processing did not complete: {}", err))
+}
+
+// runs a parsed SNS payload through the processing pipeline, Err means the payload could not be parsed
+pub async fn process_notification(
+    domain_id: i32,
+    payload: SnsPayload,
+    data: &web::Data<AppState>,
+) -> Result<HttpResponse, String> {
+    let notification = match payload {
+        SnsPayload::Raw(message) => return process_message(domain_id, message, data).await,
+        SnsPayload::Envelope(notification) => *notification,
+    };
+
+    println!("Received SNS notification: {:?}", notification);
+
+    match notification.type_field {
+        SubscriptionConfirmation => {
+            if notification.subscribe_url.is_none() {
+                return Err("subscription confirmation without SubscribeURL".into());
+            }
+
+            // a failed confirmation is stored and retried, SNS gets its answer either way
+            let _ = subscriptions::handle_confirmation(domain_id, &notification, data).await;
+
+            Ok(HttpResponse::Ok().body("ok"))
+        }
+        Notification => {
+            let Some(message) = notification.message else {
+                return Err("notification without Message".into());
+            };
+
+            process_message(domain_id, message, data).await
+        }
+    }
+}
+
+async fn process_message(domain_id: i32, message: String, data: &web::Data<AppState>) -> Result<HttpResponse, String> {
+    let parsed = payload::parse_message(&message)
+        .inspect_err(|_| log::debug!("unparsable SES message for domain {}: {}", domain_id, message))?;
+
+    // only the types processed here are modeled completely
+    if matches!(parsed.notification_type, NotificationType::Bounce | NotificationType::Complaint) {
+        strict::check("SES message", &message, &parsed)?;
+    }
+
+    // the candidate pipeline reads the same message after the response, nothing of it is written
+    shadow::spawn(domain_id, &message, data);
+
+    notification_log::record(domain_id, &parsed, &message, data).await;
+
+    let message = parsed;
+
+    match message.notification_type {
+        NotificationType::Bounce => handle_bounce(message, domain_id, data.clone()).await,
+        NotificationType::Complaint => handle_complaint(message, domain_id, data.clone()).await,
+        // dead-lettered, so it can be replayed once the service knows the type
+        NotificationType::Other(other) => {
+            println!("🔥 Received unknown notification type: {}", other);
+            Err(format!("unknown notification type: {}", other))
+        }
+        _ => {
+            println!(
+                "Received unhandled notification type: {:?}",
+                message.notification_type
+            );
+            Ok(HttpResponse::Ok().body("ok"))
+        }
+    }
+}
+
+// having a str with: \"Desert Rose Florals, LLC\" <desertroseflorals@gmail.com>" extract only the email
+pub fn extract_email_address(input: &str) -> String {
+
+    // if no index of < or >, return the same string
+    if input.find("<").is_none() || input.find(">").is_none() {
+        return input.to_string();
+    }
+
+    static ANGLE_ADDR: OnceLock<Regex> = OnceLock::new();
+    let re = ANGLE_ADDR.get_or_init(|| Regex::new(r"<(.*)>").expect("valid regex"));
+    let caps = re.captures(input);
+
+    match caps {
+        Some(caps) => caps[1].to_string(),
+        None => input.to_string()
+    }
+}
+
+async fn handle_bounce(msg: Message, domain_id: i32, data: web::Data<AppState>) -> Result<HttpResponse, String> {
+    let reason = serde_json::to_string(&msg).unwrap_or_default();
+
+    match msg.bounce {
+        None => {
+            println!("Received bounce notification without bounce field: {:?}", msg);
+            Ok(HttpResponse::Ok().body("ok"))
+        }
+        Some(bounce) => {
+            timestamps::check("bounce", &bounce.feedback_id, bounce.timestamp)?;
+            autoscaling::observe_lag(bounce.timestamp);
+
+            let settings = domains::load(domain_id, &data).await;
+            let entries = blacklist::bounce_entries(domain_id, &bounce, msg.mail.as_ref(), &reason, &settings, Utc::now().naive_utc());
+
+            Ok(suppress(entries, "bounce", domain_id, &settings, &data).await)
+        }
+    }
+}
+
+async fn handle_complaint(msg: Message, domain_id: i32, data: web::Data<AppState>) -> Result<HttpResponse, String> {
+    let reason = serde_json::to_string(&msg).unwrap_or_default();
+
+    let Some(complaint) = &msg.complaint else {
+        return Err("complaint notification without complaint field".into());
+    };
+
+    timestamps::check("complaint", &complaint.feedback_id, complaint.timestamp)?;
+    autoscaling::observe_lag(complaint.timestamp);
+
+    let settings = domains::load(domain_id, &data).await;
+    let entries = blacklist::complaint_entries(domain_id, complaint, msg.mail.as_ref(), &reason, &settings)?;
+
+    events::publish(
+        &data,
+        Event::ComplaintReceived {
+            domain_id,
+            feedback_type: complaint.complaint_feedback_type.clone(),
+            recipients: entries.len(),
+        },
+    );
+
+    let response = suppress(entries, "complaint", domain_id, &settings, &data).await;
+    reputation::check_complaint_rate(domain_id, &data).await;
+
+    Ok(response)
+}
+
+// writes the entries of one notification, `event_type` is bounce or complaint
+async fn suppress(
+    entries: Vec<NewEntry>,
+    event_type: &str,
+    domain_id: i32,
+    settings: &DomainSettings,
+    data: &web::Data<AppState>,
+) -> HttpResponse {
+    // SNS gives up after 15 seconds, answering 503 before that makes it redeliver later
+    let Some(_permit) = data.limiter.acquire(domain_id, settings.concurrency_limit(), LIMITER_TIMEOUT).await else {
+        println!("Too many concurrent notifications for domain: {}", domain_id);
+        return HttpResponse::ServiceUnavailable()
+            .json(StatusResponse::error(format!("too many concurrent notifications for domain: {}", domain_id)));
+    };
+    // held until the entries are written, so later events of the same recipients wait for this one
+    let _recipients = data.shards.lock_all(domain_id, entries.iter().map(|entry| entry.email.as_str())).await;
+    let mut suppressed: Vec<String> = vec![];
+    let mut duplicates: Vec<String> = vec![];
+    let mut failed: Option<String> = None;
+
+    // every recipient is tried, a failed write does not leave the ones after it unwritten. The redelivery of the
+    // notification then resumes with the failed ones, the others are duplicates by then.
+    for entry in entries {
+        if let Err(err) = blacklist::insert_or_reactivate(&entry, event_type, data).await {
+            if blacklist::is_duplicate(&err) {
+                println!("blacklist entry already exists for: {}", entry.email);
+                duplicates.push(entry.email);
+                continue;
+            }
+
+            println!("Failed to execute query for {}: {:?}", entry.email, err);
+            failed.get_or_insert(err);
+            continue;
+        }
+
+        suppressed.push(entry.email);
+    }
+
+    println!(
+        "Got {} notification: {:?} for domain: {}",
+        event_type, suppressed, domain_id
+    );
+
+    if let Some(err) = failed {
+        return HttpResponse::InternalServerError()
+            .json(StatusResponse::error(format!("{:?}", err)));
+    }
+
+    if !duplicates.is_empty() {
+        return blacklist::duplicate_response(format!("blacklist entry already exists for: {}", duplicates.join(", ")));
+    }
+
+    HttpResponse::Ok().json(StatusResponse::success())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_is_extracted_from_a_display_name() {
+        assert_eq!(
+            extract_email_address(r#""Desert Rose Florals, LLC" <desertroseflorals@gmail.com>"#),
+            "desertroseflorals@gmail.com"
+        );
+        assert_eq!(extract_email_address("Jane Doe <jane@example.com>"), "jane@example.com");
+        assert_eq!(extract_email_address("<jane@example.com>"), "jane@example.com");
+    }
+
+    #[test]
+    fn bare_address_is_kept() {
+        assert_eq!(extract_email_address("jane@example.com"), "jane@example.com");
+        assert_eq!(extract_email_address(""), "");
+    }
+
+    #[test]
+    fn unbalanced_brackets_are_kept() {
+        assert_eq!(extract_email_address("Jane <jane@example.com"), "Jane <jane@example.com");
+        assert_eq!(extract_email_address("jane@example.com>"), "jane@example.com>");
+        // the closing bracket comes first, nothing is enclosed
+        assert_eq!(extract_email_address("> jane@example.com <"), "> jane@example.com <");
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashSet;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+use crate::blacklist::{self, NewEntry, CATEGORY_COMPLAINT, CATEGORY_HARD_BOUNCE};
+use crate::handlers::is_admin;
+use crate::outbound;
+use crate::privacy;
+use crate::repo::{build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::responses::ErrorResponse;
+use crate::sigv4::{self, Target};
+use crate::AppState;
+
+const PAGE_SIZE: usize = 1000;
+const BATCH_SIZE: i64 = 10_000;
+// addresses listed per direction in the report
+const SAMPLE_SIZE: usize = 20;
+
+// (id, email) of a suppressed address
+type EmailRow = (i64, String);
+
+// SES_RECONCILE=report compares the blacklist of SES_RECONCILE_DOMAIN_ID with the account-level SES suppression
+// list every SES_RECONCILE_INTERVAL_SECS, to_ses / from_ses / both also resolve the differences in that direction.
+// Only hard bounces and complaints are compared, SES keeps nothing else.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Report,
+    ToSes,
+    FromSes,
+    Both,
+}
+
+impl Mode {
+    fn parse(value: &str) -> Option<Mode> {
+        Some(match value {
+            "report" => Mode::Report,
+            "to_ses" => Mode::ToSes,
+            "from_ses" => Mode::FromSes,
+            "both" => Mode::Both,
+            _ => return None,
+        })
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Report => "report",
+            Mode::ToSes => "to_ses",
+            Mode::FromSes => "from_ses",
+            Mode::Both => "both",
+        }
+    }
+
+    fn writes_ses(self) -> bool {
+        matches!(self, Mode::ToSes | Mode::Both)
+    }
+
+    fn writes_locally(self) -> bool {
+        matches!(self, Mode::FromSes | Mode::Both)
+    }
+}
+
+fn mode() -> Option<Mode> {
+    env::var("SES_RECONCILE").ok().and_then(|value| Mode::parse(&value))
+}
+
+fn number(var: &str, default: u64) -> u64 {
+    env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+fn domain_id() -> i32 {
+    number("SES_RECONCILE_DOMAIN_ID", 1) as i32
+}
+
+// pause between SES calls, the suppression API allows about one request per second
+fn throttle() -> Duration {
+    Duration::from_millis(number("SES_RECONCILE_THROTTLE_MS", 1100))
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct ReconcileReport {
+    pub mode: String,
+    pub domain_id: i32,
+    pub ses_suppressed: usize,
+    pub blacklisted: usize,
+    // suppressed by SES only, and a sample of them
+    pub missing_locally: usize,
+    pub missing_locally_sample: Vec<String>,
+    // blacklisted here only
+    pub missing_in_ses: usize,
+    pub missing_in_ses_sample: Vec<String>,
+    pub added_locally: usize,
+    pub added_to_ses: usize,
+    pub errors: Vec<String>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReconcileResponse {
+    pub success: bool,
+    pub data: ReconcileReport,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReconcileQuery {
+    // overrides SES_RECONCILE for this run, e.g. report for a dry run
+    pub mode: Option<String>,
+}
+
+fn last_report() -> &'static Mutex<Option<ReconcileReport>> {
+    static LAST: Mutex<Option<ReconcileReport>> = Mutex::new(None);
+    &LAST
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SuppressedDestination {
+    email_address: String,
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SuppressedPage {
+    #[serde(default)]
+    suppressed_destination_summaries: Vec<SuppressedDestination>,
+    next_token: Option<String>,
+}
+
+async fn region() -> Result<String, String> {
+    if let Ok(region) = env::var("SES_REGION") {
+        return Ok(region);
+    }
+
+    outbound::aws_config()
+        .await
+        .region()
+        .map(|region| region.to_string())
+        .ok_or_else(|| "no AWS region configured, set SES_REGION".into())
+}
+
+fn target(region: &str) -> Target {
+    Target {
+        url_prefix: format!("https://email.{}.amazonaws.com/", region),
+        region: region.into(),
+        service: "ses".into(),
+    }
+}
+
+// the SES v2 API signed with the ambient AWS credentials, like the SigV4 webhooks
+async fn call(target: &Target, method: reqwest::Method, path: &str, body: Option<String>) -> Result<String, String> {
+    let mut builder = outbound::client().request(method, format!("{}{}", target.url_prefix, path));
+
+    if let Some(body) = body {
+        builder = builder.header("Content-Type", "application/json").body(body);
+    }
+
+    let mut request = builder.build().map_err(|err| err.to_string())?;
+    sigv4::sign_request(&mut request, target).await?;
+
+    let response = outbound::client().execute(request).await.map_err(|err| err.to_string())?;
+    let status = response.status();
+    let text = response.text().await.map_err(|err| err.to_string())?;
+
+    if status.is_success() {
+        Ok(text)
+    } else {
+        Err(format!("SES answered {}: {}", status, text))
+    }
+}
+
+// every address of the account-level suppression list, lowercased, with its reason (BOUNCE or COMPLAINT)
+async fn ses_suppressed(target: &Target, throttle: Duration) -> Result<Vec<SuppressedDestination>, String> {
+    let mut destinations = vec![];
+    let mut next_token: Option<String> = None;
+
+    loop {
+        let mut path = format!("v2/email/suppression/addresses?PageSize={}", PAGE_SIZE);
+        if let Some(token) = &next_token {
+            path.push_str(&format!("&NextToken={}", url::form_urlencoded::byte_serialize(token.as_bytes()).collect::<String>()));
+        }
+
+        let page: SuppressedPage = serde_json::from_str(&call(target, reqwest::Method::GET, &path, None).await?)
+            .map_err(|err| format!("unexpected SES response: {}", err))?;
+
+        destinations.extend(page.suppressed_destination_summaries.into_iter().map(|destination| SuppressedDestination {
+            email_address: destination.email_address.trim().to_lowercase(),
+            reason: destination.reason,
+        }));
+
+        match page.next_token {
+            Some(token) => {
+                next_token = Some(token);
+                tokio::time::sleep(throttle).await;
+            }
+            None => return Ok(destinations),
+        }
+    }
+}
+
+// the active hard bounces and complaints of the domain, lowercased
+async fn blacklisted(domain_id: i32, data: &AppState) -> Result<HashSet<String>, String> {
+    let sql = |table: &str, postgres: bool| {
+        let (domain, after, limit) = if postgres { ("$1", "$2", "$3") } else { ("?", "?", "?") };
+        format!(
+            "SELECT id, email FROM {table} WHERE domain_id = {domain} AND id > {after} AND category IN ('{hard}', '{complaint}') AND status = 'active' AND (expires_at IS NULL OR expires_at > NOW()) ORDER BY id LIMIT {limit}",
+            table = table,
+            domain = domain,
+            after = after,
+            limit = limit,
+            hard = CATEGORY_HARD_BOUNCE,
+            complaint = CATEGORY_COMPLAINT
+        )
+    };
+
+    let mut emails = HashSet::new();
+    let mut after_id = 0;
+
+    loop {
+        let rows: Vec<EmailRow> = match &data.db_type {
+            DBType::MySQL(pool) => {
+                let sql = sql("blacklist", false);
+
+                read_mysql(data, pool, |pool| {
+                    let sql = sql.clone();
+                    async move {
+                        sqlx::query_as::<_, EmailRow>(&sql)
+                            .bind(domain_id)
+                            .bind(after_id)
+                            .bind(BATCH_SIZE)
+                            .fetch_all(&pool)
+                            .await
+                    }
+                })
+                    .await
+                    .map_err(|err| err.to_string())?
+            }
+            DBType::Postgres => {
+                let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+                client
+                    .query(&sql(&blacklist::table(), true), &[&domain_id, &after_id, &BATCH_SIZE])
+                    .await
+                    .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+                    .map_err(|err| err.to_string())?
+            }
+            DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+        };
+
+        emails.extend(rows.iter().map(|(_, email)| email.trim().to_lowercase()));
+
+        match rows.last() {
+            Some((id, _)) if rows.len() as i64 == BATCH_SIZE => after_id = *id,
+            _ => return Ok(emails),
+        }
+    }
+}
+
+fn sample(emails: &[&String]) -> Vec<String> {
+    emails.iter().take(SAMPLE_SIZE).map(|email| email.to_string()).collect()
+}
+
+fn local_entry(domain_id: i32, destination: &SuppressedDestination) -> NewEntry {
+    let category = if destination.reason == "COMPLAINT" { CATEGORY_COMPLAINT } else { CATEGORY_HARD_BOUNCE };
+
+    NewEntry {
+        domain_id,
+        email: destination.email_address.clone(),
+        reason: "ses_suppression_list".into(),
+        category: category.into(),
+        reason_summary: Some(format!("SES suppression list: {}", destination.reason.to_lowercase())),
+        ..Default::default()
+    }
+}
+
+async fn run(mode: Mode, domain_id: i32, throttle: Duration, data: &web::Data<AppState>) -> Result<ReconcileReport, String> {
+    // the hashed addresses cannot be compared with, or sent to, SES
+    if privacy::hashing_enabled() {
+        return Err("the blacklist stores hashed addresses (EMAIL_HASH_KEY), it cannot be compared with SES".into());
+    }
+
+    let target = target(&region().await?);
+    let ses = ses_suppressed(&target, throttle).await?;
+    let ses_emails: HashSet<&String> = ses.iter().map(|destination| &destination.email_address).collect();
+    let local = blacklisted(domain_id, data).await?;
+
+    let missing_locally: Vec<&SuppressedDestination> = ses
+        .iter()
+        .filter(|destination| !local.contains(&destination.email_address))
+        .collect();
+    let mut missing_in_ses: Vec<&String> = local.iter().filter(|email| !ses_emails.contains(email)).collect();
+    missing_in_ses.sort();
+
+    let mut report = ReconcileReport {
+        mode: mode.as_str().into(),
+        domain_id,
+        ses_suppressed: ses.len(),
+        blacklisted: local.len(),
+        missing_locally: missing_locally.len(),
+        missing_locally_sample: sample(&missing_locally.iter().map(|destination| &destination.email_address).collect::<Vec<_>>()),
+        missing_in_ses: missing_in_ses.len(),
+        missing_in_ses_sample: sample(&missing_in_ses),
+        ..Default::default()
+    };
+
+    if mode.writes_locally() {
+        for destination in &missing_locally {
+            match blacklist::insert(&local_entry(domain_id, destination), "blacklist", data).await {
+                Ok(()) => report.added_locally += 1,
+                // an entry that is not active (removed, allowlisted) is a decision made here, SES does not override it
+                Err(err) if blacklist::is_duplicate(&err) => {}
+                Err(err) => report.errors.push(format!("{}: {}", destination.email_address, err)),
+            }
+        }
+    }
+
+    if mode.writes_ses() {
+        for email in &missing_in_ses {
+            let body = serde_json::json!({ "EmailAddress": email, "Reason": "BOUNCE" }).to_string();
+
+            match call(&target, reqwest::Method::PUT, "v2/email/suppression/addresses", Some(body)).await {
+                Ok(_) => report.added_to_ses += 1,
+                Err(err) => report.errors.push(format!("{}: {}", email, err)),
+            }
+
+            tokio::time::sleep(throttle).await;
+        }
+    }
+
+    report.errors.truncate(SAMPLE_SIZE);
+    report.finished_at = Some(Utc::now().naive_utc());
+
+    println!(
+        "✅ SES reconciliation of domain {}: {} only in SES, {} only here, {} added here, {} added to SES",
+        domain_id, report.missing_locally, report.missing_in_ses, report.added_locally, report.added_to_ses
+    );
+
+    *last_report().lock().unwrap() = Some(report.clone());
+
+    Ok(report)
+}
+
+// the scheduled reconciliation, a no-op without SES_RECONCILE
+pub fn start(data: web::Data<AppState>) -> Option<JoinHandle<()>> {
+    let mode = mode()?;
+    let interval = Duration::from_secs(number("SES_RECONCILE_INTERVAL_SECS", 3600).max(60));
+
+    Some(tokio::spawn(async move {
+        loop {
+            if let Err(err) = run(mode, domain_id(), throttle(), &data).await {
+                println!("🔥 SES reconciliation failed: {}", err);
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    }))
+}
+
+pub async fn report_handler(req: HttpRequest) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    match last_report().lock().unwrap().clone() {
+        Some(report) => HttpResponse::Ok().json(ReconcileResponse { success: true, data: report }),
+        None => HttpResponse::NotFound().json(ErrorResponse::new("No SES reconciliation has run yet")),
+    }
+}
+
+// runs a reconciliation now, ?mode=report makes it a dry run
+pub async fn run_handler(
+    req: HttpRequest,
+    query: web::Query<ReconcileQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let Some(mode) = query.mode.as_deref().map(Mode::parse).unwrap_or(Some(mode().unwrap_or(Mode::Report))) else {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("mode must be report, to_ses, from_ses or both"));
+    };
+
+    match run(mode, domain_id(), throttle(), &data).await {
+        Ok(report) => HttpResponse::Ok().json(ReconcileResponse { success: true, data: report }),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 SES reconciliation failed: {}", err))),
+    }
+}
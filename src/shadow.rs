@@ -0,0 +1,327 @@
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::blacklist::{self, NewEntry};
+use crate::domain::{Message, NotificationType};
+use crate::domains::{self, DomainSettings};
+use crate::handlers::is_admin;
+use crate::metrics::{SHADOW_COMPARISONS, SHADOW_DISCREPANCIES};
+use crate::privacy;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::{ErrorResponse, ListResponse};
+use crate::rules;
+use crate::schema_version;
+use crate::AppState;
+
+const DEFAULT_LIMIT: i64 = 100;
+// both pipelines compute expires_at from their own clock
+const EXPIRY_TOLERANCE_SECS: i64 = 60;
+
+// SHADOW_MODE=true runs every bounce and complaint through a candidate pipeline next to the live one, after the
+// response. Only the live entries are written, the notifications the two read differently are kept in
+// shadow_discrepancies with both results, to review before the candidate replaces the live code.
+pub fn enabled() -> bool {
+    env::var("SHADOW_MODE").as_deref() == Ok("true") && !schema_version::read_only()
+}
+
+fn table() -> String {
+    env::var("PG_SHADOW_DISCREPANCIES_TABLE").unwrap_or_else(|_| "shadow_discrepancies".into())
+}
+
+// what a pipeline would write for one recipient, the fields a behavior change shows in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ShadowEntry {
+    email: String,
+    status: String,
+    category: String,
+    expires_at: Option<NaiveDateTime>,
+    reason_summary: Option<String>,
+    diagnostic_class: Option<String>,
+}
+
+impl From<NewEntry> for ShadowEntry {
+    fn from(entry: NewEntry) -> Self {
+        ShadowEntry {
+            status: entry.status().as_str().into(),
+            email: entry.email,
+            category: entry.category,
+            expires_at: entry.expires_at,
+            reason_summary: entry.reason_summary,
+            diagnostic_class: entry.diagnostic_class,
+        }
+    }
+}
+
+impl ShadowEntry {
+    fn same(&self, other: &ShadowEntry) -> bool {
+        let same_expiry = match (self.expires_at, other.expires_at) {
+            (Some(a), Some(b)) => (a - b).num_seconds().abs() <= EXPIRY_TOLERANCE_SECS,
+            (a, b) => a == b,
+        };
+
+        self.email == other.email
+            && self.status == other.status
+            && self.category == other.category
+            && self.reason_summary == other.reason_summary
+            && self.diagnostic_class == other.diagnostic_class
+            && same_expiry
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Entries(Vec<ShadowEntry>),
+    // the notification would fail, the wording of the error is not compared
+    Error(String),
+}
+
+impl Outcome {
+    fn new(result: Result<Vec<NewEntry>, String>) -> Self {
+        match result {
+            Ok(entries) => {
+                let mut entries: Vec<ShadowEntry> = entries.into_iter().map(ShadowEntry::from).collect();
+                entries.sort_by(|a, b| a.email.cmp(&b.email));
+                Outcome::Entries(entries)
+            }
+            Err(err) => Outcome::Error(err),
+        }
+    }
+
+    fn same(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Entries(a), Outcome::Entries(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same(b))
+            }
+            (Outcome::Error(_), Outcome::Error(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+// the live pipeline from the SES message to the entries, as services::notifications runs it
+fn current(domain_id: i32, raw: &str, settings: &DomainSettings) -> Result<Vec<NewEntry>, String> {
+    let message: Message = serde_json::from_str(raw).map_err(|err| format!("invalid SES message: {}", err))?;
+    let reason = serde_json::to_string(&message).unwrap_or_default();
+    let mail = message.mail.as_ref();
+
+    match &message.notification_type {
+        NotificationType::Bounce => Ok(message
+            .bounce
+            .as_ref()
+            .map(|bounce| blacklist::bounce_entries(domain_id, bounce, mail, &reason, settings, Utc::now().naive_utc()))
+            .unwrap_or_default()),
+        NotificationType::Complaint => match &message.complaint {
+            Some(complaint) => blacklist::complaint_entries(domain_id, complaint, mail, &reason, settings),
+            None => Err("complaint notification without complaint field".into()),
+        },
+        _ => Ok(vec![]),
+    }
+}
+
+// the pipeline under evaluation, it must not write anything. A refactor of the parsing or the rules engine points
+// it at the new code; SHADOW_BOUNCE_RULES (a JSON array of bounce rules) tries a rule set on every domain in
+// place of its own.
+fn candidate(domain_id: i32, raw: &str, settings: &DomainSettings) -> Result<Vec<NewEntry>, String> {
+    match env::var("SHADOW_BOUNCE_RULES").ok().filter(|rules| !rules.is_empty()) {
+        Some(rules) => {
+            let settings = DomainSettings { bounce_rules: rules::parse_rules(&rules), ..settings.clone() };
+            current(domain_id, raw, &settings)
+        }
+        None => current(domain_id, raw, settings),
+    }
+}
+
+async fn record(
+    data: &web::Data<AppState>,
+    domain_id: i32,
+    message: &Message,
+    raw: &str,
+    current: &Outcome,
+    candidate: &Outcome,
+) -> Result<(), String> {
+    let notification_type = message.notification_type.as_str();
+    let message_id = message.mail.as_ref().map(|mail| mail.message_id.clone());
+    let current = serde_json::to_string(current).map_err(|err| err.to_string())?;
+    let candidate = serde_json::to_string(candidate).map_err(|err| err.to_string())?;
+    let raw = privacy::stored_text(raw);
+
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(
+            r#"INSERT INTO shadow_discrepancies (domain_id, notification_type, message_id, payload, current_result, candidate_result)
+               VALUES (?, ?, ?, ?, ?, ?)"#,
+        )
+            .bind(domain_id)
+            .bind(notification_type)
+            .bind(&message_id)
+            .bind(raw)
+            .bind(&current)
+            .bind(&candidate)
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {} (domain_id, notification_type, message_id, payload, current_result, candidate_result)
+                           VALUES ($1, $2, $3, $4, $5, $6)"#,
+                        table()
+                    ),
+                    &[&domain_id, &notification_type, &message_id, &raw, &current, &candidate],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn compare(domain_id: i32, raw: String, data: web::Data<AppState>) {
+    let Ok(message) = serde_json::from_str::<Message>(&raw) else {
+        return;
+    };
+    if !matches!(message.notification_type, NotificationType::Bounce | NotificationType::Complaint) {
+        return;
+    }
+
+    let settings = domains::load(domain_id, &data).await;
+    let current = Outcome::new(current(domain_id, &raw, &settings));
+    let candidate = Outcome::new(candidate(domain_id, &raw, &settings));
+
+    SHADOW_COMPARISONS.inc();
+    if current.same(&candidate) {
+        return;
+    }
+
+    SHADOW_DISCREPANCIES.inc();
+    println!("🔥 Shadow pipeline disagrees on a {} of domain {}", message.notification_type.as_str(), domain_id);
+
+    if let Err(err) = record(&data, domain_id, &message, &raw, &current, &candidate).await {
+        println!("🔥 Failed to record a shadow discrepancy for domain {}: {}", domain_id, err);
+    }
+}
+
+// compares the pipelines on the SES message in the background, a no-op unless SHADOW_MODE=true
+pub fn spawn(domain_id: i32, raw: &str, data: &web::Data<AppState>) {
+    if !enabled() {
+        return;
+    }
+
+    tokio::spawn(compare(domain_id, raw.to_string(), data.clone()));
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ShadowDiscrepancy {
+    pub id: i64,
+    pub domain_id: i32,
+    pub notification_type: String,
+    pub message_id: Option<String>,
+    // the SES message as received
+    pub payload: String,
+    // {"entries": [...]} or {"error": "..."}, what the live pipeline wrote
+    #[schema(value_type = Object)]
+    pub current: Value,
+    // the same for the candidate, nothing of it was written
+    #[schema(value_type = Object)]
+    pub candidate: Value,
+    pub created_at: NaiveDateTime,
+}
+
+// (id, domain_id, notification_type, message_id, payload, current_result, candidate_result, created_at)
+type DiscrepancyRow = (i64, i32, String, Option<String>, String, String, String, NaiveDateTime);
+
+impl From<DiscrepancyRow> for ShadowDiscrepancy {
+    fn from(row: DiscrepancyRow) -> Self {
+        let (id, domain_id, notification_type, message_id, payload, current, candidate, created_at) = row;
+
+        ShadowDiscrepancy {
+            id,
+            domain_id,
+            notification_type,
+            message_id,
+            payload,
+            current: serde_json::from_str(&current).unwrap_or(Value::String(current)),
+            candidate: serde_json::from_str(&candidate).unwrap_or(Value::String(candidate)),
+            created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiscrepancyQuery {
+    pub domain_id: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+async fn discrepancies(data: &web::Data<AppState>, domain_id: Option<i32>, limit: i64) -> Result<Vec<ShadowDiscrepancy>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query_as::<_, DiscrepancyRow>(
+            r#"SELECT id, domain_id, notification_type, message_id, payload, current_result, candidate_result, created_at
+               FROM shadow_discrepancies WHERE (? IS NULL OR domain_id = ?) ORDER BY id DESC LIMIT ?"#,
+        )
+            .bind(domain_id)
+            .bind(domain_id)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+            .map(|rows| rows.into_iter().map(ShadowDiscrepancy::from).collect())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, domain_id, notification_type, message_id, payload, current_result, candidate_result, created_at
+                           FROM {} WHERE ($1::int IS NULL OR domain_id = $1) ORDER BY id DESC LIMIT $2"#,
+                        table()
+                    ),
+                    &[&domain_id, &limit],
+                )
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| {
+                            ShadowDiscrepancy::from((
+                                row.get(0),
+                                row.get(1),
+                                row.get(2),
+                                row.get(3),
+                                row.get(4),
+                                row.get(5),
+                                row.get(6),
+                                row.get(7),
+                            ))
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// the latest discrepancies first, ?domain_id= for one domain
+pub async fn list_handler(req: HttpRequest, query: web::Query<DiscrepancyQuery>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, 1000);
+
+    match discrepancies(&data, query.domain_id, limit).await {
+        Ok(rows) => HttpResponse::Ok().json(ListResponse::new(rows)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::hash::{Hash, Hasher};
+
+use tokio::sync::{Mutex, MutexGuard};
+
+const DEFAULT_SHARDS: usize = 64;
+
+// serializes the work on one recipient: every (domain, normalized address) hashes to one of PROCESSING_SHARDS
+// queues, tokio mutexes being FIFO, so a bounce then a complaint, or a status change then a bounce, of the same
+// recipient are applied in the order they arrived while other recipients proceed in parallel. The order holds per
+// instance, across instances it takes a FIFO SQS queue grouped by recipient.
+#[derive(Debug)]
+pub struct RecipientShards {
+    shards: Vec<Mutex<()>>,
+}
+
+impl RecipientShards {
+    pub fn from_env() -> Self {
+        let count = env::var("PROCESSING_SHARDS")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(DEFAULT_SHARDS)
+            .max(1);
+
+        RecipientShards { shards: (0..count).map(|_| Mutex::new(())).collect() }
+    }
+
+    fn shard(&self, domain_id: i32, email: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        domain_id.hash(&mut hasher);
+        email.trim().to_lowercase().hash(&mut hasher);
+
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    pub async fn lock(&self, domain_id: i32, email: &str) -> MutexGuard<'_, ()> {
+        self.shards[self.shard(domain_id, email)].lock().await
+    }
+
+    // the queues of several recipients of a notification, taken in shard order so two notifications sharing
+    // recipients cannot wait on each other
+    pub async fn lock_all<'a>(&self, domain_id: i32, emails: impl Iterator<Item = &'a str>) -> Vec<MutexGuard<'_, ()>> {
+        let mut shards: Vec<usize> = emails.map(|email| self.shard(domain_id, email)).collect();
+        shards.sort_unstable();
+        shards.dedup();
+
+        let mut guards = Vec::with_capacity(shards.len());
+        for shard in shards {
+            guards.push(self.shards[shard].lock().await);
+        }
+
+        guards
+    }
+}
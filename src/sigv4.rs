@@ -0,0 +1,102 @@
+use std::env;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use aws_config::SdkConfig;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::sign::v4;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+
+use crate::outbound;
+
+// WEBHOOK_SIGV4_TARGETS signs the requests to matching URLs with the ambient AWS credentials, e.g. receivers
+// behind API Gateway with IAM auth:
+// [{"url_prefix": "https://abc123.execute-api.eu-west-1.amazonaws.com/", "region": "eu-west-1"}]
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub url_prefix: String,
+    pub region: String,
+    // the signing name of the receiving service
+    #[serde(default = "default_service")]
+    pub service: String,
+}
+
+fn default_service() -> String {
+    "execute-api".into()
+}
+
+fn targets() -> &'static [Target] {
+    static TARGETS: OnceLock<Vec<Target>> = OnceLock::new();
+
+    TARGETS.get_or_init(|| {
+        let Ok(value) = env::var("WEBHOOK_SIGV4_TARGETS") else {
+            return vec![];
+        };
+
+        match serde_json::from_str(&value) {
+            Ok(targets) => targets,
+            Err(err) => {
+                println!("🔥 Invalid WEBHOOK_SIGV4_TARGETS, requests are sent unsigned: {}", err);
+                vec![]
+            }
+        }
+    })
+}
+
+pub fn target_for(url: &str) -> Option<&'static Target> {
+    targets().iter().find(|target| url.starts_with(&target.url_prefix))
+}
+
+async fn sdk_config() -> &'static SdkConfig {
+    static CONFIG: OnceCell<SdkConfig> = OnceCell::const_new();
+    CONFIG.get_or_init(outbound::aws_config).await
+}
+
+// adds the Authorization, X-Amz-Date and, for temporary credentials, X-Amz-Security-Token headers
+pub async fn sign_request(request: &mut reqwest::Request, target: &Target) -> Result<(), String> {
+    let provider = sdk_config()
+        .await
+        .credentials_provider()
+        .ok_or("no AWS credentials available to sign the request")?;
+    let credentials = provider.provide_credentials().await.map_err(|err| err.to_string())?;
+    let identity = credentials.into();
+
+    let params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(&target.region)
+        .name(&target.service)
+        .time(SystemTime::now())
+        .settings(SigningSettings::default())
+        .build()
+        .map_err(|err| err.to_string())?
+        .into();
+
+    let body = request.body().and_then(|body| body.as_bytes()).unwrap_or_default();
+    let headers: Vec<(&str, &str)> = request
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str(), value)))
+        .collect();
+
+    let signable = SignableRequest::new(
+        request.method().as_str(),
+        request.url().as_str(),
+        headers.into_iter(),
+        SignableBody::Bytes(body),
+    )
+        .map_err(|err| err.to_string())?;
+
+    let (instructions, _) = sign(signable, &params).map_err(|err| err.to_string())?.into_parts();
+    let (headers, _) = instructions.into_parts();
+
+    for header in headers {
+        let name = HeaderName::from_bytes(header.name().as_bytes()).map_err(|err| err.to_string())?;
+        let value = HeaderValue::from_str(header.value()).map_err(|err| err.to_string())?;
+        request.headers_mut().insert(name, value);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use std::env;
+
+// https://docs.aws.amazon.com/ses/latest/dg/send-an-email-from-console.html#send-email-simulator
+pub const SIMULATOR_DOMAIN: &str = "simulator.amazonses.com";
+
+// how bounces of the SES mailbox simulator are treated, SES_SIMULATOR=exclude (default) or include
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatorMode {
+    // never suppressed and left out of the stats, for smoke tests against production
+    Exclude,
+    // handled like any other address
+    Include,
+}
+
+pub fn mode() -> SimulatorMode {
+    match env::var("SES_SIMULATOR").as_deref() {
+        Ok("include") => SimulatorMode::Include,
+        Ok("exclude") | Err(_) => SimulatorMode::Exclude,
+        Ok(other) => {
+            println!("🔥 Unknown SES_SIMULATOR value {:?}, excluding simulator addresses", other);
+            SimulatorMode::Exclude
+        }
+    }
+}
+
+// bounce@, ooto@, complaint@, suppressionlist@ and labeled variants like bounce+test@
+pub fn is_simulator_address(email: &str) -> bool {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.eq_ignore_ascii_case(SIMULATOR_DOMAIN))
+        .unwrap_or(false)
+}
+
+pub fn excluded(email: &str) -> bool {
+    mode() == SimulatorMode::Exclude && is_simulator_address(email)
+}
+
+// bound into the stats queries as `(? OR email NOT LIKE '%@simulator.amazonses.com')`,
+// so simulator rows stored before the exclusion are hidden as well
+pub fn include_in_stats() -> bool {
+    mode() == SimulatorMode::Include
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::Payload;
+use actix_web::web::Bytes;
+use actix_web::{error, FromRequest, HttpRequest};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures_util::future::LocalBoxFuture;
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::sign::Verifier;
+use openssl::x509::X509;
+use regex::Regex;
+
+use crate::domain::{SnsNotification, SnsNotificationType};
+use crate::metrics;
+use crate::outbound;
+use crate::payload::{decode_body, decode_text, parse, SnsPayload};
+
+// set by SNS on subscriptions with raw message delivery, the body is then the bare SES message
+const RAW_DELIVERY_HEADER: &str = "x-amz-sns-rawdelivery";
+
+const DEFAULT_CERT_TTL_SECS: u64 = 86_400;
+const DEFAULT_CERT_CACHE_SIZE: usize = 64;
+// a signing certificate is a couple of KiB
+const MAX_CERT_BYTES: usize = 64 * 1024;
+// the download happens while SNS waits for the answer
+const CERT_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// an SNS delivery whose envelope signature was checked. Forged or unverifiable ones are rejected by the
+// extractor, and so are the payloads that do not parse while signatures are checked: nothing proves they come
+// from SNS. Without verification they are kept as Err so the handler can dead-letter them.
+#[derive(Debug, Clone)]
+pub struct VerifiedSnsMessage {
+    pub body: Bytes,
+    pub payload: Result<SnsPayload, String>,
+    // the signature was checked, false for raw deliveries and with SNS_VERIFY_SIGNATURES=false
+    pub verified: bool,
+}
+
+pub fn verification_enabled() -> bool {
+    env::var("SNS_VERIFY_SIGNATURES").as_deref() != Ok("false")
+}
+
+// https://docs.aws.amazon.com/sns/latest/dg/sns-verify-signature-of-message.html
+fn string_to_sign(notification: &SnsNotification) -> Result<String, String> {
+    let field = |name: &str, value: &Option<String>| -> Result<String, String> {
+        value
+            .as_ref()
+            .map(|value| format!("{}\n{}\n", name, value))
+            .ok_or_else(|| format!("missing {} in signed message", name))
+    };
+
+    let type_field = match notification.type_field {
+        SnsNotificationType::Notification => "Notification",
+        SnsNotificationType::SubscriptionConfirmation => "SubscriptionConfirmation",
+    };
+
+    let mut canonical = field("Message", &notification.message)?;
+    canonical.push_str(&field("MessageId", &notification.message_id)?);
+
+    match notification.type_field {
+        SnsNotificationType::Notification => {
+            if notification.subject.is_some() {
+                canonical.push_str(&field("Subject", &notification.subject)?);
+            }
+            canonical.push_str(&field("Timestamp", &notification.timestamp)?);
+        }
+        SnsNotificationType::SubscriptionConfirmation => {
+            canonical.push_str(&field("SubscribeURL", &notification.subscribe_url)?);
+            canonical.push_str(&field("Timestamp", &notification.timestamp)?);
+            canonical.push_str(&field("Token", &notification.token)?);
+        }
+    }
+
+    canonical.push_str(&field("TopicArn", &notification.topic_arn)?);
+    canonical.push_str(&format!("Type\n{}\n", type_field));
+
+    Ok(canonical)
+}
+
+fn sns_host_regex() -> &'static Regex {
+    static HOST: OnceLock<Regex> = OnceLock::new();
+    HOST.get_or_init(|| Regex::new(r"^sns\.([a-z0-9-]+)\.amazonaws\.com(\.cn)?$").expect("valid regex"))
+}
+
+// SNS_REGIONS, e.g. us-east-1,eu-west-1, restricts the SNS endpoints trusted for signing certificates and
+// subscription URLs to those regions; every region when not set
+fn trusted_regions() -> Option<Vec<String>> {
+    let regions: Vec<String> = env::var("SNS_REGIONS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|region| region.trim().to_lowercase())
+        .filter(|region| !region.is_empty())
+        .collect();
+
+    (!regions.is_empty()).then_some(regions)
+}
+
+// the region of an SNS regional endpoint URL
+fn sns_region(url: &url::Url) -> Option<String> {
+    let host = url.host_str()?;
+    sns_host_regex().captures(host).map(|caps| caps[1].to_string())
+}
+
+// an https URL of an SNS regional endpoint of a trusted region, on the default port and without credentials
+pub fn is_sns_url(url: &url::Url) -> bool {
+    if url.scheme() != "https" || url.port().is_some() || !url.username().is_empty() || url.password().is_some() {
+        return false;
+    }
+
+    match (sns_region(url), trusted_regions()) {
+        (Some(region), Some(trusted)) => trusted.contains(&region),
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+// the certificate must come from an SNS endpoint over https, otherwise anybody could sign messages. The endpoint
+// must also be in the region of the topic, a message of a us-east-1 topic is signed by sns.us-east-1.
+fn validate_cert_url(cert_url: &str, topic_arn: Option<&str>) -> Result<(), String> {
+    let url = url::Url::parse(cert_url).map_err(|err| format!("invalid SigningCertURL: {}", err))?;
+
+    if !is_sns_url(&url) || !url.path().ends_with(".pem") || url.query().is_some() {
+        return Err(format!("untrusted SigningCertURL: {}", cert_url));
+    }
+
+    let topic_region = topic_arn.and_then(|arn| arn.split(':').nth(3));
+    if let (Some(topic_region), Some(cert_region)) = (topic_region, sns_region(&url)) {
+        if topic_region != cert_region {
+            return Err(format!("SigningCertURL {} is not in the region of topic {}", cert_url, topic_arn.unwrap_or_default()));
+        }
+    }
+
+    Ok(())
+}
+
+struct CachedCert {
+    cert: X509,
+    fetched_at: Instant,
+}
+
+// SNS_CERT_CACHE_TTL_SECS, how long a fetched certificate is trusted before it is fetched again
+fn cert_ttl() -> Duration {
+    Duration::from_secs(
+        env::var("SNS_CERT_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_CERT_TTL_SECS),
+    )
+}
+
+// SNS_CERT_CACHE_SIZE, the certificates kept at most, the oldest is dropped to make room
+fn cert_cache_size() -> usize {
+    env::var("SNS_CERT_CACHE_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_CERT_CACHE_SIZE)
+        .max(1)
+}
+
+fn cert_cache() -> &'static Mutex<HashMap<String, CachedCert>> {
+    static CERTS: OnceLock<Mutex<HashMap<String, CachedCert>>> = OnceLock::new();
+    CERTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn cached_cert(cert_url: &str) -> Option<X509> {
+    let mut cache = cert_cache().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    match cache.get(cert_url) {
+        Some(cached) if cached.fetched_at.elapsed() < cert_ttl() && check_cert(&cached.cert).is_ok() => {
+            Some(cached.cert.clone())
+        }
+        Some(_) => {
+            cache.remove(cert_url);
+            None
+        }
+        None => None,
+    }
+}
+
+fn cache_cert(cert_url: &str, cert: &X509) {
+    let mut cache = cert_cache().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let ttl = cert_ttl();
+
+    cache.retain(|_, cached| cached.fetched_at.elapsed() < ttl);
+    while cache.len() >= cert_cache_size() {
+        let Some(oldest) = cache.iter().min_by_key(|(_, cached)| cached.fetched_at).map(|(url, _)| url.clone()) else {
+            break;
+        };
+        cache.remove(&oldest);
+    }
+
+    cache.insert(cert_url.into(), CachedCert { cert: cert.clone(), fetched_at: Instant::now() });
+}
+
+// a certificate issued to an SNS host and valid now
+fn check_cert(cert: &X509) -> Result<(), String> {
+    let now = Asn1Time::days_from_now(0).map_err(|err| err.to_string())?;
+
+    if cert.not_before() > now || cert.not_after() < now {
+        return Err("signing certificate is not valid now".into());
+    }
+
+    let common_name = cert
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().to_string().ok())
+        .unwrap_or_default();
+
+    static SUBJECT: OnceLock<Regex> = OnceLock::new();
+    let subject = SUBJECT.get_or_init(|| Regex::new(r"^sns(\.[a-z0-9-]+)?\.amazonaws\.com(\.cn)?$").expect("valid regex"));
+
+    if !subject.is_match(&common_name) {
+        return Err(format!("signing certificate issued to {:?}, not SNS", common_name));
+    }
+
+    Ok(())
+}
+
+async fn fetch_cert(cert_url: &str) -> Result<X509, String> {
+    let response = outbound::client()
+        .get(cert_url)
+        .timeout(CERT_FETCH_TIMEOUT)
+        .send()
+        .await
+        .map_err(|err| format!("failed to fetch signing certificate: {}", err))?;
+
+    if !response.status().is_success() {
+        return Err(format!("failed to fetch signing certificate: {}", response.status()));
+    }
+
+    let pem = response
+        .bytes()
+        .await
+        .map_err(|err| format!("failed to fetch signing certificate: {}", err))?;
+
+    if pem.len() > MAX_CERT_BYTES {
+        return Err(format!("signing certificate of {} bytes, over {}", pem.len(), MAX_CERT_BYTES));
+    }
+
+    let cert = X509::from_pem(&pem).map_err(|err| format!("invalid signing certificate: {}", err))?;
+    check_cert(&cert)?;
+
+    Ok(cert)
+}
+
+async fn signing_cert(cert_url: &str, topic_arn: Option<&str>) -> Result<X509, String> {
+    // checked before the cache too, SNS_REGIONS may have changed since the certificate was cached
+    if let Err(err) = validate_cert_url(cert_url, topic_arn) {
+        metrics::SNS_CERT_URLS_REJECTED.inc();
+        return Err(err);
+    }
+
+    if let Some(cert) = cached_cert(cert_url) {
+        return Ok(cert);
+    }
+
+    let cert = fetch_cert(cert_url).await.inspect_err(|_| metrics::SNS_CERT_FETCH_FAILURES.inc())?;
+    cache_cert(cert_url, &cert);
+
+    Ok(cert)
+}
+
+pub async fn verify(notification: &SnsNotification) -> Result<(), String> {
+    let digest = match notification.signature_version.as_deref() {
+        Some("1") => MessageDigest::sha1(),
+        Some("2") => MessageDigest::sha256(),
+        other => return Err(format!("unsupported SignatureVersion: {:?}", other)),
+    };
+
+    let cert_url = notification.signing_cert_url.as_deref().ok_or("missing SigningCertURL")?;
+    let signature = BASE64
+        .decode(notification.signature.as_deref().ok_or("missing Signature")?)
+        .map_err(|err| format!("invalid Signature: {}", err))?;
+
+    let cert = signing_cert(cert_url, notification.topic_arn.as_deref()).await?;
+    let key = cert.public_key().map_err(|err| err.to_string())?;
+    let canonical = string_to_sign(notification)?;
+
+    let mut verifier = Verifier::new(digest, &key).map_err(|err| err.to_string())?;
+    verifier.update(canonical.as_bytes()).map_err(|err| err.to_string())?;
+
+    match verifier.verify(&signature) {
+        Ok(true) => Ok(()),
+        _ => Err("signature does not match".into()),
+    }
+}
+
+impl FromRequest for VerifiedSnsMessage {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let raw_delivery = req
+            .headers()
+            .get(RAW_DELIVERY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let body = Bytes::from_request(req, payload);
+
+        Box::pin(async move {
+            let (body, repairs) = decode_text(decode_body(body.await?));
+            if repairs.bom_stripped {
+                metrics::SNS_BOM_STRIPPED.inc();
+            }
+            if repairs.lossy {
+                metrics::SNS_LOSSY_DECODED.inc();
+            }
+
+            let payload = parse(&body, raw_delivery);
+
+            let mut verified = false;
+
+            if verification_enabled() {
+                match &payload {
+                    Ok(SnsPayload::Envelope(notification)) => {
+                        if let Err(err) = verify(notification).await {
+                            println!("🔥 Rejected SNS notification: {}", err);
+                            return Err(error::ErrorForbidden(err));
+                        }
+                        verified = true;
+                    }
+                    // raw deliveries carry no signature
+                    Ok(SnsPayload::Raw(_)) if env::var("SNS_ALLOW_RAW_DELIVERY").as_deref() != Ok("true") => {
+                        println!("🔥 Rejected raw SNS delivery, set SNS_ALLOW_RAW_DELIVERY=true to accept them");
+                        return Err(error::ErrorForbidden("raw message delivery is not allowed"));
+                    }
+                    Ok(SnsPayload::Raw(_)) => {}
+                    // no signature can be checked, anyone could send it
+                    Err(err) => {
+                        println!("🔥 Rejected unparsable SNS notification: {}", err);
+                        return Err(error::ErrorBadRequest(err.clone()));
+                    }
+                }
+            }
+
+            Ok(VerifiedSnsMessage { body, payload, verified })
+        })
+    }
+}
+
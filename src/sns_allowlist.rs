@@ -0,0 +1,240 @@
+use std::env;
+use std::net::IpAddr;
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+
+use crate::handlers::client_ip;
+use crate::metrics;
+use crate::outbound;
+use crate::responses::StatusResponse;
+
+const DEFAULT_RANGES_URL: &str = "https://ip-ranges.amazonaws.com/ip-ranges.json";
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// an IPv4 or IPv6 network, IPv4 addresses are compared as IPv4-mapped IPv6
+#[derive(Debug, Clone, Copy)]
+struct Cidr {
+    network: u128,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(value: &str) -> Option<Self> {
+        let (address, prefix) = match value.trim().split_once('/') {
+            Some((address, prefix)) => (address.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+            None => (value.trim().parse::<IpAddr>().ok()?, None),
+        };
+
+        let prefix = match address {
+            IpAddr::V4(_) => 96 + prefix.unwrap_or(32).min(32),
+            IpAddr::V6(_) => prefix.unwrap_or(128).min(128),
+        };
+
+        Some(Cidr { network: bits(address) & mask(prefix), prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        bits(ip) & mask(self.prefix) == self.network
+    }
+}
+
+fn bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map(|ip| u128::from(ip.to_ipv6_mapped())).unwrap_or(u128::from(ip)),
+    }
+}
+
+fn mask(prefix: u32) -> u128 {
+    u128::MAX.checked_shl(128 - prefix).unwrap_or(0)
+}
+
+// SNS_IP_ALLOWLIST lists where SNS notifications may come from: `aws` for the published AWS ranges and/or CIDRs,
+// e.g. "aws,10.0.0.0/8". Unset, the SNS endpoints accept any source and rely on the signature verification alone.
+// SNS_IP_ALLOWLIST_MODE=report only logs the sources that would be refused.
+struct Config {
+    aws: bool,
+    cidrs: Vec<Cidr>,
+    report_only: bool,
+}
+
+fn config() -> &'static Option<Config> {
+    static CONFIG: OnceLock<Option<Config>> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let value = env::var("SNS_IP_ALLOWLIST").ok().filter(|value| !value.trim().is_empty())?;
+        let mut config = Config {
+            aws: false,
+            cidrs: vec![],
+            report_only: env::var("SNS_IP_ALLOWLIST_MODE").as_deref() == Ok("report"),
+        };
+
+        for source in value.split(',').map(str::trim).filter(|source| !source.is_empty()) {
+            if source == "aws" {
+                config.aws = true;
+            } else {
+                match Cidr::parse(source) {
+                    Some(cidr) => config.cidrs.push(cidr),
+                    None => println!("🔥 Ignored invalid SNS_IP_ALLOWLIST entry {:?}", source),
+                }
+            }
+        }
+
+        Some(config)
+    })
+}
+
+// the AWS ranges, None until the first download succeeded
+fn aws_ranges() -> &'static RwLock<Option<Vec<Cidr>>> {
+    static RANGES: OnceLock<RwLock<Option<Vec<Cidr>>>> = OnceLock::new();
+    RANGES.get_or_init(Default::default)
+}
+
+#[derive(Debug, Deserialize)]
+struct IpRanges {
+    prefixes: Vec<Ipv4Prefix>,
+    ipv6_prefixes: Vec<Ipv6Prefix>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ipv4Prefix {
+    ip_prefix: String,
+    region: String,
+    service: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ipv6Prefix {
+    ipv6_prefix: String,
+    region: String,
+    service: String,
+}
+
+// SNS has no service of its own in ip-ranges.json, it delivers from the AMAZON ranges. SNS_IP_RANGES_REGIONS
+// narrows them to the regions of the topics, e.g. "us-east-1,eu-west-1".
+async fn fetch_aws_ranges() -> Result<Vec<Cidr>, String> {
+    let url = env::var("SNS_IP_RANGES_URL").unwrap_or_else(|_| DEFAULT_RANGES_URL.into());
+    let regions: Vec<String> = env::var("SNS_IP_RANGES_REGIONS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|region| region.trim().to_string())
+        .filter(|region| !region.is_empty())
+        .collect();
+
+    let ranges: IpRanges = outbound::client()
+        .get(&url)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let wanted = |region: &str, service: &str| service == "AMAZON" && (regions.is_empty() || regions.iter().any(|wanted| wanted == region));
+
+    let ipv4 = ranges
+        .prefixes
+        .iter()
+        .filter(|prefix| wanted(&prefix.region, &prefix.service))
+        .filter_map(|prefix| Cidr::parse(&prefix.ip_prefix));
+    let ipv6 = ranges
+        .ipv6_prefixes
+        .iter()
+        .filter(|prefix| wanted(&prefix.region, &prefix.service))
+        .filter_map(|prefix| Cidr::parse(&prefix.ipv6_prefix));
+
+    let cidrs: Vec<Cidr> = ipv4.chain(ipv6).collect();
+
+    if cidrs.is_empty() {
+        return Err(format!("no AMAZON prefix in {} for regions {:?}", url, regions));
+    }
+
+    Ok(cidrs)
+}
+
+// downloads the AWS ranges, then again every SNS_IP_RANGES_REFRESH_SECS (a day by default), a no-op unless
+// SNS_IP_ALLOWLIST includes aws
+pub fn start() -> Option<JoinHandle<()>> {
+    if !config().as_ref()?.aws {
+        return None;
+    }
+
+    let refresh = Duration::from_secs(
+        env::var("SNS_IP_RANGES_REFRESH_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(86_400),
+    );
+
+    Some(tokio::spawn(async move {
+        loop {
+            let delay = match fetch_aws_ranges().await {
+                Ok(cidrs) => {
+                    println!("✅ Loaded {} AWS IP ranges for the SNS allow-list", cidrs.len());
+                    *aws_ranges().write().unwrap() = Some(cidrs);
+                    refresh
+                }
+                // the previous ranges stay in use
+                Err(err) => {
+                    println!("🔥 Failed to download the AWS IP ranges: {}", err);
+                    RETRY_DELAY
+                }
+            };
+
+            tokio::time::sleep(delay).await;
+        }
+    }))
+}
+
+fn allowed(config: &Config, ip: IpAddr) -> bool {
+    if config.cidrs.iter().any(|cidr| cidr.contains(ip)) {
+        return true;
+    }
+
+    if !config.aws {
+        return false;
+    }
+
+    match &*aws_ranges().read().unwrap() {
+        Some(cidrs) => cidrs.iter().any(|cidr| cidr.contains(ip)),
+        // fails open until the ranges are first downloaded, the signature verification still applies
+        None => {
+            println!("⚠️ AWS IP ranges not loaded yet, not checking SNS source {}", ip);
+            true
+        }
+    }
+}
+
+// refuses SNS notifications from sources outside the allow-list with 403
+pub async fn check(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(config) = config() else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+
+    let ip = client_ip(req.request());
+
+    if ip.is_some_and(|ip| allowed(config, ip)) {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    }
+
+    metrics::SNS_SOURCES_REFUSED.inc();
+    let source = ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".into());
+
+    if config.report_only {
+        println!("⚠️ SNS notification from {} outside the allow-list, accepted (SNS_IP_ALLOWLIST_MODE=report)", source);
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    }
+
+    println!("🚨 Refused SNS notification from {}, outside the allow-list", source);
+    let response = HttpResponse::Forbidden().json(StatusResponse::error("source address not allowed"));
+
+    Ok(req.into_response(response))
+}
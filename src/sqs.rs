@@ -0,0 +1,134 @@
+use std::env;
+use std::time::Duration;
+
+use actix_web::web;
+use tokio::task::JoinHandle;
+
+use crate::dead_letters;
+use crate::outbound;
+use crate::services::notifications::process_notification;
+use crate::payload::{self, SnsPayload};
+use crate::sns;
+use crate::AppState;
+
+// pause after a failed receive, so a missing permission does not turn into a busy loop
+const ERROR_BACKOFF: Duration = Duration::from_secs(5);
+
+// NOTIFICATION_SOURCE=sqs polls SQS_QUEUE_URL, a queue subscribed to the SNS topic of SQS_DOMAIN_ID, instead of
+// exposing the public SNS endpoint. Messages are deleted once processed or dead-lettered, server errors are left
+// for SQS to redeliver after the visibility timeout.
+pub struct Config {
+    queue_url: String,
+    domain_id: i32,
+    wait_secs: i32,
+    batch_size: i32,
+    // set when the subscription has raw message delivery enabled
+    raw_delivery: bool,
+}
+
+impl Config {
+    pub fn queue_url(&self) -> &str {
+        &self.queue_url
+    }
+}
+
+pub fn enabled() -> bool {
+    env::var("NOTIFICATION_SOURCE").as_deref() == Ok("sqs")
+}
+
+pub fn config() -> Result<Config, String> {
+    let number = |var: &str, default: i32| env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+
+    Ok(Config {
+        queue_url: env::var("SQS_QUEUE_URL").map_err(|_| "SQS_QUEUE_URL must be set with NOTIFICATION_SOURCE=sqs")?,
+        domain_id: env::var("SQS_DOMAIN_ID")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .ok_or("SQS_DOMAIN_ID must be set with NOTIFICATION_SOURCE=sqs")?,
+        // long polling, 20 seconds is the SQS maximum
+        wait_secs: number("SQS_WAIT_SECS", 20).clamp(0, 20),
+        batch_size: number("SQS_BATCH_SIZE", 10).clamp(1, 10),
+        raw_delivery: env::var("SQS_RAW_DELIVERY").as_deref() == Ok("true"),
+    })
+}
+
+// whether the message is done with and can be deleted
+async fn handle(config: &Config, body: &str, data: &web::Data<AppState>) -> bool {
+    let payload = payload::parse(body.as_bytes(), config.raw_delivery);
+
+    if sns::verification_enabled() {
+        if let Ok(SnsPayload::Envelope(notification)) = &payload {
+            if let Err(err) = sns::verify(notification).await {
+                println!("🔥 Rejected SNS notification from SQS: {}", err);
+                dead_letters::store(config.domain_id, body, &format!("signature verification failed: {}", err), data).await;
+                return true;
+            }
+        }
+    }
+
+    let result = match payload {
+        Ok(payload) => process_notification(config.domain_id, payload, data).await,
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(response) if response.status().is_server_error() => {
+            println!("SQS message left for redelivery, processing answered {}", response.status());
+            false
+        }
+        Ok(_) => true,
+        Err(err) => {
+            println!("Received SQS notification error: {} with body: {:?}", err, body);
+            dead_letters::store(config.domain_id, body, &err, data).await;
+            true
+        }
+    }
+}
+
+pub async fn spawn(config: Config, data: web::Data<AppState>) -> JoinHandle<()> {
+    let client = aws_sdk_sqs::Client::new(&outbound::aws_config().await);
+
+    println!("🚀 Polling {} for notifications of domain {}", config.queue_url, config.domain_id);
+
+    // the processing pipeline builds actix responses, which are not Send
+    actix_web::rt::spawn(async move {
+        loop {
+            let received = client
+                .receive_message()
+                .queue_url(&config.queue_url)
+                .max_number_of_messages(config.batch_size)
+                .wait_time_seconds(config.wait_secs)
+                .send()
+                .await;
+
+            let messages = match received {
+                Ok(output) => output.messages.unwrap_or_default(),
+                Err(err) => {
+                    println!("🔥 Failed to receive from {}: {}", config.queue_url, err);
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            for message in messages {
+                let (Some(body), Some(receipt_handle)) = (message.body(), message.receipt_handle()) else {
+                    continue;
+                };
+
+                if !handle(&config, body, &data).await {
+                    continue;
+                }
+
+                if let Err(err) = client
+                    .delete_message()
+                    .queue_url(&config.queue_url)
+                    .receipt_handle(receipt_handle)
+                    .send()
+                    .await
+                {
+                    println!("🔥 Failed to delete SQS message {:?}: {}", message.message_id(), err);
+                }
+            }
+        }
+    })
+}
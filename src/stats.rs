@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api_keys;
+use crate::handlers::is_admin;
+use crate::privacy;
+use crate::simulator;
+use crate::responses::{ErrorResponse, ListResponse};
+use crate::repo::{build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::AppState;
+
+const DEFAULT_LIMIT: i64 = 50;
+const TOP_DIAGNOSTIC_CODES: usize = 5;
+
+// (recipient_domain, bounces) and (recipient_domain, diagnostic_code, count) rows
+type DomainRow = (String, i64);
+type DiagnosticCodeRow = (String, String, i64);
+// (reporting_mta, remote_mta_ip, bounces)
+type MtaRow = (Option<String>, Option<String>, i64);
+// (source_arn, sending_account_id, bounces, hard_bounces)
+type IdentityRow = (Option<String>, Option<String>, i64, i64);
+// (diagnostic_class, bounces)
+type DiagnosticClassRow = (String, i64);
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DiagnosticCodeCount {
+    pub diagnostic_code: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RecipientDomainStats {
+    pub recipient_domain: String,
+    pub bounces: i64,
+    pub top_diagnostic_codes: Vec<DiagnosticCodeCount>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MtaStats {
+    pub reporting_mta: Option<String>,
+    pub remote_mta_ip: Option<String>,
+    pub bounces: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IdentityStats {
+    pub source_arn: Option<String>,
+    pub sending_account_id: Option<String>,
+    pub bounces: i64,
+    pub hard_bounces: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DiagnosticClassStats {
+    pub diagnostic_class: String,
+    pub bounces: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    pub limit: Option<i64>,
+    // json (default) or csv
+    pub format: Option<String>,
+}
+
+// quotes a CSV field when it contains a separator, a quote or a line break
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+async fn query_recipient_domains(
+    domain_id: i32,
+    limit: i64,
+    data: &web::Data<AppState>,
+) -> Result<Vec<RecipientDomainStats>, String> {
+    let include_simulator = simulator::include_in_stats();
+
+    let (domains, codes): (Vec<DomainRow>, Vec<DiagnosticCodeRow>) = match &data.db_type {
+        DBType::MySQL(pool) => {
+            let domains = read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, DomainRow>(
+                    r#"SELECT SUBSTRING_INDEX(email, '@', -1) AS recipient_domain, COUNT(*) AS bounces
+                       FROM blacklist WHERE domain_id = ? AND (? OR email NOT LIKE '%@simulator.amazonses.com')
+                       GROUP BY recipient_domain ORDER BY bounces DESC LIMIT ?"#,
+                )
+                    .bind(domain_id)
+                    .bind(include_simulator)
+                    .bind(limit)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())?;
+
+            let codes = read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, DiagnosticCodeRow>(
+                    r#"SELECT SUBSTRING_INDEX(email, '@', -1) AS recipient_domain, diagnostic_code, COUNT(*) AS count
+                       FROM blacklist WHERE domain_id = ? AND diagnostic_code IS NOT NULL AND (? OR email NOT LIKE '%@simulator.amazonses.com')
+                       GROUP BY recipient_domain, diagnostic_code ORDER BY count DESC"#,
+                )
+                    .bind(domain_id)
+                    .bind(include_simulator)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())?;
+
+            (domains, codes)
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            let domains = client
+                .query(
+                    &format!(
+                        r#"SELECT split_part(email, '@', 2) AS recipient_domain, COUNT(*) AS bounces
+                           FROM {table} WHERE domain_id = $1 AND ($3 OR email NOT LIKE '%@simulator.amazonses.com')
+                           GROUP BY recipient_domain ORDER BY bounces DESC LIMIT $2"#,
+                        table = table
+                    ),
+                    &[&domain_id, &limit, &include_simulator],
+                )
+                .await
+                .map_err(|err| err.to_string())?
+                .iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+
+            let codes = client
+                .query(
+                    &format!(
+                        r#"SELECT split_part(email, '@', 2) AS recipient_domain, diagnostic_code, COUNT(*) AS count
+                           FROM {table} WHERE domain_id = $1 AND diagnostic_code IS NOT NULL AND ($2 OR email NOT LIKE '%@simulator.amazonses.com')
+                           GROUP BY recipient_domain, diagnostic_code ORDER BY count DESC"#,
+                        table = table
+                    ),
+                    &[&domain_id, &include_simulator],
+                )
+                .await
+                .map_err(|err| err.to_string())?
+                .iter()
+                .map(|row| (row.get(0), row.get(1), row.get(2)))
+                .collect();
+
+            (domains, codes)
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    // codes are sorted by count, keep the first ones of every recipient domain
+    let mut top_codes: HashMap<String, Vec<DiagnosticCodeCount>> = HashMap::new();
+    for (recipient_domain, diagnostic_code, count) in codes {
+        let entry = top_codes.entry(recipient_domain).or_default();
+        if entry.len() < TOP_DIAGNOSTIC_CODES {
+            entry.push(DiagnosticCodeCount { diagnostic_code, count });
+        }
+    }
+
+    Ok(domains
+        .into_iter()
+        .map(|(recipient_domain, bounces)| RecipientDomainStats {
+            top_diagnostic_codes: top_codes.remove(&recipient_domain).unwrap_or_default(),
+            recipient_domain,
+            bounces,
+        })
+        .collect())
+}
+
+fn recipient_domains_csv(stats: &[RecipientDomainStats]) -> String {
+    let mut csv = String::from("recipient_domain,bounces,diagnostic_code,diagnostic_code_count\n");
+
+    for row in stats {
+        if row.top_diagnostic_codes.is_empty() {
+            csv.push_str(&format!("{},{},,\n", csv_field(&row.recipient_domain), row.bounces));
+        }
+
+        for code in &row.top_diagnostic_codes {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                csv_field(&row.recipient_domain),
+                row.bounces,
+                csv_field(&code.diagnostic_code),
+                code.count
+            ));
+        }
+    }
+
+    csv
+}
+
+pub async fn recipient_domain_stats(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<StatsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    if let Err(response) = api_keys::meter(&req, &data, domain_id).await {
+        return response;
+    }
+
+    // the domain is read off the stored address, a hash has none
+    if privacy::hashing_enabled() {
+        return HttpResponse::BadRequest()
+            .json(ErrorResponse::new("recipient domain stats are not available for hashed addresses (EMAIL_HASH_KEY)"));
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, 1000);
+
+    let stats = match query_recipient_domains(domain_id, limit, &data).await {
+        Ok(stats) => stats,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    match query.format.as_deref() {
+        Some("csv") => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"recipient-domains-{}.csv\"", domain_id),
+            ))
+            .body(recipient_domains_csv(&stats)),
+        _ => HttpResponse::Ok().json(ListResponse::new(stats)),
+    }
+}
+
+async fn query_mta(domain_id: i32, limit: i64, data: &web::Data<AppState>) -> Result<Vec<MtaStats>, String> {
+    let include_simulator = simulator::include_in_stats();
+
+    let rows: Vec<MtaRow> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, MtaRow>(
+                    r#"SELECT reporting_mta, remote_mta_ip, COUNT(*) AS bounces
+                       FROM blacklist WHERE domain_id = ? AND bounce_type IS NOT NULL AND (? OR email NOT LIKE '%@simulator.amazonses.com')
+                       GROUP BY reporting_mta, remote_mta_ip ORDER BY bounces DESC LIMIT ?"#,
+                )
+                    .bind(domain_id)
+                    .bind(include_simulator)
+                    .bind(limit)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())?
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT reporting_mta, remote_mta_ip, COUNT(*) AS bounces
+                           FROM {table} WHERE domain_id = $1 AND bounce_type IS NOT NULL AND ($3 OR email NOT LIKE '%@simulator.amazonses.com')
+                           GROUP BY reporting_mta, remote_mta_ip ORDER BY bounces DESC LIMIT $2"#,
+                        table = table
+                    ),
+                    &[&domain_id, &limit, &include_simulator],
+                )
+                .await
+                .map_err(|err| err.to_string())?
+                .iter()
+                .map(|row| (row.get(0), row.get(1), row.get(2)))
+                .collect()
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(|(reporting_mta, remote_mta_ip, bounces)| MtaStats { reporting_mta, remote_mta_ip, bounces })
+        .collect())
+}
+
+fn mta_csv(stats: &[MtaStats]) -> String {
+    let mut csv = String::from("reporting_mta,remote_mta_ip,bounces\n");
+
+    for row in stats {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            csv_field(row.reporting_mta.as_deref().unwrap_or_default()),
+            csv_field(row.remote_mta_ip.as_deref().unwrap_or_default()),
+            row.bounces
+        ));
+    }
+
+    csv
+}
+
+// bounces per reporting MTA (our SES sending side) and remote MTA IP (the receiving side)
+pub async fn mta_stats(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<StatsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    if let Err(response) = api_keys::meter(&req, &data, domain_id).await {
+        return response;
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, 1000);
+
+    let stats = match query_mta(domain_id, limit, &data).await {
+        Ok(stats) => stats,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    match query.format.as_deref() {
+        Some("csv") => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"mta-{}.csv\"", domain_id),
+            ))
+            .body(mta_csv(&stats)),
+        _ => HttpResponse::Ok().json(ListResponse::new(stats)),
+    }
+}
+
+async fn query_identities(domain_id: i32, limit: i64, data: &web::Data<AppState>) -> Result<Vec<IdentityStats>, String> {
+    let include_simulator = simulator::include_in_stats();
+
+    let rows: Vec<IdentityRow> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, IdentityRow>(
+                    r#"SELECT source_arn, sending_account_id, COUNT(*) AS bounces,
+                              CAST(SUM(CASE WHEN bounce_type = 'Permanent' THEN 1 ELSE 0 END) AS SIGNED) AS hard_bounces
+                       FROM blacklist WHERE domain_id = ? AND bounce_type IS NOT NULL AND (? OR email NOT LIKE '%@simulator.amazonses.com')
+                       GROUP BY source_arn, sending_account_id ORDER BY bounces DESC LIMIT ?"#,
+                )
+                    .bind(domain_id)
+                    .bind(include_simulator)
+                    .bind(limit)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())?
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT source_arn, sending_account_id, COUNT(*) AS bounces,
+                                  COUNT(*) FILTER (WHERE bounce_type = 'Permanent') AS hard_bounces
+                           FROM {table} WHERE domain_id = $1 AND bounce_type IS NOT NULL AND ($3 OR email NOT LIKE '%@simulator.amazonses.com')
+                           GROUP BY source_arn, sending_account_id ORDER BY bounces DESC LIMIT $2"#,
+                        table = table
+                    ),
+                    &[&domain_id, &limit, &include_simulator],
+                )
+                .await
+                .map_err(|err| err.to_string())?
+                .iter()
+                .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+                .collect()
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(|(source_arn, sending_account_id, bounces, hard_bounces)| IdentityStats {
+            source_arn,
+            sending_account_id,
+            bounces,
+            hard_bounces,
+        })
+        .collect())
+}
+
+fn identities_csv(stats: &[IdentityStats]) -> String {
+    let mut csv = String::from("source_arn,sending_account_id,bounces,hard_bounces\n");
+
+    for row in stats {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            csv_field(row.source_arn.as_deref().unwrap_or_default()),
+            csv_field(row.sending_account_id.as_deref().unwrap_or_default()),
+            row.bounces,
+            row.hard_bounces
+        ));
+    }
+
+    csv
+}
+
+// bounces per sending identity (mail.sourceArn) and account, to spot the identities with a bad list
+pub async fn identity_stats(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<StatsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let domain_id = path.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, 1000);
+
+    let stats = match query_identities(domain_id, limit, &data).await {
+        Ok(stats) => stats,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    match query.format.as_deref() {
+        Some("csv") => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"identities-{}.csv\"", domain_id),
+            ))
+            .body(identities_csv(&stats)),
+        _ => HttpResponse::Ok().json(ListResponse::new(stats)),
+    }
+}
+
+async fn query_diagnostic_classes(domain_id: i32, data: &web::Data<AppState>) -> Result<Vec<DiagnosticClassStats>, String> {
+    let include_simulator = simulator::include_in_stats();
+
+    let rows: Vec<DiagnosticClassRow> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| async move {
+                sqlx::query_as::<_, DiagnosticClassRow>(
+                    r#"SELECT diagnostic_class, COUNT(*) AS bounces
+                       FROM blacklist WHERE domain_id = ? AND diagnostic_class IS NOT NULL AND (? OR email NOT LIKE '%@simulator.amazonses.com')
+                       GROUP BY diagnostic_class ORDER BY bounces DESC"#,
+                )
+                    .bind(domain_id)
+                    .bind(include_simulator)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())?
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+            let table = env::var("PG_TABLE").unwrap_or_else(|_| "blacklist".into());
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT diagnostic_class, COUNT(*) AS bounces
+                           FROM {table} WHERE domain_id = $1 AND diagnostic_class IS NOT NULL AND ($2 OR email NOT LIKE '%@simulator.amazonses.com')
+                           GROUP BY diagnostic_class ORDER BY bounces DESC"#,
+                        table = table
+                    ),
+                    &[&domain_id, &include_simulator],
+                )
+                .await
+                .map_err(|err| err.to_string())?
+                .iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect()
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(|(diagnostic_class, bounces)| DiagnosticClassStats { diagnostic_class, bounces })
+        .collect())
+}
+
+fn diagnostic_classes_csv(stats: &[DiagnosticClassStats]) -> String {
+    let mut csv = String::from("diagnostic_class,bounces\n");
+
+    for row in stats {
+        csv.push_str(&format!("{},{}\n", csv_field(&row.diagnostic_class), row.bounces));
+    }
+
+    csv
+}
+
+// bounces per classified diagnostic (no_such_user, mailbox_full, timeout, policy_block, ...)
+pub async fn diagnostic_class_stats(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<StatsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let domain_id = path.into_inner();
+
+    if let Err(response) = api_keys::meter(&req, &data, domain_id).await {
+        return response;
+    }
+
+    let stats = match query_diagnostic_classes(domain_id, &data).await {
+        Ok(stats) => stats,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    match query.format.as_deref() {
+        Some("csv") => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"diagnostic-classes-{}.csv\"", domain_id),
+            ))
+            .body(diagnostic_classes_csv(&stats)),
+        _ => HttpResponse::Ok().json(ListResponse::new(stats)),
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::sync::{Mutex, OnceLock};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::metrics;
+
+// SES_PARSE_MODE=permissive (default) ignores the fields of SES payloads the models do not know, report logs each
+// new one once and counts the payloads carrying them, strict also rejects those payloads (dead-lettered, like serde's
+// deny_unknown_fields) for staging environments that should notice schema additions from AWS before production does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseMode {
+    Permissive,
+    Report,
+    Strict,
+}
+
+pub fn mode() -> ParseMode {
+    match env::var("SES_PARSE_MODE").as_deref() {
+        Ok("report") => ParseMode::Report,
+        Ok("strict") => ParseMode::Strict,
+        _ => ParseMode::Permissive,
+    }
+}
+
+fn walk(raw: &Value, known: &Value, path: &str, unknown: &mut BTreeSet<String>) {
+    match (raw, known) {
+        (Value::Object(raw), Value::Object(known)) => {
+            for (key, value) in raw {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+
+                match known.get(key) {
+                    Some(known) => walk(value, known, &child, unknown),
+                    None => {
+                        unknown.insert(child);
+                    }
+                }
+            }
+        }
+        (Value::Array(raw), Value::Array(known)) => {
+            for (value, known) in raw.iter().zip(known) {
+                walk(value, known, &format!("{}[]", path), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+// the paths of `raw` that `parsed` dropped, e.g. bounce.bouncedRecipients[].newField
+pub fn unknown_fields<T: Serialize>(raw: &str, parsed: &T) -> Vec<String> {
+    let (Ok(raw), Ok(known)) = (serde_json::from_str::<Value>(raw), serde_json::to_value(parsed)) else {
+        return vec![];
+    };
+
+    let mut unknown = BTreeSet::new();
+    walk(&raw, &known, "", &mut unknown);
+
+    unknown.into_iter().collect()
+}
+
+fn first_seen(path: &str) -> bool {
+    static SEEN: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    SEEN.get_or_init(Default::default).lock().unwrap().insert(path.to_string())
+}
+
+// compares a parsed `kind` payload with its JSON, Err in strict mode when it carries unknown fields
+pub fn check<T: Serialize>(kind: &str, raw: &str, parsed: &T) -> Result<(), String> {
+    let mode = mode();
+
+    if mode == ParseMode::Permissive {
+        return Ok(());
+    }
+
+    let unknown = unknown_fields(raw, parsed);
+
+    if unknown.is_empty() {
+        return Ok(());
+    }
+
+    metrics::SES_UNKNOWN_FIELDS.inc();
+
+    for path in unknown.iter().filter(|path| first_seen(path)) {
+        println!("🚨 Unknown field in {} payload: {}", kind, path);
+    }
+
+    match mode {
+        ParseMode::Strict => Err(format!("unknown fields in {} payload: {}", kind, unknown.join(", "))),
+        _ => Ok(()),
+    }
+}
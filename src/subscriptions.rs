@@ -0,0 +1,320 @@
+use std::env;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::domain::SnsNotification;
+use crate::handlers::is_admin;
+use crate::outbound;
+use crate::repo::{build_pg_pool, dynamodb, DBType};
+use crate::responses::{ErrorResponse, ListResponse};
+use crate::sns;
+use crate::AppState;
+
+const LIST_LIMIT: i64 = 100;
+// SNS confirmation tokens are valid for three days
+const TOKEN_TTL_HOURS: i64 = 72;
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_CONFIRMED: &str = "confirmed";
+const STATUS_EXPIRED: &str = "expired";
+
+// (id, domain_id, topic_arn, subscribe_url, created_at) of a pending confirmation
+type PendingRow = (i64, i32, Option<String>, String, NaiveDateTime);
+
+// a SubscriptionConfirmation received by the service, the SubscribeURL holding the token is not exposed
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct Subscription {
+    pub id: i64,
+    pub domain_id: i32,
+    pub topic_arn: Option<String>,
+    // pending until the SubscribeURL answered, expired once the token is too old to retry
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub confirmed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct ConfirmationSummary {
+    pub confirmed: usize,
+    pub failed: usize,
+    pub expired: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ConfirmationResponse {
+    pub success: bool,
+    pub data: ConfirmationSummary,
+}
+
+fn table() -> String {
+    env::var("PG_SNS_SUBSCRIPTIONS_TABLE").unwrap_or_else(|_| "sns_subscriptions".into())
+}
+
+async fn insert(domain_id: i32, topic_arn: Option<&str>, subscribe_url: &str, data: &AppState) -> Result<i64, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"INSERT INTO sns_subscriptions (domain_id, topic_arn, subscribe_url, status) VALUES (?,?,?,?)"#)
+                .bind(domain_id)
+                .bind(topic_arn)
+                .bind(subscribe_url)
+                .bind(STATUS_PENDING)
+                .execute(pool)
+                .await
+                .map(|result| result.last_insert_id() as i64)
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_one(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, topic_arn, subscribe_url, status) VALUES ($1,$2,$3,$4) RETURNING id"#,
+                        table = table()
+                    ),
+                    &[&domain_id, &topic_arn, &subscribe_url, &STATUS_PENDING],
+                )
+                .await
+                .map(|row| row.get(0))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// a successful confirmation settles every pending row of the topic, SNS may have sent the request more than once
+async fn mark_confirmed(id: i64, domain_id: i32, topic_arn: Option<&str>, data: &AppState) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"UPDATE sns_subscriptions SET status = ?, confirmed_at = NOW(), attempts = attempts + CASE WHEN id = ? THEN 1 ELSE 0 END, last_error = NULL
+                   WHERE id = ? OR (status = ? AND domain_id = ? AND topic_arn = ?)"#,
+            )
+                .bind(STATUS_CONFIRMED)
+                .bind(id)
+                .bind(id)
+                .bind(STATUS_PENDING)
+                .bind(domain_id)
+                .bind(topic_arn)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET status = $1, confirmed_at = NOW(), attempts = attempts + CASE WHEN id = $2 THEN 1 ELSE 0 END, last_error = NULL
+                           WHERE id = $2 OR (status = $3 AND domain_id = $4 AND topic_arn = $5)"#,
+                        table = table()
+                    ),
+                    &[&STATUS_CONFIRMED, &id, &STATUS_PENDING, &domain_id, &topic_arn],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// `status` stays pending after a failed attempt and becomes expired once the token is too old
+async fn mark_failed(id: i64, status: &str, error: &str, data: &AppState) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"UPDATE sns_subscriptions SET status = ?, attempts = attempts + 1, last_error = ? WHERE id = ?"#)
+                .bind(status)
+                .bind(error)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET status = $1, attempts = attempts + 1, last_error = $2 WHERE id = $3"#,
+                        table = table()
+                    ),
+                    &[&status, &error, &id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn pending(data: &AppState) -> Result<Vec<PendingRow>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, PendingRow>(
+                r#"SELECT id, domain_id, topic_arn, subscribe_url, created_at FROM sns_subscriptions WHERE status = ? ORDER BY id"#,
+            )
+                .bind(STATUS_PENDING)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"SELECT id, domain_id, topic_arn, subscribe_url, created_at FROM {table} WHERE status = $1 ORDER BY id"#,
+                        table = table()
+                    ),
+                    &[&STATUS_PENDING],
+                )
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4))).collect())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// only SNS endpoints are called, the URL comes from a stored message
+async fn visit(subscribe_url: &str) -> Result<(), String> {
+    let url = url::Url::parse(subscribe_url).map_err(|err| format!("invalid SubscribeURL: {}", err))?;
+
+    if !sns::is_sns_url(&url) {
+        return Err(format!("untrusted SubscribeURL: {}", subscribe_url));
+    }
+
+    outbound::client()
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+async fn attempt(id: i64, domain_id: i32, topic_arn: Option<&str>, subscribe_url: &str, data: &AppState) -> Result<(), String> {
+    match visit(subscribe_url).await {
+        Ok(()) => {
+            println!("✅ Confirmed the SNS subscription of domain {} to {:?}", domain_id, topic_arn);
+            mark_confirmed(id, domain_id, topic_arn, data).await
+        }
+        Err(err) => {
+            println!("🔥 Failed to confirm the SNS subscription of domain {} to {:?}: {}", domain_id, topic_arn, err);
+            mark_failed(id, STATUS_PENDING, &err, data).await?;
+            Err(err)
+        }
+    }
+}
+
+// keeps the confirmation before visiting the SubscribeURL, so one that fails (e.g. mid-deploy) is retried
+// at the next startup or through the admin endpoint instead of leaving the subscription pending for good
+pub async fn handle_confirmation(domain_id: i32, notification: &SnsNotification, data: &AppState) -> Result<(), String> {
+    let Some(subscribe_url) = &notification.subscribe_url else {
+        return Err("subscription confirmation without SubscribeURL".into());
+    };
+    let topic_arn = notification.topic_arn.as_deref();
+
+    println!("Confirming the SNS subscription of domain {} to {:?}", domain_id, topic_arn);
+
+    let id = match insert(domain_id, topic_arn, subscribe_url, data).await {
+        Ok(id) => id,
+        Err(err) => {
+            println!("🔥 Failed to store the subscription confirmation, it cannot be retried: {}", err);
+            return visit(subscribe_url).await;
+        }
+    };
+
+    attempt(id, domain_id, topic_arn, subscribe_url, data).await
+}
+
+// retries every pending confirmation, run at startup and by the admin endpoint
+pub async fn confirm_pending(data: &AppState) -> Result<ConfirmationSummary, String> {
+    let oldest = Utc::now().naive_utc() - Duration::hours(TOKEN_TTL_HOURS);
+    let mut summary = ConfirmationSummary::default();
+
+    for (id, domain_id, topic_arn, subscribe_url, created_at) in pending(data).await? {
+        if created_at < oldest {
+            mark_failed(id, STATUS_EXPIRED, "the confirmation token expired, resubscribe the endpoint", data).await?;
+            summary.expired += 1;
+            continue;
+        }
+
+        match attempt(id, domain_id, topic_arn.as_deref(), &subscribe_url, data).await {
+            Ok(()) => summary.confirmed += 1,
+            Err(_) => summary.failed += 1,
+        }
+    }
+
+    Ok(summary)
+}
+
+pub async fn list_subscriptions(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let columns = "id, domain_id, topic_arn, status, attempts, last_error, created_at, confirmed_at";
+
+    let query_result: Result<Vec<Subscription>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, Subscription>(&format!("SELECT {} FROM sns_subscriptions ORDER BY id DESC LIMIT ?", columns))
+                .bind(LIST_LIMIT)
+                .fetch_all(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => client
+                .query(&format!("SELECT {} FROM {} ORDER BY id DESC LIMIT $1", columns, table()), &[&LIST_LIMIT])
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| Subscription {
+                            id: row.get("id"),
+                            domain_id: row.get("domain_id"),
+                            topic_arn: row.get("topic_arn"),
+                            status: row.get("status"),
+                            attempts: row.get("attempts"),
+                            last_error: row.get("last_error"),
+                            created_at: row.get("created_at"),
+                            confirmed_at: row.get("confirmed_at"),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    match query_result {
+        Ok(subscriptions) => HttpResponse::Ok().json(ListResponse::new(subscriptions)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+pub async fn confirm_pending_handler(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    match confirm_pending(&data).await {
+        Ok(summary) => HttpResponse::Ok().json(ConfirmationResponse { success: true, data: summary }),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
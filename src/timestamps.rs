@@ -0,0 +1,64 @@
+use std::env;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::metrics;
+
+// SES stamps events when they happen, a few minutes ahead means a skewed clock on either side
+const DEFAULT_MAX_FUTURE_SECS: i64 = 300;
+// SNS gives up redelivering long before, older events come from replays
+const DEFAULT_MAX_AGE_DAYS: i64 = 30;
+
+// SES_TIMESTAMP_MAX_FUTURE_SECS and SES_TIMESTAMP_MAX_AGE_DAYS bound the event timestamps. SES_TIMESTAMP_SKEW=flag
+// (default) logs and counts events outside of them, reject fails them so they are dead-lettered for a replay.
+struct Config {
+    max_future: Duration,
+    max_age: Duration,
+    reject: bool,
+}
+
+fn config() -> Config {
+    let number = |var: &str, default: i64| env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+
+    Config {
+        max_future: Duration::seconds(number("SES_TIMESTAMP_MAX_FUTURE_SECS", DEFAULT_MAX_FUTURE_SECS)),
+        max_age: Duration::days(number("SES_TIMESTAMP_MAX_AGE_DAYS", DEFAULT_MAX_AGE_DAYS)),
+        reject: env::var("SES_TIMESTAMP_SKEW").as_deref() == Ok("reject"),
+    }
+}
+
+// what is wrong with the timestamp, None when it is within the bounds. `kind` and `id` name the event, e.g. bounce
+// and its feedback id
+pub fn problem(kind: &str, id: &str, timestamp: DateTime<Utc>) -> Option<String> {
+    let config = config();
+    let now = Utc::now();
+
+    if timestamp > now + config.max_future {
+        Some(format!("{} {} is stamped {} in the future", kind, id, timestamp))
+    } else if timestamp < now - config.max_age {
+        Some(format!("{} {} is stamped {}, older than {} days", kind, id, timestamp, config.max_age.num_days()))
+    } else {
+        None
+    }
+}
+
+// whether a timestamp with a problem fails the event
+pub fn rejects() -> bool {
+    config().reject
+}
+
+pub fn check(kind: &str, id: &str, timestamp: DateTime<Utc>) -> Result<(), String> {
+    let Some(problem) = problem(kind, id, timestamp) else {
+        return Ok(());
+    };
+
+    metrics::SES_TIMESTAMPS_SKEWED.inc();
+
+    if rejects() {
+        println!("🔥 Rejecting {}", problem);
+        return Err(problem);
+    }
+
+    println!("🚨 Clock skew: {}", problem);
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::handlers::is_admin;
+use crate::repo::{build_pg_pool, build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::responses::{ErrorResponse, ListResponse, StatusResponse};
+use crate::AppState;
+
+// how long a resolved topic is trusted, changes through the admin endpoints apply at once on this instance
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct TopicMapping {
+    pub topic_arn: String,
+    pub domain_id: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct NewTopicMapping {
+    pub topic_arn: String,
+    pub domain_id: i32,
+}
+
+fn table() -> String {
+    env::var("PG_TOPIC_MAPPINGS_TABLE").unwrap_or_else(|_| "topic_mappings".into())
+}
+
+// topic ARN -> (domain, resolved at), unmapped topics are cached too so a misrouted topic does not hit the database
+type Resolved = Mutex<HashMap<String, (Option<i32>, Instant)>>;
+
+fn cache() -> &'static Resolved {
+    static CACHE: OnceLock<Resolved> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+pub fn is_topic_arn(topic_arn: &str) -> bool {
+    topic_arn.starts_with("arn:") && topic_arn.split(':').count() == 6 && topic_arn.split(':').nth(2) == Some("sns")
+}
+
+async fn query_domain(topic_arn: &str, data: &AppState) -> Result<Option<i32>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(data, pool, |pool| {
+                let topic_arn = topic_arn.to_string();
+                async move {
+                    sqlx::query_scalar::<_, i32>(r#"SELECT domain_id FROM topic_mappings WHERE topic_arn = ?"#)
+                        .bind(topic_arn)
+                        .fetch_optional(&pool)
+                        .await
+                }
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_read_client(data).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(&format!(r#"SELECT domain_id FROM {} WHERE topic_arn = $1"#, table()), &[&topic_arn])
+                .await
+                .map(|row| row.map(|row| row.get(0)))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// drops the resolved domain of the topic, after a change of its mapping
+pub fn forget(topic_arn: &str) {
+    cache().lock().unwrap().remove(topic_arn);
+}
+
+// the domain the notifications of `topic_arn` belong to, None when the topic is not mapped
+pub async fn domain_for(topic_arn: &str, data: &AppState) -> Result<Option<i32>, String> {
+    if let Some((domain_id, resolved_at)) = cache().lock().unwrap().get(topic_arn) {
+        if resolved_at.elapsed() < CACHE_TTL {
+            return Ok(*domain_id);
+        }
+    }
+
+    let domain_id = query_domain(topic_arn, data).await?;
+    cache().lock().unwrap().insert(topic_arn.to_string(), (domain_id, Instant::now()));
+
+    Ok(domain_id)
+}
+
+async fn upsert(mapping: &NewTopicMapping, data: &AppState) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(
+                r#"INSERT INTO topic_mappings (topic_arn, domain_id) VALUES (?, ?)
+                   ON DUPLICATE KEY UPDATE domain_id = VALUES(domain_id), updated_at = NOW()"#,
+            )
+                .bind(&mapping.topic_arn)
+                .bind(mapping.domain_id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (topic_arn, domain_id) VALUES ($1, $2)
+                           ON CONFLICT (topic_arn) DO UPDATE SET domain_id = EXCLUDED.domain_id, updated_at = NOW()"#,
+                        table = table()
+                    ),
+                    &[&mapping.topic_arn, &mapping.domain_id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn delete(topic_arn: &str, data: &AppState) -> Result<u64, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"DELETE FROM topic_mappings WHERE topic_arn = ?"#)
+                .bind(topic_arn)
+                .execute(pool)
+                .await
+                .map(|result| result.rows_affected())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(&format!(r#"DELETE FROM {} WHERE topic_arn = $1"#, table()), &[&topic_arn])
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+pub async fn list_mappings(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let columns = "topic_arn, domain_id, created_at, updated_at";
+
+    let query_result: Result<Vec<TopicMapping>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(&data, pool, |pool| async move {
+                sqlx::query_as::<_, TopicMapping>(&format!("SELECT {} FROM topic_mappings ORDER BY topic_arn", columns))
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_read_client(&data).await {
+            Ok(client) => client
+                .query(&format!("SELECT {} FROM {} ORDER BY topic_arn", columns, table()), &[])
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| TopicMapping {
+                            topic_arn: row.get("topic_arn"),
+                            domain_id: row.get("domain_id"),
+                            created_at: row.get("created_at"),
+                            updated_at: row.get("updated_at"),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    match query_result {
+        Ok(mappings) => HttpResponse::Ok().json(ListResponse::new(mappings)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+// maps a topic to a domain, or moves it to another one
+pub async fn put_mapping(
+    req: HttpRequest,
+    body: web::Json<NewTopicMapping>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let mapping = NewTopicMapping { topic_arn: body.topic_arn.trim().to_string(), domain_id: body.domain_id };
+
+    if !is_topic_arn(&mapping.topic_arn) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new("topic_arn must be an SNS topic ARN"));
+    }
+
+    match upsert(&mapping, &data).await {
+        Ok(()) => {
+            forget(&mapping.topic_arn);
+            println!("✅ SNS topic {} mapped to domain {}", mapping.topic_arn, mapping.domain_id);
+            HttpResponse::Ok().json(StatusResponse::success())
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+pub async fn delete_mapping(req: HttpRequest, path: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let topic_arn = path.into_inner();
+
+    match delete(&topic_arn, &data).await {
+        Ok(0) => HttpResponse::NotFound().json(ErrorResponse::new("Topic mapping not found")),
+        Ok(_) => {
+            forget(&topic_arn);
+            println!("✅ SNS topic {} unmapped", topic_arn);
+            HttpResponse::Ok().json(StatusResponse::success())
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
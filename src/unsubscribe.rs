@@ -0,0 +1,170 @@
+use std::env;
+
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use utoipa::ToSchema;
+
+use crate::blacklist::{self, NewEntry, CATEGORY_UNSUBSCRIBE};
+use crate::domains;
+use crate::handlers::is_admin;
+use crate::privacy;
+use crate::responses::{ErrorResponse, StatusResponse};
+use crate::services::notifications::extract_email_address;
+use crate::AppState;
+
+// the links put in List-Unsubscribe are signed with UNSUBSCRIBE_SECRET, without it the endpoints answer 404
+fn secret() -> Option<String> {
+    env::var("UNSUBSCRIBE_SECRET").ok().filter(|secret| !secret.is_empty())
+}
+
+fn mac(secret: &str, domain_id: i32, email: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}\n{}", domain_id, email).as_bytes());
+    mac
+}
+
+// <domain id>.<base64url address>.<base64url HMAC-SHA256 of both>, the address is the recipient's own
+fn token(secret: &str, domain_id: i32, email: &str) -> String {
+    format!(
+        "{}.{}.{}",
+        domain_id,
+        BASE64.encode(email),
+        BASE64.encode(mac(secret, domain_id, email).finalize().into_bytes())
+    )
+}
+
+// the (domain id, address) of a token signed with the secret
+fn verify(secret: &str, token: &str) -> Option<(i32, String)> {
+    let mut parts = token.splitn(3, '.');
+    let domain_id = parts.next()?.parse::<i32>().ok()?;
+    let email = String::from_utf8(BASE64.decode(parts.next()?).ok()?).ok()?;
+    let presented = BASE64.decode(parts.next()?).ok()?;
+
+    mac(secret, domain_id, &email).verify_slice(&presented).ok()?;
+
+    Some((domain_id, email))
+}
+
+// RFC 8058: the body is List-Unsubscribe=One-Click, form urlencoded or as a multipart/form-data field
+fn is_one_click(body: &[u8]) -> bool {
+    let body = String::from_utf8_lossy(body);
+
+    let urlencoded = url::form_urlencoded::parse(body.trim().as_bytes())
+        .any(|(name, value)| name == "List-Unsubscribe" && value == "One-Click");
+    let multipart = body.contains("name=\"List-Unsubscribe\"") && body.contains("One-Click");
+
+    urlencoded || multipart
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UnsubscribeLink {
+    pub url: String,
+    // the List-Unsubscribe and List-Unsubscribe-Post header values to put in the message
+    pub list_unsubscribe: String,
+    pub list_unsubscribe_post: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UnsubscribeLinkResponse {
+    pub success: bool,
+    pub data: UnsubscribeLink,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkQuery {
+    pub email: String,
+}
+
+// the one-click link of a recipient, for the sending side to add to its messages. UNSUBSCRIBE_BASE_URL is the
+// public address of this service, e.g. https://bounces.example.com, the request's host otherwise.
+pub async fn link_handler(req: HttpRequest, path: web::Path<i32>, query: web::Query<LinkQuery>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let Some(secret) = secret() else {
+        return HttpResponse::NotFound().json(ErrorResponse::new("UNSUBSCRIBE_SECRET is not set"));
+    };
+
+    let email = extract_email_address(query.email.trim()).to_lowercase();
+
+    if !email.contains('@') {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("invalid email address {:?}", query.email)));
+    }
+
+    let base_url = env::var("UNSUBSCRIBE_BASE_URL").unwrap_or_else(|_| {
+        let info = req.connection_info();
+        format!("{}://{}", info.scheme(), info.host())
+    });
+    let url = format!("{}/unsubscribe/{}", base_url.trim_end_matches('/'), token(&secret, path.into_inner(), &email));
+
+    HttpResponse::Ok().json(UnsubscribeLinkResponse {
+        success: true,
+        data: UnsubscribeLink {
+            list_unsubscribe: format!("<{}>", url),
+            list_unsubscribe_post: "List-Unsubscribe=One-Click".into(),
+            url,
+        },
+    })
+}
+
+// the one-click POST mailbox providers send when the recipient unsubscribes: the address is suppressed in the
+// domain of the link with category unsubscribe. Posting again is a no-op, the link stays valid.
+pub async fn one_click_handler(path: web::Path<String>, body: Bytes, data: web::Data<AppState>) -> impl Responder {
+    let Some(secret) = secret() else {
+        return HttpResponse::NotFound().json(StatusResponse::fail("not found"));
+    };
+
+    let Some((domain_id, email)) = verify(&secret, &path.into_inner()) else {
+        return HttpResponse::NotFound().json(StatusResponse::fail("invalid unsubscribe link"));
+    };
+
+    if !is_one_click(&body) {
+        return HttpResponse::BadRequest().json(StatusResponse::fail("expected List-Unsubscribe=One-Click"));
+    }
+
+    let settings = domains::load(domain_id, &data).await;
+    let entry = NewEntry {
+        domain_id,
+        email: privacy::stored_email(&email),
+        reason: "List-Unsubscribe One-Click".into(),
+        category: CATEGORY_UNSUBSCRIBE.into(),
+        reason_summary: Some("Unsubscribed (one-click)".into()),
+        expires_at: settings.default_expiry(CATEGORY_UNSUBSCRIBE),
+        event_at: Some(chrono::Utc::now().naive_utc()),
+        ..NewEntry::default()
+    };
+
+    // ordered with the notifications of the recipient, see RecipientShards
+    let _recipient = data.shards.lock(domain_id, &entry.email).await;
+    match blacklist::insert_or_reactivate(&entry, "unsubscribe", &data).await {
+        Ok(()) => {
+            println!("✅ Unsubscribed {} from domain {}", entry.email, domain_id);
+            HttpResponse::Ok().json(StatusResponse::success())
+        }
+        Err(err) if blacklist::is_duplicate(&err) => HttpResponse::Ok().json(StatusResponse::success()),
+        Err(err) => {
+            println!("🔥 Failed to record the unsubscribe of {} from domain {}: {}", entry.email, domain_id, err);
+            HttpResponse::InternalServerError().json(StatusResponse::error("failed to record the unsubscribe"))
+        }
+    }
+}
+
+// opening the link in a browser must not unsubscribe (link scanners follow them), it shows a button posting it
+pub async fn page_handler(path: web::Path<String>) -> impl Responder {
+    let token = path.into_inner();
+
+    if secret().and_then(|secret| verify(&secret, &token)).is_none() {
+        return HttpResponse::NotFound().content_type("text/plain").body("invalid unsubscribe link");
+    }
+
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(format!(
+        r#"<!doctype html><form method="post" action="{}"><input type="hidden" name="List-Unsubscribe" value="One-Click"><button>Unsubscribe</button></form>"#,
+        token
+    ))
+}
@@ -0,0 +1,197 @@
+use std::env;
+use std::time::Duration;
+
+use hickory_resolver::TokioAsyncResolver;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::outbound;
+use crate::repo::EntryStatus;
+
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+
+// what the verifier thinks of the mailbox, anything it cannot tell is unknown and never blocks
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Deliverable,
+    Undeliverable(String),
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Off,
+    Warn,
+    Block,
+}
+
+// EMAIL_VERIFICATION_MODE=warn|block checks addresses before an operator removes or allowlists them, with
+// EMAIL_VERIFIER=http (a third-party API) or smtp (a RCPT TO probe of the recipient's MX)
+fn mode() -> Mode {
+    match env::var("EMAIL_VERIFICATION_MODE").as_deref() {
+        Ok("warn") => Mode::Warn,
+        Ok("block") => Mode::Block,
+        _ => Mode::Off,
+    }
+}
+
+fn timeout() -> Duration {
+    Duration::from_millis(
+        env::var("EMAIL_VERIFICATION_TIMEOUT_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_TIMEOUT_MS),
+    )
+}
+
+fn list(name: &str, default: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_else(|_| default.into())
+        .split(',')
+        .map(|value| value.trim().to_lowercase())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+// GET EMAIL_VERIFICATION_URL?{EMAIL_VERIFICATION_PARAM (email)}=..., with EMAIL_VERIFICATION_TOKEN as bearer token.
+// EMAIL_VERIFICATION_FIELD (result) of the JSON answer is compared with EMAIL_VERIFICATION_INVALID
+// (invalid,undeliverable) and EMAIL_VERIFICATION_VALID (valid,deliverable).
+async fn verify_http(email: &str) -> Verdict {
+    let Some(url) = env::var("EMAIL_VERIFICATION_URL").ok().filter(|url| !url.is_empty()) else {
+        return Verdict::Unknown("EMAIL_VERIFICATION_URL is not set".into());
+    };
+    let param = env::var("EMAIL_VERIFICATION_PARAM").unwrap_or_else(|_| "email".into());
+    let field = env::var("EMAIL_VERIFICATION_FIELD").unwrap_or_else(|_| "result".into());
+
+    let mut request = outbound::client().get(&url).query(&[(param.as_str(), email)]).timeout(timeout());
+
+    if let Ok(token) = env::var("EMAIL_VERIFICATION_TOKEN") {
+        request = request.bearer_auth(token);
+    }
+
+    let body: serde_json::Value = match request.send().await.and_then(|response| response.error_for_status()) {
+        Ok(response) => match response.json().await {
+            Ok(body) => body,
+            Err(err) => return Verdict::Unknown(format!("unreadable verification answer: {}", err)),
+        },
+        Err(err) => return Verdict::Unknown(format!("verification request failed: {}", err)),
+    };
+
+    let Some(result) = body.get(&field).and_then(|value| value.as_str()).map(|value| value.to_lowercase()) else {
+        return Verdict::Unknown(format!("verification answer has no {} field", field));
+    };
+
+    if list("EMAIL_VERIFICATION_INVALID", "invalid,undeliverable").contains(&result) {
+        Verdict::Undeliverable(format!("verification API answered {}", result))
+    } else if list("EMAIL_VERIFICATION_VALID", "valid,deliverable").contains(&result) {
+        Verdict::Deliverable
+    } else {
+        Verdict::Unknown(format!("verification API answered {}", result))
+    }
+}
+
+// reads one SMTP reply, following the `250-` continuation lines, and returns its code and last line
+async fn reply<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Result<(u16, String), String> {
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line).await.map_err(|err| err.to_string())? == 0 {
+            return Err("connection closed".into());
+        }
+
+        let code = line.get(..3).and_then(|code| code.parse().ok()).ok_or(format!("unexpected reply {:?}", line.trim()))?;
+
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code, line.trim().to_string()));
+        }
+    }
+}
+
+async fn command<S: AsyncBufReadExt + AsyncWriteExt + Unpin>(stream: &mut S, line: &str) -> Result<(u16, String), String> {
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await.map_err(|err| err.to_string())?;
+    reply(stream).await
+}
+
+// the conversation up to RCPT TO with the preferred MX, nothing is sent. Greylisting and catch-all domains
+// make the answer unknown or optimistic, so this only catches mailboxes the server plainly rejects.
+// EMAIL_VERIFICATION_HELO and EMAIL_VERIFICATION_FROM name the probe, many servers refuse anonymous ones.
+async fn probe(email: &str, domain: &str) -> Result<Verdict, String> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(|err| err.to_string())?;
+    let host = match resolver.mx_lookup(domain).await {
+        Ok(lookup) => lookup
+            .iter()
+            .min_by_key(|mx| mx.preference())
+            .map(|mx| mx.exchange().to_utf8().trim_end_matches('.').to_string())
+            .unwrap_or_else(|| domain.to_string()),
+        // without MX records mail goes to the domain itself
+        Err(_) => domain.to_string(),
+    };
+
+    let stream = TcpStream::connect((host.as_str(), 25)).await.map_err(|err| format!("{}: {}", host, err))?;
+    let mut stream = BufReader::new(stream);
+    let helo = env::var("EMAIL_VERIFICATION_HELO").unwrap_or_else(|_| "localhost".into());
+    let from = env::var("EMAIL_VERIFICATION_FROM").unwrap_or_default();
+
+    let (code, line) = reply(&mut stream).await?;
+    if code != 220 {
+        return Ok(Verdict::Unknown(format!("{} greeted with {}", host, line)));
+    }
+
+    for line in [format!("EHLO {}", helo), format!("MAIL FROM:<{}>", from)] {
+        let (code, answer) = command(&mut stream, &line).await?;
+        if !(200..300).contains(&code) {
+            return Ok(Verdict::Unknown(format!("{} refused the probe: {}", host, answer)));
+        }
+    }
+
+    let (code, answer) = command(&mut stream, &format!("RCPT TO:<{}>", email)).await?;
+    let _ = command(&mut stream, "QUIT").await;
+
+    Ok(match code {
+        250 | 251 => Verdict::Deliverable,
+        550..=553 => Verdict::Undeliverable(format!("{} answered {}", host, answer)),
+        _ => Verdict::Unknown(format!("{} answered {}", host, answer)),
+    })
+}
+
+async fn verify_smtp(email: &str) -> Verdict {
+    let Some((_, domain)) = email.rsplit_once('@') else {
+        return Verdict::Undeliverable("not an email address".into());
+    };
+
+    match tokio::time::timeout(timeout(), probe(email, domain)).await {
+        Ok(Ok(verdict)) => verdict,
+        Ok(Err(err)) => Verdict::Unknown(format!("SMTP probe failed: {}", err)),
+        Err(_) => Verdict::Unknown("SMTP probe timed out".into()),
+    }
+}
+
+pub async fn verify(email: &str) -> Verdict {
+    match env::var("EMAIL_VERIFIER").as_deref() {
+        Ok("smtp") => verify_smtp(email).await,
+        _ => verify_http(email).await,
+    }
+}
+
+// run before an operator lifts the suppression of an address. Err refuses the change in block mode,
+// Ok(Some(warning)) lets it through with the verdict to show.
+pub async fn before_removal(email: &str, to: EntryStatus, skip: bool) -> Result<Option<String>, String> {
+    let mode = mode();
+
+    if mode == Mode::Off || skip || !matches!(to, EntryStatus::Removed | EntryStatus::Allowlisted) {
+        return Ok(None);
+    }
+
+    match verify(email).await {
+        Verdict::Deliverable => Ok(None),
+        Verdict::Undeliverable(reason) if mode == Mode::Block => {
+            println!("🚨 Refused to lift the suppression of {}: {}", email, reason);
+            Err(format!("{} still looks undeliverable ({}), set skip_verification to override", email, reason))
+        }
+        Verdict::Undeliverable(reason) => Ok(Some(format!("{} still looks undeliverable: {}", email, reason))),
+        Verdict::Unknown(reason) => {
+            println!("Could not verify {} before lifting its suppression: {}", email, reason);
+            Ok(None)
+        }
+    }
+}
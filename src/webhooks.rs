@@ -0,0 +1,746 @@
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDateTime, Utc};
+use rand::Rng;
+use regex::Regex;
+use serde_json::Value;
+use sqlx::{MySql, Transaction};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::domain::WebhookDeadLetter;
+use crate::domains;
+use crate::events::{self, Event, LiveEvent};
+use crate::faults;
+use crate::outbound;
+use crate::responses::{ErrorResponse, ListResponse, ReplayOutcome, ReplayResponse};
+use crate::handlers::is_admin;
+use crate::repo::{build_pg_pool, build_pg_read_client, dynamodb, read_mysql, DBType};
+use crate::sigv4;
+use crate::AppState;
+
+const LIST_LIMIT: i64 = 100;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// a claimed delivery is attempted again by any instance after this, in case its sender died before settling it
+const CLAIM_LEASE: Duration = Duration::from_secs(60);
+
+// the event fields a payload template may use
+const TEMPLATE_FIELDS: &[&str] = &["domain_id", "event_type", "email", "category", "expires_at", "timestamp"];
+
+// deliveries being sent by this instance, for the autoscaling metrics
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+// WEBHOOK_URL receives every suppression event as JSON. The deliveries are stored in webhook_deliveries and sent by
+// a poller every WEBHOOK_POLL_MS, so retries survive a restart; failed ones are attempted again with exponential
+// backoff and full jitter and dead-lettered after WEBHOOK_MAX_ATTEMPTS. Delivery is at least once.
+struct Config {
+    url: String,
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    poll: Duration,
+    batch_size: i64,
+}
+
+fn config() -> Option<Config> {
+    let url = env::var("WEBHOOK_URL").ok().filter(|url| !url.is_empty())?;
+    let number = |var: &str, default: u64| env::var(var).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+
+    Some(Config {
+        url,
+        max_attempts: number("WEBHOOK_MAX_ATTEMPTS", 6).max(1) as u32,
+        base_delay: Duration::from_millis(number("WEBHOOK_BACKOFF_BASE_MS", 1000)),
+        max_delay: Duration::from_secs(number("WEBHOOK_BACKOFF_MAX_SECS", 300)),
+        poll: Duration::from_millis(number("WEBHOOK_POLL_MS", 1000).max(10)),
+        batch_size: number("WEBHOOK_BATCH_SIZE", 50).max(1) as i64,
+    })
+}
+
+// a stored delivery
+struct Delivery {
+    id: i64,
+    domain_id: i32,
+    url: String,
+    payload: String,
+    // failed so far
+    attempts: i64,
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        outbound::client_builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+fn pg_table() -> String {
+    env::var("PG_WEBHOOK_DEAD_LETTERS_TABLE").unwrap_or_else(|_| "webhook_dead_letters".into())
+}
+
+fn pg_deliveries_table() -> String {
+    env::var("PG_WEBHOOK_DELIVERIES_TABLE").unwrap_or_else(|_| "webhook_deliveries".into())
+}
+
+fn notify() -> &'static Notify {
+    static NOTIFY: OnceLock<Notify> = OnceLock::new();
+    NOTIFY.get_or_init(Notify::new)
+}
+
+fn from_pg_row(row: &tokio_postgres::Row) -> WebhookDeadLetter {
+    WebhookDeadLetter {
+        id: row.get("id"),
+        domain_id: row.get::<_, i32>("domain_id") as i64,
+        url: row.get("url"),
+        payload: row.get("payload"),
+        attempts: row.get("attempts"),
+        error: row.get("error"),
+        created_at: row.get("created_at"),
+        replayed_at: row.get("replayed_at"),
+        replay_status: row.get("replay_status"),
+        replay_error: row.get("replay_error"),
+    }
+}
+
+// random delay in [0, min(max, base * 2^attempt)), so receivers coming back are not hit by every retry at once
+fn backoff(config: &Config, attempt: u32) -> Duration {
+    let ceiling = config
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(config.max_delay);
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64))
+}
+
+async fn deliver(url: &str, payload: &str) -> Result<(), String> {
+    if faults::drop_webhook() {
+        return Err(format!("delivery to {} dropped by fault injection", url));
+    }
+
+    let mut request = client()
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(payload.to_string())
+        .build()
+        .map_err(|err| err.to_string())?;
+
+    // signed per attempt, SigV4 signatures expire after a few minutes
+    if let Some(target) = sigv4::target_for(url) {
+        sigv4::sign_request(&mut request, target)
+            .await
+            .map_err(|err| format!("failed to sign the request to {}: {}", url, err))?;
+    }
+
+    let response = client().execute(request).await.map_err(|err| err.to_string())?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("{} answered {}", url, response.status()))
+    }
+}
+
+// stores the live events of the bus for delivery and starts the poller sending them, a no-op without WEBHOOK_URL.
+// The suppressions of MySQL and Postgres come from the outbox, which stores their deliveries itself.
+pub fn spawn(data: web::Data<AppState>) -> Vec<JoinHandle<()>> {
+    if config().is_none() {
+        return vec![];
+    }
+
+    let events = data.events.clone();
+    let sender_data = data.clone();
+    let outboxed = !matches!(data.db_type, DBType::DynamoDB(_));
+
+    let mut tasks = vec![events::subscribe(&events, "webhook sender", move |event| match &event {
+        Event::EmailSuppressed(_) if outboxed => {}
+        _ => {
+            if let Some(event) = event.live() {
+                dispatch(&sender_data, event);
+            }
+        }
+    })];
+    tasks.extend(start(data));
+
+    tasks
+}
+
+fn placeholder_regex() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{\s*([A-Za-z_]+)\s*\}\}").expect("valid placeholder regex"))
+}
+
+fn field<'a>(fields: &'a serde_json::Map<String, Value>, name: &str) -> Result<&'a Value, String> {
+    if !TEMPLATE_FIELDS.contains(&name) {
+        return Err(format!("unknown template field {}, use one of {}", name, TEMPLATE_FIELDS.join(", ")));
+    }
+
+    Ok(fields.get(name).unwrap_or(&Value::Null))
+}
+
+fn render_value(template: &Value, fields: &serde_json::Map<String, Value>) -> Result<Value, String> {
+    Ok(match template {
+        Value::String(text) => {
+            // a string that is only a placeholder keeps the type of the field, e.g. null or a number
+            if let Some(caps) = placeholder_regex().captures(text).filter(|caps| caps[0].len() == text.len()) {
+                return field(fields, &caps[1]).cloned();
+            }
+
+            let mut rendered = String::with_capacity(text.len());
+            let mut last = 0;
+
+            for caps in placeholder_regex().captures_iter(text) {
+                let whole = caps.get(0).expect("match");
+                rendered.push_str(&text[last..whole.start()]);
+
+                match field(fields, &caps[1])? {
+                    Value::String(value) => rendered.push_str(value),
+                    Value::Null => {}
+                    value => rendered.push_str(&value.to_string()),
+                }
+
+                last = whole.end();
+            }
+
+            rendered.push_str(&text[last..]);
+            Value::String(rendered)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_value(item, fields)).collect::<Result<_, _>>()?),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), render_value(value, fields)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+// the payload of a domain's webhook_template: any JSON document whose strings may hold {{field}} placeholders of
+// TEMPLATE_FIELDS, e.g. {"properties": {"email": "{{email}}", "note": "SES {{event_type}} ({{category}})"}}
+pub fn render(template: &str, event: &LiveEvent) -> Result<String, String> {
+    let template: Value = serde_json::from_str(template).map_err(|err| format!("the template is not JSON: {}", err))?;
+    let Value::Object(fields) = serde_json::to_value(event).map_err(|err| err.to_string())? else {
+        return Err("the event is not an object".into());
+    };
+
+    serde_json::to_string(&render_value(&template, &fields)?).map_err(|err| err.to_string())
+}
+
+// the event shaped by the domain's template, a broken template falls back to the plain event so nothing is lost
+async fn payload(event: &LiveEvent, data: &web::Data<AppState>) -> String {
+    let plain = || serde_json::to_string(event).unwrap_or_default();
+
+    let Some(template) = domains::load(event.domain_id, data).await.webhook_template else {
+        return plain();
+    };
+
+    render(&template, event).unwrap_or_else(|err| {
+        println!("🔥 Invalid webhook template of domain {}, sending the plain event: {}", event.domain_id, err);
+        plain()
+    })
+}
+
+// stores the event for delivery in the background. Without a table to store it in (DynamoDB, or the database
+// failing) it is retried in memory, and lost on restart.
+fn dispatch(data: &web::Data<AppState>, event: &LiveEvent) {
+    let Some(config) = config() else {
+        return;
+    };
+
+    let event = event.clone();
+    let data = data.clone();
+
+    tokio::spawn(async move {
+        let payload = payload(&event, &data).await;
+
+        if let Err(err) = enqueue(event.domain_id, &config.url, &payload, &data).await {
+            if !matches!(data.db_type, DBType::DynamoDB(_)) {
+                println!("🔥 Failed to store the webhook delivery for domain {}, retrying in memory: {}", event.domain_id, err);
+            }
+            retry_in_memory(&config, event.domain_id, &payload, &data).await;
+        }
+    });
+}
+
+async fn enqueue(domain_id: i32, url: &str, payload: &str, data: &web::Data<AppState>) -> Result<(), String> {
+    let now = Utc::now().naive_utc();
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"INSERT INTO webhook_deliveries (domain_id, url, payload, next_attempt_at) VALUES (?, ?, ?, ?)"#)
+                .bind(domain_id)
+                .bind(url)
+                .bind(payload)
+                .bind(now)
+                .execute(pool)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, url, payload, next_attempt_at) VALUES ($1, $2, $3, $4)"#,
+                        table = pg_deliveries_table()
+                    ),
+                    &[&domain_id, &url, &payload, &now],
+                )
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        DBType::DynamoDB(_) => return Err(dynamodb::UNSUPPORTED.into()),
+    }
+
+    wake();
+    Ok(())
+}
+
+// the delivery of an outbox event, stored in the transaction marking it published so no event is marked without
+// its delivery. The poller is woken with wake() once committed.
+pub async fn enqueue_in_mysql(tx: &mut Transaction<'_, MySql>, event: &LiveEvent, data: &web::Data<AppState>) -> Result<(), String> {
+    let Some(config) = config() else {
+        return Ok(());
+    };
+
+    sqlx::query(r#"INSERT INTO webhook_deliveries (domain_id, url, payload, next_attempt_at) VALUES (?, ?, ?, ?)"#)
+        .bind(event.domain_id)
+        .bind(&config.url)
+        .bind(payload(event, data).await)
+        .bind(Utc::now().naive_utc())
+        .execute(tx)
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+pub async fn enqueue_in_pg(
+    tx: &tokio_postgres::Transaction<'_>,
+    event: &LiveEvent,
+    data: &web::Data<AppState>,
+) -> Result<(), String> {
+    let Some(config) = config() else {
+        return Ok(());
+    };
+
+    tx.execute(
+        &format!(
+            r#"INSERT INTO {table} (domain_id, url, payload, next_attempt_at) VALUES ($1, $2, $3, $4)"#,
+            table = pg_deliveries_table()
+        ),
+        &[&event.domain_id, &config.url, &payload(event, data).await, &Utc::now().naive_utc()],
+    )
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+pub fn wake() {
+    notify().notify_one();
+}
+
+async fn retry_in_memory(config: &Config, domain_id: i32, payload: &str, data: &web::Data<AppState>) {
+    let mut attempt = 0;
+
+    IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+
+    loop {
+        attempt += 1;
+
+        let err = match deliver(&config.url, payload).await {
+            Ok(()) => break,
+            Err(err) => err,
+        };
+
+        if attempt >= config.max_attempts {
+            println!("🔥 Webhook delivery for domain {} failed {} times, dead-lettering: {}", domain_id, attempt, err);
+            if let Err(err) = store(domain_id, &config.url, payload, attempt as i64, &err, data).await {
+                println!("🔥 Failed to store webhook dead letter for domain {}: {:?}", domain_id, err);
+            }
+            break;
+        }
+
+        let delay = backoff(config, attempt - 1);
+        println!("Webhook delivery for domain {} failed ({}), retrying in {:?}", domain_id, err, delay);
+        tokio::time::sleep(delay).await;
+    }
+
+    IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+}
+
+// the due deliveries, leased for CLAIM_LEASE so other instances skip them meanwhile
+async fn claim(batch_size: i64, data: &web::Data<AppState>) -> Result<Vec<Delivery>, String> {
+    let now = Utc::now().naive_utc();
+    let leased_until = now + chrono::Duration::from_std(CLAIM_LEASE).unwrap_or_default();
+
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            let mut tx = pool.begin().await.map_err(|err| err.to_string())?;
+
+            let rows = sqlx::query_as::<_, (i64, i64, String, String, i64)>(
+                r#"SELECT id, domain_id, url, payload, attempts FROM webhook_deliveries WHERE next_attempt_at <= ?
+                   ORDER BY next_attempt_at LIMIT ? FOR UPDATE SKIP LOCKED"#,
+            )
+                .bind(now)
+                .bind(batch_size)
+                .fetch_all(&mut tx)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            if rows.is_empty() {
+                return Ok(vec![]);
+            }
+
+            let ids = rows.iter().map(|row| row.0.to_string()).collect::<Vec<_>>().join(",");
+            sqlx::query(&format!("UPDATE webhook_deliveries SET next_attempt_at = ? WHERE id IN ({})", ids))
+                .bind(leased_until)
+                .execute(&mut tx)
+                .await
+                .map_err(|err| err.to_string())?;
+
+            tx.commit().await.map_err(|err| err.to_string())?;
+
+            Ok(rows
+                .into_iter()
+                .map(|(id, domain_id, url, payload, attempts)| Delivery { id, domain_id: domain_id as i32, url, payload, attempts })
+                .collect())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query(
+                    &format!(
+                        r#"UPDATE {table} SET next_attempt_at = $1 WHERE id IN (
+                               SELECT id FROM {table} WHERE next_attempt_at <= $2 ORDER BY next_attempt_at LIMIT $3 FOR UPDATE SKIP LOCKED
+                           ) RETURNING id, domain_id, url, payload, attempts"#,
+                        table = pg_deliveries_table()
+                    ),
+                    &[&leased_until, &now, &batch_size],
+                )
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| Delivery {
+                            id: row.get(0),
+                            domain_id: row.get(1),
+                            url: row.get(2),
+                            payload: row.get(3),
+                            attempts: row.get(4),
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn remove(id: i64, data: &web::Data<AppState>) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => sqlx::query(r#"DELETE FROM webhook_deliveries WHERE id = ?"#)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(&format!(r#"DELETE FROM {table} WHERE id = $1"#, table = pg_deliveries_table()), &[&id])
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn reschedule(
+    id: i64,
+    attempts: i64,
+    error: &str,
+    next_attempt_at: NaiveDateTime,
+    data: &web::Data<AppState>,
+) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"UPDATE webhook_deliveries SET attempts = ?, last_error = ?, next_attempt_at = ? WHERE id = ?"#)
+                .bind(attempts)
+                .bind(error)
+                .bind(next_attempt_at)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET attempts = $1, last_error = $2, next_attempt_at = $3 WHERE id = $4"#,
+                        table = pg_deliveries_table()
+                    ),
+                    &[&attempts, &error, &next_attempt_at, &id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+// one attempt of a stored delivery: removed once delivered or dead-lettered, otherwise scheduled again
+async fn attempt(config: &Config, delivery: Delivery, data: &web::Data<AppState>) -> Result<(), String> {
+    IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+    let delivered = deliver(&delivery.url, &delivery.payload).await;
+    IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+
+    let err = match delivered {
+        Ok(()) => return remove(delivery.id, data).await,
+        Err(err) => err,
+    };
+
+    let attempts = delivery.attempts + 1;
+    let now = Utc::now().naive_utc();
+
+    if attempts >= config.max_attempts as i64 {
+        println!("🔥 Webhook delivery for domain {} failed {} times, dead-lettering: {}", delivery.domain_id, attempts, err);
+
+        return match store(delivery.domain_id, &delivery.url, &delivery.payload, attempts, &err, data).await {
+            Ok(()) => remove(delivery.id, data).await,
+            // kept and dead-lettered on a later attempt
+            Err(store_err) => {
+                let retry_at = now + chrono::Duration::from_std(config.max_delay).unwrap_or_default();
+                reschedule(delivery.id, attempts - 1, &err, retry_at, data).await?;
+                Err(format!("failed to store the dead letter: {}", store_err))
+            }
+        };
+    }
+
+    let delay = backoff(config, attempts as u32 - 1);
+    println!("Webhook delivery for domain {} failed ({}), retrying in {:?}", delivery.domain_id, err, delay);
+
+    reschedule(delivery.id, attempts, &err, now + chrono::Duration::from_std(delay).unwrap_or_default(), data).await
+}
+
+// sends the stored deliveries as they become due, every WEBHOOK_POLL_MS or at once when this instance stored one
+fn start(data: web::Data<AppState>) -> Option<JoinHandle<()>> {
+    let config = config()?;
+    if matches!(data.db_type, DBType::DynamoDB(_)) {
+        return None;
+    }
+
+    println!("🚀 Webhook delivery poller started, polling every {:?}", config.poll);
+
+    Some(tokio::spawn(async move {
+        loop {
+            let deliveries = claim(config.batch_size, &data).await.unwrap_or_else(|err| {
+                println!("🔥 Failed to claim the due webhook deliveries: {}", err);
+                vec![]
+            });
+            let claimed = deliveries.len() as i64;
+
+            let attempts = deliveries.into_iter().map(|delivery| {
+                let (id, data, config) = (delivery.id, &data, &config);
+                async move {
+                    if let Err(err) = attempt(config, delivery, data).await {
+                        println!("🔥 Failed to settle webhook delivery {}: {}", id, err);
+                    }
+                }
+            });
+            futures_util::future::join_all(attempts).await;
+
+            // a full batch, more are likely due
+            if claimed == config.batch_size {
+                continue;
+            }
+
+            tokio::select! {
+                _ = notify().notified() => {}
+                _ = tokio::time::sleep(config.poll) => {}
+            }
+        }
+    }))
+}
+
+async fn store(
+    domain_id: i32,
+    url: &str,
+    payload: &str,
+    attempts: i64,
+    error: &str,
+    data: &web::Data<AppState>,
+) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"INSERT INTO webhook_dead_letters (domain_id, url, payload, attempts, error) VALUES (?,?,?,?,?)"#)
+                .bind(domain_id)
+                .bind(url)
+                .bind(payload)
+                .bind(attempts)
+                .bind(error)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_pool(&data.db_url).await {
+            Ok(client) => client
+                .execute(
+                    &format!(
+                        r#"INSERT INTO {table} (domain_id, url, payload, attempts, error) VALUES ($1,$2,$3,$4,$5)"#,
+                        table = pg_table()
+                    ),
+                    &[&domain_id, &url, &payload, &attempts, &error],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn find(id: i64, data: &web::Data<AppState>) -> Result<Option<WebhookDeadLetter>, String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query_as::<_, WebhookDeadLetter>(r#"SELECT * FROM webhook_dead_letters WHERE id = ?"#)
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .query_opt(&format!(r#"SELECT * FROM {table} WHERE id = $1"#, table = pg_table()), &[&id])
+                .await
+                .map(|row| row.as_ref().map(from_pg_row))
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+async fn record_replay(id: i64, status: &str, error: Option<&str>, data: &web::Data<AppState>) -> Result<(), String> {
+    match &data.db_type {
+        DBType::MySQL(pool) => {
+            sqlx::query(r#"UPDATE webhook_dead_letters SET replayed_at = NOW(), replay_status = ?, replay_error = ? WHERE id = ?"#)
+                .bind(status)
+                .bind(error)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => {
+            let client = build_pg_pool(&data.db_url).await.map_err(|err| err.to_string())?;
+
+            client
+                .execute(
+                    &format!(
+                        r#"UPDATE {table} SET replayed_at = NOW(), replay_status = $1, replay_error = $2 WHERE id = $3"#,
+                        table = pg_table()
+                    ),
+                    &[&status, &error, &id],
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    }
+}
+
+pub async fn list_dead_letters(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let query_result: Result<Vec<WebhookDeadLetter>, String> = match &data.db_type {
+        DBType::MySQL(pool) => {
+            read_mysql(&data, pool, |pool| async move {
+                sqlx::query_as::<_, WebhookDeadLetter>(r#"SELECT * FROM webhook_dead_letters ORDER BY id DESC LIMIT ?"#)
+                    .bind(LIST_LIMIT)
+                    .fetch_all(&pool)
+                    .await
+            })
+                .await
+                .map_err(|err| err.to_string())
+        }
+        DBType::Postgres => match build_pg_read_client(&data).await {
+            Ok(client) => client
+                .query(
+                    &format!(r#"SELECT * FROM {table} ORDER BY id DESC LIMIT $1"#, table = pg_table()),
+                    &[&LIST_LIMIT],
+                )
+                .await
+                .map(|rows| rows.iter().map(from_pg_row).collect())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        DBType::DynamoDB(_) => Err(dynamodb::UNSUPPORTED.into()),
+    };
+
+    match query_result {
+        Ok(dead_letters) => HttpResponse::Ok().json(ListResponse::new(dead_letters)),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err))),
+    }
+}
+
+// delivers the stored event once more to its original url, without retries
+pub async fn replay_dead_letter(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Unauthorized"));
+    }
+
+    let id = path.into_inner();
+
+    let dead_letter = match find(id, &data).await {
+        Ok(Some(dead_letter)) => dead_letter,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResponse::new("Webhook dead letter not found"));
+        }
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new(format!("🔥 Failed to query the database: {:?}", err)));
+        }
+    };
+
+    let (status, error) = match deliver(&dead_letter.url, &dead_letter.payload).await {
+        Ok(()) => ("success", None),
+        Err(err) => ("failed", Some(err)),
+    };
+
+    println!("Replayed webhook dead letter {}: {} {:?}", id, status, error);
+
+    if let Err(err) = record_replay(id, status, error.as_deref(), &data).await {
+        println!("🔥 Failed to record replay outcome for webhook dead letter {}: {:?}", id, err);
+    }
+
+    HttpResponse::Ok().json(ReplayResponse {
+        success: true,
+        data: ReplayOutcome { id, status: status.into(), error },
+    })
+}